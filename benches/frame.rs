@@ -0,0 +1,52 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use solar_mon::solarmanv5::{Request, RequestFrameType, RequestHeader, RequestPayload, Response};
+
+const RTU_FRAME: [u8; 8] = [0x1, 0x3, 0x0, 0x3b, 0x0, 0x36, 0xb4, 0x11];
+
+fn request() -> Request<'static> {
+    Request {
+        header: RequestHeader {
+            msg_id: 0,
+            logger_serial: [0x12, 0x34, 0x56, 0x78],
+        },
+        payload: RequestPayload {
+            frame_type: RequestFrameType::SolarInverter,
+            sensor_type: 0,
+            total_working_second: 0,
+            uptime_second: 0,
+            offset_seconds: 0,
+            modbus_rtu_frame: &RTU_FRAME,
+        },
+    }
+}
+
+fn build_request(c: &mut Criterion) {
+    let request = request();
+    c.bench_function("request to_bytes", |b| {
+        b.iter(|| black_box(&request).to_bytes())
+    });
+
+    let mut buffer = Vec::new();
+    c.bench_function("request write_to reused buffer", |b| {
+        b.iter(|| {
+            buffer.clear();
+            black_box(&request).write_to(&mut buffer);
+            black_box(buffer.len())
+        })
+    });
+}
+
+fn parse_response(c: &mut Criterion) {
+    // Size of the response to the monitoring request, 0x36 registers.
+    let mut frame = [0u8; 140];
+    frame[0] = 0xA5;
+    frame[1..3].copy_from_slice(&127u16.to_le_bytes());
+    frame[3..5].copy_from_slice(&[0x10, 0x15]);
+    frame[139] = 0x15;
+    c.bench_function("response from_bytes", |b| {
+        b.iter(|| Response::from_bytes(black_box(&frame)).map(|r| r.payload.rtu_frame.len()))
+    });
+}
+
+criterion_group!(benches, build_request, parse_response);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use solar_mon::registers::RegisterMap;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = RegisterMap::micro(4).decode(data);
+});
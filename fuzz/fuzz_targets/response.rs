@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use solar_mon::solarmanv5::{verify_frame, Response};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = verify_frame(data);
+    if let Ok(response) = Response::from_bytes(data) {
+        assert_eq!(response.payload.rtu_frame.len(), data.len() - Response::MIN_LENGTH);
+    }
+});
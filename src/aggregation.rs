@@ -0,0 +1,173 @@
+use crate::output::Sample;
+use chrono::{DateTime, Utc};
+
+struct FieldStats {
+    name: String,
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u32,
+}
+
+/// All values of one measurement and tag set within the current window.
+struct Series {
+    measurement: String,
+    tags: Vec<(String, String)>,
+    time: DateTime<Utc>,
+    /// Trace id of the latest poll
+    trace_id: Option<String>,
+    fields: Vec<FieldStats>,
+}
+
+/// Collects the polls of one inverter over a write interval and condenses
+/// them into one sample per series with the mean of every field and its
+/// minimum and maximum as `<field>_min` and `<field>_max`.
+pub(crate) struct Aggregator {
+    window: chrono::Duration,
+    window_start: Option<DateTime<Utc>>,
+    series: Vec<Series>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(window: std::time::Duration) -> Self {
+        Aggregator {
+            window: chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX),
+            window_start: None,
+            series: Vec::new(),
+        }
+    }
+
+    /// Adds the samples of one poll and returns the aggregated samples once
+    /// the window is over.
+    pub(crate) fn push(&mut self, samples: Vec<Sample>) -> Option<Vec<Sample>> {
+        let mut latest = None;
+        for sample in samples {
+            latest = latest.max(Some(sample.time));
+            self.window_start.get_or_insert(sample.time);
+            let idx = match self
+                .series
+                .iter()
+                .position(|series| series.measurement == sample.measurement && series.tags == sample.tags)
+            {
+                Some(idx) => idx,
+                None => {
+                    self.series.push(Series {
+                        measurement: sample.measurement,
+                        tags: sample.tags,
+                        time: sample.time,
+                        trace_id: None,
+                        fields: Vec::new(),
+                    });
+                    self.series.len() - 1
+                }
+            };
+            let series = &mut self.series[idx];
+            series.time = series.time.max(sample.time);
+            if sample.trace_id.is_some() {
+                series.trace_id = sample.trace_id;
+            }
+            for (name, value) in sample.fields {
+                match series.fields.iter_mut().find(|stats| stats.name == name) {
+                    Some(stats) => {
+                        stats.min = stats.min.min(value);
+                        stats.max = stats.max.max(value);
+                        stats.sum += value;
+                        stats.count += 1;
+                    }
+                    None => series.fields.push(FieldStats {
+                        name,
+                        min: value,
+                        max: value,
+                        sum: value,
+                        count: 1,
+                    }),
+                }
+            }
+        }
+        match (self.window_start, latest) {
+            (Some(start), Some(latest)) if latest - start >= self.window => Some(self.flush()),
+            _ => None,
+        }
+    }
+
+    /// Condenses the samples pushed so far and starts a new window.
+    pub(crate) fn flush(&mut self) -> Vec<Sample> {
+        self.window_start = None;
+        self.series
+            .drain(..)
+            .map(|series| {
+                let mut sample = Sample::new(series.measurement, series.time);
+                sample.tags = series.tags;
+                sample.trace_id = series.trace_id;
+                for stats in series.fields {
+                    sample = sample
+                        .field(format!("{}_min", stats.name), stats.min)
+                        .field(format!("{}_max", stats.name), stats.max)
+                        .field(stats.name, stats.sum / f64::from(stats.count));
+                }
+                sample
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn poll(secs: i64, power: f64) -> Vec<Sample> {
+        let time = DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+        vec![Sample::new("solar", time).tag("inverter", "roof").field("ac_power", power)]
+    }
+
+    #[test]
+    fn window_ends_after_its_duration() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(60));
+        assert!(aggregator.push(poll(0, 100.0)).is_none());
+        assert!(aggregator.push(poll(59, 200.0)).is_none());
+        let samples = aggregator.push(poll(60, 300.0)).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].time, poll(60, 0.0)[0].time);
+        // The next window starts with the next poll.
+        assert!(aggregator.push(poll(61, 100.0)).is_none());
+        assert!(aggregator.push(poll(120, 100.0)).is_none());
+        assert!(aggregator.push(poll(121, 100.0)).is_some());
+    }
+
+    #[test]
+    fn mean_minimum_and_maximum() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(60));
+        aggregator.push(poll(0, 100.0));
+        aggregator.push(poll(20, 400.0));
+        let samples = aggregator.push(poll(60, 250.0)).unwrap();
+        assert_eq!(samples[0].field_value("ac_power"), Some(250.0));
+        assert_eq!(samples[0].field_value("ac_power_min"), Some(100.0));
+        assert_eq!(samples[0].field_value("ac_power_max"), Some(400.0));
+        assert_eq!(samples[0].tag_value("inverter"), Some("roof"));
+    }
+
+    #[test]
+    fn series_are_kept_apart() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(60));
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let input = |name: &str, power| {
+            Sample::new("solar", time).tag("input", name).field("power", power)
+        };
+        aggregator.push(vec![input("A", 10.0), input("B", 20.0)]);
+        let samples = aggregator.flush();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].field_value("power"), Some(10.0));
+        assert_eq!(samples[1].field_value("power"), Some(20.0));
+    }
+
+    #[test]
+    fn empty_window() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(60));
+        assert!(aggregator.push(Vec::new()).is_none());
+        assert!(aggregator.flush().is_empty());
+        aggregator.push(poll(0, 100.0));
+        assert_eq!(aggregator.flush().len(), 1);
+        assert!(aggregator.flush().is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+//! Archive of the raw Modbus responses of every poll, so the readings can be
+//! decoded again once a decoding bug is fixed or more registers are mapped.
+//!
+//! Every day gets a file `raw-<date>.jsonl` with one JSON line per poll,
+//! dates in UTC. Files of past days are compressed to `raw-<date>.jsonl.gz`.
+
+use crate::output::{self, Sample};
+use crate::{to_samples, Config, InverterConfig, MonitoringConfig};
+use anyhow::Context;
+use chrono::{NaiveDate, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ArchiveConfig {
+    pub(crate) directory: PathBuf,
+    /// Days the files of past days are kept, forever if unset
+    #[serde(default)]
+    keep_days: Option<u32>,
+}
+
+/// A poll in the archive.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Record {
+    /// RFC 3339 time of the poll
+    pub(crate) time: String,
+    pub(crate) inverter: String,
+    /// RTU frames of the responses in hex, see
+    /// [`solar_mon::inverter::Inverter::last_responses`]
+    pub(crate) responses: Vec<String>,
+}
+
+impl Record {
+    /// Samples of the poll decoded with the current config, at the time of
+    /// the poll and tagged `backfilled`.
+    fn samples(
+        &self,
+        inverter_cfg: &InverterConfig,
+        monitoring_config: &MonitoringConfig,
+    ) -> anyhow::Result<Vec<Sample>> {
+        let time = chrono::DateTime::parse_from_rfc3339(&self.time)?.with_timezone(&Utc);
+        let responses = self
+            .responses
+            .iter()
+            .map(|frame| unhex(frame))
+            .collect::<Option<Vec<_>>>()
+            .context("Response is no hex")?;
+        let data = inverter_cfg.register_map()?.decode_responses(&responses)?;
+        let samples = to_samples(&self.inverter, inverter_cfg, monitoring_config, &data);
+        Ok(samples
+            .into_iter()
+            .map(|mut sample| {
+                sample.time = time;
+                sample.tag("backfilled", "true")
+            })
+            .collect())
+    }
+}
+
+pub(crate) struct Archive {
+    config: ArchiveConfig,
+    /// File of the current day
+    file: Mutex<Option<(NaiveDate, File)>>,
+}
+
+impl Archive {
+    pub(crate) fn open(config: &ArchiveConfig) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&config.directory).with_context(|| {
+            format!("Failed to create archive directory {}", config.directory.display())
+        })?;
+        let archive = Archive {
+            config: config.clone(),
+            file: Mutex::new(None),
+        };
+        archive.rotate(Utc::now().date_naive())?;
+        Ok(archive)
+    }
+
+    /// Appends a poll of `inverter`. Failures are logged, the poll itself
+    /// succeeded.
+    pub(crate) fn record(&self, inverter: &str, responses: &[Vec<u8>]) {
+        let now = Utc::now();
+        let record = Record {
+            time: now.to_rfc3339(),
+            inverter: inverter.to_owned(),
+            responses: responses.iter().map(|frame| hex(frame)).collect(),
+        };
+        if let Err(e) = self.append(now.date_naive(), &record) {
+            log::error!("[{inverter}] Failed to archive the raw responses ({e:#})");
+        }
+    }
+
+    fn append(&self, date: NaiveDate, record: &Record) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let file = match &mut *file {
+            Some((file_date, file)) if *file_date == date => file,
+            current => {
+                *current = None;
+                self.rotate(date)?;
+                let path = self.config.directory.join(file_name(date));
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+                &mut current.insert((date, file)).1
+            }
+        };
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Compresses the files of the days before `today` and removes those
+    /// older than `keep_days`.
+    fn rotate(&self, today: NaiveDate) -> anyhow::Result<()> {
+        let oldest = self
+            .config
+            .keep_days
+            .and_then(|days| today.checked_sub_days(chrono::Days::new(days.into())));
+        for entry in std::fs::read_dir(&self.config.directory)? {
+            let path = entry?.path();
+            let Some((date, compressed)) = parse_file_name(&path) else {
+                continue;
+            };
+            if oldest.is_some_and(|oldest| date < oldest) {
+                log::info!("Removing expired archive {}", path.display());
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            } else if !compressed && date < today {
+                if let Err(e) = compress(&path) {
+                    log::warn!("Keeping {} uncompressed ({e:#})", path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn file_name(date: NaiveDate) -> String {
+    format!("raw-{}.jsonl", date.format("%Y-%m-%d"))
+}
+
+/// Date of an archive file and whether it is compressed.
+pub(crate) fn parse_file_name(path: &Path) -> Option<(NaiveDate, bool)> {
+    let name = path.file_name()?.to_str()?.strip_prefix("raw-")?;
+    let (date, compressed) = match name.strip_suffix(".jsonl.gz") {
+        Some(date) => (date, true),
+        None => (name.strip_suffix(".jsonl")?, false),
+    };
+    Some((NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?, compressed))
+}
+
+/// Replaces the file at `path` by a gzip compressed copy. The original is
+/// only removed once the copy is on disk and decompresses to the same
+/// length, on any error the copy is removed instead.
+fn compress(path: &Path) -> anyhow::Result<()> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".gz");
+    let compressed = PathBuf::from(compressed);
+    let written = write_compressed(&compressed, &data)
+        .with_context(|| format!("Failed to write {}", compressed.display()))
+        .and_then(|()| {
+            let mut decompressed = Vec::new();
+            MultiGzDecoder::new(File::open(&compressed)?)
+                .read_to_end(&mut decompressed)
+                .with_context(|| format!("Failed to decompress {}", compressed.display()))?;
+            if decompressed.len() != data.len() {
+                anyhow::bail!(
+                    "{} decompresses to {} instead of {} bytes",
+                    compressed.display(),
+                    decompressed.len(),
+                    data.len()
+                );
+            }
+            Ok(())
+        });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&compressed);
+        return Err(e);
+    }
+    std::fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+fn write_compressed(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(path)?, Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()?.sync_all()?;
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Archived polls of `day`, `None` if there are none.
+fn read_day(directory: &Path, day: NaiveDate) -> anyhow::Result<Option<String>> {
+    let path = directory.join(file_name(day));
+    let mut compressed = path.clone().into_os_string();
+    compressed.push(".gz");
+    let compressed = PathBuf::from(compressed);
+    let data = if compressed.exists() {
+        let file = File::open(&compressed)
+            .with_context(|| format!("Failed to read {}", compressed.display()))?;
+        let mut data = Vec::new();
+        MultiGzDecoder::new(file)
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to decompress {}", compressed.display()))?;
+        data
+    } else if path.exists() {
+        std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(String::from_utf8(data)?))
+}
+
+/// Decodes the archived polls of the days `from` to `to` with the current
+/// register maps and writes them to every output. Polls that still fail to
+/// decode are counted and skipped.
+pub(crate) fn reprocess(
+    config: &Config,
+    from: NaiveDate,
+    to: NaiveDate,
+    inverters: &[String],
+) -> anyhow::Result<()> {
+    let archive_config = config
+        .archive
+        .as_ref()
+        .context("reprocess requires an [archive] section in the config")?;
+    let mut outputs = output::create_sinks(config)?;
+    let mut unknown = HashSet::new();
+    for day in from.iter_days().take_while(|day| *day <= to) {
+        let Some(lines) = read_day(&archive_config.directory, day)? else {
+            log::info!("No archived polls of {day}");
+            continue;
+        };
+        let mut samples = Vec::new();
+        let (mut polls, mut failed) = (0, 0);
+        for (number, line) in lines.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+            let record: Record = serde_json::from_str(line).with_context(|| {
+                format!("Line {} of the archive of {day} is corrupt", number + 1)
+            })?;
+            if !inverters.is_empty() && !inverters.contains(&record.inverter) {
+                continue;
+            }
+            let Some(inverter_cfg) = config.inverter.get(&record.inverter) else {
+                if unknown.insert(record.inverter.clone()) {
+                    log::warn!("[{}] Skipping polls of unconfigured inverter", record.inverter);
+                }
+                continue;
+            };
+            polls += 1;
+            match record.samples(inverter_cfg, &config.monitoring) {
+                Ok(poll) => samples.extend(poll),
+                Err(e) => {
+                    failed += 1;
+                    let inverter = &record.inverter;
+                    log::debug!("[{inverter}] Failed to decode poll of {} ({e:#})", record.time);
+                }
+            }
+        }
+        log::info!("Reprocessing {polls} polls of {day}, {failed} failed to decode");
+        for chunk in samples.chunks(5000) {
+            if !outputs.write_all(chunk, "reprocess") {
+                anyhow::bail!("Failed to store the polls of {day}");
+            }
+        }
+    }
+    if !outputs.flush_all("reprocess") {
+        anyhow::bail!("Failed to store the reprocessed polls");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_replaces_the_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("raw-2024-06-01.jsonl");
+        let lines = "{\"inverter\":\"roof\"}\n".repeat(1000);
+        std::fs::write(&path, &lines).unwrap();
+        compress(&path).unwrap();
+        assert!(!path.exists());
+        let day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        assert_eq!(read_day(directory.path(), day).unwrap(), Some(lines));
+    }
+
+    #[test]
+    fn compress_keeps_the_file_on_error() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("raw-2024-06-01.jsonl");
+        std::fs::write(&path, "{}\n").unwrap();
+        // A directory in the way of the compressed file.
+        std::fs::create_dir(directory.path().join("raw-2024-06-01.jsonl.gz")).unwrap();
+        assert!(compress(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}\n");
+    }
+}
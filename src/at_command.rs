@@ -0,0 +1,149 @@
+//! AT command interface of the logger sticks, reachable over UDP next to the
+//! SolarmanV5 port. It exposes the stick itself, e.g. its WiFi status.
+
+use crate::error_code::ErrorCode;
+use anyhow::Context;
+use std::io::ErrorKind;
+use std::net::{IpAddr, UdpSocket};
+use std::time::Duration;
+
+/// UDP port of the AT interface.
+pub const PORT: u16 = 48899;
+
+/// Greeting that opens an AT session, answered with `<ip>,<mac>,<serial>`.
+const HELLO: &[u8] = b"WIFIKIT-214028-READ";
+
+pub struct AtSession {
+    socket: UdpSocket,
+}
+
+impl AtSession {
+    pub fn open(addr: IpAddr, port: u16, timeout: Duration) -> anyhow::Result<Self> {
+        let bind_addr = match addr {
+            IpAddr::V4(_) => "0.0.0.0:0",
+            IpAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(bind_addr).context("Failed to open UDP socket")?;
+        socket.connect((addr, port))?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        let session = AtSession { socket };
+        session.socket.send(HELLO)?;
+        let greeting = session.receive().context("Logger stick did not answer the AT greeting")?;
+        if greeting.split(',').count() < 3 {
+            return Err(ErrorCode::AtCommandFailed.error(format!("Unexpected AT greeting '{greeting}'")));
+        }
+        session.socket.send(b"+ok")?;
+        Ok(session)
+    }
+
+    fn receive(&self) -> anyhow::Result<String> {
+        let mut buffer = [0; 512];
+        let len = self.socket.recv(&mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer[..len]).trim().to_owned())
+    }
+
+    /// Sends `AT+<command>` and returns the answer without the `+ok=`
+    /// prefix.
+    pub fn command(&mut self, command: &str) -> anyhow::Result<String> {
+        self.socket.send(format!("AT+{command}\n").as_bytes())?;
+        let reply = self
+            .receive()
+            .with_context(|| format!("Logger stick did not answer AT+{command}"))?;
+        match reply.strip_prefix("+ok") {
+            Some(value) => Ok(value.trim_start_matches('=').to_owned()),
+            None => Err(ErrorCode::AtCommandFailed.error(format!("AT+{command} failed ({reply})"))),
+        }
+    }
+}
+
+impl Drop for AtSession {
+    fn drop(&mut self) {
+        // Leaves command mode, the stick otherwise ignores new greetings for a while.
+        let _ = self.socket.send(b"AT+Q\n");
+    }
+}
+
+/// Signal quality in percent from an `AT+WSLQ` answer like `Normal, 83%`,
+/// `None` while the stick is not connected to a network.
+pub fn parse_signal_quality(reply: &str) -> Option<u8> {
+    let (value, _) = reply.split_once('%')?;
+    let digits = value.rsplit(|c: char| !c.is_ascii_digit()).next()?;
+    digits.parse().ok()
+}
+
+/// Reads the WiFi signal quality of the stick at `addr` in percent.
+pub fn signal_quality(addr: IpAddr, port: u16, timeout: Duration) -> anyhow::Result<u8> {
+    let reply = AtSession::open(addr, port, timeout)?.command("WSLQ")?;
+    parse_signal_quality(&reply).with_context(|| format!("Logger stick has no WiFi signal ({reply})"))
+}
+
+/// Firmware version of the stick at `addr`, e.g. `LSW3_15_FFFF_1.0.65`.
+pub fn firmware_version(addr: IpAddr, port: u16, timeout: Duration) -> anyhow::Result<String> {
+    let reply = AtSession::open(addr, port, timeout)?.command("YZVER")?;
+    Ok(reply.trim().to_owned())
+}
+
+/// Restarts the stick at `addr`, it is unreachable for about half a minute
+/// afterwards.
+pub fn reboot(addr: IpAddr, port: u16, timeout: Duration) -> anyhow::Result<()> {
+    AtSession::open(addr, port, timeout)?.command("Z")?;
+    Ok(())
+}
+
+/// Logger stick that answered a discovery greeting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredStick {
+    /// Address the answer came from
+    pub addr: IpAddr,
+    pub mac: String,
+    pub serial: u32,
+}
+
+/// Sends the AT greeting to `addr`, usually the broadcast address of the
+/// network, and collects the sticks answering within `timeout`.
+pub fn discover(
+    addr: IpAddr,
+    port: u16,
+    timeout: Duration,
+) -> anyhow::Result<Vec<DiscoveredStick>> {
+    let bind_addr = match addr {
+        IpAddr::V4(_) => "0.0.0.0:0",
+        IpAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(bind_addr).context("Failed to open UDP socket")?;
+    socket.set_broadcast(true)?;
+    socket.send_to(HELLO, (addr, port))?;
+    let deadline = std::time::Instant::now() + timeout;
+    let mut sticks: Vec<DiscoveredStick> = Vec::new();
+    let mut buffer = [0; 512];
+    loop {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (len, peer) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e.into()),
+        };
+        // `<ip>,<mac>,<serial>`, anything else is not a logger stick.
+        let greeting = String::from_utf8_lossy(&buffer[..len]);
+        let mut parts = greeting.trim().split(',');
+        let (Some(_), Some(mac), Some(serial)) = (parts.next(), parts.next(), parts.next()) else {
+            continue;
+        };
+        let Ok(serial) = serial.parse() else {
+            continue;
+        };
+        if sticks.iter().all(|stick| stick.serial != serial) {
+            sticks.push(DiscoveredStick {
+                addr: peer.ip(),
+                mac: mac.to_owned(),
+                serial,
+            });
+        }
+    }
+    Ok(sticks)
+}
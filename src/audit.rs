@@ -0,0 +1,185 @@
+//! Append-only record of every action changing the inverters or the running
+//! daemon, like register writes or pausing an inverter.
+
+use crate::output::{Databases, InfluxOutput, Output, Sample};
+use crate::Config;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct AuditConfig {
+    /// File every action is appended to as a line of JSON
+    pub(crate) file: PathBuf,
+    /// Also write the actions to this measurement of the InfluxDB database
+    #[serde(default)]
+    measurement: Option<String>,
+}
+
+struct Audit {
+    file: PathBuf,
+    influx: Option<(String, Mutex<InfluxOutput>)>,
+}
+
+static AUDIT: OnceLock<Audit> = OnceLock::new();
+
+/// Starts recording the actions if the config has an `[audit]` section.
+pub(crate) fn init(config: &Config) -> anyhow::Result<()> {
+    let Some(audit_config) = &config.audit else {
+        return Ok(());
+    };
+    let influx = match &audit_config.measurement {
+        Some(measurement) => {
+            // Actions are rare and must not wait in a buffer.
+            let mut monitoring_config = config.monitoring.clone();
+            monitoring_config.influx_flush_intervall_secs = None;
+            let output = InfluxOutput::new(
+                &monitoring_config,
+                reqwest::blocking::Client::new(),
+                Databases::default(),
+            )?;
+            Some((measurement.clone(), Mutex::new(output)))
+        }
+        None => None,
+    };
+    let audit = Audit {
+        file: audit_config.file.clone(),
+        influx,
+    };
+    AUDIT.set(audit).ok().context("Audit log was already started")
+}
+
+/// Where an action was requested and by whom, if known.
+#[derive(Debug, Clone)]
+pub(crate) struct Origin {
+    /// `cli`, `api`, `mqtt` or `bridge`
+    pub(crate) source: &'static str,
+    pub(crate) user: Option<String>,
+}
+
+impl Origin {
+    pub(crate) fn new(source: &'static str, user: Option<String>) -> Self {
+        Origin { source, user }
+    }
+
+    /// The user running this process.
+    pub(crate) fn cli() -> Self {
+        let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok();
+        Origin::new("cli", user)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Action {
+    time: String,
+    source: &'static str,
+    user: Option<String>,
+    action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inverter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    register: Option<u16>,
+    /// Register values before the write, if they could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_value: Option<Vec<u16>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_value: Option<Vec<u16>>,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Action {
+    pub(crate) fn new(origin: &Origin, action: &'static str) -> Self {
+        Action {
+            time: chrono::Utc::now().to_rfc3339(),
+            source: origin.source,
+            user: origin.user.clone(),
+            action,
+            inverter: None,
+            register: None,
+            old_value: None,
+            new_value: None,
+            ok: true,
+            error: None,
+        }
+    }
+
+    pub(crate) fn inverter(mut self, name: &str) -> Self {
+        self.inverter = Some(name.to_owned());
+        self
+    }
+
+    pub(crate) fn registers(
+        mut self,
+        register: u16,
+        old_value: Option<Vec<u16>>,
+        new_value: Vec<u16>,
+    ) -> Self {
+        self.register = Some(register);
+        self.old_value = old_value;
+        self.new_value = Some(new_value);
+        self
+    }
+
+    /// Records the action with the outcome `result`. Failing to record it
+    /// is logged, the action itself already happened.
+    pub(crate) fn record<T>(mut self, result: &anyhow::Result<T>) {
+        let Some(audit) = AUDIT.get() else {
+            return;
+        };
+        if let Err(e) = result {
+            self.ok = false;
+            self.error = Some(format!("{e:#}"));
+        }
+        if let Err(e) = append(&audit.file, &self) {
+            log::error!("Failed to write audit log {} ({e:#})", audit.file.display());
+        }
+        if let Some((measurement, output)) = &audit.influx {
+            let sample = self.to_sample(measurement);
+            let written = output.lock().unwrap_or_else(PoisonError::into_inner).write(&[sample]);
+            if let Err(e) = written {
+                log::error!("Failed to write audit measurement ({e:#})");
+            }
+        }
+    }
+
+    /// Values are fields of the first register only, the file has all.
+    fn to_sample(&self, measurement: &str) -> Sample {
+        let mut sample = Sample::new(measurement, chrono::Utc::now())
+            .field("ok", if self.ok { 1.0 } else { 0.0 })
+            .tag("source", self.source)
+            .tag("action", self.action);
+        if let Some(user) = &self.user {
+            sample = sample.tag("user", user.clone());
+        }
+        if let Some(inverter) = &self.inverter {
+            sample = sample.tag("inverter", inverter.clone());
+        }
+        if let Some(register) = self.register {
+            sample = sample.field("register", f64::from(register));
+        }
+        let first = |values: &Option<Vec<u16>>| values.as_ref()?.first().copied();
+        if let Some(value) = first(&self.old_value) {
+            sample = sample.field("old_value", f64::from(value));
+        }
+        if let Some(value) = first(&self.new_value) {
+            sample = sample.field("new_value", f64::from(value));
+        }
+        sample
+    }
+}
+
+fn append(path: &PathBuf, action: &Action) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(action)?;
+    line.push(b'\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! Share of the expected polls that succeeded during daylight, to tell how
+//! reliable a logger stick is.
+
+use crate::output::Sample;
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct AvailabilityConfig {
+    /// Measurement of the daily availability
+    #[serde(default = "default_availability_measurement")]
+    measurement: String,
+    /// Local hours `[from, to)` in which polls are expected to succeed
+    #[serde(default = "crate::default_daylight_hours")]
+    daylight_hours: [u32; 2],
+}
+
+fn default_availability_measurement() -> String {
+    "availability".to_owned()
+}
+
+/// Counts the polls of one inverter during the daylight hours of the
+/// current local day.
+pub(crate) struct Availability {
+    config: AvailabilityConfig,
+    interval_secs: f64,
+    day: Option<NaiveDate>,
+    /// Daylight time the daemon polled the inverter on `day`
+    daylight_secs: f64,
+    polls: u32,
+    /// Time of the previous step, `None` while paused
+    last_step: Option<DateTime<Local>>,
+}
+
+impl Availability {
+    pub(crate) fn new(config: AvailabilityConfig, interval_secs: u32) -> Self {
+        Availability {
+            config,
+            interval_secs: f64::from(interval_secs),
+            day: None,
+            daylight_secs: 0.0,
+            polls: 0,
+            last_step: None,
+        }
+    }
+
+    fn is_daylight(&self, time: DateTime<Local>) -> bool {
+        let [from, to] = self.config.daylight_hours;
+        (from..to).contains(&time.hour())
+    }
+
+    /// Accounts the time since the previous step, before polling at `now`.
+    /// Returns the sample of the previous day once a new day starts.
+    pub(crate) fn step(&mut self, inverter_name: &str, now: DateTime<Local>) -> Option<Sample> {
+        let today = now.date_naive();
+        let finished = match self.day {
+            Some(day) if day != today => self.sample(inverter_name, day),
+            _ => None,
+        };
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.daylight_secs = 0.0;
+            self.polls = 0;
+            self.last_step = None;
+        }
+        if self.is_daylight(now) {
+            // Only the daylight part of the time since the previous step.
+            let daylight_start = now
+                .with_hour(self.config.daylight_hours[0])
+                .and_then(|time| time.with_minute(0))
+                .and_then(|time| time.with_second(0))
+                .unwrap_or(now);
+            if let Some(last) = self.last_step {
+                let since = last.max(daylight_start);
+                self.daylight_secs += (now - since).num_milliseconds() as f64 / 1000.0;
+            }
+        }
+        self.last_step = Some(now);
+        finished
+    }
+
+    /// Time while paused is not expected to be polled.
+    pub(crate) fn pause(&mut self) {
+        self.last_step = None;
+    }
+
+    /// Counts a successful poll at `now`.
+    pub(crate) fn succeeded(&mut self, now: DateTime<Local>) {
+        if self.is_daylight(now) {
+            self.polls += 1;
+        }
+    }
+
+    fn expected_polls(&self) -> f64 {
+        self.daylight_secs / self.interval_secs
+    }
+
+    /// Availability of the day so far between 0 and 1, `None` before a
+    /// poll was expected.
+    pub(crate) fn ratio(&self) -> Option<f64> {
+        let expected = self.expected_polls();
+        (expected >= 1.0).then(|| (f64::from(self.polls) / expected).min(1.0))
+    }
+
+    /// Sample of `day`, stamped with its start.
+    fn sample(&self, inverter_name: &str, day: NaiveDate) -> Option<Sample> {
+        let ratio = self.ratio()?;
+        let midnight = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+        let time = Local.from_local_datetime(&midnight).earliest()?;
+        Some(
+            Sample::new(self.config.measurement.clone(), time.to_utc())
+                .field("availability", ratio)
+                .field("polls", f64::from(self.polls))
+                .field("expected_polls", self.expected_polls().round())
+                .tag("inverter", inverter_name),
+        )
+    }
+}
@@ -0,0 +1,148 @@
+use crate::audit::{Action, Origin};
+use crate::modbus_tcp;
+use crate::writes::{self, WritesConfig};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use solar_mon::inverter::Inverter;
+use solar_mon::registers::crc16;
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Local Modbus TCP server forwarding every request to an inverter.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct BridgeConfig {
+    /// Modbus has no authentication, every client reaching this address
+    /// may write the registers of the inverters
+    #[serde(default = "default_bridge_listen")]
+    listen: String,
+}
+
+fn default_bridge_listen() -> String {
+    "127.0.0.1:1502".to_owned()
+}
+
+/// Exception code for requests the inverter did not answer.
+const SLAVE_DEVICE_FAILURE: u8 = 0x04;
+
+/// Exception code for writes that are not allowed now.
+const ILLEGAL_FUNCTION: u8 = 0x01;
+
+/// An inverter shared between its poll thread and the bridge, the lock
+/// serializes the requests of both.
+pub(crate) type SharedInverter = Arc<Mutex<Inverter>>;
+
+/// Forwards the requests for unit id 1, 2, ... to the inverters sorted by
+/// name once their poll thread connected.
+pub(crate) struct Bridge {
+    unit_ids: HashMap<u8, String>,
+    inverters: Mutex<HashMap<String, SharedInverter>>,
+    writes: Option<WritesConfig>,
+}
+
+impl Bridge {
+    pub(crate) fn start(
+        config: &BridgeConfig,
+        inverters: &[&str],
+        writes: Option<WritesConfig>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let mut inverters = inverters.to_vec();
+        inverters.sort_unstable();
+        let bridge = Arc::new(Bridge {
+            unit_ids: (1..=247).zip(inverters.into_iter().map(str::to_owned)).collect(),
+            inverters: Mutex::new(HashMap::new()),
+            writes,
+        });
+        let listener = TcpListener::bind(&config.listen)
+            .with_context(|| format!("Failed to listen on {}", config.listen))?;
+        let server_bridge = bridge.clone();
+        modbus_tcp::spawn_server("bridge", listener, move |unit_id, pdu| {
+            server_bridge.forward(unit_id, pdu)
+        })
+        .context("Failed to start Modbus bridge thread")?;
+        log::info!("Modbus bridge listening on {}", config.listen);
+        Ok(bridge)
+    }
+
+    pub(crate) fn register(&self, name: &str, inverter: SharedInverter) {
+        self.inverters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_owned(), inverter);
+    }
+
+    fn forward(&self, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+        let inverter = self.unit_ids.get(&unit_id).and_then(|name| {
+            let inverters = self.inverters.lock().unwrap_or_else(PoisonError::into_inner);
+            Some((name, inverters.get(name)?.clone()))
+        });
+        let Some((name, inverter)) = inverter else {
+            return modbus_tcp::exception(pdu, modbus_tcp::GATEWAY_TARGET_FAILED);
+        };
+        let mut inverter = inverter.lock().unwrap_or_else(PoisonError::into_inner);
+        if !writes::is_write(pdu) {
+            return Self::send(name, &mut inverter, pdu);
+        }
+        let action = Action::new(&Origin::new("bridge", None), "bridge_write").inverter(name);
+        // Any client reaching the bridge may send writes, they need to be
+        // allowed explicitly.
+        let rejection = match &self.writes {
+            None => Some("Writes need a [writes] section"),
+            Some(writes) if !writes.allowed_now() => Some("Outside the allowed windows"),
+            Some(_) => None,
+        };
+        if let Some(rejection) = rejection {
+            log::warn!("[{name}] Rejected bridged write ({rejection})");
+            action.record::<()>(&Err(anyhow::anyhow!(rejection)));
+            return modbus_tcp::exception(pdu, ILLEGAL_FUNCTION);
+        }
+        Self::forward_write(name, &mut inverter, pdu, action)
+    }
+
+    /// Forwards a write, recording it with the values it replaced.
+    fn forward_write(
+        name: &str,
+        inverter: &mut Inverter,
+        pdu: &[u8],
+        mut action: Action,
+    ) -> Vec<u8> {
+        if let Some((register, values)) = writes::written_registers(pdu) {
+            let old_values = writes::read_registers(inverter, register, values.len() as u16);
+            action = action.registers(register, old_values, values);
+        }
+        let response = Self::send(name, inverter, pdu);
+        let result = match response.first() {
+            Some(function) if Some(function) == pdu.first() => Ok(()),
+            _ => Err(anyhow::anyhow!(
+                "Inverter rejected the write (exception {:#04x})",
+                response.get(1).copied().unwrap_or_default()
+            )),
+        };
+        action.record(&result);
+        response
+    }
+
+    fn send(name: &str, inverter: &mut Inverter, pdu: &[u8]) -> Vec<u8> {
+        let mut rtu_frame = vec![inverter.slave_id()];
+        rtu_frame.extend_from_slice(pdu);
+        rtu_frame.extend(crc16(&rtu_frame).to_le_bytes());
+        match inverter.send_raw(&rtu_frame) {
+            Ok(response) if response.len() >= 4 => {
+                let (frame, crc) = response.split_at(response.len() - 2);
+                if crc16(frame).to_le_bytes() != crc {
+                    log::debug!("[{name}] Bridged response has a wrong CRC");
+                    return modbus_tcp::exception(pdu, SLAVE_DEVICE_FAILURE);
+                }
+                frame[1..].to_vec()
+            }
+            Ok(response) => {
+                log::debug!("[{name}] Bridged response too short ({} bytes)", response.len());
+                modbus_tcp::exception(pdu, SLAVE_DEVICE_FAILURE)
+            }
+            Err(e) => {
+                log::debug!("[{name}] Failed to forward bridged request ({e})");
+                modbus_tcp::exception(pdu, SLAVE_DEVICE_FAILURE)
+            }
+        }
+    }
+}
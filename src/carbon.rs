@@ -0,0 +1,114 @@
+//! CO2 emissions avoided by the production, from the carbon intensity of
+//! the grid electricity it replaces.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long a fetched intensity is used before it is fetched again.
+const FETCH_INTERVAL: Duration = Duration::from_secs(3600);
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Carbon intensity of the grid in g CO2eq per kWh.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct CarbonConfig {
+    /// Static intensity, also used while the API can't be reached
+    #[serde(default)]
+    intensity: Option<f64>,
+    /// Fetch the current intensity of a zone from Electricity Maps
+    #[serde(default)]
+    electricity_maps: Option<ElectricityMapsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ElectricityMapsConfig {
+    /// Zone of the grid, e.g. `DE`
+    zone: String,
+    token: String,
+    #[serde(default = "default_electricity_maps_url")]
+    url: String,
+}
+
+fn default_electricity_maps_url() -> String {
+    "https://api.electricitymap.org/v3/carbon-intensity/latest".to_owned()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IntensityResponse {
+    carbon_intensity: f64,
+}
+
+/// Shared by all pollers, so the API is asked once per interval and not
+/// once per inverter.
+struct Fetched {
+    /// Time of the last request, successful or not
+    attempt: Option<Instant>,
+    /// Intensity of the last successful request
+    intensity: Option<f64>,
+}
+
+static FETCHED: Mutex<Fetched> = Mutex::new(Fetched {
+    attempt: None,
+    intensity: None,
+});
+
+impl CarbonConfig {
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if self.intensity.is_none() && self.electricity_maps.is_none() {
+            anyhow::bail!("The carbon section needs `intensity` or `electricity_maps`");
+        }
+        Ok(())
+    }
+
+    /// Current intensity, refreshed at most once an hour in the background
+    /// so the poll doesn't wait for the API. Falls back to the last fetched
+    /// and then the static intensity until a fetch succeeds, `None` if there
+    /// is neither.
+    pub(crate) fn intensity(&self) -> Option<f64> {
+        let Some(api) = &self.electricity_maps else {
+            return self.intensity;
+        };
+        let mut fetched = FETCHED.lock().unwrap_or_else(PoisonError::into_inner);
+        if fetched.attempt.is_none_or(|attempt| attempt.elapsed() >= FETCH_INTERVAL) {
+            fetched.attempt = Some(Instant::now());
+            let api = api.clone();
+            let spawned = std::thread::Builder::new()
+                .name("carbon intensity".to_owned())
+                .spawn(move || api.refresh());
+            if let Err(e) = spawned {
+                log::warn!("Failed to start the carbon intensity fetch ({e})");
+            }
+        }
+        fetched.intensity.or(self.intensity)
+    }
+}
+
+impl ElectricityMapsConfig {
+    /// Fetches the intensity into `FETCHED`, keeping the last one on error.
+    fn refresh(&self) {
+        match self.fetch() {
+            Ok(intensity) => {
+                log::debug!("Grid carbon intensity in {} is {intensity} g/kWh", self.zone);
+                FETCHED.lock().unwrap_or_else(PoisonError::into_inner).intensity = Some(intensity);
+            }
+            Err(e) => log::warn!("Failed to fetch the grid carbon intensity ({e:#})"),
+        }
+    }
+
+    fn fetch(&self) -> anyhow::Result<f64> {
+        let response: IntensityResponse = reqwest::blocking::Client::new()
+            .get(&self.url)
+            .query(&[("zone", self.zone.as_str())])
+            .header("auth-token", &self.token)
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Failed to reach Electricity Maps")?
+            .json()
+            .context("Failed to parse Electricity Maps response")?;
+        Ok(response.carbon_intensity)
+    }
+}
@@ -0,0 +1,305 @@
+use crate::completions::Shell;
+use crate::export::ExportFormat;
+use crate::fault_log::FaultFormat;
+use crate::table::OutputFormat;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Monitoring for Deye SUN600G3 micro inverters")]
+pub(crate) struct Cli {
+    /// Path of the configuration file
+    #[arg(short, long, default_value = "config.toml")]
+    pub(crate) config: PathBuf,
+
+    /// Poll every inverter once, store the data and exit. The exit status
+    /// reflects whether all inverters were read and stored successfully.
+    #[arg(long)]
+    pub(crate) once: bool,
+
+    /// Directory of the state, status and archive files, overrides
+    /// `state_dir` of the config
+    #[arg(long, global = true)]
+    pub(crate) state_dir: Option<PathBuf>,
+
+    /// Directory of cached data like access tokens, overrides `cache_dir`
+    #[arg(long, global = true)]
+    pub(crate) cache_dir: Option<PathBuf>,
+
+    /// Directory of the audit and log files, overrides `log_dir`
+    #[arg(long, global = true)]
+    pub(crate) log_dir: Option<PathBuf>,
+
+    /// Format of the results printed by the commands
+    #[arg(long, global = true, value_enum, default_value = "table")]
+    pub(crate) output_format: OutputFormat,
+
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Detach from the terminal and keep running in the background
+    #[cfg(unix)]
+    #[arg(long)]
+    pub(crate) daemon: bool,
+
+    /// Write the process id of the daemon to this file
+    #[cfg(unix)]
+    #[arg(long, requires = "daemon")]
+    pub(crate) pid_file: Option<PathBuf>,
+
+    /// File mode creation mask of the daemon in octal notation
+    #[cfg(unix)]
+    #[arg(long, requires = "daemon", value_parser = parse_umask, default_value = "027")]
+    pub(crate) umask: u32,
+
+    /// Redirect the log output of the daemon to this file
+    #[cfg(unix)]
+    #[arg(long, requires = "daemon")]
+    pub(crate) log_file: Option<PathBuf>,
+
+    /// Register the monitoring as a Windows service using the given config file
+    #[cfg(windows)]
+    #[arg(long, conflicts_with = "run_as_service")]
+    pub(crate) install_service: bool,
+
+    /// Remove the previously installed Windows service
+    #[cfg(windows)]
+    #[arg(long, conflicts_with_all = ["install_service", "run_as_service"])]
+    pub(crate) uninstall_service: bool,
+
+    /// Run under the Windows service control manager, only used by the installed service
+    #[cfg(windows)]
+    #[arg(long, hide = true)]
+    pub(crate) run_as_service: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Exit successfully if the running daemon stored data recently,
+    /// requires `status_file` in the monitoring config
+    Healthcheck {
+        /// Maximum age of the last successful write, defaults to three intervals
+        #[arg(long)]
+        max_age_secs: Option<u64>,
+    },
+    /// Dump stored samples as CSV or JSON
+    Export {
+        /// Start of the time range, a date (YYYY-MM-DD) or RFC 3339 time
+        #[arg(long, value_parser = crate::export::parse_time)]
+        from: DateTime<Utc>,
+        /// End of the time range (exclusive), defaults to now
+        #[arg(long, value_parser = crate::export::parse_time)]
+        to: Option<DateTime<Utc>>,
+        /// Only export this inverter, can be given multiple times
+        #[arg(long = "inverter")]
+        inverters: Vec<String>,
+        /// Defaults to JSON with `--output-format json`, else CSV
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Import the station history from the Solarman cloud into the database
+    ImportSolarman {
+        /// First day to import (YYYY-MM-DD)
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last day to import (YYYY-MM-DD), defaults to today
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+    /// Decode the polls in the raw archive again with the current register
+    /// maps and write them to the outputs, tagged as backfilled
+    Reprocess {
+        /// First day to reprocess (YYYY-MM-DD, UTC)
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last day to reprocess (YYYY-MM-DD, UTC), defaults to today
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// Only reprocess this inverter, can be given multiple times
+        #[arg(long = "inverter")]
+        inverters: Vec<String>,
+    },
+    /// Print the grid protection settings of the inverters, e.g. to check
+    /// the country profile after a firmware update
+    GridSettings {
+        /// Only read this inverter, can be given multiple times
+        #[arg(long = "inverter")]
+        inverters: Vec<String>,
+    },
+    /// Print the production of the inverters per month or year, kept in
+    /// the state file by the daemon
+    Stats {
+        /// Only show this inverter, can be given multiple times
+        #[arg(long = "inverter")]
+        inverters: Vec<String>,
+        /// Sum up whole years instead of months
+        #[arg(long)]
+        yearly: bool,
+    },
+    /// Work with the history of faults kept in the fault log
+    #[command(subcommand)]
+    Faults(FaultsCommand),
+    /// Poll every inverter once, write the data to every output and print
+    /// which steps passed, e.g. to check a new installation
+    Selftest {
+        /// Write to this measurement instead of the configured one
+        #[arg(long)]
+        measurement: Option<String>,
+    },
+    /// Show the current values of the inverters in a table that refreshes
+    /// until interrupted, e.g. for a quick check over SSH
+    Watch {
+        /// Only show this inverter, can be given multiple times
+        #[arg(long = "inverter")]
+        inverters: Vec<String>,
+        /// Time between two refreshes
+        #[arg(long, default_value_t = 5)]
+        interval_secs: u64,
+    },
+    /// Poll one inverter at a high rate and write DC input and AC output
+    /// power pairs as CSV, e.g. to plot its efficiency curve
+    EfficiencyCurve {
+        /// Name of the inverter as configured
+        #[arg(long)]
+        inverter: String,
+        /// How long to record
+        #[arg(long, default_value_t = 600)]
+        duration_secs: u64,
+        /// Time between two polls
+        #[arg(long, default_value_t = 1000)]
+        interval_ms: u64,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write holding registers of an inverter, e.g. its power limit
+    WriteRegister {
+        /// Name of the inverter as configured
+        #[arg(long)]
+        inverter: String,
+        /// First register, decimal or hex with 0x prefix
+        #[arg(long, value_parser = parse_register)]
+        register: u16,
+        /// Values written to the register and the following ones
+        #[arg(long = "value", required = true, value_parser = parse_register)]
+        values: Vec<u16>,
+        /// Write even outside the windows of the `[writes]` section
+        #[arg(long = "i-know-what-i-am-doing")]
+        force: bool,
+    },
+    /// Select another grid standard (country profile) on an inverter, asks
+    /// for the inverter name as confirmation
+    SetGridStandard {
+        /// Name of the inverter as configured
+        #[arg(long)]
+        inverter: String,
+        /// Number of the standard, decimal or hex with 0x prefix
+        #[arg(long, value_parser = parse_register)]
+        standard: u16,
+        /// Inverter name as confirmation instead of asking for it, for scripts
+        #[arg(long)]
+        confirm: Option<String>,
+        /// Write even outside the windows of the `[writes]` section
+        #[arg(long = "i-know-what-i-am-doing")]
+        force: bool,
+    },
+    /// Print the SolarmanV5 frames in a Wireshark or tcpdump capture of the
+    /// traffic of a logger stick, no config needed
+    DecodePcap {
+        /// Capture file in pcap or pcapng format
+        file: PathBuf,
+    },
+    /// Print the completion script of a shell, no config needed
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print the man page in roff format, no config needed
+    Man,
+    /// Create the config file interactively: find the logger sticks in the
+    /// network, test poll them, check the database and write the config to
+    /// the path given by `--config`
+    Setup,
+    /// Stop polling an inverter in the running daemon until it is resumed,
+    /// e.g. during maintenance
+    Pause(Remote),
+    /// Resume polling a paused inverter in the running daemon
+    Resume(Remote),
+    /// Manage the encrypted store of the `[secrets]` section
+    #[command(subcommand)]
+    Secrets(SecretsCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum SecretsCommand {
+    /// Store a secret, the value is read from stdin
+    Set {
+        name: String,
+    },
+    /// Remove a secret
+    Remove {
+        name: String,
+    },
+    /// Print the names of the stored secrets
+    List,
+    /// Write a new age identity to a key file for `key_file`
+    GenerateKey {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum FaultsCommand {
+    /// Write the faults with their start and end times, e.g. for a warranty
+    /// claim
+    Export {
+        /// Start of the time range, a date (YYYY-MM-DD) or RFC 3339 time
+        #[arg(long, value_parser = crate::export::parse_time)]
+        from: Option<DateTime<Utc>>,
+        /// End of the time range (exclusive)
+        #[arg(long, value_parser = crate::export::parse_time)]
+        to: Option<DateTime<Utc>>,
+        /// Only export this inverter, can be given multiple times
+        #[arg(long = "inverter")]
+        inverters: Vec<String>,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: FaultFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Target of a command sent to the running daemon through its HTTP output.
+#[derive(Debug, clap::Args)]
+pub(crate) struct Remote {
+    /// Name of the inverter as configured or added
+    pub(crate) inverter: String,
+    /// Base URL of the HTTP output, defaults to the one with `control = true`
+    /// on this host
+    #[arg(long)]
+    pub(crate) url: Option<String>,
+    /// Bearer token of an admin user of the HTTP output
+    #[arg(long)]
+    pub(crate) token: Option<String>,
+}
+
+fn parse_register(value: &str) -> Result<u16, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| format!("'{value}' is not a 16 bit number"))
+}
+
+#[cfg(unix)]
+fn parse_umask(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mask| *mask <= 0o777)
+        .ok_or_else(|| format!("'{value}' is not an octal file mode mask"))
+}
@@ -0,0 +1,26 @@
+//! Shell completions and the man page, generated from the clap definition
+//! of the command line so packagers can ship them with the binary.
+
+use crate::cli::Cli;
+use clap::CommandFactory;
+use std::io::Write;
+
+pub(crate) use clap_complete::Shell;
+
+pub(crate) fn print_completions(shell: Shell) -> anyhow::Result<()> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_owned();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+    std::io::stdout().lock().write_all(&script)?;
+    Ok(())
+}
+
+pub(crate) fn print_man_page() -> anyhow::Result<()> {
+    let command = Cli::command().long_about(
+        "Polls the inverters through their logger sticks and writes the readings to InfluxDB \
+         and the other configured outputs. Without a command it runs as daemon.",
+    );
+    clap_mangen::Man::new(command).render(&mut std::io::stdout().lock())?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+//! Process wide limit on the outbound connections open at the same time,
+//! so many inverters starting to poll together do not flood the network.
+
+use std::sync::{Condvar, Mutex, OnceLock, PoisonError};
+
+struct Limit {
+    max: usize,
+    open: Mutex<usize>,
+    released: Condvar,
+}
+
+static LIMIT: OnceLock<Limit> = OnceLock::new();
+
+/// Limits the connections to `max` from now on, the first call wins.
+/// Without a call connections are never limited.
+pub fn set_max_connections(max: usize) {
+    let _ = LIMIT.set(Limit {
+        max: max.max(1),
+        open: Mutex::new(0),
+        released: Condvar::new(),
+    });
+}
+
+/// Allows one connection until it is dropped.
+#[must_use]
+pub struct Permit {
+    limit: Option<&'static Limit>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(limit) = self.limit {
+            *limit.open.lock().unwrap_or_else(PoisonError::into_inner) -= 1;
+            limit.released.notify_one();
+        }
+    }
+}
+
+/// Blocks until another connection to `target` may be opened.
+pub fn acquire(target: impl std::fmt::Display) -> Permit {
+    let Some(limit) = LIMIT.get() else {
+        return Permit { limit: None };
+    };
+    let mut open = limit.open.lock().unwrap_or_else(PoisonError::into_inner);
+    if *open >= limit.max {
+        log::debug!("Waiting for one of {} connections to close to connect to {target}", limit.max);
+    }
+    while *open >= limit.max {
+        open = limit.released.wait(open).unwrap_or_else(PoisonError::into_inner);
+    }
+    *open += 1;
+    Permit { limit: Some(limit) }
+}
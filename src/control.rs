@@ -0,0 +1,105 @@
+use crate::audit::Origin;
+use crate::cli::Remote;
+use crate::{output, Config, InverterConfig};
+use anyhow::Context;
+use serde::Deserialize;
+use std::sync::mpsc;
+
+/// Changes to the running daemon requested through the HTTP API or MQTT.
+pub(crate) enum Command {
+    AddInverter(Box<AddInverter>),
+    /// Stops polling the named inverter until it is resumed
+    Pause(String),
+    Resume(String),
+}
+
+impl Command {
+    /// Command addressed by a path like `<name>/pause`, `None` if there is
+    /// no such command.
+    pub(crate) fn for_inverter(path: &str) -> Option<Self> {
+        let (name, action) = path.rsplit_once('/')?;
+        match action {
+            "pause" => Some(Command::Pause(name.to_owned())),
+            "resume" => Some(Command::Resume(name.to_owned())),
+            _ => None,
+        }
+    }
+}
+
+/// Inverter to poll from now on, the config is the same as in an
+/// `[inverter.<name>]` table.
+#[derive(Debug, Deserialize)]
+pub(crate) struct AddInverter {
+    pub(crate) name: String,
+    /// Also append the inverter to the config file
+    #[serde(default)]
+    pub(crate) persist: bool,
+    #[serde(flatten)]
+    pub(crate) config: InverterConfig,
+}
+
+pub(crate) struct Request {
+    pub(crate) command: Command,
+    /// Who asked for the command, for the audit log
+    pub(crate) origin: Origin,
+    pub(crate) reply: mpsc::Sender<anyhow::Result<()>>,
+}
+
+/// Handle for sending commands to the daemon, shared by everything
+/// accepting them.
+#[derive(Clone)]
+pub(crate) struct Control {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Control {
+    pub(crate) fn channel() -> (Self, mpsc::Receiver<Request>) {
+        let (sender, receiver) = mpsc::channel();
+        (Control { sender }, receiver)
+    }
+
+    /// Hands `command` to the daemon and waits until it was carried out.
+    pub(crate) fn send(&self, origin: &Origin, command: Command) -> anyhow::Result<()> {
+        let (reply, result) = mpsc::channel();
+        let origin = origin.clone();
+        self.sender
+            .send(Request { command, origin, reply })
+            .ok()
+            .context("Daemon does not accept commands")?;
+        result.recv().context("Daemon did not answer the command")?
+    }
+
+    /// Parses the JSON body of an add request and sends it.
+    pub(crate) fn add_inverter(&self, origin: &Origin, body: &[u8]) -> anyhow::Result<()> {
+        let request: AddInverter = serde_json::from_slice(body).context("Invalid inverter")?;
+        self.send(origin, Command::AddInverter(Box::new(request)))
+    }
+}
+
+/// Sends `action`, e.g. `pause`, for the inverter of `remote` to the
+/// running daemon through its HTTP output.
+pub(crate) fn send_remote(config: &Config, remote: &Remote, action: &str) -> anyhow::Result<()> {
+    let url = match &remote.url {
+        Some(url) => url.trim_end_matches('/').to_owned(),
+        None => output::control_url(config)
+            .context("No HTTP output with control = true configured, pass --url")?,
+    };
+    let mut request = reqwest::blocking::Client::new()
+        .post(format!("{url}/api/v1/inverters/{}/{action}", remote.inverter));
+    if let Some(token) = &remote.token {
+        request = request.bearer_auth(token);
+    }
+    let response = request
+        .send()
+        .with_context(|| format!("Failed to reach the daemon at {url}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        let message = response
+            .json::<serde_json::Value>()
+            .ok()
+            .and_then(|body| body["error"].as_str().map(str::to_owned))
+            .unwrap_or_else(|| status.to_string());
+        anyhow::bail!("Daemon rejected the command ({message})");
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use crate::output::Sample;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Measurement, tags and field name of a series.
+type SeriesKey = (String, Vec<(String, String)>, String);
+
+/// Drops field values of consecutive polls of one inverter that hardly
+/// changed since the value last written, e.g. the flat zero of the night.
+/// Every series is written again after the heartbeat interval, so gaps
+/// still tell a missing inverter from a constant value.
+pub(crate) struct DeltaFilter {
+    /// Smallest change written per field name
+    deltas: HashMap<String, f64>,
+    heartbeat: chrono::Duration,
+    /// Value and time last written per series
+    written: HashMap<SeriesKey, (f64, DateTime<Utc>)>,
+}
+
+impl DeltaFilter {
+    pub(crate) fn new(deltas: HashMap<String, f64>, heartbeat_secs: u32) -> Self {
+        DeltaFilter {
+            deltas,
+            heartbeat: chrono::Duration::seconds(heartbeat_secs.into()),
+            written: HashMap::new(),
+        }
+    }
+
+    /// Removes the configured fields that changed by no more than their
+    /// delta, samples left without fields are dropped.
+    pub(crate) fn apply(&mut self, samples: Vec<Sample>) -> Vec<Sample> {
+        if self.deltas.is_empty() {
+            return samples;
+        }
+        samples
+            .into_iter()
+            .filter_map(|mut sample| {
+                let (measurement, tags, time) = (&sample.measurement, &sample.tags, sample.time);
+                sample.fields.retain(|(name, value)| {
+                    let Some(delta) = self.deltas.get(name) else {
+                        return true;
+                    };
+                    let key = (measurement.clone(), tags.clone(), name.clone());
+                    let changed = self.written.get(&key).is_none_or(|(last, last_time)| {
+                        (value - last).abs() > *delta || time - *last_time >= self.heartbeat
+                    });
+                    if changed {
+                        self.written.insert(key, (*value, time));
+                    }
+                    changed
+                });
+                (!sample.fields.is_empty()).then_some(sample)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> DeltaFilter {
+        DeltaFilter::new(HashMap::from([("energy_today".to_owned(), 0.05)]), 600)
+    }
+
+    /// The `energy_today` written for a poll `secs` into the day, if any.
+    fn written(filter: &mut DeltaFilter, secs: i64, energy: f64) -> Option<f64> {
+        let time = DateTime::from_timestamp(1_700_000_000 + secs, 0)?;
+        let sample = Sample::new("solar", time)
+            .tag("inverter", "roof")
+            .field("energy_today", energy);
+        filter.apply(vec![sample]).first()?.field_value("energy_today")
+    }
+
+    #[test]
+    fn first_value_is_written() {
+        assert_eq!(written(&mut filter(), 0, 0.0), Some(0.0));
+    }
+
+    #[test]
+    fn small_changes_are_dropped_until_they_add_up() {
+        let mut filter = filter();
+        written(&mut filter, 0, 1.0);
+        assert_eq!(written(&mut filter, 60, 1.03), None);
+        assert_eq!(written(&mut filter, 120, 1.04), None);
+        // Compared with the value last written, not the last polled one
+        assert_eq!(written(&mut filter, 180, 1.07), Some(1.07));
+    }
+
+    #[test]
+    fn counter_reset_is_written() {
+        let mut filter = filter();
+        written(&mut filter, 0, 4.2);
+        assert_eq!(written(&mut filter, 60, 0.0), Some(0.0));
+        assert_eq!(written(&mut filter, 120, 0.0), None);
+    }
+
+    #[test]
+    fn heartbeat_writes_constant_values() {
+        let mut filter = filter();
+        written(&mut filter, 0, 0.0);
+        assert_eq!(written(&mut filter, 599, 0.0), None);
+        assert_eq!(written(&mut filter, 600, 0.0), Some(0.0));
+        assert_eq!(written(&mut filter, 660, 0.0), None);
+    }
+
+    #[test]
+    fn other_fields_are_kept() {
+        let mut filter = filter();
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let sample = || {
+            Sample::new("solar", time)
+                .field("energy_today", 1.0)
+                .field("ac_power", 300.0)
+        };
+        filter.apply(vec![sample()]);
+        let samples = filter.apply(vec![sample()]);
+        assert_eq!(samples[0].fields, [("ac_power".to_owned(), 300.0)]);
+    }
+}
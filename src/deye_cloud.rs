@@ -0,0 +1,401 @@
+//! Deye Cloud as data source for inverters whose sticks report there instead
+//! of to the Solarman cloud and can't be polled locally.
+
+use crate::queue::BoundedQueue;
+use crate::solarman_cloud::{data_values, input_key, password_hash, DataItem};
+use crate::state_file::StateFile;
+use crate::{enqueue, to_samples, Batch, Config, InverterConfig, MonitoringConfig};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use solar_mon::registers::{InputReading, MonitoringData};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Devices per request, the most the API accepts.
+const BATCH_SIZE: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DeyeCloudConfig {
+    /// API host of the data center of the account
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    app_id: String,
+    app_secret: String,
+    email: String,
+    password: String,
+    /// Time between two requests, the sticks report about every five
+    /// minutes
+    #[serde(default = "default_interval")]
+    interval_secs: u64,
+    #[serde(default)]
+    keys: DataKeys,
+}
+
+/// Keys of the values in the data list of a device. `{n}` is the input
+/// number starting at 1.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+struct DataKeys {
+    ac_power: String,
+    energy_today: String,
+    energy_total: String,
+    input_voltage: String,
+    input_current: String,
+}
+
+impl Default for DataKeys {
+    fn default() -> Self {
+        DataKeys {
+            ac_power: "TotalSolarPower".to_owned(),
+            energy_today: "DailyActiveProduction".to_owned(),
+            energy_total: "TotalActiveProduction".to_owned(),
+            input_voltage: "DCVoltagePV{n}".to_owned(),
+            input_current: "DCCurrentPV{n}".to_owned(),
+        }
+    }
+}
+
+fn default_base_url() -> String {
+    "https://eu1-developer.deyecloud.com".to_owned()
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenResponse {
+    access_token: Option<String>,
+    #[serde(default)]
+    success: bool,
+    msg: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LatestResponse {
+    #[serde(default)]
+    success: bool,
+    msg: Option<String>,
+    #[serde(default)]
+    device_data_list: Vec<DeviceData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceData {
+    device_sn: String,
+    /// Unix timestamp in seconds of the values
+    collection_time: Option<f64>,
+    #[serde(default)]
+    data_list: Vec<DataItem>,
+}
+
+struct DeyeCloud {
+    config: DeyeCloudConfig,
+    client: reqwest::blocking::Client,
+    /// Logged in on first use, dropped after a failed request
+    access_token: Option<String>,
+}
+
+impl DeyeCloud {
+    fn login(&self) -> anyhow::Result<String> {
+        let response: TokenResponse = self
+            .client
+            .post(format!("{}/v1.0/account/token", self.config.base_url))
+            .query(&[("appId", self.config.app_id.as_str())])
+            .json(&serde_json::json!({
+                "appSecret": self.config.app_secret,
+                "email": self.config.email,
+                "password": password_hash(&self.config.password),
+            }))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Failed to reach Deye Cloud")?
+            .json()
+            .context("Failed to parse Deye Cloud login response")?;
+        match response.access_token {
+            Some(token) if response.success => Ok(token),
+            _ => anyhow::bail!(
+                "Deye Cloud login failed ({})",
+                response.msg.unwrap_or_default()
+            ),
+        }
+    }
+
+    /// Latest values of the devices with the given serials.
+    fn latest(&mut self, serials: &[&str]) -> anyhow::Result<Vec<DeviceData>> {
+        let access_token = match &self.access_token {
+            Some(token) => token.clone(),
+            None => self.access_token.insert(self.login()?).clone(),
+        };
+        let response = self
+            .client
+            .post(format!("{}/v1.0/device/latest", self.config.base_url))
+            .bearer_auth(access_token)
+            .json(&serde_json::json!({ "deviceList": serials }))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Failed to reach Deye Cloud")
+            .and_then(|response| {
+                response
+                    .json::<LatestResponse>()
+                    .context("Failed to parse Deye Cloud device response")
+            });
+        let response = match response {
+            Ok(response) if response.success => response,
+            Ok(response) => {
+                self.access_token = None;
+                anyhow::bail!(
+                    "Deye Cloud device request failed ({})",
+                    response.msg.unwrap_or_default()
+                );
+            }
+            Err(e) => {
+                // The token may have expired, log in again next time.
+                self.access_token = None;
+                return Err(e);
+            }
+        };
+        Ok(response.device_data_list)
+    }
+}
+
+impl DataKeys {
+    /// Monitoring data of the data list, `None` if a value is missing.
+    fn data(&self, values: &HashMap<String, f64>, mppt_inputs: u8) -> Option<MonitoringData> {
+        let inputs = (0..mppt_inputs)
+            .map(|idx| {
+                Some(InputReading {
+                    name: char::from(b'A' + idx).to_string(),
+                    voltage: *values.get(&input_key(&self.input_voltage, idx.into()))?,
+                    current: *values.get(&input_key(&self.input_current, idx.into()))?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(MonitoringData {
+            inputs,
+            ac_power: *values.get(&self.ac_power)?,
+            energy_today: *values.get(&self.energy_today)?,
+            energy_total: *values.get(&self.energy_total)?,
+            meter: None,
+            derating: None,
+            insulation: None,
+        })
+    }
+}
+
+/// Stores the values of the inverters with `deye_cloud_serial` like
+/// polled ones.
+struct Receiver {
+    cloud: DeyeCloud,
+    /// Name and config by device serial
+    inverters: HashMap<String, (String, InverterConfig)>,
+    monitoring_config: MonitoringConfig,
+    queue: Arc<BoundedQueue<Batch>>,
+    state_file: Arc<StateFile>,
+    /// Collection time of the values stored last by device serial
+    collected: HashMap<String, i64>,
+}
+
+impl Receiver {
+    fn fetch(&mut self) -> anyhow::Result<()> {
+        let serials: Vec<_> = self.inverters.keys().cloned().collect();
+        for batch in serials.chunks(BATCH_SIZE) {
+            let serials: Vec<_> = batch.iter().map(String::as_str).collect();
+            for device in self.cloud.latest(&serials)? {
+                self.store(device);
+            }
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, device: DeviceData) {
+        let Some((inverter_name, inverter_cfg)) = self.inverters.get(&device.device_sn) else {
+            return;
+        };
+        let Some(collected) = device.collection_time.map(|time| time as i64) else {
+            log::debug!("[{inverter_name}] Deye Cloud has no values");
+            return;
+        };
+        if self
+            .collected
+            .get(&device.device_sn)
+            .is_some_and(|last| collected <= *last)
+        {
+            return;
+        }
+        let values = data_values(device.data_list);
+        let Some(data) = self
+            .cloud
+            .config
+            .keys
+            .data(&values, inverter_cfg.mppt_inputs)
+        else {
+            log::warn!("[{inverter_name}] Values missing in the Deye Cloud data, check the keys");
+            log::debug!("[{inverter_name}] Deye Cloud data: {values:?}");
+            return;
+        };
+        let Some(time) = chrono::DateTime::from_timestamp(collected, 0) else {
+            return;
+        };
+        self.collected.insert(device.device_sn, collected);
+        log::debug!("[{inverter_name}] Received Deye Cloud data: {data:#?}");
+        let samples = to_samples(inverter_name, inverter_cfg, &self.monitoring_config, &data)
+            .into_iter()
+            .map(|mut sample| {
+                sample.time = time;
+                sample
+            })
+            .collect();
+        enqueue(
+            &self.queue,
+            Batch {
+                inverter: inverter_name.clone(),
+                samples,
+            },
+        );
+        self.state_file.update(inverter_name, false, |record| {
+            record.last_success = Some(chrono::Utc::now().timestamp());
+        });
+    }
+}
+
+/// Fetches the values of the inverters with `deye_cloud_serial` in the
+/// background.
+pub(crate) fn start(
+    cloud_cfg: &DeyeCloudConfig,
+    config: &Config,
+    queue: Arc<BoundedQueue<Batch>>,
+    state_file: Arc<StateFile>,
+) -> anyhow::Result<()> {
+    let inverters: HashMap<_, _> = config
+        .inverter
+        .iter()
+        .filter_map(|(name, inverter_cfg)| {
+            let serial = inverter_cfg.deye_cloud_serial.clone()?;
+            Some((serial, (name.clone(), inverter_cfg.clone())))
+        })
+        .collect();
+    if inverters.is_empty() {
+        log::warn!("No inverter has a deye_cloud_serial, Deye Cloud is not used");
+        return Ok(());
+    }
+    let interval = Duration::from_secs(cloud_cfg.interval_secs);
+    let mut receiver = Receiver {
+        cloud: DeyeCloud {
+            config: cloud_cfg.clone(),
+            client: reqwest::blocking::Client::new(),
+            access_token: None,
+        },
+        inverters,
+        monitoring_config: config.monitoring.clone(),
+        queue,
+        state_file,
+        collected: HashMap::new(),
+    };
+    log::info!(
+        "Fetching {} inverters from Deye Cloud",
+        receiver.inverters.len()
+    );
+    std::thread::Builder::new()
+        .name("deye cloud".to_owned())
+        .spawn(move || loop {
+            if let Err(e) = receiver.fetch() {
+                log::warn!("Failed to fetch the values from Deye Cloud ({e:#})");
+            }
+            std::thread::sleep(interval);
+        })
+        .context("Failed to start Deye Cloud thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::OverflowPolicy;
+
+    fn values(entries: &[(&str, f64)]) -> HashMap<String, f64> {
+        entries.iter().map(|(key, value)| ((*key).to_owned(), *value)).collect()
+    }
+
+    #[test]
+    fn data_of_the_default_keys() {
+        let values = values(&[
+            ("TotalSolarPower", 410.0),
+            ("DailyActiveProduction", 1.2),
+            ("TotalActiveProduction", 345.6),
+            ("DCVoltagePV1", 35.1),
+            ("DCCurrentPV1", 6.2),
+            ("DCVoltagePV2", 34.8),
+            ("DCCurrentPV2", 5.5),
+        ]);
+        let data = DataKeys::default().data(&values, 2).unwrap();
+        assert_eq!(data.ac_power, 410.0);
+        assert_eq!(data.energy_today, 1.2);
+        assert_eq!(data.energy_total, 345.6);
+        assert_eq!(data.inputs.len(), 2);
+        assert_eq!(data.inputs[1].name, "B");
+        assert_eq!((data.inputs[1].voltage, data.inputs[1].current), (34.8, 5.5));
+        // A third input is missing.
+        assert!(DataKeys::default().data(&values, 3).is_none());
+    }
+
+    fn receiver(queue: Arc<BoundedQueue<Batch>>) -> Receiver {
+        let config: DeyeCloudConfig = toml::from_str(
+            "app_id = \"1\"\napp_secret = \"secret\"\nemail = \"user@example.com\"\n\
+             password = \"password\"",
+        )
+        .unwrap();
+        let inverter_cfg: InverterConfig = toml::from_str("mppt_inputs = 1").unwrap();
+        let monitoring_config: MonitoringConfig =
+            toml::from_str("influx_ip = \"127.0.0.1\"\nintervall_secs = 60").unwrap();
+        Receiver {
+            cloud: DeyeCloud {
+                config,
+                client: reqwest::blocking::Client::new(),
+                access_token: None,
+            },
+            inverters: HashMap::from([("2306123456".to_owned(), ("roof".to_owned(), inverter_cfg))]),
+            monitoring_config,
+            queue,
+            state_file: Arc::new(StateFile::load(None).unwrap()),
+            collected: HashMap::new(),
+        }
+    }
+
+    fn device(collection_time: f64, ac_power: &str) -> DeviceData {
+        serde_json::from_value(serde_json::json!({
+            "deviceSn": "2306123456",
+            "collectionTime": collection_time,
+            "dataList": [
+                { "key": "TotalSolarPower", "value": ac_power },
+                { "key": "DailyActiveProduction", "value": "1.2" },
+                { "key": "TotalActiveProduction", "value": "345.6" },
+                { "key": "DCVoltagePV1", "value": "35.1" },
+                { "key": "DCCurrentPV1", "value": "6.2" },
+                { "key": "Status", "value": "normal" },
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn stores_new_values_once() {
+        let queue = Arc::new(BoundedQueue::new(10, OverflowPolicy::DropOldest));
+        let mut receiver = receiver(queue.clone());
+        receiver.store(device(1_717_236_000.0, "410"));
+        receiver.store(device(1_717_236_000.0, "410"));
+        receiver.store(device(1_717_235_700.0, "380"));
+        receiver.store(device(1_717_236_300.0, "420"));
+        let times: Vec<_> = [queue.pop(), queue.pop()]
+            .iter()
+            .map(|batch| batch.as_ref().unwrap().samples[0].time.timestamp())
+            .collect();
+        assert_eq!(times, [1_717_236_000, 1_717_236_300]);
+        assert_eq!(receiver.collected["2306123456"], 1_717_236_300);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{connect_inverter, Config};
+use anyhow::Context;
+use serde::Serialize;
+use solar_mon::error_code::ErrorCode;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// One poll of the efficiency log, DC and AC power come from the same
+/// response and therefore belong to the same moment.
+#[derive(Debug, Serialize)]
+struct EfficiencyRow {
+    time: String,
+    dc_power: f64,
+    ac_power: f64,
+    /// Missing while the inputs deliver no power
+    efficiency: Option<f64>,
+}
+
+/// Polls `inverter_name` every `interval` for `duration` and writes the
+/// DC/AC power pairs as CSV. Rows are flushed right away so an interrupted
+/// run still leaves usable data.
+pub(crate) fn log_efficiency_curve(
+    config: &Config,
+    inverter_name: &str,
+    duration: Duration,
+    interval: Duration,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let inverter_cfg = config
+        .inverter
+        .get(inverter_name)
+        .with_context(|| format!("Inverter {inverter_name} is not configured"))?;
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    let mut inverter = connect_inverter(inverter_cfg, &config.monitoring)?;
+
+    let start = Instant::now();
+    let mut rows = 0;
+    while start.elapsed() < duration {
+        let poll_start = Instant::now();
+        match inverter.get_data() {
+            Ok(data) => {
+                let dc_power = data.dc_power();
+                csv_writer.serialize(EfficiencyRow {
+                    time: chrono::Utc::now().to_rfc3339(),
+                    dc_power,
+                    ac_power: data.ac_power,
+                    efficiency: (dc_power > 0.0).then(|| data.ac_power / dc_power),
+                })?;
+                csv_writer.flush()?;
+                rows += 1;
+            }
+            Err(e) => log::warn!("[{inverter_name}] Failed to recieve data ({}: {e})", ErrorCode::of(&e)),
+        }
+        std::thread::sleep(interval.saturating_sub(poll_start.elapsed()));
+    }
+    log::info!("Recorded {rows} power pairs");
+    Ok(())
+}
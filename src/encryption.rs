@@ -0,0 +1,115 @@
+//! AES encryption of the SolarmanV5 payload, which newer logger firmware
+//! can switch on. Only the payload is encrypted, header, checksum and end
+//! byte stay readable.
+
+use crate::error_code::ErrorCode;
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use sha2::{Digest, Sha256};
+
+/// Whether requests to a logger stick are encrypted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encryption {
+    /// Plain until the stick answers with an encrypted payload
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// AES-128-CBC with PKCS#7 padding and an all zero IV.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PayloadCipher {
+    key: [u8; 16],
+}
+
+impl std::fmt::Debug for PayloadCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PayloadCipher(..)")
+    }
+}
+
+const IV: [u8; 16] = [0; 16];
+
+impl PayloadCipher {
+    pub fn new(key: [u8; 16]) -> Self {
+        PayloadCipher { key }
+    }
+
+    /// Key the firmware derives from the logger serial: the first half of
+    /// the SHA-256 of the serial in decimal.
+    pub fn for_serial(logger_serial: [u8; 4]) -> Self {
+        let serial = u32::from_le_bytes(logger_serial).to_string();
+        let digest = Sha256::digest(serial.as_bytes());
+        let mut key = [0; 16];
+        key.copy_from_slice(&digest[..16]);
+        PayloadCipher { key }
+    }
+
+    pub fn encrypt(&self, plain: &[u8]) -> Vec<u8> {
+        cbc::Encryptor::<aes::Aes128>::new(&self.key.into(), &IV.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plain)
+    }
+
+    pub fn decrypt(&self, encrypted: &[u8]) -> anyhow::Result<Vec<u8>> {
+        cbc::Decryptor::<aes::Aes128>::new(&self.key.into(), &IV.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(encrypted)
+            .map_err(|_| ErrorCode::DecryptionFailed.error("Failed to decrypt the payload"))
+    }
+
+    /// Complete V5 frame with the payload of `frame` encrypted.
+    pub fn encrypt_frame(&self, frame: &[u8]) -> Vec<u8> {
+        let (header, payload) = split_frame(frame);
+        join_frame(header, &self.encrypt(payload))
+    }
+
+    /// Complete V5 frame with the payload of `frame` decrypted.
+    pub fn decrypt_frame(&self, frame: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let (header, payload) = split_frame(frame);
+        Ok(join_frame(header, &self.decrypt(payload)?))
+    }
+
+    /// [`Self::decrypt_frame`] for responses, which fails unless the result
+    /// is a plain response payload.
+    pub fn decrypt_response(&self, frame: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let plain = self.decrypt_frame(frame)?;
+        if plain.get(11) != Some(&0x02) {
+            return Err(ErrorCode::DecryptionFailed.error("Decrypted payload is no response"));
+        }
+        Ok(plain)
+    }
+}
+
+/// Header and payload of a frame checked by
+/// [`crate::solarmanv5::verify_frame`].
+fn split_frame(frame: &[u8]) -> (&[u8], &[u8]) {
+    (&frame[..11], &frame[11..frame.len() - 2])
+}
+
+/// Frame of `header` and `payload` with the length, checksum and end byte
+/// updated.
+fn join_frame(header: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(header.len() + payload.len() + 2);
+    frame.extend_from_slice(header);
+    frame[1..3].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame.push(crate::solarmanv5::checksum(&frame[1..]));
+    frame.push(0x15);
+    frame
+}
+
+/// Whether the payload of the response `frame` is encrypted. Plain
+/// responses carry the frame type 0x02 and an RTU frame with a valid CRC,
+/// encrypted payloads are a whole number of AES blocks.
+pub fn is_encrypted(frame: &[u8]) -> bool {
+    let (_, payload) = split_frame(frame);
+    if payload.is_empty() || payload.len() % 16 != 0 {
+        return false;
+    }
+    let plain = payload.len() > 16
+        && payload[0] == 0x02
+        && payload[14..]
+            .split_last_chunk::<2>()
+            .is_some_and(|(rtu, crc)| crate::registers::crc16(rtu).to_le_bytes() == *crc);
+    !plain
+}
@@ -0,0 +1,135 @@
+//! Monthly and yearly production per inverter and, with a tariff, its
+//! value and the CO2 it avoided, kept in the state file so they survive
+//! restarts and don't depend on the retention of the database.
+
+use crate::state_file::{InverterRecord, StateFile};
+use crate::table::{OutputFormat, Table};
+use crate::tariff::TariffConfig;
+use crate::Config;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+
+/// What the production is worth.
+pub(crate) struct Valuation<'a> {
+    pub(crate) tariff: Option<&'a TariffConfig>,
+    /// Grid carbon intensity in g CO2eq per kWh, see [`crate::carbon`]
+    pub(crate) co2_intensity: Option<f64>,
+}
+
+/// Adds the energy produced since the last full read to the month of
+/// `now`. A total lower than before, e.g. after the inverter was replaced,
+/// only restarts the counting. Energy produced while the daemon was not
+/// running counts for the month it is noticed in, valued at the price and
+/// carbon intensity of the hour it is noticed in.
+pub(crate) fn record(
+    record: &mut InverterRecord,
+    energy_total: f64,
+    now: DateTime<Local>,
+    valuation: &Valuation,
+) {
+    if let Some(last) = record.last_energy_total.filter(|last| energy_total >= *last) {
+        let month = now.format("%Y-%m").to_string();
+        let energy = energy_total - last;
+        *record.energy_months.entry(month.clone()).or_default() += energy;
+        if let Some(tariff) = valuation.tariff {
+            let (savings, earnings) = tariff.value(energy, now);
+            *record.savings_months.entry(month.clone()).or_default() += savings;
+            *record.earnings_months.entry(month.clone()).or_default() += earnings;
+        }
+        if let Some(intensity) = valuation.co2_intensity {
+            *record.co2_months.entry(month).or_default() += energy * intensity / 1000.0;
+        }
+    }
+    record.last_energy_total = Some(energy_total);
+}
+
+/// Not `sum()`, which is -0 without any month.
+fn total(months: &BTreeMap<String, f64>) -> f64 {
+    months.values().fold(0.0, |sum, value| sum + value)
+}
+
+/// Savings and earnings since the tariff was configured.
+pub(crate) fn value_totals(record: &InverterRecord) -> (f64, f64) {
+    (total(&record.savings_months), total(&record.earnings_months))
+}
+
+/// CO2 avoided in kg since the carbon intensity was configured.
+pub(crate) fn co2_total(record: &InverterRecord) -> f64 {
+    total(&record.co2_months)
+}
+
+/// Sums of monthly values by year (`YYYY`).
+pub(crate) fn years(months: &BTreeMap<String, f64>) -> BTreeMap<String, f64> {
+    let mut years = BTreeMap::new();
+    for (month, value) in months {
+        let year = month.split('-').next().unwrap_or(month);
+        *years.entry(year.to_owned()).or_default() += value;
+    }
+    years
+}
+
+/// Monthly and yearly production of an inverter, its value and the CO2 it
+/// avoided for `/api/status`.
+pub(crate) fn to_json(record: &InverterRecord) -> serde_json::Value {
+    let periods = |months: &BTreeMap<String, f64>| {
+        serde_json::json!({ "months": months, "years": years(months) })
+    };
+    let mut json = periods(&record.energy_months);
+    if !record.savings_months.is_empty() {
+        json["savings"] = periods(&record.savings_months);
+        json["earnings"] = periods(&record.earnings_months);
+    }
+    if !record.co2_months.is_empty() {
+        json["co2_avoided"] = periods(&record.co2_months);
+    }
+    json
+}
+
+/// Prints the production of `inverters` per month or per year, of every
+/// configured one if empty, read from the state file.
+pub(crate) fn report(
+    config: &Config,
+    inverters: &[String],
+    yearly: bool,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let Some(path) = &config.monitoring.state_file else {
+        anyhow::bail!("Statistics need `state_file` in the monitoring config");
+    };
+    if let Some(unknown) = inverters.iter().find(|name| !config.inverter.contains_key(*name)) {
+        anyhow::bail!("Inverter {unknown} is not configured");
+    }
+    let state_file = StateFile::load(Some(path))?;
+    let mut names: Vec<_> = config
+        .inverter
+        .keys()
+        .filter(|name| inverters.is_empty() || inverters.contains(name))
+        .collect();
+    names.sort();
+    let period = if yearly { "year" } else { "month" };
+    let mut table =
+        Table::new(["inverter", period, "energy", "savings", "earnings", "co2_avoided"]);
+    // The sums carry floating point noise.
+    let round = |value: f64, factor: f64| (value * factor).round() / factor;
+    for inverter_name in names {
+        let record = state_file.get(inverter_name);
+        let periods = |months| if yearly { years(months) } else { months.clone() };
+        let savings = periods(&record.savings_months);
+        let earnings = periods(&record.earnings_months);
+        let co2 = periods(&record.co2_months);
+        for (period, energy) in periods(&record.energy_months) {
+            let value = |values: &BTreeMap<String, f64>| {
+                values.get(&period).map(|value| round(*value, 100.0))
+            };
+            table.push(vec![
+                inverter_name.as_str().into(),
+                period.as_str().into(),
+                round(energy, 1000.0).into(),
+                value(&savings).into(),
+                value(&earnings).into(),
+                value(&co2).into(),
+            ]);
+        }
+    }
+    table.write(format, std::io::stdout().lock())
+}
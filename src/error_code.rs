@@ -0,0 +1,148 @@
+//! Stable codes of the ways talking to an inverter can fail, so monitoring
+//! can alert on a class of failures instead of matching log messages. Codes
+//! are never reused or renumbered.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The logger stick did not accept the connection in time
+    ConnectTimeout,
+    /// Connecting failed otherwise, e.g. refused or no route to the stick
+    ConnectFailed,
+    /// The stick did not answer a request in time
+    ReadTimeout,
+    /// The connection was closed or reset during a request
+    ConnectionLost,
+    /// Only garbage was received instead of a frame
+    NoFrame,
+    /// A frame with wrong start or end byte
+    MalformedFrame,
+    /// A SolarmanV5 response too short for its header
+    ResponseTooShort,
+    /// A valid frame that is not the expected response
+    UnexpectedFrame,
+    /// A frame with a wrong checksum
+    ChecksumMismatch,
+    /// An encrypted payload could not be decrypted, usually a wrong key
+    DecryptionFailed,
+    /// The Modbus response holds fewer registers than requested
+    ModbusResponseTooShort,
+    /// A register is outside of the block read, a configuration problem
+    RegisterOutsideBlock,
+    /// The AT interface of the stick rejected a command
+    AtCommandFailed,
+    /// Anything not covered by a more specific code
+    Unknown,
+}
+
+impl ErrorCode {
+    /// Identifier like `E014`.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::ConnectTimeout => "E001",
+            ErrorCode::ConnectFailed => "E002",
+            ErrorCode::ReadTimeout => "E003",
+            ErrorCode::ConnectionLost => "E004",
+            ErrorCode::NoFrame => "E010",
+            ErrorCode::MalformedFrame => "E011",
+            ErrorCode::ResponseTooShort => "E012",
+            ErrorCode::UnexpectedFrame => "E013",
+            ErrorCode::ChecksumMismatch => "E014",
+            ErrorCode::DecryptionFailed => "E015",
+            ErrorCode::ModbusResponseTooShort => "E020",
+            ErrorCode::RegisterOutsideBlock => "E021",
+            ErrorCode::AtCommandFailed => "E030",
+            ErrorCode::Unknown => "E099",
+        }
+    }
+
+    /// Human readable name like `checksum_mismatch`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCode::ConnectTimeout => "connect_timeout",
+            ErrorCode::ConnectFailed => "connect_failed",
+            ErrorCode::ReadTimeout => "read_timeout",
+            ErrorCode::ConnectionLost => "connection_lost",
+            ErrorCode::NoFrame => "no_frame",
+            ErrorCode::MalformedFrame => "malformed_frame",
+            ErrorCode::ResponseTooShort => "response_too_short",
+            ErrorCode::UnexpectedFrame => "unexpected_frame",
+            ErrorCode::ChecksumMismatch => "checksum_mismatch",
+            ErrorCode::DecryptionFailed => "decryption_failed",
+            ErrorCode::ModbusResponseTooShort => "modbus_response_too_short",
+            ErrorCode::RegisterOutsideBlock => "register_outside_block",
+            ErrorCode::AtCommandFailed => "at_command_failed",
+            ErrorCode::Unknown => "unknown",
+        }
+    }
+
+    /// Error with this code.
+    pub fn error(self, message: impl Into<String>) -> anyhow::Error {
+        CodedError {
+            code: self,
+            message: message.into(),
+            source: None,
+        }
+        .into()
+    }
+
+    /// Code of `error`, taken from the first cause in its chain that has
+    /// one. Plain I/O errors are classified by their kind.
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(coded) = cause.downcast_ref::<CodedError>() {
+                return coded.code;
+            }
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                return match e.kind() {
+                    std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+                        ErrorCode::ReadTimeout
+                    }
+                    _ => ErrorCode::ConnectionLost,
+                };
+            }
+        }
+        ErrorCode::Unknown
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Error carrying an [`ErrorCode`], optionally caused by an I/O error.
+#[derive(Debug)]
+struct CodedError {
+    code: ErrorCode,
+    message: String,
+    source: Option<std::io::Error>,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|e| e as _)
+    }
+}
+
+/// Error of a failed connection attempt, coded by whether it timed out.
+pub(crate) fn connect_error(error: std::io::Error) -> anyhow::Error {
+    let code = match error.kind() {
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => ErrorCode::ConnectTimeout,
+        _ => ErrorCode::ConnectFailed,
+    };
+    CodedError {
+        code,
+        message: format!("Failed to connect ({error})"),
+        source: Some(error),
+    }
+    .into()
+}
@@ -0,0 +1,250 @@
+use crate::output::InfluxSchema;
+use crate::{Config, MonitoringConfig};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub(crate) enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// One stored row as written by the monitoring loop.
+#[derive(Debug, Serialize)]
+struct ExportSample {
+    time: String,
+    measurement: String,
+    inverter: String,
+    input: String,
+    voltage: Option<f64>,
+    current: Option<f64>,
+    power: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryResponse {
+    #[serde(default)]
+    results: Vec<QueryResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryResult {
+    #[serde(default)]
+    series: Vec<Series>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Series {
+    name: String,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    columns: Vec<String>,
+    values: Vec<Vec<serde_json::Value>>,
+}
+
+/// Parses either an RFC 3339 timestamp or a plain date, which is taken as
+/// midnight UTC.
+pub(crate) fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| Utc.from_utc_datetime(&time))
+        .ok_or_else(|| format!("'{value}' is neither a date (YYYY-MM-DD) nor an RFC 3339 time"))
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn build_query(
+    measurements: &BTreeSet<&str>,
+    inverters: &[&str],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    schema: InfluxSchema,
+) -> String {
+    let measurements = measurements
+        .iter()
+        .map(|m| quote_identifier(m))
+        .collect::<Vec<_>>()
+        .join(",");
+    let inverters = inverters
+        .iter()
+        .map(|name| format!("\"inverter\" = {}", quote_string(name)))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let range = format!(
+        "time >= {} AND time < {}",
+        quote_string(&from.to_rfc3339()),
+        quote_string(&to.to_rfc3339()),
+    );
+    match schema {
+        InfluxSchema::Wide => format!(
+            "SELECT \"voltage\",\"current\",\"power\" FROM {measurements} \
+             WHERE {range} AND ({inverters}) GROUP BY \"inverter\",\"input\""
+        ),
+        InfluxSchema::Narrow => format!(
+            "SELECT \"value\" FROM {measurements} WHERE {range} AND ({inverters}) \
+             AND (\"metric\" = 'voltage' OR \"metric\" = 'current' OR \"metric\" = 'power') \
+             GROUP BY \"inverter\",\"input\",\"metric\""
+        ),
+    }
+}
+
+fn query(
+    monitoring_config: &MonitoringConfig,
+    database: &str,
+    query: &str,
+) -> anyhow::Result<QueryResponse> {
+    let url = reqwest::Url::parse_with_params(
+        &format!(
+            "http://{}:{}/query",
+            monitoring_config.influx_ip, monitoring_config.influx_port
+        ),
+        &[
+            ("db", database),
+            ("q", query),
+            ("epoch", "s"),
+        ],
+    )
+    .context("Influxdb ip or port invalid")?;
+    reqwest::blocking::get(url)
+        .and_then(reqwest::blocking::Response::error_for_status)
+        .with_context(|| format!("Failed to query database {database}"))?
+        .json()
+        .context("Failed to parse database response")
+}
+
+/// Rows of the response, the values of the narrow schema are joined into
+/// rows by time, inverter and input.
+fn to_samples(response: QueryResponse) -> anyhow::Result<Vec<ExportSample>> {
+    let mut samples = BTreeMap::new();
+    for result in response.results {
+        if let Some(error) = result.error {
+            anyhow::bail!("Database query failed: {error}");
+        }
+        for series in result.series {
+            let column = |name: &str| series.columns.iter().position(|c| c == name);
+            let time = column("time");
+            let metric = series.tags.get("metric").map(String::as_str);
+            let columns = match metric {
+                Some(metric) => {
+                    let value = column("value");
+                    let is = |name| if metric == name { value } else { None };
+                    (is("voltage"), is("current"), is("power"))
+                }
+                None => (column("voltage"), column("current"), column("power")),
+            };
+            let tag = |name: &str| series.tags.get(name).cloned().unwrap_or_default();
+            for row in &series.values {
+                let field = |idx: Option<usize>| idx.and_then(|i| row.get(i)?.as_f64());
+                let timestamp = time
+                    .and_then(|i| row.get(i)?.as_i64())
+                    .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+                    .context("Database response is missing timestamps")?;
+                let key = (timestamp, series.name.clone(), tag("inverter"), tag("input"));
+                let sample = samples.entry(key).or_insert_with(|| ExportSample {
+                    time: timestamp.to_rfc3339(),
+                    measurement: series.name.clone(),
+                    inverter: tag("inverter"),
+                    input: tag("input"),
+                    voltage: None,
+                    current: None,
+                    power: None,
+                });
+                let (voltage, current, power) = columns;
+                sample.voltage = sample.voltage.or(field(voltage));
+                sample.current = sample.current.or(field(current));
+                sample.power = sample.power.or(field(power));
+            }
+        }
+    }
+    Ok(samples.into_values().collect())
+}
+
+fn write_samples(
+    samples: &[ExportSample],
+    format: ExportFormat,
+    writer: impl Write,
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for sample in samples {
+                csv_writer.serialize(sample)?;
+            }
+            csv_writer.flush()?;
+        }
+        ExportFormat::Json => {
+            let mut writer = writer;
+            serde_json::to_writer_pretty(&mut writer, samples)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Dumps the samples stored in the database for the selected inverters
+/// (all configured ones if none are given) in the time range `[from, to)`.
+pub(crate) fn export(
+    config: &Config,
+    inverters: &[String],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let selected: Vec<&str> = if inverters.is_empty() {
+        config.inverter.keys().map(String::as_str).collect()
+    } else {
+        inverters.iter().map(String::as_str).collect()
+    };
+    // Measurements and inverters per database, inverters may write to a
+    // database of their own.
+    let mut databases: BTreeMap<&str, (BTreeSet<&str>, Vec<&str>)> = BTreeMap::new();
+    for name in &selected {
+        let inverter_cfg = config
+            .inverter
+            .get(*name)
+            .with_context(|| format!("Inverter {name} is not configured"))?;
+        let (measurements, inverters) =
+            databases.entry(inverter_cfg.database(&config.monitoring)).or_default();
+        measurements.insert(inverter_cfg.location.as_str());
+        inverters.push(name);
+    }
+    if selected.is_empty() {
+        anyhow::bail!("No inverters configured");
+    }
+
+    let mut samples = Vec::new();
+    for (database, (measurements, inverters)) in databases {
+        let response = query(
+            &config.monitoring,
+            database,
+            &build_query(&measurements, &inverters, from, to, config.monitoring.influx_schema),
+        )?;
+        samples.extend(to_samples(response)?);
+    }
+    samples.sort_by(|a, b| (&a.time, &a.inverter, &a.input).cmp(&(&b.time, &b.inverter, &b.input)));
+    log::info!("Exporting {} samples", samples.len());
+    match output {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            write_samples(&samples, format, std::io::BufWriter::new(file))
+        }
+        None => write_samples(&samples, format, std::io::stdout().lock()),
+    }
+}
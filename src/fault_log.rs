@@ -0,0 +1,378 @@
+//! Incident history of the inverters, e.g. to back a warranty claim with
+//! the times the inverters failed. Every incident is appended to a JSON
+//! lines file when it starts and again when it ends, so incidents open
+//! during a crash or restart are not lost.
+
+use crate::state::{InverterState, StateEvent};
+use crate::Config;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub(crate) enum FaultFormat {
+    Csv,
+    /// iCalendar with one event per incident
+    Ical,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Incident {
+    inverter: String,
+    /// `faulted` or `offline`
+    state: InverterState,
+    reason: String,
+    error_code: Option<String>,
+    /// Unix time the incident started
+    start: i64,
+    /// Unix time it ended, unset while it lasts
+    end: Option<i64>,
+}
+
+impl Incident {
+    fn start(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.start, 0).unwrap_or_default()
+    }
+
+    fn end(&self) -> Option<DateTime<Utc>> {
+        self.end.and_then(|end| DateTime::from_timestamp(end, 0))
+    }
+
+    /// Whether the incident overlaps `[from, to)`, open ones last until now.
+    fn overlaps(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> bool {
+        from.is_none_or(|from| self.end().is_none_or(|end| end > from))
+            && to.is_none_or(|to| self.start() < to)
+    }
+}
+
+/// Whether entering the state of `event` starts an incident. The sticks go
+/// offline every night, only losing them while producing counts.
+fn starts_incident(event: &StateEvent) -> bool {
+    match event.to {
+        InverterState::Faulted => true,
+        InverterState::Offline => event.from == InverterState::Producing,
+        _ => false,
+    }
+}
+
+/// Open incidents by log and inverter. Read from the log on first use, so
+/// a state change doesn't read the whole log again.
+static OPEN: Mutex<BTreeMap<PathBuf, BTreeMap<String, Incident>>> = Mutex::new(BTreeMap::new());
+
+/// Records the start or end of an incident caused by `event`. Failing to
+/// write is logged, the monitoring goes on.
+pub(crate) fn record(path: &Path, event: &StateEvent) {
+    let mut open = OPEN.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Err(e) = record_in(&mut open, path, event) {
+        log::error!("Failed to write fault log {} ({e:#})", path.display());
+    }
+}
+
+fn record_in(
+    open: &mut BTreeMap<PathBuf, BTreeMap<String, Incident>>,
+    path: &Path,
+    event: &StateEvent,
+) -> anyhow::Result<()> {
+    let open = match open.entry(path.to_owned()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(read_open(path)?),
+    };
+    if matches!(event.from, InverterState::Faulted | InverterState::Offline) {
+        if let Some(incident) = open.remove(&event.inverter) {
+            append(
+                path,
+                &Incident {
+                    end: Some(event.time.timestamp()),
+                    ..incident
+                },
+            )?;
+        }
+    }
+    if starts_incident(event) {
+        let incident = Incident {
+            inverter: event.inverter.clone(),
+            state: event.to,
+            reason: event.reason.to_owned(),
+            error_code: event.error_code.map(|code| code.code().to_owned()),
+            start: event.time.timestamp(),
+            end: None,
+        };
+        append(path, &incident)?;
+        open.insert(incident.inverter.clone(), incident);
+    }
+    Ok(())
+}
+
+/// The latest open incident of every inverter in the log.
+fn read_open(path: &Path) -> anyhow::Result<BTreeMap<String, Incident>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    // Ordered by start, so the latest incident of an inverter wins
+    Ok(read(path)?
+        .into_values()
+        .filter(|incident| incident.end.is_none())
+        .map(|incident| (incident.inverter.clone(), incident))
+        .collect())
+}
+
+fn append(path: &Path, incident: &Incident) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(incident)?;
+    line.push(b'\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)?;
+    Ok(())
+}
+
+/// Incidents by inverter and start, the last line of each wins.
+fn read(path: &Path) -> anyhow::Result<BTreeMap<(i64, String), Incident>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open fault log {}", path.display()))?;
+    let mut incidents = BTreeMap::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let incident: Incident = serde_json::from_str(&line)
+            .with_context(|| format!("Line {} of fault log {}", number + 1, path.display()))?;
+        incidents.insert((incident.start, incident.inverter.clone()), incident);
+    }
+    Ok(incidents)
+}
+
+/// Writes the incidents of `inverters` that overlap `[from, to)`, of every
+/// inverter if empty.
+pub(crate) fn export(
+    config: &Config,
+    inverters: &[String],
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    format: FaultFormat,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let Some(path) = &config.monitoring.fault_log else {
+        anyhow::bail!("The fault history needs `fault_log` in the monitoring config");
+    };
+    let incidents: Vec<_> = if path.exists() { read(path)? } else { BTreeMap::new() }
+        .into_values()
+        .filter(|incident| inverters.is_empty() || inverters.contains(&incident.inverter))
+        .filter(|incident| incident.overlaps(from, to))
+        .collect();
+    let mut out: Box<dyn Write> = match output {
+        Some(output) => Box::new(
+            std::fs::File::create(output)
+                .with_context(|| format!("Failed to create {}", output.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        FaultFormat::Csv => write_csv(&incidents, &mut out)?,
+        FaultFormat::Ical => write_ical(&incidents, &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_csv(incidents: &[Incident], out: impl Write) -> anyhow::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(out);
+    csv_writer.write_record([
+        "inverter",
+        "state",
+        "reason",
+        "error_code",
+        "start",
+        "end",
+        "duration_secs",
+    ])?;
+    for incident in incidents {
+        let duration = incident.end.map(|end| end - incident.start);
+        csv_writer.write_record([
+            incident.inverter.clone(),
+            incident.state.to_string(),
+            incident.reason.clone(),
+            incident.error_code.clone().unwrap_or_default(),
+            incident.start().to_rfc3339(),
+            incident.end().map(|end| end.to_rfc3339()).unwrap_or_default(),
+            duration.map(|secs| secs.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+/// Escapes text for iCalendar property values (RFC 5545, 3.3.11).
+fn ical_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn ical_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Writes a content line folded after 75 octets (RFC 5545, 3.1), never
+/// inside a UTF-8 sequence.
+fn ical_line(out: &mut impl Write, line: &str) -> std::io::Result<()> {
+    let mut rest = line;
+    // Continuation lines start with a space
+    let mut limit = 75;
+    loop {
+        let mut end = rest.len().min(limit);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        out.write_all(chunk.as_bytes())?;
+        out.write_all(b"\r\n")?;
+        if tail.is_empty() {
+            return Ok(());
+        }
+        out.write_all(b" ")?;
+        rest = tail;
+        limit = 74;
+    }
+}
+
+/// Incidents as calendar events, open ones end now.
+fn write_ical(incidents: &[Incident], mut out: impl Write) -> anyhow::Result<()> {
+    let now = Utc::now();
+    for line in ["BEGIN:VCALENDAR", "VERSION:2.0", "PRODID:-//solar_mon//fault log//EN"] {
+        ical_line(&mut out, line)?;
+    }
+    for incident in incidents {
+        let error_code = incident.error_code.as_deref().unwrap_or("no error code");
+        let summary = format!("{} {} ({error_code})", incident.inverter, incident.state);
+        let mut description = format!("Reason: {}", incident.reason);
+        if incident.end.is_none() {
+            description.push_str("\nStill ongoing");
+        }
+        for line in [
+            "BEGIN:VEVENT".to_owned(),
+            format!("UID:{}-{}@solar_mon", ical_text(&incident.inverter), incident.start),
+            format!("DTSTAMP:{}", ical_time(now)),
+            format!("DTSTART:{}", ical_time(incident.start())),
+            format!("DTEND:{}", ical_time(incident.end().unwrap_or(now))),
+            format!("SUMMARY:{}", ical_text(&summary)),
+            format!("DESCRIPTION:{}", ical_text(&description)),
+            "END:VEVENT".to_owned(),
+        ] {
+            ical_line(&mut out, &line)?;
+        }
+    }
+    ical_line(&mut out, "END:VCALENDAR")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(from: InverterState, to: InverterState, time: i64) -> StateEvent {
+        StateEvent {
+            inverter: "roof".to_owned(),
+            time: DateTime::from_timestamp(time, 0).unwrap(),
+            from,
+            to,
+            duration_secs: 0,
+            reason: "connect_failed",
+            error_code: None,
+        }
+    }
+
+    fn incident(start: i64, end: Option<i64>) -> Incident {
+        Incident {
+            inverter: "roof".to_owned(),
+            state: InverterState::Offline,
+            reason: "connect_failed".to_owned(),
+            error_code: None,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn records_start_and_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("faults.jsonl");
+        let mut open = BTreeMap::new();
+        let events = [
+            event(InverterState::Producing, InverterState::Offline, 100),
+            event(InverterState::Offline, InverterState::Producing, 160),
+            // The stick going offline at night is no incident
+            event(InverterState::Idle, InverterState::Offline, 200),
+            event(InverterState::Offline, InverterState::Idle, 260),
+        ];
+        for event in &events {
+            record_in(&mut open, &path, event).unwrap();
+        }
+        let incidents: Vec<_> = read(&path).unwrap().into_values().collect();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!((incidents[0].start, incidents[0].end), (100, Some(160)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn ends_incident_opened_before_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("faults.jsonl");
+        let started = event(InverterState::Idle, InverterState::Faulted, 100);
+        record_in(&mut BTreeMap::new(), &path, &started).unwrap();
+        let ended = event(InverterState::Faulted, InverterState::Idle, 300);
+        record_in(&mut BTreeMap::new(), &path, &ended).unwrap();
+        let incidents: Vec<_> = read(&path).unwrap().into_values().collect();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].end, Some(300));
+    }
+
+    #[test]
+    fn overlaps_half_open_range() {
+        let at = |secs| DateTime::from_timestamp(secs, 0);
+        let closed = incident(100, Some(200));
+        assert!(closed.overlaps(None, None));
+        assert!(closed.overlaps(at(150), at(160)));
+        assert!(closed.overlaps(at(199), None));
+        assert!(!closed.overlaps(at(200), None));
+        assert!(!closed.overlaps(None, at(100)));
+        assert!(closed.overlaps(None, at(101)));
+        let open = incident(100, None);
+        assert!(open.overlaps(at(1_000), None));
+        assert!(!open.overlaps(None, at(50)));
+    }
+
+    #[test]
+    fn escapes_text() {
+        assert_eq!(ical_text("a\\b;c,d\ne"), "a\\\\b\\;c\\,d\\ne");
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let mut out = Vec::new();
+        ical_line(&mut out, "short").unwrap();
+        assert_eq!(out, b"short\r\n");
+
+        let line = format!("DESCRIPTION:{}", "ä".repeat(60));
+        let mut out = Vec::new();
+        ical_line(&mut out, &line).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.split("\r\n").filter(|line| !line.is_empty()).collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        let unfolded: String = lines
+            .iter()
+            .map(|line| line.strip_prefix(' ').unwrap_or(line))
+            .collect();
+        assert_eq!(unfolded, line);
+    }
+}
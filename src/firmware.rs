@@ -0,0 +1,128 @@
+//! Firmware versions of the inverters and their logger sticks. Both get
+//! updated over the air from the cloud without notice, and updates have
+//! changed register contents before, so changes are reported.
+
+use crate::output::Sample;
+use serde::{Deserialize, Serialize};
+use solar_mon::inverter::Inverter;
+use solar_mon::registers;
+
+/// Registers of the inverter firmware versions. They are not documented
+/// by Deye, so the inverter version is only read once they are configured.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct FirmwareConfig {
+    /// First and last register of the version block
+    #[serde(default, deserialize_with = "deserialize_register_range")]
+    registers: Option<[u16; 2]>,
+    /// Also read the stick firmware through its AT interface
+    #[serde(default = "default_stick_firmware")]
+    stick: bool,
+}
+
+impl Default for FirmwareConfig {
+    fn default() -> Self {
+        FirmwareConfig {
+            registers: None,
+            stick: default_stick_firmware(),
+        }
+    }
+}
+
+fn default_stick_firmware() -> bool {
+    true
+}
+
+fn deserialize_register_range<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<[u16; 2]>, D::Error> {
+    let [first, last] = <[u16; 2]>::deserialize(deserializer)?;
+    if first > last {
+        return Err(serde::de::Error::custom("registers must be [first, last] with first <= last"));
+    }
+    if last - first >= registers::MAX_REGISTERS {
+        return Err(serde::de::Error::custom(format!(
+            "registers can span at most {} registers",
+            registers::MAX_REGISTERS
+        )));
+    }
+    Ok(Some([first, last]))
+}
+
+impl FirmwareConfig {
+    pub(crate) fn stick(&self) -> bool {
+        self.stick
+    }
+
+    /// Version of the inverter firmware as the raw register values in hex,
+    /// e.g. `0104-0212-1001`. `None` without configured registers.
+    pub(crate) fn read_inverter(&self, inverter: &mut Inverter) -> Option<anyhow::Result<String>> {
+        let [first, last] = self.registers?;
+        let count = last - first + 1;
+        let frame = registers::read_frame(inverter.slave_id(), first, count);
+        let values = inverter
+            .send_raw(&frame)
+            .and_then(|response| registers::decode_values(response, first, count));
+        Some(values.map(|values| {
+            let words: Vec<_> = values.iter().map(|value| format!("{value:04x}")).collect();
+            words.join("-")
+        }))
+    }
+}
+
+/// Part of the installation a firmware version belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Component {
+    Inverter,
+    Stick,
+}
+
+impl Component {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Component::Inverter => "inverter",
+            Component::Stick => "stick",
+        }
+    }
+}
+
+/// Sample of a firmware version read after connecting, `changed` is 1 if it
+/// differs from the one read before.
+pub(crate) fn sample(
+    measurement: &str,
+    inverter_name: &str,
+    component: Component,
+    version: &str,
+    previous: Option<&str>,
+) -> Sample {
+    let changed = previous.is_some_and(|previous| previous != version);
+    let sample = Sample::new(measurement, chrono::Utc::now())
+        .field("changed", if changed { 1.0 } else { 0.0 })
+        .tag("inverter", inverter_name)
+        .tag("component", component.name())
+        .tag("version", version);
+    match previous {
+        Some(previous) => sample.tag("previous_version", previous),
+        None => sample,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_register_range() {
+        let config: FirmwareConfig = toml::from_str("").unwrap();
+        assert_eq!(config.registers, None);
+        let config: FirmwareConfig = toml::from_str("registers = [0x0c, 0x0e]").unwrap();
+        assert_eq!(config.registers, Some([0x0c, 0x0e]));
+        let config: FirmwareConfig = toml::from_str("registers = [0x0c, 0x0c]").unwrap();
+        assert_eq!(config.registers, Some([0x0c, 0x0c]));
+    }
+
+    #[test]
+    fn rejects_reversed_and_long_ranges() {
+        assert!(toml::from_str::<FirmwareConfig>("registers = [0x0e, 0x0c]").is_err());
+        assert!(toml::from_str::<FirmwareConfig>("registers = [0, 0xffff]").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Grid protection settings of the inverters, to check the country profile
+//! is still the expected one after a firmware update.
+
+use crate::audit::{Action, Origin};
+use crate::output::Sample;
+use crate::table::{self, OutputFormat, Table};
+use crate::{connect_inverter, writes, Config};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solar_mon::error_code::ErrorCode;
+use solar_mon::registers::{self, GridProtection, GridProtectionRegisters};
+use std::io::{BufRead, Write};
+
+/// Register addresses of the grid protection settings. They are not
+/// documented by Deye, so nothing is read before all limits are configured.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct GridProtectionConfig {
+    #[serde(default)]
+    over_voltage: Option<u16>,
+    #[serde(default)]
+    under_voltage: Option<u16>,
+    #[serde(default)]
+    over_frequency: Option<u16>,
+    #[serde(default)]
+    under_frequency: Option<u16>,
+    #[serde(default)]
+    reconnect_delay: Option<u16>,
+    /// Grid standard (country profile) selection. There is no default, a
+    /// write to the wrong register could change any setting
+    #[serde(default)]
+    standard: Option<u16>,
+}
+
+impl GridProtectionConfig {
+    /// The registers of the limits, `None` unless all are configured.
+    pub(crate) fn registers(&self) -> Option<GridProtectionRegisters> {
+        Some(GridProtectionRegisters {
+            over_voltage: self.over_voltage?,
+            under_voltage: self.under_voltage?,
+            over_frequency: self.over_frequency?,
+            under_frequency: self.under_frequency?,
+            reconnect_delay: self.reconnect_delay?,
+        })
+    }
+
+    /// Fails if only some of the limit registers are configured.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        let limits = [
+            self.over_voltage,
+            self.under_voltage,
+            self.over_frequency,
+            self.under_frequency,
+            self.reconnect_delay,
+        ];
+        if limits.iter().any(Option::is_some) && self.registers().is_none() {
+            anyhow::bail!(
+                "grid_protection needs all of over_voltage, under_voltage, over_frequency, \
+                 under_frequency and reconnect_delay"
+            );
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn sample(measurement: &str, inverter_name: &str, settings: &GridProtection) -> Sample {
+    Sample::new(measurement, chrono::Utc::now())
+        .field("over_voltage", settings.over_voltage)
+        .field("under_voltage", settings.under_voltage)
+        .field("over_frequency", settings.over_frequency)
+        .field("under_frequency", settings.under_frequency)
+        .field("reconnect_delay", settings.reconnect_delay_secs)
+        .tag("inverter", inverter_name)
+}
+
+/// Prints the grid protection settings of `inverters`, of every configured
+/// one if empty. Fails if any inverter could not be read.
+pub(crate) fn report(
+    config: &Config,
+    inverters: &[String],
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let mut names: Vec<_> = config
+        .inverter
+        .keys()
+        .filter(|name| inverters.is_empty() || inverters.contains(name))
+        .collect();
+    names.sort();
+    if let Some(unknown) = inverters.iter().find(|name| !config.inverter.contains_key(*name)) {
+        anyhow::bail!("Inverter {unknown} is not configured");
+    }
+    let mut table = Table::new([
+        "inverter",
+        "over_voltage",
+        "under_voltage",
+        "over_frequency",
+        "under_frequency",
+        "reconnect_delay",
+    ]);
+    let mut failed = Vec::new();
+    for inverter_name in names {
+        let inverter_cfg = &config.inverter[inverter_name];
+        let Some(registers) = inverter_cfg.grid_protection.registers() else {
+            log::error!(
+                "[{inverter_name}] The grid protection registers are unknown, \
+                 set them in [inverter.{inverter_name}.grid_protection]"
+            );
+            failed.push(inverter_name.as_str());
+            continue;
+        };
+        let settings = connect_inverter(inverter_cfg, &config.monitoring)
+            .and_then(|mut inverter| inverter.grid_protection(&registers));
+        match settings {
+            Ok(settings) => table.push(vec![
+                Value::from(inverter_name.as_str()),
+                table::number(settings.over_voltage, 1),
+                table::number(settings.under_voltage, 1),
+                table::number(settings.over_frequency, 2),
+                table::number(settings.under_frequency, 2),
+                table::number(settings.reconnect_delay_secs, 0),
+            ]),
+            Err(e) => {
+                let code = ErrorCode::of(&e);
+                log::error!("[{inverter_name}] Failed to read grid protection settings ({code}: {e:#})");
+                failed.push(inverter_name.as_str());
+            }
+        }
+    }
+    table.write(format, std::io::stdout().lock())?;
+    if !failed.is_empty() {
+        anyhow::bail!("Reading the grid protection settings failed for {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// Selects another grid standard on an inverter after the user confirmed it
+/// by typing the inverter name, or passed it as `confirmation`. The register
+/// is read back afterwards, as some firmware ignores writes to it while
+/// feeding in. The change is recorded in the audit log.
+pub(crate) fn set_standard(
+    config: &Config,
+    origin: &Origin,
+    inverter_name: &str,
+    standard: u16,
+    confirmation: Option<&str>,
+    force: bool,
+) -> anyhow::Result<()> {
+    let inverter_cfg = config
+        .inverter
+        .get(inverter_name)
+        .with_context(|| format!("Inverter {inverter_name} is not configured"))?;
+    let Some(register) = inverter_cfg.grid_protection.standard else {
+        anyhow::bail!(
+            "The grid standard register of {inverter_name} is unknown, \
+             set standard in [inverter.{inverter_name}.grid_protection]"
+        );
+    };
+    writes::check(config.writes.as_ref(), force)?;
+    let mut inverter = connect_inverter(inverter_cfg, &config.monitoring)?;
+    let old_values = writes::read_registers(&mut inverter, register, 1);
+    let current = old_values
+        .as_ref()
+        .and_then(|values| values.first())
+        .map_or_else(|| "unknown".to_owned(), u16::to_string);
+    if current == standard.to_string() {
+        log::info!("[{inverter_name}] Grid standard is already {standard}");
+        return Ok(());
+    }
+    let confirmed = match confirmation {
+        Some(confirmation) => confirmation == inverter_name,
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            writeln!(
+                stdout,
+                "Changing the grid standard of {inverter_name} from {current} to {standard}. \
+                 A wrong standard can violate the grid code of your country."
+            )?;
+            write!(stdout, "Type the inverter name to confirm: ")?;
+            stdout.flush()?;
+            let mut answer = String::new();
+            std::io::stdin().lock().read_line(&mut answer)?;
+            answer.trim() == inverter_name
+        }
+    };
+    if !confirmed {
+        anyhow::bail!("Not confirmed, the grid standard was not changed");
+    }
+    let result = writes::write(&mut inverter, register, &[standard]).and_then(|()| {
+        let frame = registers::read_frame(inverter.slave_id(), register, 1);
+        let read_back = inverter
+            .send_raw(&frame)
+            .and_then(|response| registers::decode_values(response, register, 1))
+            .context("Failed to read the grid standard back")?;
+        if read_back != [standard] {
+            anyhow::bail!(
+                "Inverter kept grid standard {} after the write",
+                read_back.first().copied().unwrap_or_default()
+            );
+        }
+        Ok(())
+    });
+    Action::new(origin, "set_grid_standard")
+        .inverter(inverter_name)
+        .registers(register, old_values, vec![standard])
+        .record(&result);
+    result?;
+    log::info!("[{inverter_name}] Changed the grid standard from {current} to {standard}");
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Translations of the texts meant for people: the column names of the CLI
+//! tables, the settings page and the daily report. JSON and CSV output keep
+//! the English names, scripts rely on them.
+
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Language {
+    #[default]
+    En,
+    De,
+}
+
+/// German texts by their English original.
+const GERMAN: &[(&str, &str)] = &[
+    // Column names
+    ("inverter", "Wechselrichter"),
+    ("input", "Eingang"),
+    ("voltage", "Spannung"),
+    ("current", "Strom"),
+    ("power", "Leistung"),
+    ("energy", "Energie"),
+    ("energy_today", "Energie heute"),
+    ("status", "Status"),
+    ("last_error", "Letzter Fehler"),
+    ("month", "Monat"),
+    ("year", "Jahr"),
+    ("savings", "Ersparnis"),
+    ("earnings", "Vergütung"),
+    ("co2_avoided", "CO2 vermieden"),
+    ("peak", "Spitze"),
+    ("online", "Online"),
+    ("availability", "Verfügbarkeit"),
+    ("faults", "Störungen"),
+    ("poll", "Abfrage"),
+    ("over_voltage", "Überspannung"),
+    ("under_voltage", "Unterspannung"),
+    ("over_frequency", "Überfrequenz"),
+    ("under_frequency", "Unterfrequenz"),
+    ("reconnect_delay", "Wiederzuschaltung"),
+    // Daily report
+    ("Solar report", "Solarbericht"),
+    ("No readings", "Keine Messwerte"),
+    ("Energy", "Energie"),
+    ("Faults", "Störungen"),
+    ("no error code", "kein Fehlercode"),
+    // Settings page
+    ("Settings", "Einstellungen"),
+    (
+        "Inverters, outputs and intervals are edited in the TOML config below. Saving validates \
+         the config first, the daemon uses it after a reload.",
+        "Wechselrichter, Ausgaben und Intervalle werden in der TOML-Konfiguration unten \
+         bearbeitet. Beim Speichern wird sie zuerst geprüft, der Dienst verwendet sie nach dem \
+         Neuladen.",
+    ),
+    ("Save", "Speichern"),
+    ("Reload", "Neu laden"),
+    ("Saved, reload to apply the changes.", "Gespeichert, zum Übernehmen neu laden."),
+    ("Restarting...", "Startet neu..."),
+];
+
+/// `language` of the config.
+static CONFIGURED: OnceLock<Option<Language>> = OnceLock::new();
+
+pub(crate) fn init(configured: Option<Language>) {
+    // Only the first config loaded counts.
+    let _ = CONFIGURED.set(configured);
+}
+
+pub(crate) fn configured() -> Option<Language> {
+    CONFIGURED.get().copied().flatten()
+}
+
+/// Language of the locale environment variables, English unless it is
+/// German.
+fn from_locale() -> Language {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    Language::from_tag(&locale).unwrap_or_default()
+}
+
+/// Text in the language of the process.
+pub(crate) fn tr(text: &str) -> &str {
+    Language::current().tr(text)
+}
+
+impl Language {
+    /// Language of a locale like `de_DE.UTF-8` or a language tag like
+    /// `de-AT`, `None` if there is no translation.
+    pub(crate) fn from_tag(tag: &str) -> Option<Self> {
+        match tag.get(..2)?.to_ascii_lowercase().as_str() {
+            "en" => Some(Language::En),
+            "de" => Some(Language::De),
+            _ => None,
+        }
+    }
+
+    /// The configured language or else the one of the locale.
+    pub(crate) fn current() -> Self {
+        configured().unwrap_or_else(from_locale)
+    }
+
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            Language::En => "en",
+            Language::De => "de",
+        }
+    }
+
+    /// `text` translated, unchanged if there is no translation.
+    pub(crate) fn tr(self, text: &str) -> &str {
+        let translations = match self {
+            Language::En => return text,
+            Language::De => GERMAN,
+        };
+        translations
+            .iter()
+            .find(|(english, _)| *english == text)
+            .map_or(text, |(_, translated)| translated)
+    }
+
+    /// Replaces every `{{text}}` in `page` by its translation and
+    /// `{{lang}}` by the language code.
+    pub(crate) fn render(self, page: &str) -> String {
+        let mut rendered = String::with_capacity(page.len());
+        let mut rest = page;
+        while let Some((before, after)) = rest.split_once("{{") {
+            let Some((text, after)) = after.split_once("}}") else {
+                break;
+            };
+            rendered.push_str(before);
+            rendered.push_str(if text == "lang" { self.code() } else { self.tr(text) });
+            rest = after;
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
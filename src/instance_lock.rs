@@ -0,0 +1,187 @@
+//! Lock files per logger stick, so a second instance started by accident
+//! fails right away instead of both instances polling the same stick and
+//! running into timeouts.
+
+use crate::{Config, InverterConfig};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+
+/// Locks held by this process by endpoint, released when dropped or when
+/// the process exits or restarts.
+pub(crate) struct InstanceLocks {
+    dir: PathBuf,
+    /// Config path written to the lock files for the error of other instances
+    owner: String,
+    files: HashMap<String, File>,
+}
+
+impl InstanceLocks {
+    /// Locks the endpoints of all polled inverters of `config`. The lock
+    /// files are kept in the state directory, without one in a private
+    /// directory of the user.
+    pub(crate) fn acquire(config: &Config) -> anyhow::Result<Self> {
+        let dir = match &config.state_dir {
+            Some(state_dir) => state_dir.clone(),
+            None => private_dir()?,
+        };
+        let mut locks = InstanceLocks {
+            dir,
+            owner: std::path::absolute(&config.path)
+                .unwrap_or_else(|_| config.path.clone())
+                .display()
+                .to_string(),
+            files: HashMap::new(),
+        };
+        let mut inverters: Vec<_> = config.inverter.iter().collect();
+        inverters.sort_unstable_by_key(|(name, _)| *name);
+        for (name, inverter_cfg) in inverters {
+            locks.add(name, inverter_cfg)?;
+        }
+        Ok(locks)
+    }
+
+    /// Locks the endpoint of another inverter, e.g. one added at runtime.
+    /// Several inverters behind the same stick share one lock.
+    pub(crate) fn add(&mut self, name: &str, inverter_cfg: &InverterConfig) -> anyhow::Result<()> {
+        if inverter_cfg.listen_only || inverter_cfg.deye_cloud_serial.is_some() {
+            return Ok(());
+        }
+        let endpoint = format!("{}:{}", inverter_cfg.ip, inverter_cfg.port);
+        if self.files.contains_key(&endpoint) {
+            return Ok(());
+        }
+        let path = self.dir.join(lock_file_name(&endpoint));
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).truncate(false).read(true).write(true);
+        // Don't follow a link planted in place of the lock file.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(
+            &mut options,
+            rustix::fs::OFlags::NOFOLLOW.bits().cast_signed(),
+        );
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+        if let Err(e) = file.try_lock() {
+            return Err(match e {
+                std::fs::TryLockError::WouldBlock => anyhow::anyhow!(
+                    "Inverter {name} at {endpoint} is already polled by another instance{} \
+                     (lock file {})",
+                    holder(&mut file).map(|owner| format!(" using {owner}")).unwrap_or_default(),
+                    path.display()
+                ),
+                std::fs::TryLockError::Error(e) => anyhow::Error::new(e)
+                    .context(format!("Failed to lock {}", path.display())),
+            });
+        }
+        file.set_len(0)
+            .and_then(|()| file.write_all(self.owner.as_bytes()))
+            .with_context(|| format!("Failed to write lock file {}", path.display()))?;
+        log::debug!("[{name}] Locked {}", path.display());
+        self.files.insert(endpoint, file);
+        Ok(())
+    }
+}
+
+/// `XDG_RUNTIME_DIR`, or else a directory in the temp directory only the
+/// user can access.
+fn private_dir() -> anyhow::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    let dir = std::env::temp_dir().join(format!("solar_mon-{user}"));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    match builder.create(&dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| format!("Failed to create {}", dir.display()));
+        }
+        _ => {}
+    }
+    let metadata = std::fs::symlink_metadata(&dir)
+        .with_context(|| format!("Failed to check {}", dir.display()))?;
+    if !metadata.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if metadata.uid() != rustix::process::geteuid().as_raw() {
+            anyhow::bail!("{} belongs to another user, set state_dir", dir.display());
+        }
+        if metadata.mode() & 0o077 != 0 {
+            anyhow::bail!(
+                "{} is accessible by other users, remove it or set state_dir",
+                dir.display()
+            );
+        }
+    }
+    Ok(dir)
+}
+
+/// Config path of the instance holding a lock, if it wrote one.
+fn holder(file: &mut File) -> Option<String> {
+    let mut owner = String::new();
+    file.rewind().and_then(|()| file.read_to_string(&mut owner)).ok()?;
+    Some(owner.trim().to_owned()).filter(|owner| !owner.is_empty())
+}
+
+/// File name of the lock of `endpoint`, without the colons of IPv6
+/// addresses which Windows does not allow.
+fn lock_file_name(endpoint: &str) -> String {
+    let name: String = endpoint
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+        .collect();
+    format!("solar_mon-{name}.lock")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locks(dir: &std::path::Path) -> InstanceLocks {
+        InstanceLocks {
+            dir: dir.to_owned(),
+            owner: "/etc/solar_mon/config.toml".to_owned(),
+            files: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn second_lock_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let inverter_cfg: InverterConfig = toml::from_str("ip = \"192.168.1.40\"").unwrap();
+        let mut first = locks(dir.path());
+        first.add("roof", &inverter_cfg).unwrap();
+        // Another inverter behind the same stick.
+        first.add("garage", &inverter_cfg).unwrap();
+        let e = locks(dir.path()).add("roof", &inverter_cfg).unwrap_err();
+        assert!(
+            e.to_string().contains("using /etc/solar_mon/config.toml"),
+            "{e}"
+        );
+        drop(first);
+        locks(dir.path()).add("roof", &inverter_cfg).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let inverter_cfg: InverterConfig = toml::from_str("ip = \"192.168.1.40\"").unwrap();
+        let target = dir.path().join("target");
+        std::fs::write(&target, "keep").unwrap();
+        std::os::unix::fs::symlink(&target, dir.path().join(lock_file_name("192.168.1.40:8899")))
+            .unwrap();
+        assert!(locks(dir.path()).add("roof", &inverter_cfg).is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "keep");
+    }
+}
@@ -1,43 +1,177 @@
-use crate::solarmanv5::SolarmanDevice;
+use crate::encryption::Encryption;
+use crate::registers::{GridProtection, GridProtectionRegisters, RegisterMap};
+pub use crate::registers::{
+    Derating, InputReading, InsulationReading, MeterReading, MonitoringData,
+};
+use crate::solarmanv5::{LinkStats, SolarmanDevice, Timeouts};
+use crate::transport::Transport;
 
-pub struct Inverter {
-    device: SolarmanDevice,
+/// Inverter reached through the transport `T`, by default a logger stick.
+pub struct Inverter<T = SolarmanDevice> {
+    device: T,
+    register_map: RegisterMap,
+    request_frame: Vec<u8>,
+    fast_request_frame: Vec<u8>,
+    meter_request_frame: Option<Vec<u8>>,
+    /// Values of the last full read, completing the fast reads
+    last_data: Option<MonitoringData>,
+    /// RTU frames received by the last poll
+    responses: Vec<Vec<u8>>,
 }
 
-#[derive(Debug)]
-pub struct MonitoringData {
-    pub voltage_a: f64,
-    pub current_a: f64,
-    pub voltage_b: f64,
-    pub current_b: f64,
-}
-
-impl Inverter {
+impl Inverter<SolarmanDevice> {
     pub fn new(
         addr: std::net::IpAddr,
         port: u16,
-        timeout: std::time::Duration,
+        timeouts: impl Into<Timeouts>,
     ) -> anyhow::Result<Self> {
-        Ok(Inverter {
-            device: SolarmanDevice::new(addr, port, timeout)?,
-        })
+        Ok(Inverter::from_device(SolarmanDevice::new(addr, port, timeouts)?))
+    }
+
+    /// Creates an inverter behind a logger stick with a known serial, see
+    /// [`SolarmanDevice::with_serial`].
+    pub fn with_serial(
+        addr: std::net::IpAddr,
+        port: u16,
+        timeouts: impl Into<Timeouts>,
+        logger_serial: [u8; 4],
+    ) -> Self {
+        Inverter::from_device(SolarmanDevice::with_serial(addr, port, timeouts, logger_serial))
+    }
+
+    /// Encrypts the payload of the requests, see
+    /// [`SolarmanDevice::set_encryption`].
+    pub fn with_encryption(mut self, encryption: Encryption, key: Option<[u8; 16]>) -> Self {
+        self.device.set_encryption(encryption, key);
+        self
+    }
+
+    pub fn logger_serial(&self) -> [u8; 4] {
+        self.device.logger_serial()
+    }
+
+    /// Whether the requests are encrypted, see [`SolarmanDevice::encrypted`].
+    pub fn encrypted(&self) -> bool {
+        self.device.encrypted()
+    }
+
+    /// Age of the last reading according to the stick clock, see
+    /// [`SolarmanDevice::data_age`].
+    pub fn data_age(&self) -> Option<std::time::Duration> {
+        self.device.data_age()
+    }
+
+    /// When the stick took the last reading, see
+    /// [`SolarmanDevice::reading_time`].
+    pub fn reading_time(&self) -> Option<std::time::SystemTime> {
+        self.device.reading_time()
+    }
+
+    /// Estimated skew of the stick clock in ms, see
+    /// [`SolarmanDevice::clock_skew_ms`].
+    pub fn clock_skew_ms(&self) -> Option<i64> {
+        self.device.clock_skew_ms()
+    }
+
+    /// Text put in front of the log messages of the following requests, see
+    /// [`SolarmanDevice::set_log_prefix`].
+    pub fn set_log_prefix(&mut self, prefix: impl Into<String>) {
+        self.device.set_log_prefix(prefix);
+    }
+}
+
+impl<T: Transport> Inverter<T> {
+    /// Inverter with the default dual input register map behind any
+    /// transport, e.g. a [`crate::transport::MockTransport`] in tests.
+    pub fn from_device(device: T) -> Self {
+        let register_map = RegisterMap::default();
+        Inverter {
+            device,
+            request_frame: register_map.request_frame(),
+            fast_request_frame: register_map.fast_request_frame(),
+            meter_request_frame: None,
+            last_data: None,
+            responses: Vec::new(),
+            register_map,
+        }
+    }
+
+    /// Replaces the default dual input register map, e.g. for single or four
+    /// input models.
+    pub fn with_register_map(mut self, register_map: RegisterMap) -> Self {
+        self.request_frame = register_map.request_frame();
+        self.fast_request_frame = register_map.fast_request_frame();
+        self.meter_request_frame = register_map.meter_request_frame();
+        self.last_data = None;
+        self.register_map = register_map;
+        self
+    }
+
+    /// The transport the inverter is reached through.
+    pub fn device(&self) -> &T {
+        &self.device
+    }
+
+    /// Communication statistics of the transport, see [`LinkStats`].
+    pub fn link_stats(&self) -> LinkStats {
+        self.device.stats()
+    }
+
+    /// Modbus slave id of the inverter.
+    pub fn slave_id(&self) -> u8 {
+        self.register_map.slave_id
+    }
+
+    /// Sends an arbitrary Modbus RTU frame and returns the RTU frame of the
+    /// response, see [`Transport::send_modbus_frame`].
+    pub fn send_raw(&mut self, rtu_frame: &[u8]) -> anyhow::Result<&[u8]> {
+        self.device.send_modbus_frame(rtu_frame)
     }
 
     pub fn get_data(&mut self) -> anyhow::Result<MonitoringData> {
-        let resp_frame = self
-            .device
-            .send_modbus_frame(&[0x1, 0x3, 0x0, 0x3b, 0x0, 0x36, 0xb4, 0x11])?;
-
-        let voltage_a = ((((resp_frame[103] as u32) << 8) + resp_frame[104] as u32) as f64) / 10.0;
-        let current_a = ((((resp_frame[105] as u32) << 8) + resp_frame[106] as u32) as f64) / 10.0;
-        let voltage_b = (((resp_frame[107] as u32) << 8) + resp_frame[108] as u32) as f64 / 10.0;
-        let current_b = (((resp_frame[109] as u32) << 8) + resp_frame[110] as u32) as f64 / 10.0;
-
-        Ok(MonitoringData {
-            voltage_a,
-            current_a,
-            voltage_b,
-            current_b,
-        })
+        self.responses.clear();
+        let resp_frame = self.device.send_modbus_frame(&self.request_frame)?.to_vec();
+        let meter_resp_frame = match &self.meter_request_frame {
+            Some(frame) => Some(self.device.send_modbus_frame(frame)?.to_vec()),
+            None => None,
+        };
+        self.responses.push(resp_frame);
+        self.responses.extend(meter_resp_frame);
+        let data = self.register_map.decode_responses(&self.responses)?;
+        self.last_data = Some(data.clone());
+        Ok(data)
+    }
+
+    /// Reads only the quickly changing values, see
+    /// [`RegisterMap::fast_block`], and takes the others over from the last
+    /// [`Self::get_data`]. Reads everything if there was none yet.
+    pub fn get_fast_data(&mut self) -> anyhow::Result<MonitoringData> {
+        let Some(previous) = &self.last_data else {
+            return self.get_data();
+        };
+        // The responses are only kept for full reads.
+        self.responses.clear();
+        let resp_frame = self.device.send_modbus_frame(&self.fast_request_frame)?;
+        self.register_map.decode_fast(resp_frame, previous)
+    }
+
+    /// Reads the grid protection settings from `registers`.
+    pub fn grid_protection(
+        &mut self,
+        registers: &GridProtectionRegisters,
+    ) -> anyhow::Result<GridProtection> {
+        let frame = registers.request_frame(self.register_map.slave_id);
+        registers.decode(self.device.send_modbus_frame(&frame)?)
+    }
+
+    /// RTU frames of the responses the last [`Self::get_data`] received,
+    /// also if decoding them failed. Empty if not all of them arrived or
+    /// after [`Self::get_fast_data`].
+    pub fn last_responses(&self) -> &[Vec<u8>] {
+        &self.responses
+    }
+
+    pub fn register_map(&self) -> &RegisterMap {
+        &self.register_map
     }
 }
@@ -0,0 +1,29 @@
+#![forbid(unsafe_code)]
+#![warn(
+    clippy::dbg_macro,
+    clippy::decimal_literal_representation,
+    clippy::panic,
+    clippy::panic_in_result_fn,
+    clippy::print_stderr,
+    clippy::print_stdout,
+    clippy::todo,
+    clippy::unimplemented,
+    clippy::unwrap_in_result,
+    clippy::unwrap_used,
+    clippy::use_debug
+)]
+
+//! Reading Deye micro inverters through the SolarmanV5 protocol of their
+//! logger sticks.
+
+pub mod at_command;
+pub mod connection_limit;
+pub mod encryption;
+pub mod error_code;
+pub mod inverter;
+pub mod one_shot;
+pub mod registers;
+pub mod solarmanv5;
+pub mod transport;
+
+pub use one_shot::{read_current, read_current_async};
@@ -0,0 +1,144 @@
+use crate::queue::BoundedQueue;
+use crate::state_file::StateFile;
+use crate::{enqueue, to_samples, Batch, Config, InverterConfig, MonitoringConfig};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use solar_mon::error_code::ErrorCode;
+use solar_mon::solarmanv5::{self, StickFrame, StickFrameType};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Server the logger sticks connect to on their own, entered as remote
+/// server ("Server B") on the web interface of the stick.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ListenConfig {
+    #[serde(default = "default_listen_address")]
+    address: String,
+}
+
+fn default_listen_address() -> String {
+    "0.0.0.0:10000".to_owned()
+}
+
+/// Sticks send a heartbeat about every minute, a connection silent for
+/// longer is gone.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Turns the readings pushed by the sticks into samples.
+struct Receiver {
+    inverters: HashMap<String, InverterConfig>,
+    monitoring_config: MonitoringConfig,
+    queue: Arc<BoundedQueue<Batch>>,
+    state_file: Arc<StateFile>,
+}
+
+impl Receiver {
+    /// Inverter behind the stick with `serial`, by the configured serial or
+    /// the one remembered from polling it.
+    fn inverter(&self, serial: u32) -> Option<(&String, &InverterConfig)> {
+        self.inverters.iter().find(|(name, inverter_cfg)| {
+            inverter_cfg.logger_serial.or_else(|| self.state_file.get(name).logger_serial)
+                == Some(serial)
+        })
+    }
+
+    fn store(&self, serial: u32, registers: &[u8]) {
+        let Some((inverter_name, inverter_cfg)) = self.inverter(serial) else {
+            log::debug!("[{serial}] Ignoring data of logger stick without inverter");
+            return;
+        };
+        let data = inverter_cfg.register_map().and_then(|map| map.decode_snapshot(registers));
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                let code = ErrorCode::of(&e);
+                log::debug!("[{inverter_name}] Failed to decode pushed data ({code}: {e})");
+                return;
+            }
+        };
+        log::debug!("[{inverter_name}] Received pushed data: {data:#?}");
+        let samples = to_samples(inverter_name, inverter_cfg, &self.monitoring_config, &data);
+        enqueue(
+            &self.queue,
+            Batch {
+                inverter: inverter_name.clone(),
+                samples,
+            },
+        );
+        self.state_file.update(inverter_name, false, |record| {
+            record.last_success = Some(chrono::Utc::now().timestamp());
+        });
+    }
+}
+
+/// Accepts stick connections in the background, each stick gets its own
+/// thread. The readings they push are stored like polled ones.
+pub(crate) fn start(
+    listen_cfg: &ListenConfig,
+    config: &Config,
+    queue: Arc<BoundedQueue<Batch>>,
+    state_file: Arc<StateFile>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(&listen_cfg.address)
+        .with_context(|| format!("Failed to listen on {}", listen_cfg.address))?;
+    let receiver = Arc::new(Receiver {
+        inverters: config.inverter.clone(),
+        monitoring_config: config.monitoring.clone(),
+        queue,
+        state_file,
+    });
+    std::thread::Builder::new()
+        .name("listen".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("Failed to accept logger stick connection ({e})");
+                        continue;
+                    }
+                };
+                let receiver = receiver.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = serve_stick(stream, &receiver) {
+                        log::debug!("Logger stick disconnected ({e:#})");
+                    }
+                });
+            }
+        })
+        .context("Failed to start listen thread")?;
+    log::info!("Listening for logger sticks on {}", listen_cfg.address);
+    Ok(())
+}
+
+/// Answers every frame of a stick until it disconnects.
+fn serve_stick(mut stream: TcpStream, receiver: &Receiver) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let peer = stream.peer_addr()?;
+    let mut buffer = Vec::new();
+    loop {
+        let frame_length = solarmanv5::read_stick_frame(&mut stream, &mut buffer)?;
+        let frame = StickFrame::from_bytes(&buffer[..frame_length])?;
+        let serial = u32::from_le_bytes(frame.logger_serial);
+        match frame.frame_type {
+            StickFrameType::Announcement => {
+                log::info!("Logger stick {serial} connected from {peer}");
+            }
+            frame_type => log::debug!(
+                "[{serial}] Received {} frame ({} bytes)",
+                frame_type.name(),
+                frame.payload.len()
+            ),
+        }
+        let now = u32::try_from(chrono::Utc::now().timestamp()).unwrap_or_default();
+        stream.write_all(&frame.response(now))?;
+        if let Some(registers) = frame.data_registers() {
+            receiver.store(serial, registers);
+        }
+        buffer.drain(..frame_length);
+    }
+}
@@ -14,15 +14,83 @@
 )]
 
 
+use aggregation::Aggregator;
 use anyhow::Context;
-use inverter::Inverter;
-use rinfluxdb::line_protocol::blocking::Client;
-use rinfluxdb::line_protocol::LineBuilder;
+use archive::Archive;
+use availability::Availability;
+use bridge::Bridge;
+use chrono::Timelike;
+use clap::Parser;
+use delta::DeltaFilter;
+use instance_lock::InstanceLocks;
+use mppt_stats::MpptStats;
+use output::{OutputEntry, Outputs, Sample};
+use queue::{BoundedQueue, OverflowPolicy};
+use ramp::RampRates;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, str::FromStr};
+use smoothing::{SmoothingConfig, Smoother};
+use solar_mon::at_command;
+use solar_mon::connection_limit;
+use solar_mon::encryption::Encryption;
+use solar_mon::error_code::ErrorCode;
+use solar_mon::inverter::{Inverter, MonitoringData};
+use solar_mon::registers::{
+    InsulationRegisters, MeterRegisters, ReadFunction, RegisterMap, Scales, MAX_REGISTERS,
+};
+use solar_mon::solarmanv5::{LinkStats, Timeouts};
+use state::{InverterState, StateEvent, StateTracker};
+use state_file::StateFile;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU16, Ordering},
+    sync::{Arc, Mutex, PoisonError},
+};
 
-mod inverter;
-mod solarmanv5;
+mod aggregation;
+mod archive;
+mod audit;
+mod availability;
+mod bridge;
+mod carbon;
+mod cli;
+mod completions;
+mod control;
+mod delta;
+mod deye_cloud;
+mod efficiency;
+mod energy_stats;
+mod export;
+mod fault_log;
+mod firmware;
+mod grid_settings;
+mod i18n;
+mod instance_lock;
+mod listen;
+mod modbus_tcp;
+mod mppt_stats;
+mod output;
+mod paths;
+mod pcap;
+mod queue;
+mod ramp;
+mod register_dump;
+mod scheduler;
+mod secrets;
+mod self_metrics;
+mod selftest;
+mod service;
+mod setup;
+mod smoothing;
+mod solarman_cloud;
+mod state;
+mod state_file;
+mod table;
+mod tariff;
+mod total_failure;
+mod watch;
+mod writes;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct InverterConfig {
@@ -32,21 +100,369 @@ struct InverterConfig {
     port: u16,
     #[serde(default = "default_inverter_location")]
     location: String,
+    /// Names used as `input` tag instead of the input letters
+    #[serde(default)]
+    inputs: HashMap<String, String>,
+    /// Number of PV inputs, 1 (SUN300) up to 4 (SUN2000G3)
+    #[serde(default = "default_mppt_inputs", deserialize_with = "deserialize_mppt_inputs")]
+    mppt_inputs: u8,
+    /// Grid meter registers, only for models with a bidirectional meter
+    grid_meter: Option<GridMeterConfig>,
+    /// Insulation monitoring registers, only for models reporting them
+    insulation: Option<InsulationConfig>,
+    /// Registers of the grid protection settings
+    #[serde(default)]
+    grid_protection: grid_settings::GridProtectionConfig,
+    /// Where the firmware versions are read from
+    #[serde(default)]
+    firmware: firmware::FirmwareConfig,
+    /// Read the WiFi signal quality of the logger stick with every poll
+    #[serde(default)]
+    wifi_signal: bool,
+    /// Read why the inverter limits its output with every poll
+    #[serde(default)]
+    derating: Option<DeratingConfig>,
+    /// Resolution overrides for firmware revisions deviating from the
+    /// register map
+    #[serde(default)]
+    scale: ScaleConfig,
+    /// Read all registers of a poll in one Modbus request, so the values
+    /// can't come from different moments
+    #[serde(default)]
+    strict_snapshot: bool,
+    /// Modbus function reading the registers, for variants answering on
+    /// input instead of holding registers
+    #[serde(default)]
+    read_function: ReadFunctionConfig,
+    /// Added to the register addresses of the poll request, for variants
+    /// with the same layout at other addresses
+    #[serde(default)]
+    register_base: u16,
+    /// InfluxDB database of this inverter instead of the one of the
+    /// monitoring section, e.g. to keep sites apart
+    #[serde(default)]
+    database: Option<String>,
+    /// Encrypt the SolarmanV5 payload, detected from the responses if unset
+    #[serde(default)]
+    encryption: Option<bool>,
+    /// AES key as 32 hex digits for sticks not using the key derived from
+    /// their serial
+    #[serde(default)]
+    encryption_key: Option<String>,
+    /// Serial of the logger stick, to match the data it pushes in listen
+    /// mode. The serial found when polling is used if unset
+    #[serde(default)]
+    logger_serial: Option<u32>,
+    /// Only store the data the stick pushes in listen mode, never poll it
+    #[serde(default)]
+    listen_only: bool,
+    /// Delay of the first poll, so inverters sharing a WiFi access point
+    /// are not polled at the same time
+    #[serde(default)]
+    start_offset_secs: Option<u32>,
+    /// Serial of the inverter in the Solarman cloud, for `live_fallback`
+    #[serde(default)]
+    cloud_serial: Option<String>,
+    /// Serial of the inverter in Deye Cloud, to take its data from there
+    /// instead of polling it
+    #[serde(default)]
+    deye_cloud_serial: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ReadFunctionConfig {
+    /// Function 0x03
+    #[default]
+    Holding,
+    /// Function 0x04
+    Input,
+}
+
+/// Factor per raw register value, e.g. `current = 0.01` for firmware
+/// reporting the current in 0.01 A. Unset fields keep the register map's
+/// scale.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct ScaleConfig {
+    voltage: Option<f64>,
+    current: Option<f64>,
+    ac_power: Option<f64>,
+    energy_today: Option<f64>,
+    energy_total: Option<f64>,
+}
+
+/// Register addresses of the grid meter, the defaults match the Deye
+/// single phase hybrid inverters.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct GridMeterConfig {
+    /// Low and high word of the total imported energy
+    #[serde(default = "default_import_energy_registers")]
+    import_energy: [u16; 2],
+    /// Low and high word of the total exported energy
+    #[serde(default = "default_export_energy_registers")]
+    export_energy: [u16; 2],
+    #[serde(default = "default_grid_power_register")]
+    power: u16,
+}
+
+/// Register addresses of the insulation monitoring values. They are not
+/// documented by Deye and have no defaults.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct InsulationConfig {
+    /// Insulation resistance to earth in kOhm
+    resistance: u16,
+    /// Residual current in mA
+    leakage_current: u16,
+}
+
+/// Register telling why the inverter limits its output. Neither its address
+/// nor the meaning of its bits are documented by Deye, so both have to be
+/// configured.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct DeratingConfig {
+    register: u16,
+    /// Bit number of every reason, 0 being the lowest bit, e.g.
+    /// `{ temperature = 0 }`
+    bits: BTreeMap<String, u8>,
+}
+
+impl InverterConfig {
+    fn database<'a>(&'a self, monitoring_config: &'a MonitoringConfig) -> &'a str {
+        self.database.as_deref().unwrap_or(&monitoring_config.database)
+    }
+
+    fn input_name<'a>(&'a self, input: &'a str) -> &'a str {
+        self.inputs.get(input).map_or(input, String::as_str)
+    }
+
+    /// Fails for registers past the end of the address range.
+    fn register_map(&self) -> anyhow::Result<RegisterMap> {
+        let defaults = Scales::default();
+        let scales = Scales {
+            voltage: self.scale.voltage.unwrap_or(defaults.voltage),
+            current: self.scale.current.unwrap_or(defaults.current),
+            ac_power: self.scale.ac_power.unwrap_or(defaults.ac_power),
+            energy_today: self.scale.energy_today.unwrap_or(defaults.energy_today),
+            energy_total: self.scale.energy_total.unwrap_or(defaults.energy_total),
+        };
+        let function = match self.read_function {
+            ReadFunctionConfig::Holding => ReadFunction::HoldingRegisters,
+            ReadFunctionConfig::Input => ReadFunction::InputRegisters,
+        };
+        let mut register_map = RegisterMap::micro(self.mppt_inputs)
+            .with_scales(scales)
+            .with_addressing(function, self.register_base);
+        if let Some(meter) = &self.grid_meter {
+            register_map = register_map.with_meter(MeterRegisters {
+                import_energy: meter.import_energy,
+                export_energy: meter.export_energy,
+                power: meter.power,
+            });
+        }
+        if let Some(insulation) = &self.insulation {
+            register_map = register_map.with_insulation(InsulationRegisters {
+                resistance: insulation.resistance,
+                leakage_current: insulation.leakage_current,
+            })?;
+        }
+        if let Some(derating) = &self.derating {
+            register_map = register_map.with_derating(derating.register)?;
+        }
+        if self.strict_snapshot {
+            register_map = register_map.single_request()?;
+        }
+        Ok(register_map)
+    }
+
+    fn encryption_key(&self) -> anyhow::Result<Option<[u8; 16]>> {
+        let Some(hex) = &self.encryption_key else {
+            return Ok(None);
+        };
+        let mut key = [0; 16];
+        if hex.len() != 32 || !hex.is_ascii() {
+            anyhow::bail!("encryption_key must be 32 hex digits");
+        }
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits)?;
+            *byte = u8::from_str_radix(digits, 16)
+                .with_context(|| format!("encryption_key has invalid hex digits '{digits}'"))?;
+        }
+        Ok(Some(key))
+    }
+
+    /// Applies register map and encryption to a new connection.
+    fn configure(&self, inverter: Inverter) -> Inverter {
+        let encryption = match self.encryption {
+            None => Encryption::Auto,
+            Some(true) => Encryption::Always,
+            Some(false) => Encryption::Never,
+        };
+        // The key and the register map were checked when the config was
+        // loaded.
+        let key = self.encryption_key().ok().flatten();
+        let register_map =
+            self.register_map().unwrap_or_else(|_| RegisterMap::micro(self.mppt_inputs));
+        inverter.with_register_map(register_map).with_encryption(encryption, key)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct MonitoringConfig {
-    influx_ip: std::net::IpAddr,
+    /// IP address or host name, names are resolved again when writes keep
+    /// failing
+    influx_ip: String,
     #[serde(default = "default_influx_port")]
     influx_port: u16,
     #[serde(default = "default_database_name")]
     database: String,
     #[serde(default = "default_measurement_name")]
     measurement: String,
+    /// Compress the requests to InfluxDB
+    #[serde(default)]
+    influx_gzip: bool,
+    #[serde(default)]
+    influx_schema: output::InfluxSchema,
+    /// Collect the data and write it to InfluxDB in one request per interval
+    #[serde(default)]
+    influx_flush_intervall_secs: Option<u32>,
     #[serde(default = "default_monitoring_intervall")]
     intervall_secs: u32,
+    /// Aggregate the polls over this interval and only write min, max and
+    /// mean of every field
+    #[serde(default)]
+    write_intervall_secs: Option<u32>,
+    /// Read the slowly changing values like the energy counters only at
+    /// this interval, the polls in between read the input values and the
+    /// AC power only
+    #[serde(default)]
+    slow_intervall_secs: Option<u32>,
     #[serde(default = "default_monitoring_timeout")]
     timeout_secs: u32,
+    /// How long to wait for the first connection attempts before polling
+    #[serde(default = "default_startup_timeout")]
+    startup_timeout_secs: u32,
+    /// Overrides `timeout_secs` for establishing the connection
+    #[serde(default)]
+    connect_timeout_secs: Option<u32>,
+    /// Overrides `timeout_secs` for waiting on a response
+    #[serde(default)]
+    read_timeout_secs: Option<u32>,
+    /// Overrides `timeout_secs` for sending a request
+    #[serde(default)]
+    write_timeout_secs: Option<u32>,
+    #[serde(default)]
+    status_file: Option<PathBuf>,
+    #[serde(default = "default_queue_size")]
+    queue_size: usize,
+    #[serde(default = "default_queue_overflow")]
+    queue_overflow: OverflowPolicy,
+    /// Keep running or exit once every inverter and output has been
+    /// failing for a while
+    #[serde(default)]
+    on_total_failure: total_failure::OnTotalFailure,
+    #[serde(default = "default_event_measurement")]
+    event_measurement: String,
+    /// Write the communication statistics of every inverter to this
+    /// measurement with every poll
+    #[serde(default)]
+    link_measurement: Option<String>,
+    /// Write the grid protection settings of every inverter to this
+    /// measurement after connecting and once a day
+    #[serde(default)]
+    grid_settings_measurement: Option<String>,
+    /// Write the firmware versions of every inverter and its stick to this
+    /// measurement after connecting, changes are also logged
+    #[serde(default)]
+    firmware_measurement: Option<String>,
+    /// Write the daily MPPT voltage range of every input to this
+    /// measurement with the first poll of the next day
+    #[serde(default)]
+    mppt_measurement: Option<String>,
+    /// Write the share of the expected polls that succeeded during
+    /// daylight per day
+    #[serde(default)]
+    availability: Option<availability::AvailabilityConfig>,
+    /// Stamp readings the logger stick held back with the time it took them
+    /// according to its clock instead of the reception time
+    #[serde(default)]
+    stick_clock: bool,
+    #[serde(default)]
+    state_file: Option<PathBuf>,
+    /// Append the start and end of every fault to this file
+    #[serde(default)]
+    fault_log: Option<PathBuf>,
+    /// Decimal places per field name, applied before writing
+    #[serde(default)]
+    precision: HashMap<String, u8>,
+    /// Fields whose negative values are replaced by 0, e.g. the noise
+    /// around zero at dawn
+    #[serde(default)]
+    clamp_negative: HashSet<String>,
+    /// Smoothing per field name, applied to the polled data
+    #[serde(default)]
+    smoothing: HashMap<String, SmoothingConfig>,
+    /// Add the change of the power per second since the previous poll as
+    /// `power_ramp` to every input
+    #[serde(default)]
+    ramp_rates: bool,
+    /// Only write a field when it changed by more than this since the value
+    /// last written, per field name
+    #[serde(default)]
+    deltas: HashMap<String, f64>,
+    /// Write the fields of `deltas` at least this often, even unchanged
+    #[serde(default = "default_delta_heartbeat")]
+    delta_heartbeat_secs: u32,
+    /// Poll all inverters from this many threads instead of one thread per
+    /// inverter
+    #[serde(default)]
+    worker_threads: Option<usize>,
+    /// Spread the polls of the inverters without `start_offset_secs` evenly
+    /// over the poll interval
+    #[serde(default)]
+    stagger_polls: bool,
+    /// Connections to logger sticks and databases open at the same time,
+    /// unlimited if unset
+    #[serde(default)]
+    max_connections: Option<usize>,
+    #[serde(default)]
+    watchdog: Option<WatchdogConfig>,
+    /// Value the production with these prices, needs `state_file`
+    #[serde(default)]
+    tariff: Option<tariff::TariffConfig>,
+    /// Count the CO2 avoided with this grid carbon intensity, needs
+    /// `state_file`
+    #[serde(default)]
+    carbon: Option<carbon::CarbonConfig>,
+    /// Dump every register of the poll block now and then
+    #[serde(default)]
+    periodic_register_dump: Option<register_dump::RegisterDumpConfig>,
+}
+
+/// Reboots logger sticks that stopped answering.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct WatchdogConfig {
+    /// Consecutive failed polls before the stick is rebooted
+    #[serde(default = "default_watchdog_failures")]
+    failures: u32,
+    /// Local hours `[from, to)` in which failures count
+    #[serde(default = "default_daylight_hours")]
+    daylight_hours: [u32; 2],
+}
+
+impl MonitoringConfig {
+    fn timeouts(&self) -> Timeouts {
+        let secs = |timeout: Option<u32>| {
+            std::time::Duration::from_secs(timeout.unwrap_or(self.timeout_secs).into())
+        };
+        Timeouts {
+            connect: secs(self.connect_timeout_secs),
+            read: secs(self.read_timeout_secs),
+            write: secs(self.write_timeout_secs),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -55,12 +471,76 @@ struct Config {
     inverter: HashMap<String, InverterConfig>,
     #[serde(default = "default_log_level")]
     log_level: String,
+    /// Language of the tables, the settings page and the daily report,
+    /// taken from the locale if missing
+    #[serde(default)]
+    language: Option<i18n::Language>,
+    #[serde(default)]
+    solarman: Option<solarman_cloud::SolarmanCloudConfig>,
+    #[serde(default)]
+    output: Vec<OutputEntry>,
+    #[serde(default)]
+    bridge: Option<bridge::BridgeConfig>,
+    /// Times the inverters may be written to, always if missing
+    #[serde(default)]
+    writes: Option<writes::WritesConfig>,
+    /// Where writes and other control actions are recorded, nowhere if missing
+    #[serde(default)]
+    audit: Option<audit::AuditConfig>,
+    /// Accept connections of logger sticks reporting to us
+    #[serde(default)]
+    listen: Option<listen::ListenConfig>,
+    /// Fetch the inverters with `deye_cloud_serial` from Deye Cloud
+    #[serde(default)]
+    deye_cloud: Option<deye_cloud::DeyeCloudConfig>,
+    /// Keep the raw responses of every poll to decode them again later
+    #[serde(default)]
+    archive: Option<archive::ArchiveConfig>,
+    /// Encrypted store of the values written as `secret:<name>`
+    #[serde(default)]
+    secrets: Option<secrets::SecretsConfig>,
+    /// Directory relative state, status and archive paths are resolved in
+    #[serde(default)]
+    state_dir: Option<PathBuf>,
+    /// Directory of data that can be fetched again, like access tokens
+    #[serde(default)]
+    cache_dir: Option<PathBuf>,
+    /// Directory relative audit and log files are resolved in
+    #[serde(default)]
+    log_dir: Option<PathBuf>,
+    /// File the config was loaded from
+    #[serde(skip)]
+    path: PathBuf,
 }
 
 fn default_inverter_location() -> String {
     "no_location".to_owned()
 }
 
+fn default_mppt_inputs() -> u8 {
+    2
+}
+
+fn deserialize_mppt_inputs<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let inputs = u8::deserialize(deserializer)?;
+    if !(1..=4).contains(&inputs) {
+        return Err(serde::de::Error::custom("mppt_inputs must be between 1 and 4"));
+    }
+    Ok(inputs)
+}
+
+fn default_import_energy_registers() -> [u16; 2] {
+    [0x4e, 0x50]
+}
+
+fn default_export_energy_registers() -> [u16; 2] {
+    [0x51, 0x52]
+}
+
+fn default_grid_power_register() -> u16 {
+    0xa9
+}
+
 fn default_inverter_ip() -> std::net::IpAddr {
     std::net::IpAddr::V4(std::net::Ipv4Addr::new(10, 10, 100, 254))
 }
@@ -80,6 +560,10 @@ fn default_database_name() -> String {
 fn default_influx_port() -> u16 {
     8086
 }
+fn default_delta_heartbeat() -> u32 {
+    900
+}
+
 fn default_monitoring_intervall() -> u32 {
     300
 }
@@ -88,96 +572,1397 @@ fn default_monitoring_timeout() -> u32 {
     10
 }
 
-fn run_monitoring(
+fn default_watchdog_failures() -> u32 {
+    10
+}
+
+fn default_startup_timeout() -> u32 {
+    30
+}
+
+fn default_daylight_hours() -> [u32; 2] {
+    [9, 17]
+}
+
+fn default_queue_size() -> usize {
+    100
+}
+
+fn default_queue_overflow() -> OverflowPolicy {
+    OverflowPolicy::DropOldest
+}
+
+fn default_event_measurement() -> String {
+    "inverter_events".to_string()
+}
+
+/// Samples of one poll on their way to the outputs.
+struct Batch {
+    inverter: String,
+    samples: Vec<Sample>,
+}
+
+fn connect_inverter(
+    inverter_cfg: &InverterConfig,
+    monitoring_config: &MonitoringConfig,
+) -> anyhow::Result<Inverter> {
+    Ok(inverter_cfg.configure(Inverter::new(
+        inverter_cfg.ip,
+        inverter_cfg.port,
+        monitoring_config.timeouts(),
+    )?))
+}
+
+fn to_samples(
+    inverter_name: &str,
+    inverter_cfg: &InverterConfig,
+    monitoring_config: &MonitoringConfig,
+    data: &MonitoringData,
+) -> Vec<Sample> {
+    let now = chrono::Utc::now();
+    let mut samples: Vec<_> = data
+        .inputs
+        .iter()
+        .map(|input| {
+            Sample::new(inverter_cfg.location.clone(), now)
+                .field("voltage", input.voltage)
+                .field("current", input.current)
+                .field("power", input.power())
+                .tag("inverter", inverter_name)
+                .tag("input", inverter_cfg.input_name(&input.name))
+        })
+        .collect();
+    let mut totals = Sample::new(inverter_cfg.location.clone(), now)
+        .field("ac_power", data.ac_power)
+        .field("energy_today", data.energy_today)
+        .field("energy_total", data.energy_total)
+        .tag("inverter", inverter_name);
+    if let Some(meter) = &data.meter {
+        totals = totals
+            .field("grid_import_energy", meter.import_energy)
+            .field("grid_export_energy", meter.export_energy)
+            .field("grid_power", meter.power);
+    }
+    if let Some(insulation) = &data.insulation {
+        totals = totals
+            .field("insulation_resistance", insulation.resistance)
+            .field("leakage_current", insulation.leakage_current);
+    }
+    if let (Some(derating), Some(derating_cfg)) = (data.derating, &inverter_cfg.derating) {
+        totals = totals.field("derating", f64::from(u8::from(derating.active())));
+        for (reason, bit) in &derating_cfg.bits {
+            let derated = f64::from(u8::from(derating.bit(*bit)));
+            totals = totals.field(format!("derating_{reason}"), derated);
+        }
+    }
+    samples.push(totals);
+    for (name, value) in samples.iter_mut().flat_map(|sample| &mut sample.fields) {
+        if *value < 0.0 && monitoring_config.clamp_negative.contains(name) {
+            *value = 0.0;
+        }
+    }
+    samples
+}
+
+/// Fields of the totals sample read with every poll, the others only with
+/// every `slow_intervall_secs`.
+const FAST_FIELDS: [&str; 1] = ["ac_power"];
+
+/// Communication statistics of one inverter, the counters are totals since
+/// the daemon started.
+fn link_sample(
+    measurement: &str,
+    inverter_name: &str,
+    stats: &LinkStats,
+    clock_skew_ms: Option<i64>,
+) -> Sample {
+    let mut sample = Sample::new(measurement, chrono::Utc::now())
+        .field("requests", stats.requests as f64)
+        .field("failures", stats.failures as f64)
+        .field("timeouts", stats.timeouts as f64)
+        .field("checksum_errors", stats.checksum_errors as f64)
+        .field("rejected_frames", stats.rejected_frames as f64)
+        .tag("inverter", inverter_name);
+    if let Some(latency) = stats.average_latency() {
+        sample = sample.field("latency_ms", latency.as_secs_f64() * 1000.0);
+    }
+    if let Some(skew) = clock_skew_ms {
+        sample = sample.field("clock_skew_ms", skew as f64);
+    }
+    sample
+}
+
+/// Id of a poll, unique within the daemon and ordered by time: the
+/// millisecond timestamp followed by a counter, 16 hex digits in total.
+fn next_trace_id() -> String {
+    static COUNTER: AtomicU16 = AtomicU16::new(0);
+    let millis = chrono::Utc::now().timestamp_millis() as u64 & 0xffff_ffff_ffff;
+    format!("{millis:012x}{:04x}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Records the time of the last successful write for `healthcheck`.
+fn touch_status_file(monitoring_config: &MonitoringConfig) {
+    let Some(path) = &monitoring_config.status_file else {
+        return;
+    };
+    let now = chrono::Utc::now().timestamp().to_string();
+    if let Err(e) = std::fs::write(path, now) {
+        log::warn!("Failed to update status file {} ({e})", path.display());
+    }
+}
+
+fn enqueue(queue: &BoundedQueue<Batch>, batch: Batch) {
+    if let Some(dropped) = queue.push(batch) {
+        log::warn!(
+            "[{}] Output queue full, dropped data from {}",
+            dropped.inverter,
+            dropped.samples.first().map(|s| s.time.to_rfc3339()).unwrap_or_default()
+        );
+    }
+}
+
+/// Logs a state transition, remembers it and hands it to the outputs.
+fn report_transition(
+    event: Option<StateEvent>,
+    detail: &str,
+    monitoring_config: &MonitoringConfig,
+    queue: &BoundedQueue<Batch>,
+    state_file: &StateFile,
+) {
+    let Some(event) = event else {
+        return;
+    };
+    state_file.update(&event.inverter, true, |record| {
+        record.state = Some(event.to);
+        record.state_since = Some(event.time.timestamp());
+    });
+    if let Some(path) = &monitoring_config.fault_log {
+        fault_log::record(path, &event);
+    }
+    log::info!(
+        "[{}] State changed from {} to {} after {}s ({}{detail})",
+        event.inverter,
+        event.from,
+        event.to,
+        event.duration_secs,
+        event.reason
+    );
+    enqueue(
+        queue,
+        Batch {
+            inverter: event.inverter.clone(),
+            samples: vec![event.to_sample(&monitoring_config.event_measurement)],
+        },
+    );
+}
+
+/// Polling loop of one inverter, split into steps so it can run on its own
+/// thread or share a worker pool with other inverters.
+struct Poller {
     inverter_name: String,
     inverter_cfg: InverterConfig,
     monitoring_config: MonitoringConfig,
-) -> anyhow::Result<()>{
-    let mut inverter = loop {
-        match Inverter::new(
-            inverter_cfg.ip,
-            inverter_cfg.port,
-            std::time::Duration::from_secs(monitoring_config.timeout_secs.into()),
-        ) {
-            Ok(inv) => break inv,
+    queue: Arc<BoundedQueue<Batch>>,
+    state_file: Arc<StateFile>,
+    bridge: Option<Arc<Bridge>>,
+    archive: Option<Arc<Archive>>,
+    state: StateTracker,
+    /// Connected inverter, `None` until the logger serial is known
+    inverter: Option<Arc<Mutex<Inverter>>>,
+    smoother: Smoother,
+    ramp_rates: Option<RampRates>,
+    aggregator: Option<Aggregator>,
+    delta_filter: DeltaFilter,
+    /// Consecutive failed steps counted by the watchdog
+    failures: u32,
+    /// Skip polling until resumed
+    paused: bool,
+    /// Time of the newest reading written
+    last_reading: Option<chrono::DateTime<chrono::Utc>>,
+    /// When all values were last read, see `slow_intervall_secs`
+    full_read: Option<std::time::Instant>,
+    /// When the grid protection settings were last read
+    grid_settings_read: Option<std::time::Instant>,
+    /// When the registers were last dumped, see `periodic_register_dump`
+    register_dump: Option<std::time::Instant>,
+    /// Whether the firmware versions were read since the stick was last
+    /// unreachable
+    firmware_checked: bool,
+    mppt_stats: Option<MpptStats>,
+    availability: Option<Availability>,
+}
+
+impl Poller {
+    fn new(
+        inverter_name: String,
+        inverter_cfg: InverterConfig,
+        monitoring_config: MonitoringConfig,
+        queue: Arc<BoundedQueue<Batch>>,
+        state_file: Arc<StateFile>,
+        bridge: Option<Arc<Bridge>>,
+        archive: Option<Arc<Archive>>,
+    ) -> Self {
+        let record = state_file.get(&inverter_name);
+        let restored_since = record
+            .state_since
+            .and_then(|since| chrono::DateTime::from_timestamp(since, 0));
+        let state = match (record.state, restored_since) {
+            (Some(restored), Some(since)) => StateTracker::restore(&inverter_name, restored, since),
+            _ => StateTracker::new(&inverter_name),
+        };
+        let smoother = Smoother::new(monitoring_config.smoothing.clone());
+        let delta_filter = DeltaFilter::new(
+            monitoring_config.deltas.clone(),
+            monitoring_config.delta_heartbeat_secs,
+        );
+        let ramp_rates = monitoring_config
+            .ramp_rates
+            .then(|| RampRates::new(monitoring_config.intervall_secs));
+        let mppt_stats = monitoring_config.mppt_measurement.clone().map(MpptStats::new);
+        let availability = monitoring_config
+            .availability
+            .clone()
+            .map(|config| Availability::new(config, monitoring_config.intervall_secs));
+        let aggregator = monitoring_config
+            .write_intervall_secs
+            .filter(|secs| *secs > monitoring_config.intervall_secs)
+            .map(|secs| Aggregator::new(std::time::Duration::from_secs(secs.into())));
+        Poller {
+            inverter_name,
+            inverter_cfg,
+            monitoring_config,
+            queue,
+            state_file,
+            bridge,
+            archive,
+            state,
+            inverter: None,
+            smoother,
+            ramp_rates,
+            aggregator,
+            delta_filter,
+            failures: 0,
+            paused: false,
+            last_reading: None,
+            full_read: None,
+            grid_settings_read: None,
+            register_dump: None,
+            firmware_checked: false,
+            mppt_stats,
+            availability,
+        }
+    }
+
+    /// Connects or polls once and returns the delay until the next step,
+    /// `None` once the daemon should exit.
+    fn step(&mut self) -> Option<std::time::Duration> {
+        let interval = std::time::Duration::from_secs(self.monitoring_config.intervall_secs.into());
+        if self.paused {
+            let event = self.state.update(InverterState::Paused, "paused");
+            report_transition(event, "", &self.monitoring_config, &self.queue, &self.state_file);
+            // Failures before the pause don't count towards a reboot.
+            self.failures = 0;
+            if let Some(availability) = &mut self.availability {
+                availability.pause();
+            }
+            return Some(interval);
+        }
+        let finished_day = self
+            .availability
+            .as_mut()
+            .and_then(|availability| availability.step(&self.inverter_name, chrono::Local::now()));
+        if let Some(sample) = finished_day {
+            enqueue(
+                &self.queue,
+                Batch {
+                    inverter: self.inverter_name.clone(),
+                    samples: vec![sample],
+                },
+            );
+        }
+        let (success, delay) = match self.inverter.clone() {
+            Some(inverter) => (self.poll(&inverter), interval),
+            // Poll right away once connected.
+            None if self.connect() => (true, std::time::Duration::ZERO),
+            None => (false, std::time::Duration::from_millis(5000)),
+        };
+        self.watchdog(success);
+        if !success {
+            self.fill_in_from_cloud();
+        }
+        total_failure::record_inverter(&self.inverter_name, success);
+        if self.monitoring_config.on_total_failure.exit_due() {
+            log::error!("Every inverter and output is failing, exiting");
+            return None;
+        }
+        Some(delay)
+    }
+
+    /// Writes the values the inverter reported to the cloud instead of the
+    /// failed poll, see `live_fallback`.
+    fn fill_in_from_cloud(&self) {
+        let (Some(cloud_fallback), Some(serial)) = (solarman_cloud::live_fallback(), &self.inverter_cfg.cloud_serial) else {
+            return;
+        };
+        let input_names: Vec<_> = (0..self.inverter_cfg.mppt_inputs)
+            .map(|idx| char::from(b'A' + idx).to_string())
+            .collect();
+        let input_names: Vec<_> = input_names.iter().map(|input| self.inverter_cfg.input_name(input)).collect();
+        let fetched = cloud_fallback.fetch(&self.inverter_name, serial, &self.inverter_cfg.location, &input_names);
+        match fetched {
+            Ok(samples) if samples.is_empty() => {}
+            Ok(samples) => {
+                log::info!("[{}] Filling in the failed poll with values from the Solarman cloud", self.inverter_name);
+                enqueue(
+                    &self.queue,
+                    Batch {
+                        inverter: self.inverter_name.clone(),
+                        samples,
+                    },
+                );
+            }
+            Err(e) => log::warn!("[{}] Failed to fetch the values from the Solarman cloud ({e:#})", self.inverter_name),
+        }
+    }
+
+    /// Reboots the logger stick after too many consecutive failures during
+    /// daylight.
+    fn watchdog(&mut self, success: bool) {
+        let Some(watchdog) = &self.monitoring_config.watchdog else {
+            return;
+        };
+        let [from, to] = watchdog.daylight_hours;
+        // The sticks are powered by the PV inputs and go offline every night.
+        if success || !(from..to).contains(&chrono::Local::now().hour()) {
+            self.failures = 0;
+            return;
+        }
+        self.failures += 1;
+        if self.failures < watchdog.failures {
+            return;
+        }
+        log::warn!("[{}] {} failed polls in a row, rebooting the logger stick", self.inverter_name, self.failures);
+        self.failures = 0;
+        let rebooted = at_command::reboot(
+            self.inverter_cfg.ip,
+            at_command::PORT,
+            self.monitoring_config.timeouts().read,
+        );
+        if let Err(e) = rebooted {
+            log::error!(
+                "[{}] Failed to reboot the logger stick ({}: {e:#})",
+                self.inverter_name,
+                ErrorCode::of(&e)
+            );
+        }
+    }
+
+    /// Whether the inverter is connected now.
+    fn connect(&mut self) -> bool {
+        let result = connect_inverter(&self.inverter_cfg, &self.monitoring_config);
+        self.connected(result)
+    }
+
+    /// Takes over the result of a connection attempt, whether the inverter
+    /// is connected now.
+    fn connected(&mut self, result: anyhow::Result<Inverter>) -> bool {
+        let inverter_name = &self.inverter_name;
+        let record = self.state_file.get(inverter_name);
+        let inverter = match result {
+            Ok(inv) => inv,
             Err(e) => {
-		log::debug!("[{inverter_name}] Failed to connect to {}:{} ({e})",inverter_cfg.ip, inverter_cfg.port);
-		std::thread::sleep(std::time::Duration::from_millis(5000));
-		}
+                let code = ErrorCode::of(&e);
+                log::debug!("[{inverter_name}] Failed to connect to {}:{} ({code}: {e})",self.inverter_cfg.ip, self.inverter_cfg.port);
+                let Some(serial) = record.logger_serial else {
+                    let event = self
+                        .state
+                        .update(InverterState::Offline, "connect_failed")
+                        .map(|event| event.with_error_code(code));
+                    report_transition(event, &format!(": {code} {e}"), &self.monitoring_config, &self.queue, &self.state_file);
+                    return false;
+                };
+                // The first poll shows whether the stick is reachable after all.
+                log::info!("[{inverter_name}] Serial detection failed, using remembered logger serial {serial}");
+                self.inverter_cfg.configure(Inverter::with_serial(
+                    self.inverter_cfg.ip,
+                    self.inverter_cfg.port,
+                    self.monitoring_config.timeouts(),
+                    serial.to_le_bytes(),
+                ))
+            }
+        };
+        let serial = u32::from_le_bytes(inverter.logger_serial());
+        if record.logger_serial != Some(serial) {
+            if let Some(previous) = record.logger_serial {
+                log::info!("[{inverter_name}] Logger serial changed from {previous} to {serial}");
+            }
+            self.state_file.update(inverter_name, true, |record| record.logger_serial = Some(serial));
+        }
+        let inverter = Arc::new(Mutex::new(inverter));
+        if let Some(bridge) = &self.bridge {
+            bridge.register(inverter_name, inverter.clone());
         }
+        self.inverter = Some(inverter);
+        true
+    }
 
-    };
-    let sleep_dur = std::time::Duration::from_secs(monitoring_config.intervall_secs.into());
-    let client = loop {
-        if let Ok(client) = Client::new::<String, String>(
-            reqwest::Url::parse(&format!(
-                "http://{}:{}",
-                monitoring_config.influx_ip, monitoring_config.influx_port
-            ))
-            .context("Influxdb ip or port invalid")?,
-            None,
-        ) {
-            break client;
+    /// Whether the inverter answered.
+    fn poll(&mut self, inverter: &Mutex<Inverter>) -> bool {
+        let inverter_name = &self.inverter_name;
+        let trace_id = next_trace_id();
+        let full = self.full_read_due();
+        let (result, link_stats, reading_time, clock_skew_ms) = {
+            let mut inverter = inverter.lock().unwrap_or_else(PoisonError::into_inner);
+            inverter.set_log_prefix(format!("[{inverter_name} {trace_id}] "));
+            let result = if full { inverter.get_data() } else { inverter.get_fast_data() };
+            let responses = inverter.last_responses();
+            if let Some(archive) = self.archive.as_ref().filter(|_| !responses.is_empty()) {
+                archive.record(inverter_name, responses);
+            }
+            if let (Some(dump_cfg), Some(block)) = (&self.monitoring_config.periodic_register_dump, responses.first()) {
+                let interval = std::time::Duration::from_secs(dump_cfg.interval_secs);
+                if self.register_dump.is_none_or(|dumped| dumped.elapsed() >= interval) {
+                    dump_cfg.write(inverter_name, inverter.register_map(), block);
+                    self.register_dump = Some(std::time::Instant::now());
+                }
+            }
+            // Requests of the Modbus bridge are not part of the poll.
+            inverter.set_log_prefix("");
+            let reading_time = inverter.data_age().zip(inverter.reading_time());
+            (result, inverter.link_stats(), reading_time, inverter.clock_skew_ms())
+        };
+        if let Some(measurement) = &self.monitoring_config.link_measurement {
+            let mut sample = link_sample(measurement, inverter_name, &link_stats, clock_skew_ms);
+            sample.trace_id = Some(trace_id.clone());
+            enqueue(
+                &self.queue,
+                Batch {
+                    inverter: inverter_name.clone(),
+                    samples: vec![sample],
+                },
+            );
         }
-    };
-    loop {
-        let data = match inverter.get_data() {
+        let data = match result {
             Ok(data) => {
-                log::debug!("[{inverter_name}] Recieved data: {data:#?}");
+                log::debug!("[{inverter_name} {trace_id}] Recieved data: {data:#?}");
                 data
             }
             Err(e) => {
-                log::debug!("[{inverter_name}] Failed to recieve data ({e})");
-                std::thread::sleep(sleep_dur);
-                continue;
-            }
-        };
-
-	let power_a = data.voltage_a * data.current_a;
-	let power_b = data.voltage_b * data.current_b;
-        let lines = vec![
-            LineBuilder::new(inverter_cfg.location.clone())
-                .insert_field("voltage", data.voltage_a)
-                .insert_field("current", data.current_a)
-		.insert_field("power", power_a)
-                .insert_tag("inverter", inverter_name.clone())
-                .insert_tag("input", "A")
-                .build(),
-            LineBuilder::new(inverter_cfg.location.clone())
-                .insert_field("voltage", data.voltage_b)
-                .insert_field("current", data.current_b)
-		.insert_field("power", power_b)
-                .insert_tag("inverter", inverter_name.clone())
-                .insert_tag("input", "B")
-                .build(),
+                let code = ErrorCode::of(&e);
+                log::debug!("[{inverter_name} {trace_id}] Failed to recieve data ({code}: {e})");
+                let event = if state::is_connection_error(&e) {
+                    // An update restarts the stick.
+                    self.firmware_checked = false;
+                    self.state.update(InverterState::Offline, "unreachable")
+                } else {
+                    self.state.update(InverterState::Faulted, "invalid_response")
+                };
+                let event = event.map(|event| event.with_error_code(code));
+                report_transition(event, &format!(": {code} {e}"), &self.monitoring_config, &self.queue, &self.state_file);
+                self.smoother.reset();
+                return false;
+            }
+        };
+
+        let mut samples = to_samples(inverter_name, &self.inverter_cfg, &self.monitoring_config, &data);
+        if let Some(availability) = &mut self.availability {
+            availability.succeeded(chrono::Local::now());
+        }
+        if full {
+            self.full_read = Some(std::time::Instant::now());
+            let valuation = energy_stats::Valuation {
+                tariff: self.monitoring_config.tariff.as_ref(),
+                co2_intensity: self.monitoring_config.carbon.as_ref().and_then(|carbon| carbon.intensity()),
+            };
+            let mut totals = Vec::new();
+            self.state_file.update(inverter_name, false, |record| {
+                energy_stats::record(record, data.energy_total, chrono::Local::now(), &valuation);
+                if valuation.tariff.is_some() {
+                    let (savings, earnings) = energy_stats::value_totals(record);
+                    totals.push(("savings_total".to_owned(), savings));
+                    totals.push(("earnings_total".to_owned(), earnings));
+                }
+                if self.monitoring_config.carbon.is_some() {
+                    totals.push(("co2_avoided_total".to_owned(), energy_stats::co2_total(record)));
+                }
+            });
+            if let Some(totals_sample) = samples.last_mut() {
+                totals_sample.fields.extend(totals);
+            }
+        } else if let Some(totals) = samples.last_mut() {
+            // The other values are the ones of the last full read.
+            totals.fields.retain(|(name, _)| FAST_FIELDS.contains(&name.as_str()));
+        }
+        let availability = self.availability.as_ref().and_then(Availability::ratio);
+        if let (Some(totals), Some(availability)) = (samples.last_mut(), availability) {
+            totals.fields.push(("availability".to_owned(), availability));
+        }
+        for sample in &mut samples {
+            sample.trace_id = Some(trace_id.clone());
+        }
+        if let Some(mppt_stats) = &mut self.mppt_stats {
+            let daily = mppt_stats.push(inverter_name, &self.inverter_cfg, &data, chrono::Local::now());
+            if !daily.is_empty() {
+                enqueue(
+                    &self.queue,
+                    Batch {
+                        inverter: inverter_name.clone(),
+                        samples: daily,
+                    },
+                );
+            }
+        }
+        let reading_time = reading_time.filter(|_| self.monitoring_config.stick_clock);
+        if let Some((age, time)) = reading_time {
+            log::debug!("[{inverter_name} {trace_id}] Reading is {}s old according to the logger stick", age.as_secs());
+            for sample in &mut samples {
+                sample.time = time.into();
+            }
+        }
+        if self.inverter_cfg.wifi_signal {
+            let quality = at_command::signal_quality(
+                self.inverter_cfg.ip,
+                at_command::PORT,
+                self.monitoring_config.timeouts().read,
+            );
+            match (quality, samples.last_mut()) {
+                // The totals sample comes last.
+                (Ok(quality), Some(totals)) => {
+                    totals.fields.push(("wifi_signal".to_owned(), f64::from(quality)));
+                }
+                (Ok(_), None) => {}
+                (Err(e), _) => {
+                    let code = ErrorCode::of(&e);
+                    log::debug!("[{inverter_name} {trace_id}] Failed to read WiFi signal ({code}: {e:#})");
+                }
+            }
+        }
+        let producing = samples
+            .iter()
+            .flat_map(|sample| &sample.fields)
+            .any(|(name, value)| name == "power" && *value > 0.0);
+        let event = if producing {
+            self.state.update(InverterState::Producing, "power")
+        } else {
+            self.state.update(InverterState::Idle, "no_power")
+        };
+        // A reading dated back to the time of an earlier one is the same
+        // reading returned again, it must not count twice in the aggregates.
+        let time = samples.last().map(|sample| sample.time);
+        let repeated = reading_time.is_some()
+            && time.is_some_and(|time| self.last_reading.is_some_and(|last| time <= last));
+        if repeated {
+            log::debug!("[{inverter_name} {trace_id}] Skipping reading that was already written");
+        }
+        self.last_reading = time.max(self.last_reading);
+        // The state follows the raw values so it changes without delay.
+        let samples = if repeated {
+            None
+        } else {
+            // From the raw values, smoothing would flatten the ramps.
+            if let Some(ramp_rates) = &mut self.ramp_rates {
+                ramp_rates.apply(&mut samples);
+            }
+            self.smoother.apply(&mut samples);
+            match &mut self.aggregator {
+                Some(aggregator) => aggregator.push(samples),
+                None => Some(samples),
+            }
+        };
+        let samples = samples
+            .map(|samples| self.delta_filter.apply(samples))
+            .filter(|samples| !samples.is_empty());
+        if let Some(samples) = samples {
+            enqueue(
+                &self.queue,
+                Batch {
+                    inverter: inverter_name.clone(),
+                    samples,
+                },
+            );
+        }
+        self.state_file.update(inverter_name, false, |record| {
+            record.last_success = Some(chrono::Utc::now().timestamp());
+        });
+        report_transition(event, "", &self.monitoring_config, &self.queue, &self.state_file);
+        self.read_grid_settings(inverter, &trace_id);
+        self.check_firmware(inverter, &trace_id);
+        true
+    }
+}
+
+impl Poller {
+    /// Whether the next poll reads all values instead of only the fast
+    /// changing ones.
+    fn full_read_due(&self) -> bool {
+        let slow_interval = self
+            .monitoring_config
+            .slow_intervall_secs
+            .filter(|secs| *secs > self.monitoring_config.intervall_secs);
+        match (slow_interval, self.full_read) {
+            // Polls run late rather than early, so half a poll interval
+            // early is on time.
+            (Some(secs), Some(read)) => {
+                let interval = std::time::Duration::from_secs(self.monitoring_config.intervall_secs.into());
+                read.elapsed() + interval / 2 >= std::time::Duration::from_secs(secs.into())
+            }
+            _ => true,
+        }
+    }
+
+    /// Writes the grid protection settings after connecting and then once a
+    /// day, if `grid_settings_measurement` is set.
+    fn read_grid_settings(&mut self, inverter: &Mutex<Inverter>, trace_id: &str) {
+        let Some(measurement) = &self.monitoring_config.grid_settings_measurement else {
+            return;
+        };
+        let due = self
+            .grid_settings_read
+            .is_none_or(|read| read.elapsed() >= std::time::Duration::from_secs(24 * 3600));
+        if !due {
+            return;
+        }
+        let inverter_name = &self.inverter_name;
+        // Unknown registers are never guessed.
+        let Some(registers) = self.inverter_cfg.grid_protection.registers() else {
+            return;
+        };
+        let settings = inverter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .grid_protection(&registers);
+        // Retried with the next poll on failure.
+        match settings {
+            Ok(settings) => {
+                log::debug!("[{inverter_name} {trace_id}] Grid protection settings: {settings:#?}");
+                self.grid_settings_read = Some(std::time::Instant::now());
+                let mut sample = grid_settings::sample(measurement, inverter_name, &settings);
+                sample.trace_id = Some(trace_id.to_owned());
+                enqueue(
+                    &self.queue,
+                    Batch {
+                        inverter: inverter_name.clone(),
+                        samples: vec![sample],
+                    },
+                );
+            }
+            Err(e) => {
+                let code = ErrorCode::of(&e);
+                log::debug!("[{inverter_name} {trace_id}] Failed to read grid protection settings ({code}: {e:#})");
+            }
+        }
+    }
+}
+
+impl Poller {
+    /// Reads the firmware versions after connecting and whenever the stick
+    /// comes back after being unreachable and compares them with the ones
+    /// read before, if `firmware_measurement` is set. Versions that could
+    /// not be read are tried again with the next reconnect.
+    fn check_firmware(&mut self, inverter: &Mutex<Inverter>, trace_id: &str) {
+        let Some(measurement) = &self.monitoring_config.firmware_measurement else {
+            return;
+        };
+        if self.firmware_checked {
+            return;
+        }
+        self.firmware_checked = true;
+        let inverter_name = &self.inverter_name;
+        let firmware_cfg = &self.inverter_cfg.firmware;
+        let record = self.state_file.get(inverter_name);
+        let inverter_version =
+            firmware_cfg.read_inverter(&mut inverter.lock().unwrap_or_else(PoisonError::into_inner));
+        let stick_version = firmware_cfg.stick().then(|| {
+            at_command::firmware_version(
+                self.inverter_cfg.ip,
+                at_command::PORT,
+                self.monitoring_config.timeouts().read,
+            )
+        });
+        let versions = [
+            (firmware::Component::Inverter, inverter_version, record.inverter_firmware),
+            (firmware::Component::Stick, stick_version, record.stick_firmware),
         ];
+        let mut samples = Vec::new();
+        for (component, version, previous) in versions {
+            let name = component.name();
+            let version = match version {
+                Some(Ok(version)) => version,
+                Some(Err(e)) => {
+                    let code = ErrorCode::of(&e);
+                    log::debug!("[{inverter_name} {trace_id}] Failed to read {name} firmware version ({code}: {e:#})");
+                    continue;
+                }
+                None => continue,
+            };
+            match previous.as_deref() {
+                Some(previous) if previous != version => {
+                    log::warn!("[{inverter_name}] Firmware of the {name} changed from {previous} to {version}");
+                }
+                Some(_) => {}
+                None => log::info!("[{inverter_name}] Firmware of the {name} is {version}"),
+            }
+            let mut sample =
+                firmware::sample(measurement, inverter_name, component, &version, previous.as_deref());
+            sample.trace_id = Some(trace_id.to_owned());
+            samples.push(sample);
+            self.state_file.update(inverter_name, true, |record| match component {
+                firmware::Component::Inverter => record.inverter_firmware = Some(version),
+                firmware::Component::Stick => record.stick_firmware = Some(version),
+            });
+        }
+        if !samples.is_empty() {
+            enqueue(
+                &self.queue,
+                Batch {
+                    inverter: inverter_name.clone(),
+                    samples,
+                },
+            );
+        }
+    }
+}
 
-        if client.send(&monitoring_config.database, &lines).is_err() {
-            log::error!("[{inverter_name}] Failed to store data in database");
+/// Polls every inverter a single time and stores the results. Fails if any
+/// inverter could not be read or stored.
+fn run_once(config: Config) -> anyhow::Result<()> {
+    let mut outputs = output::create_sinks(&config)?;
+    let mut failed = Vec::new();
+    for (inverter_name, inverter_cfg) in &config.inverter {
+        let data = connect_inverter(inverter_cfg, &config.monitoring)
+            .and_then(|mut inverter| inverter.get_data());
+        let stored = match data {
+            Ok(data) => {
+                let samples = to_samples(inverter_name, inverter_cfg, &config.monitoring, &data);
+                outputs.write_all(&samples, inverter_name)
+            }
+            Err(e) => {
+                log::error!("[{inverter_name}] Failed to recieve data ({}: {e:#})", ErrorCode::of(&e));
+                false
+            }
+        };
+        if !stored {
+            failed.push(inverter_name.as_str());
         }
-        std::thread::sleep(sleep_dur)
     }
+    let flushed = outputs.flush_all("once");
+    if !failed.is_empty() {
+        anyhow::bail!("Polling failed for {}", failed.join(", "));
+    }
+    if !flushed {
+        anyhow::bail!("Failed to store the buffered data");
+    }
+    touch_status_file(&config.monitoring);
+    Ok(())
 }
-fn main() -> anyhow::Result<()> {
-    let config_str =
-        std::fs::read_to_string("config.toml").context("Failed to read config file.")?;
 
-    let config: Config = toml::from_str(&config_str).context("Failed to parse config file.")?;
+/// Checks that a running daemon stored data recently, judged by the status file.
+fn healthcheck(config: &Config, max_age_secs: Option<u64>) -> anyhow::Result<()> {
+    let path = config
+        .monitoring
+        .status_file
+        .as_ref()
+        .context("healthcheck requires monitoring.status_file to be configured")?;
+    let last_write: i64 = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read status file {}", path.display()))?
+        .trim()
+        .parse()
+        .context("Status file is corrupt")?;
+    let max_age = max_age_secs.unwrap_or(3 * u64::from(config.monitoring.intervall_secs));
+    let age = chrono::Utc::now().timestamp().saturating_sub(last_write);
+    if age > i64::try_from(max_age).unwrap_or(i64::MAX) {
+        anyhow::bail!("Last successful write was {age}s ago (limit {max_age}s)");
+    }
+    Ok(())
+}
+
+fn load_config(path: &Path) -> anyhow::Result<Config> {
+    let config_str = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}.", path.display()))?;
+    let mut config = parse_config(&config_str)?;
+    path.clone_into(&mut config.path);
+    Ok(config)
+}
+
+/// Parses and validates a config without loading it, e.g. before it is
+/// saved from the web UI.
+fn parse_config(config_str: &str) -> anyhow::Result<Config> {
+    let mut config: Config = toml::from_str(config_str).context("Failed to parse config file.")?;
+    if config.secrets.is_some() {
+        // Parsed again with the secrets filled in, the first pass points
+        // to the line of an error.
+        let mut table: toml::Table = toml::from_str(config_str)?;
+        secrets::resolve(&mut table)?;
+        config = toml::Value::from(table).try_into().context("Failed to parse config file.")?;
+    }
+    log::Level::from_str(&config.log_level)
+        .with_context(|| format!("Invalid log_level {}", config.log_level))?;
+    for (name, inverter_cfg) in &config.inverter {
+        validate_inverter(&config, name, inverter_cfg)?;
+    }
+    if let Some(carbon) = &config.monitoring.carbon {
+        carbon.validate()?;
+    }
+    output::validate(&config)?;
+    Ok(config)
+}
+
+fn validate_inverter(
+    config: &Config,
+    name: &str,
+    inverter_cfg: &InverterConfig,
+) -> anyhow::Result<()> {
+    inverter_cfg.encryption_key().with_context(|| format!("Invalid inverter {name}"))?;
+    let live_fallback = config.solarman.as_ref().and_then(|cloud| cloud.live_fallback.as_ref());
+    if inverter_cfg.cloud_serial.is_some() && live_fallback.is_none() {
+        anyhow::bail!("Inverter {name} has a cloud_serial, which needs [solarman.live_fallback]");
+    }
+    if inverter_cfg.deye_cloud_serial.is_some() && config.deye_cloud.is_none() {
+        anyhow::bail!("Inverter {name} has a deye_cloud_serial, which needs [deye_cloud]");
+    }
+    inverter_cfg.grid_protection.validate().with_context(|| format!("Invalid inverter {name}"))?;
+    let derating_bits = inverter_cfg.derating.iter().flat_map(|derating| &derating.bits);
+    if let Some((reason, bit)) = derating_bits.into_iter().find(|(_, bit)| **bit > 15) {
+        anyhow::bail!("Inverter {name} has derating bit {bit} for {reason}, registers have 16 bits");
+    }
+    let count = inverter_cfg
+        .register_map()
+        .with_context(|| format!("Invalid inverter {name}"))?
+        .count;
+    if count > MAX_REGISTERS {
+        let separate_meter = InverterConfig {
+            strict_snapshot: false,
+            ..inverter_cfg.clone()
+        };
+        if inverter_cfg.strict_snapshot && separate_meter.register_map()?.count <= MAX_REGISTERS {
+            anyhow::bail!(
+                "Inverter {name} has strict_snapshot = true, which reads the grid_meter registers \
+                 together with the others: {count} registers at once, at most {MAX_REGISTERS} \
+                 are possible. Set strict_snapshot = false to read the meter separately"
+            );
+        }
+        anyhow::bail!(
+            "Inverter {name} would read {count} registers at once, at most {MAX_REGISTERS} are \
+             possible. The derating or insulation registers are too far from the others"
+        );
+    }
+    Ok(())
+}
+
+/// Appends an `[inverter.<name>]` table to the config file, leaving the
+/// rest of the file untouched.
+fn persist_inverter(path: &Path, name: &str, inverter_cfg: &InverterConfig) -> anyhow::Result<()> {
+    let mut values =
+        toml::Table::try_from(inverter_cfg).context("Failed to serialize inverter config")?;
+    // Unset options would show up as empty tables.
+    values.retain(|_, value| value.as_table().is_none_or(|table| !table.is_empty()));
+    let table = toml::to_string(&HashMap::from([("inverter", HashMap::from([(name, values)]))]))
+        .context("Failed to serialize inverter config")?;
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open config file {}", path.display()))?;
+    std::io::Write::write_all(&mut file, format!("\n{table}").as_bytes())
+        .with_context(|| format!("Failed to write config file {}", path.display()))
+}
+
+/// Loads the config and sets up the process wide state shared by all
+/// commands, the daemon and the Windows service.
+fn init(cli: &mut cli::Cli) -> anyhow::Result<Config> {
+    let mut config = load_config(&cli.config)?;
+    i18n::init(config.language);
+    paths::apply(&mut config, cli)?;
+    audit::init(&config)?;
+    solarman_cloud::init_live_fallback(&config);
+    if let Some(max) = config.monitoring.max_connections {
+        connection_limit::set_max_connections(max);
+    }
+    Ok(config)
+}
+
+fn init_logging(config: &Config) -> anyhow::Result<()> {
     simple_logger::init_with_level(log::Level::from_str(&config.log_level)?)
-        .context("Failed to init logging")?;
-    let mut handles = Vec::new();
-    for inverter_cfg in config.inverter {
-        let mon_cfg = config.monitoring.clone();
-        handles.push(std::thread::spawn(move || {
-            run_monitoring(inverter_cfg.0, inverter_cfg.1, mon_cfg)
-        }));
+        .context("Failed to init logging")
+}
+
+/// Drains the queue filled by the pollers, so slow outputs don't delay polling.
+fn run_writer(
+    queue: &BoundedQueue<Batch>,
+    mut outputs: Outputs,
+    monitoring_config: &MonitoringConfig,
+) {
+    while let Some(batch) = queue.pop() {
+        if outputs.write_all(&batch.samples, &batch.inverter) {
+            touch_status_file(monitoring_config);
+        }
+    }
+    // The queue is closed, the daemon is about to exit.
+    outputs.flush_all("shutdown");
+}
+
+fn run_daemon(config: Config, mut locks: InstanceLocks) -> anyhow::Result<()> {
+    let queue = Arc::new(BoundedQueue::new(
+        config.monitoring.queue_size,
+        config.monitoring.queue_overflow,
+    ));
+    let (control, commands) = control::Control::channel();
+    let outputs = output::create_outputs(&config, &control)?;
+    let databases = outputs.databases();
+    let writer_queue = queue.clone();
+    let writer_cfg = config.monitoring.clone();
+    let writer = std::thread::spawn(move || run_writer(&writer_queue, outputs, &writer_cfg));
+
+    let state_file = Arc::new(StateFile::load(config.monitoring.state_file.as_deref())?);
+    let bridge = match &config.bridge {
+        Some(bridge_cfg) => {
+            let inverters: Vec<_> = config.inverter.keys().map(String::as_str).collect();
+            Some(Bridge::start(bridge_cfg, &inverters, config.writes.clone())?)
+        }
+        None => None,
+    };
+    if let Some(listen_cfg) = &config.listen {
+        listen::start(listen_cfg, &config, queue.clone(), state_file.clone())?;
+    }
+    if let Some(deye_cloud_cfg) = &config.deye_cloud {
+        deye_cloud::start(deye_cloud_cfg, &config, queue.clone(), state_file.clone())?;
     }
 
-    for handle in handles {
-        if let Err(e) = handle.join().expect("monitoring threads do not panic"){
-            log::error!("Thread exited unexpectedly: {e}");
+    let archive = config.archive.as_ref().map(Archive::open).transpose()?.map(Arc::new);
+    let new_poller = |inverter_name: String, inverter_cfg: InverterConfig| {
+        Poller::new(
+            inverter_name,
+            inverter_cfg,
+            config.monitoring.clone(),
+            queue.clone(),
+            state_file.clone(),
+            bridge.clone(),
+            archive.clone(),
+        )
+    };
+    let mut inverters: Vec<_> = config
+        .inverter
+        .clone()
+        .into_iter()
+        .filter(|(_, inverter_cfg)| {
+            !inverter_cfg.listen_only && inverter_cfg.deye_cloud_serial.is_none()
+        })
+        .collect();
+    inverters.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let offsets = start_offsets(&config.monitoring, &inverters);
+    // Position of every poller in the scheduler by inverter name
+    let mut indices = HashMap::new();
+    let mut pollers: Vec<_> = inverters
+        .into_iter()
+        .zip(offsets)
+        .enumerate()
+        .map(|(index, ((inverter_name, inverter_cfg), offset))| {
+            if !offset.is_zero() {
+                log::debug!("[{inverter_name}] First poll in {} s", offset.as_secs_f64());
+            }
+            indices.insert(inverter_name.clone(), index);
+            (new_poller(inverter_name, inverter_cfg), offset)
+        })
+        .collect();
+    connect_all(&mut pollers, &config.monitoring);
+    // Without a pool every inverter gets a thread of its own.
+    let threads = config.monitoring.worker_threads;
+    log::info!(
+        "Polling {} inverters from {} threads",
+        pollers.len(),
+        threads.unwrap_or(pollers.len()).clamp(1, pollers.len().max(1))
+    );
+    let scheduler = scheduler::Scheduler::new(pollers);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let commands = commands;
+            while !scheduler.stopped() {
+                let request = match commands.recv_timeout(std::time::Duration::from_secs(1)) {
+                    Ok(request) => request,
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                };
+                let origin = &request.origin;
+                let result = match request.command {
+                    control::Command::AddInverter(add) => {
+                        let action =
+                            audit::Action::new(origin, "add_inverter").inverter(&add.name);
+                        let result = add_inverter(
+                            &config,
+                            &mut indices,
+                            &mut locks,
+                            &scheduler,
+                            &databases,
+                            new_poller,
+                            *add,
+                        );
+                        action.record(&result);
+                        result
+                    }
+                    control::Command::Pause(name) => {
+                        let result = set_paused(&indices, &scheduler, &name, true);
+                        audit::Action::new(origin, "pause").inverter(&name).record(&result);
+                        result
+                    }
+                    control::Command::Resume(name) => {
+                        let result = set_paused(&indices, &scheduler, &name, false);
+                        audit::Action::new(origin, "resume").inverter(&name).record(&result);
+                        result
+                    }
+                };
+                // The requester may have given up waiting.
+                let _ = request.reply.send(result);
+            }
+        });
+        scheduler.run(threads, Poller::step);
+    });
+
+    // Only stopped by on_total_failure, write what was polled before.
+    queue.close();
+    let _ = writer.join();
+    state_file.flush();
+    anyhow::bail!("Every inverter and output is failing")
+}
+
+/// Connects to all inverters at once, so a few unreachable ones don't hold
+/// up the others, and logs how many are reachable. Attempts still running
+/// after `startup_timeout_secs` are abandoned, these pollers connect with
+/// their first step.
+fn connect_all(pollers: &mut [(Poller, std::time::Duration)], monitoring_config: &MonitoringConfig) {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for (index, (poller, _)) in pollers.iter().enumerate() {
+        let sender = sender.clone();
+        let inverter_cfg = poller.inverter_cfg.clone();
+        let monitoring_config = poller.monitoring_config.clone();
+        std::thread::spawn(move || {
+            // Nobody waits for the result after the deadline.
+            let _ = sender.send((index, connect_inverter(&inverter_cfg, &monitoring_config)));
+        });
+    }
+    drop(sender);
+    let deadline = std::time::Instant::now()
+        + std::time::Duration::from_secs(monitoring_config.startup_timeout_secs.into());
+    let mut answered = vec![false; pollers.len()];
+    let mut unreachable = Vec::new();
+    while let Ok((index, result)) =
+        receiver.recv_timeout(deadline.saturating_duration_since(std::time::Instant::now()))
+    {
+        let poller = &mut pollers[index].0;
+        answered[index] = true;
+        if result.is_err() {
+            unreachable.push(poller.inverter_name.clone());
         }
+        poller.connected(result);
+    }
+    let pending: Vec<_> = pollers
+        .iter()
+        .zip(answered)
+        .filter(|(_, answered)| !answered)
+        .map(|((poller, _), _)| poller.inverter_name.clone())
+        .collect();
+    let reachable = pollers.len() - unreachable.len() - pending.len();
+    log::info!("{reachable} of {} inverters reachable at startup", pollers.len());
+    if !unreachable.is_empty() {
+        log::warn!("Unreachable at startup: {}", unreachable.join(", "));
+    }
+    if !pending.is_empty() {
+        log::warn!("Still connecting after {} s: {}", monitoring_config.startup_timeout_secs, pending.join(", "));
     }
+}
+
+/// Delay of the first poll of every inverter. Inverters without
+/// `start_offset_secs` start right away or, with `stagger_polls`, one after
+/// the other spread over the poll interval.
+fn start_offsets(
+    monitoring_config: &MonitoringConfig,
+    inverters: &[(String, InverterConfig)],
+) -> Vec<std::time::Duration> {
+    let interval = std::time::Duration::from_secs(monitoring_config.intervall_secs.into());
+    let staggered = inverters.iter().filter(|(_, cfg)| cfg.start_offset_secs.is_none()).count();
+    let mut position = 0;
+    inverters
+        .iter()
+        .map(|(_, inverter_cfg)| match inverter_cfg.start_offset_secs {
+            Some(secs) => std::time::Duration::from_secs(secs.into()),
+            None if monitoring_config.stagger_polls => {
+                let offset = interval * position / staggered as u32;
+                position += 1;
+                offset
+            }
+            None => std::time::Duration::ZERO,
+        })
+        .collect()
+}
+
+/// Starts polling an inverter registered at runtime.
+fn add_inverter(
+    config: &Config,
+    indices: &mut HashMap<String, usize>,
+    locks: &mut InstanceLocks,
+    scheduler: &scheduler::Scheduler<Poller>,
+    databases: &output::Databases,
+    new_poller: impl Fn(String, InverterConfig) -> Poller,
+    add: control::AddInverter,
+) -> anyhow::Result<()> {
+    if add.name.is_empty() {
+        anyhow::bail!("Inverter name must not be empty");
+    }
+    if indices.contains_key(&add.name) {
+        anyhow::bail!("Inverter {} already exists", add.name);
+    }
+    validate_inverter(config, &add.name, &add.config)?;
+    locks.add(&add.name, &add.config)?;
+    if add.persist {
+        persist_inverter(&config.path, &add.name, &add.config)?;
+    }
+    if let Some(database) = &add.config.database {
+        databases
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(add.name.clone(), database.clone());
+    }
+    log::info!("[{}] Added inverter at {}:{}", add.name, add.config.ip, add.config.port);
+    let offset = std::time::Duration::from_secs(add.config.start_offset_secs.unwrap_or(0).into());
+    let index = scheduler.add(new_poller(add.name.clone(), add.config), offset);
+    indices.insert(add.name, index);
+    Ok(())
+}
 
+/// Pauses or resumes polling an inverter, it keeps its connection.
+fn set_paused(
+    indices: &HashMap<String, usize>,
+    scheduler: &scheduler::Scheduler<Poller>,
+    name: &str,
+    paused: bool,
+) -> anyhow::Result<()> {
+    let index = *indices.get(name).with_context(|| format!("Unknown inverter {name}"))?;
+    scheduler.update(index, |poller| poller.paused = paused);
+    if !paused {
+        log::info!("[{name}] Polling resumed");
+    }
     Ok(())
 }
+
+fn main() -> anyhow::Result<()> {
+    let mut cli = cli::Cli::parse();
+
+    #[cfg(windows)]
+    {
+        if cli.install_service {
+            return service::install(&cli);
+        }
+        if cli.uninstall_service {
+            return service::uninstall();
+        }
+        if cli.run_as_service {
+            return service::run();
+        }
+    }
+
+    match &cli.command {
+        Some(cli::Command::DecodePcap { file }) => return pcap::decode(file),
+        Some(cli::Command::Completions { shell }) => return completions::print_completions(*shell),
+        Some(cli::Command::Man) => return completions::print_man_page(),
+        Some(cli::Command::Setup) => return setup::setup(&cli.config),
+        Some(cli::Command::Secrets(command)) => return secrets::run(&cli.config, command),
+        _ => {}
+    }
+    let config = init(&mut cli)?;
+    match cli.command {
+        Some(cli::Command::Healthcheck { max_age_secs }) => {
+            return healthcheck(&config, max_age_secs);
+        }
+        Some(cli::Command::Export {
+            from,
+            to,
+            inverters,
+            format,
+            output,
+        }) => {
+            init_logging(&config)?;
+            let to = to.unwrap_or_else(chrono::Utc::now);
+            let format = format.unwrap_or(match cli.output_format {
+                table::OutputFormat::Json => export::ExportFormat::Json,
+                table::OutputFormat::Csv | table::OutputFormat::Table => export::ExportFormat::Csv,
+            });
+            return export::export(&config, &inverters, from, to, format, output.as_deref());
+        }
+        Some(cli::Command::ImportSolarman { from, to }) => {
+            init_logging(&config)?;
+            let to = to.unwrap_or_else(|| chrono::Local::now().date_naive());
+            return solarman_cloud::import(&config, from, to);
+        }
+        Some(cli::Command::Reprocess { from, to, inverters }) => {
+            init_logging(&config)?;
+            let to = to.unwrap_or_else(|| chrono::Utc::now().date_naive());
+            return archive::reprocess(&config, from, to, &inverters);
+        }
+        Some(cli::Command::GridSettings { inverters }) => {
+            init_logging(&config)?;
+            return grid_settings::report(&config, &inverters, cli.output_format);
+        }
+        Some(cli::Command::Faults(cli::FaultsCommand::Export {
+            from,
+            to,
+            inverters,
+            format,
+            output,
+        })) => {
+            return fault_log::export(&config, &inverters, from, to, format, output.as_deref());
+        }
+        Some(cli::Command::Stats { inverters, yearly }) => {
+            return energy_stats::report(&config, &inverters, yearly, cli.output_format);
+        }
+        Some(cli::Command::Watch {
+            inverters,
+            interval_secs,
+        }) => {
+            init_logging(&config)?;
+            let interval = std::time::Duration::from_secs(interval_secs);
+            return watch::watch(&config, &inverters, interval, cli.output_format);
+        }
+        Some(cli::Command::Selftest { measurement }) => {
+            init_logging(&config)?;
+            return selftest::selftest(&config, measurement.as_deref(), cli.output_format);
+        }
+        Some(cli::Command::EfficiencyCurve {
+            inverter,
+            duration_secs,
+            interval_ms,
+            output,
+        }) => {
+            init_logging(&config)?;
+            return efficiency::log_efficiency_curve(
+                &config,
+                &inverter,
+                std::time::Duration::from_secs(duration_secs),
+                std::time::Duration::from_millis(interval_ms),
+                output.as_deref(),
+            );
+        }
+        Some(cli::Command::WriteRegister {
+            inverter,
+            register,
+            values,
+            force,
+        }) => {
+            init_logging(&config)?;
+            let origin = audit::Origin::cli();
+            return writes::write_registers(&config, &origin, &inverter, register, &values, force);
+        }
+        Some(cli::Command::SetGridStandard {
+            inverter,
+            standard,
+            confirm,
+            force,
+        }) => {
+            init_logging(&config)?;
+            let origin = audit::Origin::cli();
+            return grid_settings::set_standard(
+                &config,
+                &origin,
+                &inverter,
+                standard,
+                confirm.as_deref(),
+                force,
+            );
+        }
+        Some(cli::Command::Pause(remote)) => {
+            return control::send_remote(&config, &remote, "pause");
+        }
+        Some(cli::Command::Resume(remote)) => {
+            return control::send_remote(&config, &remote, "resume");
+        }
+        // Runs before the config is loaded
+        Some(
+            cli::Command::DecodePcap { .. }
+            | cli::Command::Completions { .. }
+            | cli::Command::Man
+            | cli::Command::Setup
+            | cli::Command::Secrets(_),
+        )
+        | None => {}
+    }
+    // Taken before detaching, so a second instance fails in the terminal.
+    let locks = InstanceLocks::acquire(&config)?;
+    if cli.once {
+        init_logging(&config)?;
+        return run_once(config);
+    }
+    #[cfg(unix)]
+    if cli.daemon {
+        service::daemonize(&cli)?;
+    }
+    init_logging(&config)?;
+    run_daemon(config, locks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOLARMAN: &str = r#"
+[solarman]
+app_id = "1"
+app_secret = "secret"
+email = "user@example.com"
+password = "password"
+station_id = 1
+"#;
+
+    #[test]
+    fn cloud_serial_needs_live_fallback() {
+        let inverter = "[monitoring]\ninflux_ip = \"127.0.0.1\"\nintervall_secs = 60\n\n\
+                        [inverter.roof]\ncloud_serial = \"2306123456\"\n";
+        let e = parse_config(&format!("{inverter}{SOLARMAN}")).unwrap_err();
+        assert!(e.to_string().contains("[solarman.live_fallback]"), "{e:#}");
+        assert!(parse_config(inverter).is_err());
+        parse_config(&format!("{inverter}{SOLARMAN}[solarman.live_fallback]\n")).unwrap();
+    }
+
+    #[test]
+    fn deye_cloud_serial_needs_deye_cloud() {
+        let inverter = "[monitoring]\ninflux_ip = \"127.0.0.1\"\nintervall_secs = 60\n\n\
+                        [inverter.roof]\ndeye_cloud_serial = \"2306123456\"\n";
+        let e = parse_config(inverter).unwrap_err();
+        assert!(e.to_string().contains("[deye_cloud]"), "{e:#}");
+        let deye_cloud = "[deye_cloud]\napp_id = \"1\"\napp_secret = \"secret\"\n\
+                          email = \"user@example.com\"\npassword = \"password\"\n";
+        parse_config(&format!("{inverter}{deye_cloud}")).unwrap();
+    }
+
+    #[test]
+    fn grid_protection_needs_all_limits() {
+        let inverter = "[monitoring]\ninflux_ip = \"127.0.0.1\"\nintervall_secs = 60\n\n\
+                        [inverter.roof.grid_protection]\n";
+        let config = parse_config(inverter).unwrap();
+        assert!(config.inverter["roof"].grid_protection.registers().is_none());
+        assert!(parse_config(&format!("{inverter}over_voltage = 0x1b\n")).is_err());
+        let limits = "over_voltage = 0x1b\nunder_voltage = 0x1c\nover_frequency = 0x1d\n\
+                      under_frequency = 0x1e\nreconnect_delay = 0x1f\n";
+        let config = parse_config(&format!("{inverter}{limits}")).unwrap();
+        assert!(config.inverter["roof"].grid_protection.registers().is_some());
+    }
+
+    #[test]
+    fn rejects_registers_past_the_last_address() {
+        let inverter = "[monitoring]\ninflux_ip = \"127.0.0.1\"\nintervall_secs = 60\n\n\
+                        [inverter.roof.derating]\nbits = { temperature = 0 }\n";
+        let e = parse_config(&format!("{inverter}register = 0xffff\n")).unwrap_err();
+        assert!(format!("{e:#}").contains("last register address"), "{e:#}");
+    }
+
+    #[test]
+    fn names_the_cause_of_too_many_registers() {
+        let inverter = "[monitoring]\ninflux_ip = \"127.0.0.1\"\nintervall_secs = 60\n\n\
+                        [inverter.roof]\n";
+        let meter = "[inverter.roof.grid_meter]\npower = 0x200\n";
+        parse_config(&format!("{inverter}{meter}")).unwrap();
+        let e = parse_config(&format!("{inverter}strict_snapshot = true\n{meter}")).unwrap_err();
+        assert!(e.to_string().contains("strict_snapshot = false"), "{e:#}");
+
+        let derating = "[inverter.roof.derating]\nregister = 0x200\nbits = { temperature = 0 }\n";
+        let e = parse_config(&format!("{inverter}strict_snapshot = true\n{derating}")).unwrap_err();
+        assert!(e.to_string().contains("derating"), "{e:#}");
+        assert!(!e.to_string().contains("strict_snapshot"), "{e:#}");
+    }
+}
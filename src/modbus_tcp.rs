@@ -0,0 +1,68 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+/// Exception code for requests to unit ids without device behind them.
+pub(crate) const GATEWAY_TARGET_FAILED: u8 = 0x0B;
+
+/// Turns the unit id and PDU of a request into the response PDU.
+type Handler = dyn Fn(u8, &[u8]) -> Vec<u8> + Send + Sync;
+
+/// Exception response to the request `pdu`.
+pub(crate) fn exception(pdu: &[u8], code: u8) -> Vec<u8> {
+    vec![pdu.first().copied().unwrap_or_default() | 0x80, code]
+}
+
+fn serve_client(
+    mut stream: TcpStream,
+    handler: &Handler,
+) -> std::io::Result<()> {
+    loop {
+        let mut header = [0; 7];
+        stream.read_exact(&mut header)?;
+        let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        if !(2..=254).contains(&length) {
+            return Ok(());
+        }
+        let mut pdu = vec![0; length - 1];
+        stream.read_exact(&mut pdu)?;
+        let unit_id = header[6];
+        let response_pdu = handler(unit_id, &pdu);
+        let mut response = header[..4].to_vec();
+        response.extend(((response_pdu.len() + 1) as u16).to_be_bytes());
+        response.push(unit_id);
+        response.extend(response_pdu);
+        stream.write_all(&response)?;
+    }
+}
+
+/// Runs a Modbus TCP server in the background, `handler` turns the unit id
+/// and PDU of every request into the response PDU. Each client gets its own
+/// thread.
+pub(crate) fn spawn_server(
+    name: &str,
+    listener: TcpListener,
+    handler: impl Fn(u8, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+) -> std::io::Result<()> {
+    let handler: Arc<Handler> = Arc::new(handler);
+    let name = name.to_owned();
+    std::thread::Builder::new().name(name.clone()).spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("[{name}] Failed to accept Modbus connection ({e})");
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            let name = name.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = serve_client(stream, handler.as_ref()) {
+                    log::debug!("[{name}] Modbus client disconnected ({e})");
+                }
+            });
+        }
+    })?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use crate::output::Sample;
+use crate::InverterConfig;
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use solar_mon::inverter::MonitoringData;
+use std::collections::BTreeMap;
+
+/// Operating points of one input during a day, only counting polls where
+/// the input delivered current, so the open circuit voltage at dawn does
+/// not widen the range.
+#[derive(Debug, Clone, Copy)]
+struct InputRange {
+    min_voltage: f64,
+    max_voltage: f64,
+    peak_power: f64,
+    /// Voltage the peak power was reached at
+    peak_power_voltage: f64,
+}
+
+/// Collects the daily MPPT voltage range of every input of one inverter,
+/// to check the strings stay within the MPPT window of the inverter.
+pub(crate) struct MpptStats {
+    measurement: String,
+    /// Local day the ranges belong to
+    day: Option<NaiveDate>,
+    ranges: BTreeMap<String, InputRange>,
+}
+
+impl MpptStats {
+    pub(crate) fn new(measurement: String) -> Self {
+        MpptStats {
+            measurement,
+            day: None,
+            ranges: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a poll taken at `now`. Returns the samples of the previous day
+    /// once the first poll of a new day arrives.
+    pub(crate) fn push(
+        &mut self,
+        inverter_name: &str,
+        inverter_cfg: &InverterConfig,
+        data: &MonitoringData,
+        now: DateTime<Local>,
+    ) -> Vec<Sample> {
+        let today = now.date_naive();
+        let finished = match self.day {
+            Some(day) if day != today => self.samples(inverter_name, inverter_cfg, day),
+            _ => Vec::new(),
+        };
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.ranges.clear();
+        }
+        for input in data.inputs.iter().filter(|input| input.current > 0.0) {
+            let (voltage, power) = (input.voltage, input.power());
+            let range = self.ranges.entry(input.name.clone()).or_insert(InputRange {
+                min_voltage: voltage,
+                max_voltage: voltage,
+                peak_power: power,
+                peak_power_voltage: voltage,
+            });
+            range.min_voltage = range.min_voltage.min(voltage);
+            range.max_voltage = range.max_voltage.max(voltage);
+            if power > range.peak_power {
+                range.peak_power = power;
+                range.peak_power_voltage = voltage;
+            }
+        }
+        finished
+    }
+
+    /// One sample per input that delivered power on `day`, stamped with the
+    /// start of the day.
+    fn samples(
+        &self,
+        inverter_name: &str,
+        inverter_cfg: &InverterConfig,
+        day: NaiveDate,
+    ) -> Vec<Sample> {
+        let midnight = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+        let Some(time) = Local.from_local_datetime(&midnight).earliest() else {
+            return Vec::new();
+        };
+        self.ranges
+            .iter()
+            .map(|(input, range)| {
+                Sample::new(self.measurement.clone(), time.to_utc())
+                    .field("min_voltage", range.min_voltage)
+                    .field("max_voltage", range.max_voltage)
+                    .field("peak_power", range.peak_power)
+                    .field("peak_power_voltage", range.peak_power_voltage)
+                    .tag("inverter", inverter_name)
+                    .tag("input", inverter_cfg.input_name(input))
+            })
+            .collect()
+    }
+}
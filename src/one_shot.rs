@@ -0,0 +1,77 @@
+//! Single reads without keeping a connection, for scripts and tools that
+//! only need the current values once.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! let data = solar_mon::read_current("192.168.1.40".parse()?, 8899, Duration::from_secs(5))?;
+//! println!("{} W", data.ac_power);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::inverter::{Inverter, MonitoringData};
+use crate::solarmanv5::Timeouts;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+/// Connects to the logger stick at `addr`, reads the current values of a
+/// dual input inverter with the default register map and disconnects.
+/// Other models need an [`Inverter`] with their register map.
+pub fn read_current(
+    addr: IpAddr,
+    port: u16,
+    timeouts: impl Into<Timeouts>,
+) -> anyhow::Result<MonitoringData> {
+    Inverter::new(addr, port, timeouts)?.get_data()
+}
+
+/// Same as [`read_current`] for async code. The blocking read runs on a
+/// thread of its own, so the future works with any executor.
+pub fn read_current_async(
+    addr: IpAddr,
+    port: u16,
+    timeouts: impl Into<Timeouts>,
+) -> ReadFuture {
+    let timeouts = timeouts.into();
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    let thread_shared = shared.clone();
+    std::thread::spawn(move || {
+        let result = read_current(addr, port, timeouts);
+        let mut shared = thread_shared.lock().unwrap_or_else(PoisonError::into_inner);
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+    ReadFuture { shared }
+}
+
+#[derive(Default)]
+struct Shared {
+    result: Option<anyhow::Result<MonitoringData>>,
+    /// Waker of the last poll before the result arrived
+    waker: Option<Waker>,
+}
+
+/// Result of [`read_current_async`].
+pub struct ReadFuture {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Future for ReadFuture {
+    type Output = anyhow::Result<MonitoringData>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
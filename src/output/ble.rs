@@ -0,0 +1,212 @@
+use super::display::Display;
+use super::{Output, Sample};
+use anyhow::Context;
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MessageType;
+use dbus_crossroads::Crossroads;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+const ADVERTISEMENT_PATH: &str = "/org/solar_mon/advertisement";
+
+/// Advertises the summed power and energy of all inverters as Bluetooth LE
+/// manufacturer data through BlueZ, for display gadgets nearby that can't
+/// or shouldn't join the network.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct BleConfig {
+    /// BlueZ adapter
+    #[serde(default = "default_adapter")]
+    adapter: String,
+    /// Company identifier of the manufacturer data, 0xFFFF is reserved for
+    /// tests and own devices
+    #[serde(default = "default_company_id")]
+    company_id: u16,
+    #[serde(default = "default_local_name")]
+    local_name: String,
+    /// Minimum time between two changes of the advertisement
+    #[serde(default = "default_update_secs")]
+    update_secs: u64,
+}
+
+fn default_adapter() -> String {
+    "hci0".to_owned()
+}
+
+fn default_company_id() -> u16 {
+    0xffff
+}
+
+fn default_local_name() -> String {
+    "solar_mon".to_owned()
+}
+
+fn default_update_secs() -> u64 {
+    10
+}
+
+type Payload = Arc<Mutex<Vec<u8>>>;
+
+/// Sends a call to BlueZ without waiting for the reply, BlueZ reads the
+/// advertisement from us before it answers. Errors arrive as messages.
+fn call_manager(connection: &Connection, adapter: &str, method: &str) -> anyhow::Result<()> {
+    let mut call = dbus::Message::new_method_call(
+        "org.bluez",
+        format!("/org/bluez/{adapter}"),
+        "org.bluez.LEAdvertisingManager1",
+        method,
+    )
+    .map_err(anyhow::Error::msg)?
+    .append1(dbus::Path::from(ADVERTISEMENT_PATH));
+    if method == "RegisterAdvertisement" {
+        let options: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        call = call.append1(options);
+    }
+    connection
+        .send(call)
+        .map_err(|()| anyhow::anyhow!("Failed to send {method} to BlueZ"))?;
+    Ok(())
+}
+
+/// Serves the advertisement and registers it again with every update until
+/// the updates channel closes.
+fn advertise(
+    config: &BleConfig,
+    payload: &Payload,
+    updates: &mpsc::Receiver<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let connection = Connection::new_system().context("Failed to connect to the system bus")?;
+    let mut crossroads = Crossroads::new();
+    let token = crossroads.register("org.bluez.LEAdvertisement1", |builder| {
+        builder
+            .property("Type")
+            .get(|_, _: &mut (Payload, BleConfig)| Ok("broadcast".to_owned()));
+        builder
+            .property("LocalName")
+            .get(|_, (_, config): &mut (Payload, BleConfig)| Ok(config.local_name.clone()));
+        builder.property("ManufacturerData").get(
+            |_, (payload, config): &mut (Payload, BleConfig)| {
+                let data = payload
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone();
+                Ok(HashMap::from([(config.company_id, Variant(data))]))
+            },
+        );
+        builder.method("Release", (), (), |_, _: &mut (Payload, BleConfig), ()| {
+            log::info!("BlueZ released the advertisement");
+            Ok(())
+        });
+    });
+    crossroads.insert(
+        ADVERTISEMENT_PATH,
+        &[token],
+        (payload.clone(), config.clone()),
+    );
+    connection.start_receive(
+        dbus::message::MatchRule::new(),
+        Box::new(move |message, connection| {
+            if message.msg_type() == MessageType::Error {
+                let name = message
+                    .as_result()
+                    .err()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default();
+                log::warn!("BlueZ refused the advertisement ({name})");
+            } else {
+                let _ = crossroads.handle_message(message, connection);
+            }
+            true
+        }),
+    );
+    call_manager(&connection, &config.adapter, "RegisterAdvertisement")?;
+    log::info!("Advertising on {}", config.adapter);
+    loop {
+        connection.process(Duration::from_millis(200))?;
+        let data = match updates.try_recv() {
+            Ok(data) => data,
+            Err(mpsc::TryRecvError::Empty) => continue,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                return call_manager(&connection, &config.adapter, "UnregisterAdvertisement");
+            }
+        };
+        *payload.lock().unwrap_or_else(PoisonError::into_inner) = data;
+        // BlueZ only reads the advertisement when it is registered.
+        call_manager(&connection, &config.adapter, "UnregisterAdvertisement")?;
+        call_manager(&connection, &config.adapter, "RegisterAdvertisement")?;
+    }
+}
+
+pub(crate) struct BleOutput {
+    config: BleConfig,
+    updates: Option<mpsc::Sender<Vec<u8>>>,
+    /// Sums of all inverters, also taking the state events into account
+    display: Display,
+    advertised: Option<(Instant, Vec<u8>)>,
+}
+
+impl BleOutput {
+    pub(crate) fn new(config: &BleConfig, event_measurement: &str) -> Self {
+        BleOutput {
+            config: config.clone(),
+            updates: None,
+            display: Display::new(event_measurement),
+            advertised: None,
+        }
+    }
+
+    fn start(&self, data: Vec<u8>) -> anyhow::Result<mpsc::Sender<Vec<u8>>> {
+        let (updates, receiver) = mpsc::channel();
+        let config = self.config.clone();
+        let payload = Arc::new(Mutex::new(data));
+        std::thread::Builder::new()
+            .name("ble".to_owned())
+            .spawn(move || {
+                if let Err(e) = advertise(&config, &payload, &receiver) {
+                    log::error!("Bluetooth advertisement stopped ({e:#})");
+                }
+            })
+            .context("Failed to start Bluetooth thread")?;
+        Ok(updates)
+    }
+}
+
+impl Output for BleOutput {
+    fn name(&self) -> &str {
+        "bluetooth le"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        self.display.update(samples);
+        let Some(data) = self.display.manufacturer_data() else {
+            return Ok(());
+        };
+        let interval = Duration::from_secs(self.config.update_secs);
+        let due = match &self.advertised {
+            Some((time, advertised)) => *advertised != data && time.elapsed() >= interval,
+            None => true,
+        };
+        if !due {
+            return Ok(());
+        }
+        let sent = match &self.updates {
+            Some(updates) => updates.send(data.clone()).is_ok(),
+            None => {
+                self.updates = Some(self.start(data.clone())?);
+                true
+            }
+        };
+        if !sent {
+            // Restarted with the next write.
+            self.updates = None;
+            self.advertised = None;
+            anyhow::bail!("Bluetooth advertisement is not running");
+        }
+        self.advertised = Some((Instant::now(), data));
+        Ok(())
+    }
+}
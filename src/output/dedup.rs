@@ -0,0 +1,83 @@
+use super::Sample;
+use std::collections::{HashSet, VecDeque};
+
+/// Keys remembered, enough for several hours of polls of a few inverters.
+const CAPACITY: usize = 10_000;
+
+/// Measurement, time in ns and all tags of a sample, sorted by name.
+type Key = (String, i64, Vec<(String, String)>);
+
+/// Drops samples that were already written, e.g. a reading the logger
+/// stick returned twice and that got the same time from its clock both
+/// times.
+#[derive(Default)]
+pub(crate) struct Deduplicator {
+    written: HashSet<Key>,
+    /// Keys in the order they were written, to forget the oldest
+    order: VecDeque<Key>,
+}
+
+impl Deduplicator {
+    /// The samples not seen before, which are remembered from now on.
+    pub(crate) fn apply(&mut self, samples: &[Sample]) -> Vec<Sample> {
+        samples
+            .iter()
+            .filter(|sample| {
+                let mut tags = sample.tags.clone();
+                tags.sort();
+                let key = (
+                    sample.measurement.clone(),
+                    sample.time.timestamp_nanos_opt().unwrap_or_default(),
+                    tags,
+                );
+                if !self.written.insert(key.clone()) {
+                    log::debug!("Skipping {} sample written before at {}", key.0, sample.time);
+                    return false;
+                }
+                self.order.push_back(key);
+                if self.order.len() > CAPACITY {
+                    if let Some(oldest) = self.order.pop_front() {
+                        self.written.remove(&oldest);
+                    }
+                }
+                true
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn sample(tags: &[(&str, &str)]) -> Sample {
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        tags.iter()
+            .fold(Sample::new("inverter", time), |sample, (name, value)| sample.tag(*name, *value))
+            .field("power", 100.0)
+    }
+
+    #[test]
+    fn drops_repeated_samples() {
+        let mut dedup = Deduplicator::default();
+        let first = sample(&[("inverter", "roof"), ("site", "home")]);
+        assert_eq!(dedup.apply(&[first]).len(), 1);
+        // The same tags in another order are the same sample
+        let again = sample(&[("site", "home"), ("inverter", "roof")]);
+        assert!(dedup.apply(&[again]).is_empty());
+    }
+
+    #[test]
+    fn keeps_samples_differing_in_any_tag() {
+        let mut dedup = Deduplicator::default();
+        let samples = [
+            sample(&[("inverter", "roof"), ("site", "home")]),
+            sample(&[("inverter", "roof"), ("site", "barn")]),
+            sample(&[("inverter", "roof")]),
+            sample(&[("inverter", "roof"), ("input", "1")]),
+        ];
+        assert_eq!(dedup.apply(&samples).len(), samples.len());
+    }
+}
@@ -0,0 +1,217 @@
+//! Compact payload for microcontroller displays: the summed AC power in W,
+//! the energy of the day in kWh and a status icon code, separated by `;`,
+//! e.g. `532;2.41;1`, and the same sums as Bluetooth LE manufacturer data.
+
+use super::Sample;
+use std::collections::BTreeMap;
+
+/// Version of the manufacturer data layout, see
+/// [`Display::manufacturer_data`].
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+const LAYOUT_VERSION: u8 = 1;
+
+/// Status icon of the display payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Icon {
+    /// No inverter reachable, usually the night
+    Sleeping = 0,
+    Producing = 1,
+    /// Reachable without PV power, or paused
+    Idle = 2,
+    /// An inverter reports a fault
+    Fault = 3,
+}
+
+impl Icon {
+    /// Icon for the states of all inverters, `None` before the first state
+    /// event of an inverter.
+    fn of<'a>(states: impl IntoIterator<Item = Option<&'a str>>) -> Self {
+        let states: Vec<_> = states.into_iter().flatten().collect();
+        if states.contains(&"faulted") {
+            Icon::Fault
+        } else if states.contains(&"producing") {
+            Icon::Producing
+        } else if states
+            .iter()
+            .any(|state| matches!(*state, "idle" | "paused"))
+        {
+            Icon::Idle
+        } else {
+            Icon::Sleeping
+        }
+    }
+}
+
+fn payload(power: f64, energy_today: f64, icon: Icon) -> String {
+    format!(
+        "{:.0};{:.2};{}",
+        power.max(0.0),
+        energy_today.max(0.0),
+        icon as u8
+    )
+}
+
+/// Latest power, energy and state of one inverter.
+#[derive(Debug, Default)]
+struct InverterValues {
+    power: f64,
+    energy_today: f64,
+    energy_total: f64,
+    state: Option<String>,
+}
+
+/// Collects the samples of all inverters for outputs without a store of
+/// their own.
+#[derive(Debug)]
+pub(crate) struct Display {
+    event_measurement: String,
+    inverters: BTreeMap<String, InverterValues>,
+}
+
+impl Display {
+    pub(crate) fn new(event_measurement: &str) -> Self {
+        Display {
+            event_measurement: event_measurement.to_owned(),
+            inverters: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, samples: &[Sample]) {
+        for sample in samples {
+            let (Some(inverter), None) = (sample.tag_value("inverter"), sample.tag_value("input"))
+            else {
+                continue;
+            };
+            if sample.measurement == self.event_measurement {
+                let values = self.inverters.entry(inverter.to_owned()).or_default();
+                values.state = sample.tag_value("state").map(str::to_owned);
+                // The last reading of an inverter that went offline is stale.
+                if values.state.as_deref() != Some("producing") {
+                    values.power = 0.0;
+                }
+                continue;
+            }
+            let Some(power) = sample.field_value("ac_power") else {
+                continue;
+            };
+            let values = self.inverters.entry(inverter.to_owned()).or_default();
+            values.power = power;
+            // Polls only reading the fast changing values keep the energy.
+            if let Some(energy_today) = sample.field_value("energy_today") {
+                values.energy_today = energy_today;
+            }
+            if let Some(energy_total) = sample.field_value("energy_total") {
+                values.energy_total = energy_total;
+            }
+        }
+    }
+
+    /// Sum of a value of all inverters.
+    fn sum(&self, value: impl Fn(&InverterValues) -> f64) -> f64 {
+        self.inverters
+            .values()
+            .fold(0.0, |sum, values| sum + value(values))
+    }
+
+    /// `None` until an inverter was seen.
+    pub(crate) fn payload(&self) -> Option<String> {
+        if self.inverters.is_empty() {
+            return None;
+        }
+        let power = self.sum(|values| values.power);
+        let energy_today = self.sum(|values| values.energy_today);
+        let icon = Icon::of(
+            self.inverters
+                .values()
+                .map(|values| values.state.as_deref()),
+        );
+        Some(payload(power, energy_today, icon))
+    }
+
+    /// Bluetooth LE manufacturer data: layout version, AC power in W (u16),
+    /// energy of the day in 10 Wh (u16) and lifetime energy in 0.1 kWh
+    /// (u32), little endian. `None` until an inverter was seen.
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    pub(crate) fn manufacturer_data(&self) -> Option<Vec<u8>> {
+        if self.inverters.is_empty() {
+            return None;
+        }
+        let power = self.sum(|values| values.power);
+        let energy_today = self.sum(|values| values.energy_today);
+        let energy_total = self.sum(|values| values.energy_total);
+        let mut data = vec![LAYOUT_VERSION];
+        data.extend((power.round().clamp(0.0, f64::from(u16::MAX)) as u16).to_le_bytes());
+        let energy_today = (energy_today * 100.0)
+            .round()
+            .clamp(0.0, f64::from(u16::MAX));
+        data.extend((energy_today as u16).to_le_bytes());
+        let energy_total = (energy_total * 10.0)
+            .round()
+            .clamp(0.0, f64::from(u32::MAX));
+        data.extend((energy_total as u32).to_le_bytes());
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(inverter: &str, ac_power: f64, energy_today: f64, energy_total: f64) -> Sample {
+        Sample::new("inverters", chrono::Utc::now())
+            .field("ac_power", ac_power)
+            .field("energy_today", energy_today)
+            .field("energy_total", energy_total)
+            .tag("inverter", inverter)
+    }
+
+    fn state(inverter: &str, state: &str) -> Sample {
+        Sample::new("events", chrono::Utc::now())
+            .field("state_code", 0.0)
+            .tag("inverter", inverter)
+            .tag("state", state)
+    }
+
+    #[test]
+    fn sums_all_inverters() {
+        let mut display = Display::new("events");
+        assert_eq!(display.payload(), None);
+        assert_eq!(display.manufacturer_data(), None);
+        display.update(&[
+            state("roof", "producing"),
+            state("garage", "producing"),
+            reading("roof", 300.4, 1.2, 100.0),
+            reading("garage", 231.7, 1.21, 23.45),
+        ]);
+        assert_eq!(display.payload().unwrap(), "532;2.41;1");
+        assert_eq!(
+            display.manufacturer_data().unwrap(),
+            [1, 0x14, 0x02, 0xf1, 0x00, 0xd3, 0x04, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn offline_inverter_has_no_power() {
+        let mut display = Display::new("events");
+        display.update(&[
+            state("roof", "producing"),
+            reading("roof", 300.0, 1.2, 100.0),
+            state("roof", "offline"),
+        ]);
+        assert_eq!(display.payload().unwrap(), "0;1.20;0");
+        assert_eq!(
+            display.manufacturer_data().unwrap(),
+            [1, 0, 0, 0x78, 0x00, 0xe8, 0x03, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn manufacturer_data_is_clamped() {
+        let mut display = Display::new("events");
+        display.update(&[reading("roof", 70_000.0, -1.0, 1.0e12)]);
+        assert_eq!(
+            display.manufacturer_data().unwrap(),
+            [1, 0xff, 0xff, 0, 0, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+}
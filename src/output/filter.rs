@@ -0,0 +1,129 @@
+use super::Sample;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Selects which samples and fields reach an output. Every criterion that
+/// is given has to match, names may end with `*` to match a prefix.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub(crate) struct SampleFilter {
+    /// Only samples of these measurements
+    #[serde(default)]
+    measurements: Vec<String>,
+    /// Only samples carrying all of these tag values
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    /// Only these fields, samples without any of them are dropped
+    #[serde(default)]
+    fields: Vec<String>,
+}
+
+fn matches(pattern: &str, value: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => value.starts_with(prefix),
+        None => pattern == value,
+    }
+}
+
+impl SampleFilter {
+    fn selects(&self, sample: &Sample) -> bool {
+        (self.measurements.is_empty()
+            || self.measurements.iter().any(|pattern| matches(pattern, &sample.measurement)))
+            && self
+                .tags
+                .iter()
+                .all(|(tag, pattern)| sample.tag_value(tag).is_some_and(|value| matches(pattern, value)))
+    }
+
+    /// The selected samples, reduced to the selected fields.
+    pub(crate) fn apply(&self, samples: &[Sample]) -> Vec<Sample> {
+        samples
+            .iter()
+            .filter(|sample| self.selects(sample))
+            .filter_map(|sample| {
+                let mut sample = sample.clone();
+                if !self.fields.is_empty() {
+                    sample
+                        .fields
+                        .retain(|(name, _)| self.fields.iter().any(|pattern| matches(pattern, name)));
+                }
+                (!sample.fields.is_empty()).then_some(sample)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Sample> {
+        let time = chrono::Utc::now();
+        vec![
+            Sample::new("solar", time)
+                .tag("inverter", "roof")
+                .field("power", 500.0)
+                .field("pv1_power", 300.0)
+                .field("temperature", 40.0),
+            Sample::new("solar", time).tag("inverter", "garage").field("power", 200.0),
+            Sample::new("solar_daily", time).tag("inverter", "roof").field("energy", 3.5),
+        ]
+    }
+
+    fn apply(filter: &str) -> Vec<Sample> {
+        toml::from_str::<SampleFilter>(filter).unwrap().apply(&samples())
+    }
+
+    fn summary(samples: &[Sample]) -> Vec<(&str, &str, usize)> {
+        samples
+            .iter()
+            .map(|sample| {
+                let inverter = sample.tag_value("inverter").unwrap_or_default();
+                (sample.measurement.as_str(), inverter, sample.fields.len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_filter_passes_everything() {
+        let selected = apply("");
+        assert_eq!(summary(&selected), summary(&samples()));
+    }
+
+    #[test]
+    fn measurements() {
+        let selected = apply("measurements = [\"solar\"]");
+        assert_eq!(summary(&selected), [("solar", "roof", 3), ("solar", "garage", 1)]);
+        let selected = apply("measurements = [\"solar*\"]");
+        assert_eq!(selected.len(), 3);
+        assert!(apply("measurements = [\"sol\"]").is_empty());
+    }
+
+    #[test]
+    fn tags() {
+        let selected = apply("tags = { inverter = \"roof\" }");
+        assert_eq!(summary(&selected), [("solar", "roof", 3), ("solar_daily", "roof", 1)]);
+        let selected = apply("tags = { inverter = \"g*\" }");
+        assert_eq!(summary(&selected), [("solar", "garage", 1)]);
+        // Samples without the tag are dropped.
+        assert!(apply("tags = { input = \"*\" }").is_empty());
+    }
+
+    #[test]
+    fn fields() {
+        let selected = apply("fields = [\"power\"]");
+        assert_eq!(summary(&selected), [("solar", "roof", 1), ("solar", "garage", 1)]);
+        assert_eq!(selected[0].fields, [("power".to_owned(), 500.0)]);
+
+        let selected = apply("fields = [\"pv*\", \"energy\"]");
+        assert_eq!(summary(&selected), [("solar", "roof", 1), ("solar_daily", "roof", 1)]);
+        assert_eq!(selected[0].field_value("pv1_power"), Some(300.0));
+    }
+
+    #[test]
+    fn all_criteria_have_to_match() {
+        let selected = apply(
+            "measurements = [\"solar\"]\nfields = [\"power\"]\ntags = { inverter = \"roof\" }",
+        );
+        assert_eq!(summary(&selected), [("solar", "roof", 1)]);
+    }
+}
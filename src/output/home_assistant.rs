@@ -0,0 +1,93 @@
+use super::mqtt::Message;
+use super::Sample;
+
+/// How Home Assistant should treat a field.
+struct SensorClass {
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    state_class: &'static str,
+}
+
+fn sensor_class(field: &str) -> SensorClass {
+    let (unit, device_class, state_class) = match field {
+        "voltage" => (Some("V"), Some("voltage"), "measurement"),
+        "current" => (Some("A"), Some("current"), "measurement"),
+        "power" | "ac_power" | "grid_power" => (Some("W"), Some("power"), "measurement"),
+        "energy_today" | "energy_total" | "grid_import_energy" | "grid_export_energy" => {
+            (Some("kWh"), Some("energy"), "total_increasing")
+        }
+        "wifi_signal" => (Some("%"), None, "measurement"),
+        _ => (None, None, "measurement"),
+    };
+    SensorClass {
+        unit,
+        device_class,
+        state_class,
+    }
+}
+
+/// Counters that only reset when the inverter is replaced. A lower reading
+/// is a glitch, which Home Assistant would take as reset and count the
+/// whole lifetime energy again.
+pub(crate) fn is_lifetime_total(field: &str) -> bool {
+    matches!(field, "energy_total" | "grid_import_energy" | "grid_export_energy")
+}
+
+/// Characters allowed in discovery object ids.
+fn object_id(parts: &[&str]) -> String {
+    parts
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            part.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Retained MQTT discovery config announcing `field` of `sample`, published
+/// on `state_topic`, as sensor.
+pub(crate) fn discovery_message(
+    prefix: &str,
+    sample: &Sample,
+    field: &str,
+    state_topic: &str,
+    json_payload: bool,
+) -> Message {
+    let inverter = sample.tag_value("inverter").unwrap_or("unknown");
+    let input = sample.tag_value("input").unwrap_or_default();
+    let id = object_id(&["solar_mon", &sample.measurement, inverter, input, field]);
+    let class = sensor_class(field);
+    let name = if input.is_empty() {
+        field.replace('_', " ")
+    } else {
+        format!("{input} {}", field.replace('_', " "))
+    };
+    let mut config = serde_json::json!({
+        "name": name,
+        "unique_id": id,
+        "state_topic": state_topic,
+        "state_class": class.state_class,
+        "device": {
+            "identifiers": [object_id(&["solar_mon", inverter])],
+            "name": inverter,
+            "manufacturer": "Deye",
+        },
+    });
+    if let Some(unit) = class.unit {
+        config["unit_of_measurement"] = unit.into();
+    }
+    if let Some(device_class) = class.device_class {
+        config["device_class"] = device_class.into();
+    }
+    if json_payload {
+        config["value_template"] = format!("{{{{ value_json.fields.{field} }}}}").into();
+    }
+    Message {
+        topic: format!("{prefix}/sensor/{id}/config"),
+        payload: config.to_string().into_bytes(),
+        retain: true,
+    }
+}
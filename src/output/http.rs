@@ -0,0 +1,888 @@
+use super::display::Display;
+use super::{Output, Sample};
+use crate::audit::{Action, Origin};
+use crate::control::{Command, Control};
+use crate::energy_stats;
+use crate::i18n::{self, Language};
+use crate::state_file::StateFile;
+use crate::Config;
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Serves the latest values of all inverters over HTTP for tools polling
+/// JSON endpoints.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct HttpConfig {
+    #[serde(default = "default_http_listen")]
+    listen: String,
+    /// Additionally serve `/api/livedata/status` in the schema of OpenDTU
+    #[serde(default)]
+    opendtu: bool,
+    /// Serve a page at `/settings` to edit the config file and restart the
+    /// daemon with it
+    #[serde(default)]
+    settings: bool,
+    /// Accept commands changing the running daemon under `/api/v1`, e.g. to
+    /// add inverters
+    #[serde(default)]
+    control: bool,
+    /// Serve HTTPS instead of plain HTTP
+    #[serde(default)]
+    tls: Option<TlsConfig>,
+    /// Users allowed to access the server, anyone may if empty
+    #[serde(default)]
+    users: Vec<HttpUser>,
+}
+
+impl HttpConfig {
+    /// Base URL reaching the server from this host, if it accepts commands.
+    pub(crate) fn control_url(&self) -> Option<String> {
+        if !self.control {
+            return None;
+        }
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        let host = match self.listen.parse::<SocketAddr>() {
+            Ok(addr) if addr.ip().is_unspecified() => match addr {
+                SocketAddr::V4(_) => format!("127.0.0.1:{}", addr.port()),
+                SocketAddr::V6(_) => format!("[::1]:{}", addr.port()),
+            },
+            _ => self.listen.clone(),
+        };
+        Some(format!("{scheme}://{host}"))
+    }
+
+    /// Rejects users without a secret, and the settings page and commands
+    /// without an admin to log in. They show and replace the whole config
+    /// file or change what the daemon polls.
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        let missing_secret = |user: &&HttpUser| user.password.is_none() && user.token.is_none();
+        if let Some(user) = self.users.iter().find(missing_secret) {
+            anyhow::bail!("HTTP user {} needs a password or a token", user.name);
+        }
+        let has_admin = self.users.iter().any(|user| user.role == Role::Admin);
+        if self.settings && !has_admin {
+            anyhow::bail!("The HTTP settings page needs a user with the admin role");
+        }
+        if self.control && !has_admin {
+            anyhow::bail!("HTTP commands with control = true need a user with the admin role");
+        }
+        Ok(())
+    }
+}
+
+/// Certificate chain and PKCS#8 private key, both PEM encoded.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct TlsConfig {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+/// A user authenticating with HTTP basic auth or a bearer token.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct HttpUser {
+    name: String,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    role: Role,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "snake_case")]
+enum Role {
+    /// Live data and status endpoints
+    #[default]
+    Read,
+    /// Additionally the settings page and commands
+    Admin,
+}
+
+fn default_http_listen() -> String {
+    "0.0.0.0:8080".to_owned()
+}
+
+/// Latest values of one inverter.
+#[derive(Debug, Clone, Default)]
+struct InverterValues {
+    time: Option<DateTime<Utc>>,
+    /// Trace id of the last poll
+    trace_id: Option<String>,
+    ac_power: f64,
+    energy_today: f64,
+    energy_total: f64,
+    /// Voltage, current and power by input name
+    inputs: BTreeMap<String, [f64; 3]>,
+    /// Current state from the last state event
+    state: Option<String>,
+    /// Code of the error that caused the current state
+    error_code: Option<String>,
+    /// Fields of the last communication statistics sample
+    link: BTreeMap<String, f64>,
+    /// Insulation monitoring values of the last poll, if the model has them
+    diagnostics: BTreeMap<String, f64>,
+}
+
+/// Fields of the totals sample shown as diagnostics in `/api/status`.
+const DIAGNOSTIC_FIELDS: [&str; 2] = ["insulation_resistance", "leakage_current"];
+
+type LiveData = Arc<Mutex<BTreeMap<String, InverterValues>>>;
+
+/// Largest request body accepted, enough for any config file.
+const MAX_BODY: usize = 256 * 1024;
+
+const SETTINGS_PAGE: &str = include_str!("settings.html");
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    host: Option<String>,
+    /// `Origin` or else `Referer` of requests sent by browsers
+    source: Option<String>,
+    /// First language the browser asks for
+    language: Option<Language>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+    /// Restart the daemon once the response is sent
+    #[cfg_attr(not(unix), allow(dead_code))]
+    restart: bool,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Response {
+            status: "200 OK",
+            content_type,
+            body,
+            restart: false,
+        }
+    }
+
+    fn json(value: &serde_json::Value) -> Self {
+        Self::ok("application/json", value.to_string())
+    }
+
+    fn error(status: &'static str) -> Self {
+        Self::error_message(status, status)
+    }
+
+    fn error_message(status: &'static str, message: &str) -> Self {
+        Response {
+            status,
+            ..Self::json(&serde_json::json!({ "error": message }))
+        }
+    }
+}
+
+/// Everything the request handlers share.
+struct Server {
+    config: HttpConfig,
+    tls: Option<native_tls::TlsAcceptor>,
+    /// Config file edited on the settings page
+    config_path: PathBuf,
+    live: LiveData,
+    display: Arc<Mutex<Display>>,
+    /// Source of the monthly and yearly production, see
+    /// [`crate::energy_stats`]
+    state_file: Option<PathBuf>,
+    /// Only set in the daemon
+    control: Option<Control>,
+}
+
+/// Total PV power and energy in the shape of evcc's custom meter, e.g.
+/// `jq: .power`.
+fn evcc(inverters: &BTreeMap<String, InverterValues>) -> Response {
+    if inverters.is_empty() {
+        return Response::error("503 Service Unavailable");
+    }
+    let per_inverter: serde_json::Map<_, _> = inverters
+        .iter()
+        .map(|(name, values)| {
+            (
+                name.clone(),
+                serde_json::json!({ "power": values.ac_power, "energy": values.energy_total }),
+            )
+        })
+        .collect();
+    Response::json(&serde_json::json!({
+        "power": inverters.values().map(|values| values.ac_power).sum::<f64>(),
+        "energy": inverters.values().map(|values| values.energy_total).sum::<f64>(),
+        "updated": inverters.values().filter_map(|values| values.time).min().map(|time| time.to_rfc3339()),
+        "inverters": per_inverter,
+    }))
+}
+
+/// Value in OpenDTU's `{"v": .., "u": .., "d": ..}` notation, `d` being the
+/// number of decimals to display.
+fn opendtu_value(value: f64, unit: &str, decimals: u8) -> serde_json::Value {
+    serde_json::json!({ "v": value, "u": unit, "d": decimals })
+}
+
+/// Live data in the schema of OpenDTU's `/api/livedata/status` so
+/// dashboards written for it work unchanged. The Deye inputs are the DC
+/// channels, AC values besides the power are not available.
+fn opendtu(inverters: &BTreeMap<String, InverterValues>) -> Response {
+    let now = Utc::now();
+    let entries: Vec<_> = inverters
+        .iter()
+        .enumerate()
+        .map(|(order, (name, values))| {
+            let dc: serde_json::Map<_, _> = values
+                .inputs
+                .iter()
+                .enumerate()
+                .map(|(channel, (input, [voltage, current, power]))| {
+                    (
+                        channel.to_string(),
+                        serde_json::json!({
+                            "name": { "u": input },
+                            "Power": opendtu_value(*power, "W", 1),
+                            "Voltage": opendtu_value(*voltage, "V", 1),
+                            "Current": opendtu_value(*current, "A", 2),
+                        }),
+                    )
+                })
+                .collect();
+            let dc_power: f64 = values.inputs.values().map(|[_, _, power]| power).sum();
+            let efficiency = if dc_power > 0.0 { values.ac_power / dc_power * 100.0 } else { 0.0 };
+            let data_age = values.time.map_or(0, |time| (now - time).num_seconds().max(0));
+            serde_json::json!({
+                "serial": name,
+                "name": name,
+                "order": order,
+                "data_age": data_age,
+                "poll_enabled": true,
+                "reachable": true,
+                "producing": values.ac_power > 0.0,
+                "AC": { "0": { "Power": opendtu_value(values.ac_power, "W", 1) } },
+                "DC": dc,
+                "INV": { "0": {
+                    "Power DC": opendtu_value(dc_power, "W", 1),
+                    "YieldDay": opendtu_value(values.energy_today * 1000.0, "Wh", 0),
+                    "YieldTotal": opendtu_value(values.energy_total, "kWh", 3),
+                    "Efficiency": opendtu_value(efficiency, "%", 3),
+                } },
+                "events": 0,
+            })
+        })
+        .collect();
+    Response::json(&serde_json::json!({
+        "inverters": entries,
+        "total": {
+            "Power": opendtu_value(inverters.values().map(|values| values.ac_power).sum(), "W", 0),
+            "YieldDay": opendtu_value(
+                inverters.values().map(|values| values.energy_today * 1000.0).sum(),
+                "Wh",
+                0
+            ),
+            "YieldTotal": opendtu_value(
+                inverters.values().map(|values| values.energy_total).sum(),
+                "kWh",
+                2
+            ),
+        },
+        "hints": { "time_sync": false, "radio_problem": false, "default_password": false },
+    }))
+}
+
+/// Poll state and communication statistics of every inverter and the
+/// counters of the daemon itself. The production statistics are read from
+/// the state file, which the daemon writes about once a minute.
+fn status(inverters: &BTreeMap<String, InverterValues>, state_file: Option<&Path>) -> Response {
+    let state_file = match state_file.map(|path| StateFile::load(Some(path))).transpose() {
+        Ok(state_file) => state_file,
+        Err(e) => {
+            log::debug!("Failed to read statistics ({e:#})");
+            None
+        }
+    };
+    let per_inverter: serde_json::Map<_, _> = inverters
+        .iter()
+        .map(|(name, values)| {
+            (
+                name.clone(),
+                serde_json::json!({
+                    "updated": values.time.map(|time| time.to_rfc3339()),
+                    "trace_id": values.trace_id,
+                    "state": values.state,
+                    "error_code": values.error_code,
+                    "link": values.link,
+                    "diagnostics": values.diagnostics,
+                    "energy": state_file
+                        .as_ref()
+                        .map(|state_file| energy_stats::to_json(&state_file.get(name))),
+                }),
+            )
+        })
+        .collect();
+    Response::json(&serde_json::json!({
+        "inverters": per_inverter,
+        "daemon": crate::self_metrics::to_json(),
+    }))
+}
+
+/// Validates a config sent from the settings page and replaces the config
+/// file with it. The running daemon keeps the old config until restarted.
+fn save_config(path: &Path, body: &[u8]) -> Response {
+    let Ok(config_str) = std::str::from_utf8(body) else {
+        return Response::error_message("400 Bad Request", "Config is not valid UTF-8");
+    };
+    if let Err(e) = crate::parse_config(config_str) {
+        return Response::error_message("422 Unprocessable Entity", &format!("{e:#}"));
+    }
+    match crate::paths::replace_file(path, config_str.as_bytes(), false) {
+        Ok(()) => {
+            log::info!("Config file {} changed from the settings page", path.display());
+            Response::json(&serde_json::json!({ "saved": true }))
+        }
+        Err(e) => Response::error_message("500 Internal Server Error", &format!("{e:#}")),
+    }
+}
+
+/// Compares secrets in constant time, so response times don't reveal how
+/// much of a guess was right.
+fn secret_eq(secret: &str, guess: &str) -> bool {
+    secret.len() == guess.len()
+        && secret.bytes().zip(guess.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Role of the user a request authenticates as, if any.
+fn authenticate<'a>(users: &'a [HttpUser], authorization: &str) -> Option<&'a HttpUser> {
+    let (scheme, credentials) = authorization.split_once(' ')?;
+    let credentials = credentials.trim();
+    let user = if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = base64::engine::general_purpose::STANDARD.decode(credentials).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (name, password) = decoded.split_once(':')?;
+        users.iter().find(|user| {
+            user.name == name
+                && user.password.as_deref().is_some_and(|secret| secret_eq(secret, password))
+        })
+    } else if scheme.eq_ignore_ascii_case("bearer") {
+        users
+            .iter()
+            .find(|user| user.token.as_deref().is_some_and(|secret| secret_eq(secret, credentials)))
+    } else {
+        None
+    };
+    user
+}
+
+/// Rejects requests of unknown users and of users lacking the role the
+/// path needs. Without configured users only reading is allowed. Returns the
+/// name of the user otherwise.
+fn check_access(config: &HttpConfig, request: &Request) -> Result<Option<String>, Response> {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let required = match (request.method.as_str(), path) {
+        ("POST", _) | (_, "/settings" | "/api/config") => Role::Admin,
+        _ => Role::Read,
+    };
+    if config.users.is_empty() {
+        // Changes to the daemon and the config file are never allowed
+        // without logging in.
+        if required == Role::Admin {
+            return Err(Response::error_message(
+                "403 Forbidden",
+                "This needs a user with the admin role",
+            ));
+        }
+        return Ok(None);
+    }
+    match request
+        .authorization
+        .as_deref()
+        .and_then(|authorization| authenticate(&config.users, authorization))
+    {
+        Some(user) if user.role >= required => Ok(Some(user.name.clone())),
+        Some(_) => Err(Response::error("403 Forbidden")),
+        None => Err(Response::error("401 Unauthorized")),
+    }
+}
+
+/// Rejects requests changing something that a browser sent from a page of
+/// another site. Browsers send the basic auth credentials with these too, and
+/// without users they need none at all. Clients like curl send neither
+/// `Origin` nor `Referer` and are let through.
+fn check_same_origin(request: &Request) -> Result<(), Response> {
+    if matches!(request.method.as_str(), "GET" | "HEAD") {
+        return Ok(());
+    }
+    let Some(source) = &request.source else {
+        return Ok(());
+    };
+    // `null` from sandboxed pages and files has no host and is rejected.
+    let source_host = source
+        .split_once("://")
+        .map(|(_, rest)| rest.split('/').next().unwrap_or_default());
+    match (source_host, &request.host) {
+        (Some(source_host), Some(host)) if source_host.eq_ignore_ascii_case(host) => Ok(()),
+        _ => Err(Response::error_message("403 Forbidden", "Cross-origin request")),
+    }
+}
+
+/// Records a change made through the settings page in the audit log.
+fn audited(origin: &Origin, action: &'static str, response: Response) -> Response {
+    let result = if response.status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("{}", response.status))
+    };
+    Action::new(origin, action).record(&result);
+    response
+}
+
+/// Sends a command to the daemon, answering with `status` once it was
+/// carried out.
+fn command(
+    server: &Server,
+    send: impl FnOnce(&Control) -> anyhow::Result<()>,
+    status: &'static str,
+) -> Response {
+    let Some(control) = &server.control else {
+        return Response::error_message("503 Service Unavailable", "Commands need the daemon");
+    };
+    match send(control) {
+        Ok(()) => Response {
+            status,
+            ..Response::json(&serde_json::json!({ "ok": true }))
+        },
+        Err(e) => Response::error_message("422 Unprocessable Entity", &format!("{e:#}")),
+    }
+}
+
+fn route(server: &Server, request: &Request, origin: &Origin) -> Response {
+    let inverters = || server.live.lock().unwrap_or_else(PoisonError::into_inner);
+    let settings = server.config.settings;
+    match (request.method.as_str(), request.path.split('?').next().unwrap_or_default()) {
+        ("GET", "/api/evcc") => evcc(&inverters()),
+        ("GET", "/api/display") => {
+            match server.display.lock().unwrap_or_else(PoisonError::into_inner).payload() {
+                Some(payload) => Response::ok("text/plain", payload),
+                None => Response::error("503 Service Unavailable"),
+            }
+        }
+        ("GET", "/api/status") => status(&inverters(), server.state_file.as_deref()),
+        ("GET", "/api/livedata/status") if server.config.opendtu => opendtu(&inverters()),
+        ("GET", "/settings") if settings => {
+            // The configured language wins over the one of the browser.
+            let language = i18n::configured().or(request.language).unwrap_or_default();
+            Response::ok("text/html; charset=utf-8", language.render(SETTINGS_PAGE))
+        }
+        ("GET", "/api/config") if settings => match std::fs::read_to_string(&server.config_path) {
+            Ok(config_str) => Response::ok("text/plain; charset=utf-8", config_str),
+            Err(e) => Response::error_message(
+                "500 Internal Server Error",
+                &format!("Failed to read config file ({e})"),
+            ),
+        },
+        ("POST", "/api/config") if settings => {
+            audited(origin, "save_config", save_config(&server.config_path, &request.body))
+        }
+        ("POST", "/api/reload") if settings && cfg!(unix) => audited(
+            origin,
+            "reload",
+            Response {
+                restart: true,
+                ..Response::json(&serde_json::json!({ "restarting": true }))
+            },
+        ),
+        ("POST", "/api/reload") if settings => Response::error_message(
+            "501 Not Implemented",
+            "Restart the service to apply the config",
+        ),
+        ("POST", "/api/v1/inverters") if server.config.control => {
+            command(server, |control| control.add_inverter(origin, &request.body), "201 Created")
+        }
+        ("POST", path) if server.config.control => {
+            match path.strip_prefix("/api/v1/inverters/").and_then(Command::for_inverter) {
+                Some(inverter_command) => {
+                    command(server, |control| control.send(origin, inverter_command), "200 OK")
+                }
+                None => Response::error("404 Not Found"),
+            }
+        }
+        ("GET" | "POST", _) => Response::error("404 Not Found"),
+        _ => Response::error("405 Method Not Allowed"),
+    }
+}
+
+fn read_request(stream: &mut impl Read) -> anyhow::Result<Request> {
+    let mut request = Vec::new();
+    let mut chunk = [0; 1024];
+    let header_end = loop {
+        if let Some(position) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        let read = stream.read(&mut chunk)?;
+        if read == 0 || request.len() > 8192 {
+            anyhow::bail!("Incomplete HTTP request");
+        }
+        request.extend_from_slice(&chunk[..read]);
+    };
+    let header = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let mut lines = header.lines();
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (method, path) = (
+        request_line.next().unwrap_or_default().to_owned(),
+        request_line.next().unwrap_or_default().to_owned(),
+    );
+    let headers: Vec<_> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    let header_value = |wanted: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| *value)
+    };
+    let content_length = header_value("content-length")
+        .map(str::parse::<usize>)
+        .transpose()
+        .context("Invalid Content-Length")?
+        .unwrap_or_default();
+    if content_length > MAX_BODY {
+        anyhow::bail!("HTTP request body too large ({content_length} bytes)");
+    }
+    let authorization = header_value("authorization").map(str::to_owned);
+    let host = header_value("host").map(str::to_owned);
+    let source = header_value("origin").or_else(|| header_value("referer")).map(str::to_owned);
+    let language = header_value("accept-language")
+        .and_then(|languages| Language::from_tag(languages.trim_start()));
+    let mut body = request.split_off(header_end);
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            anyhow::bail!("Incomplete HTTP request body");
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+    Ok(Request {
+        method,
+        path,
+        authorization,
+        host,
+        source,
+        language,
+        body,
+    })
+}
+
+fn handle_client(server: &Server, mut stream: impl Read + Write) -> anyhow::Result<()> {
+    let request = read_request(&mut stream)?;
+    let access = check_same_origin(&request).and_then(|()| check_access(&server.config, &request));
+    let response = match access {
+        Ok(user) => route(server, &request, &Origin::new("api", user)),
+        Err(response) => response,
+    };
+    // Lets browsers ask for the credentials.
+    let challenge = if response.status.starts_with("401") {
+        "WWW-Authenticate: Basic realm=\"solar_mon\"\r\n"
+    } else {
+        ""
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{challenge}Connection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()?;
+    #[cfg(unix)]
+    if response.restart {
+        log::info!("Restarting from the settings page");
+        let e = crate::service::restart();
+        log::error!("Failed to restart ({e:#})");
+    }
+    Ok(())
+}
+
+fn accept(server: &Server, stream: TcpStream) -> anyhow::Result<()> {
+    // Also limits the TLS handshake.
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+    match &server.tls {
+        Some(acceptor) => {
+            let stream = acceptor.accept(stream).context("TLS handshake failed")?;
+            handle_client(server, stream)
+        }
+        None => handle_client(server, stream),
+    }
+}
+
+fn tls_acceptor(config: &TlsConfig) -> anyhow::Result<native_tls::TlsAcceptor> {
+    let cert = std::fs::read(&config.cert)
+        .with_context(|| format!("Failed to read certificate {}", config.cert.display()))?;
+    let key = std::fs::read(&config.key)
+        .with_context(|| format!("Failed to read private key {}", config.key.display()))?;
+    let identity = native_tls::Identity::from_pkcs8(&cert, &key)
+        .context("Invalid certificate or private key, the key must be PKCS#8")?;
+    native_tls::TlsAcceptor::new(identity).context("Failed to set up TLS")
+}
+
+pub(crate) struct HttpOutput {
+    live: LiveData,
+    display: Arc<Mutex<Display>>,
+    event_measurement: String,
+    link_measurement: Option<String>,
+}
+
+impl HttpOutput {
+    pub(crate) fn new(
+        config: &HttpConfig,
+        app_config: &Config,
+        control: Option<&Control>,
+    ) -> anyhow::Result<Self> {
+        config.validate()?;
+        let tls = config.tls.as_ref().map(tls_acceptor).transpose()?;
+        let listener = TcpListener::bind(&config.listen)
+            .with_context(|| format!("Failed to listen on {}", config.listen))?;
+        let live = LiveData::default();
+        let display = Arc::new(Mutex::new(Display::new(&app_config.monitoring.event_measurement)));
+        let server = Arc::new(Server {
+            config: config.clone(),
+            tls,
+            config_path: app_config.path.clone(),
+            live: live.clone(),
+            display: display.clone(),
+            state_file: app_config.monitoring.state_file.clone(),
+            control: control.cloned(),
+        });
+        std::thread::Builder::new()
+            .name("http".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let server = server.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = accept(&server, stream) {
+                            log::debug!("Failed to answer HTTP request ({e})");
+                        }
+                    });
+                }
+            })
+            .context("Failed to start HTTP server thread")?;
+        Ok(HttpOutput {
+            live,
+            display,
+            event_measurement: app_config.monitoring.event_measurement.clone(),
+            link_measurement: app_config.monitoring.link_measurement.clone(),
+        })
+    }
+}
+
+impl Output for HttpOutput {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        self.display.lock().unwrap_or_else(PoisonError::into_inner).update(samples);
+        let mut live = self.live.lock().unwrap_or_else(PoisonError::into_inner);
+        for sample in samples {
+            let Some(inverter) = sample.tag_value("inverter") else {
+                continue;
+            };
+            if sample.measurement == self.event_measurement {
+                let values = live.entry(inverter.to_owned()).or_default();
+                values.state = sample.tag_value("state").map(str::to_owned);
+                values.error_code = sample.tag_value("error_code").map(str::to_owned);
+                continue;
+            }
+            if self.link_measurement.as_ref() == Some(&sample.measurement) {
+                let values = live.entry(inverter.to_owned()).or_default();
+                values.link = sample.fields.iter().cloned().collect();
+                continue;
+            }
+            if let Some(input) = sample.tag_value("input") {
+                let (Some(voltage), Some(current), Some(power)) = (
+                    sample.field_value("voltage"),
+                    sample.field_value("current"),
+                    sample.field_value("power"),
+                ) else {
+                    continue;
+                };
+                let values = live.entry(inverter.to_owned()).or_default();
+                values.inputs.insert(input.to_owned(), [voltage, current, power]);
+                continue;
+            }
+            let Some(ac_power) = sample.field_value("ac_power") else {
+                continue;
+            };
+            let values = live.entry(inverter.to_owned()).or_default();
+            values.time = Some(sample.time);
+            values.trace_id.clone_from(&sample.trace_id);
+            values.ac_power = ac_power;
+            // Polls only reading the fast changing values keep the others.
+            if let Some(energy_today) = sample.field_value("energy_today") {
+                values.energy_today = energy_today;
+            }
+            if let Some(energy_total) = sample.field_value("energy_total") {
+                values.energy_total = energy_total;
+            }
+            for name in DIAGNOSTIC_FIELDS {
+                if let Some(value) = sample.field_value(name) {
+                    values.diagnostics.insert(name.to_owned(), value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_config(config: &str) -> HttpConfig {
+        toml::from_str(config).unwrap()
+    }
+
+    #[test]
+    fn settings_need_an_admin() {
+        assert!(http_config("settings = true").validate().is_err());
+        let reader = "settings = true\nusers = [{ name = \"grafana\", token = \"t\" }]";
+        assert!(http_config(reader).validate().is_err());
+        let admin = "settings = true\nusers = [{ name = \"a\", password = \"p\", role = \"admin\" }]";
+        assert!(http_config(admin).validate().is_ok());
+    }
+
+    #[test]
+    fn control_needs_an_admin() {
+        assert!(http_config("control = true").validate().is_err());
+        let admin = "control = true\nusers = [{ name = \"a\", token = \"t\", role = \"admin\" }]";
+        assert!(http_config(admin).validate().is_ok());
+    }
+
+    const USERS: &str = "users = [
+        { name = \"admin\", password = \"se:cret\", role = \"admin\" },
+        { name = \"grafana\", token = \"0123456789abcdef\" },
+    ]";
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+    }
+
+    fn request(method: &str, path: &str, authorization: Option<&str>) -> Request {
+        Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            authorization: authorization.map(str::to_owned),
+            host: None,
+            source: None,
+            language: None,
+            body: Vec::new(),
+        }
+    }
+
+    fn access(
+        config: &HttpConfig,
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+    ) -> String {
+        match check_access(config, &request(method, path, authorization)) {
+            Ok(user) => user.unwrap_or_else(|| "anonymous".to_owned()),
+            Err(response) => response.status.to_owned(),
+        }
+    }
+
+    #[test]
+    fn secrets_have_to_match_exactly() {
+        assert!(secret_eq("secret", "secret"));
+        assert!(!secret_eq("secret", "secreT"));
+        assert!(!secret_eq("secret", "secre"));
+        assert!(!secret_eq("secret", "secrets"));
+        assert!(!secret_eq("secret", ""));
+    }
+
+    #[test]
+    fn authenticates_basic_and_bearer() {
+        let config = http_config(USERS);
+        let name = |authorization: &str| {
+            authenticate(&config.users, authorization).map(|user| user.name.as_str())
+        };
+        assert_eq!(name(&basic("admin:se:cret")), Some("admin"));
+        assert_eq!(name(&basic("admin:se:cret").replace("Basic", "basic")), Some("admin"));
+        assert_eq!(name("Bearer 0123456789abcdef"), Some("grafana"));
+        assert_eq!(name("bearer  0123456789abcdef "), Some("grafana"));
+    }
+
+    #[test]
+    fn rejects_wrong_credentials() {
+        let config = http_config(USERS);
+        let users = &config.users;
+        assert!(authenticate(users, &basic("admin:secret")).is_none());
+        assert!(authenticate(users, &basic("admin:")).is_none());
+        assert!(authenticate(users, &basic("grafana:se:cret")).is_none());
+        // The token only works as bearer, and passwords not as token.
+        assert!(authenticate(users, &basic("grafana:0123456789abcdef")).is_none());
+        assert!(authenticate(users, "Bearer se:cret").is_none());
+        assert!(authenticate(users, "Bearer 0123456789abcde").is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_authorization() {
+        let config = http_config(USERS);
+        let users = &config.users;
+        assert!(authenticate(users, "").is_none());
+        assert!(authenticate(users, "Basic").is_none());
+        assert!(authenticate(users, "Bearer").is_none());
+        assert!(authenticate(users, "Basic not*base64").is_none());
+        assert!(authenticate(users, &basic("admin")).is_none());
+        let invalid_utf8 = base64::engine::general_purpose::STANDARD.encode([0xff, b':', b'x']);
+        assert!(authenticate(users, &format!("Basic {invalid_utf8}")).is_none());
+        assert!(authenticate(users, "Token 0123456789abcdef").is_none());
+        assert!(authenticate(users, "Digest username=\"admin\"").is_none());
+    }
+
+    #[test]
+    fn checks_the_role() {
+        let config = http_config(USERS);
+        let admin = basic("admin:se:cret");
+        let token = "Bearer 0123456789abcdef";
+        assert_eq!(access(&config, "GET", "/api/status", Some(token)), "grafana");
+        assert_eq!(access(&config, "GET", "/api/status", Some(&admin)), "admin");
+        assert_eq!(access(&config, "GET", "/settings", Some(token)), "403 Forbidden");
+        assert_eq!(access(&config, "GET", "/api/config?x=1", Some(token)), "403 Forbidden");
+        assert_eq!(access(&config, "POST", "/api/control", Some(token)), "403 Forbidden");
+        assert_eq!(access(&config, "GET", "/settings", Some(&admin)), "admin");
+        assert_eq!(access(&config, "POST", "/api/control", Some(&admin)), "admin");
+    }
+
+    #[test]
+    fn needs_credentials_with_users() {
+        let config = http_config(USERS);
+        assert_eq!(access(&config, "GET", "/api/status", None), "401 Unauthorized");
+        let wrong = basic("admin:wrong");
+        assert_eq!(access(&config, "GET", "/api/status", Some(&wrong)), "401 Unauthorized");
+        assert_eq!(access(&config, "GET", "/api/status", Some("Bearer")), "401 Unauthorized");
+    }
+
+    #[test]
+    fn without_users_only_reading_is_allowed() {
+        let config = http_config("");
+        assert_eq!(access(&config, "GET", "/api/status", None), "anonymous");
+        assert_eq!(access(&config, "GET", "/settings", None), "403 Forbidden");
+        assert_eq!(access(&config, "POST", "/api/control", None), "403 Forbidden");
+        let admin = basic("admin:se:cret");
+        assert_eq!(access(&config, "POST", "/api/control", Some(&admin)), "403 Forbidden");
+    }
+}
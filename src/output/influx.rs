@@ -0,0 +1,155 @@
+use super::{Output, Sample};
+use crate::MonitoringConfig;
+use anyhow::Context;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use super::influx3::{database, to_lines, Databases, InfluxSchema};
+use crate::self_metrics::INFLUX_RECONNECTS;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// Lines kept while the database is unreachable, older ones are dropped.
+const MAX_BUFFERED_LINES: usize = 100_000;
+
+/// Failed writes in a row after which the client is rebuilt, so the host
+/// name is resolved again instead of reusing connections to an old address.
+const RECONNECT_AFTER_FAILURES: u32 = 3;
+
+pub(crate) struct InfluxOutput {
+    client: reqwest::blocking::Client,
+    url: reqwest::Url,
+    database: String,
+    /// Databases of the inverters writing elsewhere than `database`
+    databases: Databases,
+    gzip: bool,
+    schema: InfluxSchema,
+    flush_intervall: Option<Duration>,
+    /// Lines not written yet with their database and when the oldest of
+    /// them was added
+    buffer: Vec<(String, String)>,
+    buffered_since: Option<Instant>,
+    /// Failed writes since the last successful one
+    failures: u32,
+}
+
+impl InfluxOutput {
+    pub(crate) fn new(
+        monitoring_config: &MonitoringConfig,
+        client: reqwest::blocking::Client,
+        databases: Databases,
+    ) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(&format!(
+            "http://{}:{}/write",
+            monitoring_config.influx_ip, monitoring_config.influx_port
+        ))
+        .context("Influxdb host or port invalid")?;
+        Ok(InfluxOutput {
+            client,
+            url,
+            database: monitoring_config.database.clone(),
+            databases,
+            gzip: monitoring_config.influx_gzip,
+            schema: monitoring_config.influx_schema,
+            flush_intervall: monitoring_config
+                .influx_flush_intervall_secs
+                .map(|secs| Duration::from_secs(secs.into())),
+            buffer: Vec::new(),
+            buffered_since: None,
+            failures: 0,
+        })
+    }
+
+    /// Writes the buffer and rebuilds the client once writes failed
+    /// repeatedly, e.g. because the database container got a new address.
+    fn write_buffer(&mut self) -> anyhow::Result<()> {
+        let result = self.flush_databases();
+        if result.is_ok() {
+            self.failures = 0;
+            return result;
+        }
+        self.failures += 1;
+        if self.failures >= RECONNECT_AFTER_FAILURES {
+            log::warn!(
+                "{} failed writes in a row, reconnecting to {}",
+                self.failures,
+                self.url.host_str().unwrap_or_default()
+            );
+            self.client = reqwest::blocking::Client::new();
+            self.failures = 0;
+            INFLUX_RECONNECTS.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Writes the buffered lines with one request per database. Lines of
+    /// databases that failed stay buffered.
+    fn flush_databases(&mut self) -> anyhow::Result<()> {
+        let databases: BTreeSet<_> =
+            self.buffer.iter().map(|(database, _)| database.clone()).collect();
+        for database in databases {
+            let body = self
+                .buffer
+                .iter()
+                .filter(|(line_database, _)| *line_database == database)
+                .map(|(_, line)| line.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let mut request = self
+                .client
+                .post(self.url.clone())
+                .query(&[("db", database.as_str()), ("precision", "ns")]);
+            request = if self.gzip {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body.as_bytes())?;
+                request
+                    .header(reqwest::header::CONTENT_ENCODING, "gzip")
+                    .body(encoder.finish()?)
+            } else {
+                request.body(body)
+            };
+            request
+                .send()
+                .and_then(reqwest::blocking::Response::error_for_status)
+                .with_context(|| format!("Write request to database {database} failed"))?;
+            self.buffer.retain(|(line_database, _)| *line_database != database);
+        }
+        self.buffered_since = None;
+        Ok(())
+    }
+}
+
+impl Output for InfluxOutput {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        for sample in samples {
+            let database = database(&self.databases, &self.database, sample);
+            self.buffer.extend(
+                to_lines(sample, self.schema)
+                    .into_iter()
+                    .map(|line| (database.clone(), line)),
+            );
+        }
+        if self.buffer.len() > MAX_BUFFERED_LINES {
+            let dropped = self.buffer.len() - MAX_BUFFERED_LINES;
+            log::warn!("Dropping {dropped} lines buffered for the database");
+            self.buffer.drain(..dropped);
+        }
+        let buffered_since = *self.buffered_since.get_or_insert_with(Instant::now);
+        match self.flush_intervall {
+            Some(intervall) if buffered_since.elapsed() < intervall => Ok(()),
+            _ => self.write_buffer(),
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.write_buffer()
+    }
+}
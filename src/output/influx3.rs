@@ -0,0 +1,158 @@
+use super::{Output, Sample};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Writes to the v3 write API of InfluxDB 3.x, which authenticates with a
+/// token and addresses databases by name instead of buckets.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct Influx3Config {
+    /// Base url of the server, e.g. `http://localhost:8181`
+    url: String,
+    database: String,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default = "default_influx3_timeout")]
+    timeout_secs: u64,
+    #[serde(default)]
+    schema: InfluxSchema,
+}
+
+/// Layout of the points written to InfluxDB.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InfluxSchema {
+    /// One point per sample with every value as a field of its own
+    #[default]
+    Wide,
+    /// One point per value with the field name as tag `metric` and the
+    /// value as field `value`
+    Narrow,
+}
+
+fn default_influx3_timeout() -> u64 {
+    10
+}
+
+pub(crate) struct Influx3Output {
+    config: Influx3Config,
+    client: reqwest::blocking::Client,
+    /// Databases of the inverters writing elsewhere than `config.database`
+    databases: Databases,
+}
+
+impl Influx3Output {
+    pub(crate) fn new(
+        config: &Influx3Config,
+        client: reqwest::blocking::Client,
+        databases: Databases,
+    ) -> Self {
+        Influx3Output {
+            config: config.clone(),
+            client,
+            databases,
+        }
+    }
+}
+
+/// Databases of the inverters writing elsewhere than the default one, by
+/// inverter name. Shared by the outputs, so inverters added at runtime are
+/// written to theirs as well.
+pub(crate) type Databases = Arc<RwLock<HashMap<String, String>>>;
+
+/// Database a sample is written to, the one of its inverter if that has
+/// its own.
+pub(crate) fn database(databases: &Databases, default: &str, sample: &Sample) -> String {
+    let databases = databases.read().unwrap_or_else(PoisonError::into_inner);
+    sample
+        .tag_value("inverter")
+        .and_then(|inverter| databases.get(inverter))
+        .map_or(default, String::as_str)
+        .to_owned()
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Formats a sample as line protocol with a nanosecond timestamp.
+pub(crate) fn to_line_protocol(sample: &Sample) -> String {
+    let mut line = escape(&sample.measurement, &[',', ' ']);
+    for (name, value) in &sample.tags {
+        line.push(',');
+        line.push_str(&escape(name, &[',', '=', ' ']));
+        line.push('=');
+        line.push_str(&escape(value, &[',', '=', ' ']));
+    }
+    let fields = sample
+        .fields
+        .iter()
+        .map(|(name, value)| format!("{}={value}", escape(name, &[',', '=', ' '])))
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push(' ');
+    line.push_str(&fields);
+    if let Some(nanos) = sample.time.timestamp_nanos_opt() {
+        line.push(' ');
+        line.push_str(&nanos.to_string());
+    }
+    line
+}
+
+/// Formats a sample as lines of line protocol laid out in `schema`.
+pub(crate) fn to_lines(sample: &Sample, schema: InfluxSchema) -> Vec<String> {
+    match schema {
+        InfluxSchema::Wide => vec![to_line_protocol(sample)],
+        InfluxSchema::Narrow => sample
+            .fields
+            .iter()
+            .map(|(name, value)| {
+                let mut point = Sample::new(sample.measurement.clone(), sample.time);
+                point.tags.clone_from(&sample.tags);
+                point = point.tag("metric", name.clone()).field("value", *value);
+                to_line_protocol(&point)
+            })
+            .collect(),
+    }
+}
+
+impl Output for Influx3Output {
+    fn name(&self) -> &str {
+        "influxdb 3"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let mut lines: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for sample in samples {
+            lines
+                .entry(database(&self.databases, &self.config.database, sample))
+                .or_default()
+                .extend(to_lines(sample, self.config.schema));
+        }
+        for (database, lines) in lines {
+            let mut request = self
+                .client
+                .post(format!("{}/api/v3/write_lp", self.config.url.trim_end_matches('/')))
+                .query(&[("db", database.as_str()), ("precision", "nanosecond")])
+                .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .timeout(std::time::Duration::from_secs(self.config.timeout_secs))
+                .body(lines.join("\n"));
+            if let Some(token) = &self.config.token {
+                request = request.bearer_auth(token);
+            }
+            request
+                .send()
+                .and_then(reqwest::blocking::Response::error_for_status)
+                .with_context(|| format!("Write request to database {database} failed"))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use super::{Output, Sample};
+use anyhow::Context;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+use serde::{Deserialize, Serialize};
+
+/// Publishes every sample as JSON message to a Kafka topic, keyed by the
+/// inverter name so the samples of one inverter stay in order.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct KafkaConfig {
+    /// Comma separated list of `host:port` bootstrap servers
+    brokers: String,
+    topic: String,
+    #[serde(default = "default_kafka_timeout")]
+    timeout_secs: u64,
+}
+
+fn default_kafka_timeout() -> u64 {
+    10
+}
+
+pub(crate) struct KafkaOutput {
+    topic: String,
+    timeout: std::time::Duration,
+    producer: BaseProducer,
+}
+
+impl KafkaOutput {
+    pub(crate) fn new(config: &KafkaConfig) -> anyhow::Result<Self> {
+        let timeout = std::time::Duration::from_secs(config.timeout_secs);
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("message.timeout.ms", timeout.as_millis().to_string())
+            .create()
+            .context("Failed to create kafka producer")?;
+        Ok(KafkaOutput {
+            topic: config.topic.clone(),
+            timeout,
+            producer,
+        })
+    }
+}
+
+impl Output for KafkaOutput {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        for sample in samples {
+            let key = sample.tag_value("inverter").unwrap_or_default();
+            let payload = sample.to_json().to_string();
+            self.producer
+                .send(BaseRecord::to(&self.topic).key(key).payload(&payload))
+                .map_err(|(e, _)| e)
+                .context("Failed to queue kafka message")?;
+        }
+        self.producer
+            .flush(self.timeout)
+            .context("Failed to deliver kafka messages")
+    }
+}
@@ -0,0 +1,359 @@
+use crate::control::Control;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use solar_mon::connection_limit;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "ble")]
+mod ble;
+mod dedup;
+mod display;
+mod filter;
+mod home_assistant;
+mod http;
+mod influx;
+mod influx3;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod mqtt;
+mod nats;
+mod prometheus;
+mod report;
+mod sunspec;
+mod template;
+#[cfg(feature = "victron")]
+mod victron;
+
+pub(crate) use filter::SampleFilter;
+pub(crate) use influx::InfluxOutput;
+pub(crate) use influx3::{Databases, InfluxSchema};
+pub(crate) use mqtt::MqttConnection;
+
+/// A set of values measured at the same time, the common representation
+/// handed to every output.
+#[derive(Debug, Clone)]
+pub(crate) struct Sample {
+    pub(crate) measurement: String,
+    pub(crate) time: DateTime<Utc>,
+    pub(crate) tags: Vec<(String, String)>,
+    pub(crate) fields: Vec<(String, f64)>,
+    /// Id of the poll the values come from, to find its log messages
+    pub(crate) trace_id: Option<String>,
+}
+
+impl Sample {
+    pub(crate) fn new(measurement: impl Into<String>, time: DateTime<Utc>) -> Self {
+        Sample {
+            measurement: measurement.into(),
+            time,
+            tags: Vec::new(),
+            fields: Vec::new(),
+            trace_id: None,
+        }
+    }
+
+    pub(crate) fn tag(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((name.into(), value.into()));
+        self
+    }
+
+    pub(crate) fn field(mut self, name: impl Into<String>, value: f64) -> Self {
+        self.fields.push((name.into(), value));
+        self
+    }
+
+    pub(crate) fn tag_value(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn field_value(&self, name: &str) -> Option<f64> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// Copy with the fields listed in `precision` rounded to the given
+    /// number of decimal places.
+    pub(crate) fn rounded(&self, precision: &HashMap<String, u8>) -> Sample {
+        let mut sample = self.clone();
+        for (name, value) in &mut sample.fields {
+            if let Some(decimals) = precision.get(name) {
+                let factor = 10f64.powi(i32::from(*decimals));
+                *value = (*value * factor).round() / factor;
+            }
+        }
+        sample
+    }
+
+    /// Self-describing JSON representation for message based outputs.
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let tags: serde_json::Map<_, _> = self
+            .tags
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone().into()))
+            .collect();
+        let fields: serde_json::Map<_, _> = self
+            .fields
+            .iter()
+            .map(|(name, value)| (name.clone(), (*value).into()))
+            .collect();
+        serde_json::json!({
+            "measurement": self.measurement,
+            "time": self.time.to_rfc3339(),
+            "tags": tags,
+            "fields": fields,
+        })
+    }
+}
+
+pub(crate) trait Output: Send {
+    /// Short description used in log messages.
+    fn name(&self) -> &str;
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()>;
+
+    /// Writes samples held back by `write`, e.g. before the program exits.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Additional outputs configured as `[[output]]` tables, distinguished by
+/// their `type` key.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum OutputConfig {
+    PrometheusRemoteWrite(prometheus::RemoteWriteConfig),
+    Influxdb3(influx3::Influx3Config),
+    Nats(nats::NatsConfig),
+    Mqtt(mqtt::MqttConfig),
+    Sunspec(sunspec::SunSpecConfig),
+    Http(http::HttpConfig),
+    DailyReport(report::ReportConfig),
+    #[cfg(feature = "kafka")]
+    Kafka(kafka::KafkaConfig),
+    #[cfg(feature = "victron")]
+    Victron(victron::VictronConfig),
+    #[cfg(feature = "ble")]
+    Ble(ble::BleConfig),
+}
+
+/// An `[[output]]` table, the output itself and the samples routed to it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct OutputEntry {
+    #[serde(flatten)]
+    pub(crate) output: OutputConfig,
+    /// Only write matching samples, everything is written if missing
+    #[serde(default)]
+    pub(crate) filter: Option<SampleFilter>,
+}
+
+/// All configured outputs together with the processing applied before
+/// samples reach any of them.
+pub(crate) struct Outputs {
+    outputs: Vec<(Box<dyn Output>, Option<SampleFilter>)>,
+    precision: HashMap<String, u8>,
+    dedup: dedup::Deduplicator,
+    databases: Databases,
+}
+
+impl OutputConfig {
+    /// Whether the output serves the latest values to clients, e.g. over
+    /// HTTP or Bluetooth, instead of storing or sending the samples.
+    fn is_server(&self) -> bool {
+        match self {
+            OutputConfig::Sunspec(_) | OutputConfig::Http(_) => true,
+            #[cfg(feature = "victron")]
+            OutputConfig::Victron(_) => true,
+            #[cfg(feature = "ble")]
+            OutputConfig::Ble(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Creates the outputs of the daemon, the InfluxDB output of the monitoring
+/// section followed by all additional outputs. The HTTP and MQTT outputs
+/// accept commands changing the running daemon through `control`.
+pub(crate) fn create_outputs(config: &crate::Config, control: &Control) -> anyhow::Result<Outputs> {
+    build_outputs(config, Some(control))
+}
+
+/// Creates only the outputs storing or sending samples, for commands writing
+/// them once like `reprocess`. Servers would compete with the running daemon
+/// for their ports and serve old samples as live data.
+pub(crate) fn create_sinks(config: &crate::Config) -> anyhow::Result<Outputs> {
+    build_outputs(config, None)
+}
+
+/// All outputs with `control`, only the sinks without.
+fn build_outputs(config: &crate::Config, control: Option<&Control>) -> anyhow::Result<Outputs> {
+    // Shared by all HTTP based outputs so they use one connection pool.
+    let client = reqwest::blocking::Client::new();
+    let databases: Databases = Arc::new(RwLock::new(
+        config
+            .inverter
+            .iter()
+            .filter_map(|(name, inverter_cfg)| Some((name.clone(), inverter_cfg.database.clone()?)))
+            .collect(),
+    ));
+    let mut outputs: Vec<(Box<dyn Output>, Option<SampleFilter>)> = vec![(
+        Box::new(InfluxOutput::new(&config.monitoring, client.clone(), databases.clone())?),
+        None,
+    )];
+    for entry in &config.output {
+        if control.is_none() && entry.output.is_server() {
+            continue;
+        }
+        let output: Box<dyn Output> = match &entry.output {
+            OutputConfig::PrometheusRemoteWrite(cfg) => {
+                Box::new(prometheus::RemoteWriteOutput::new(cfg, client.clone()))
+            }
+            OutputConfig::Influxdb3(cfg) => {
+                Box::new(influx3::Influx3Output::new(cfg, client.clone(), databases.clone()))
+            }
+            OutputConfig::Nats(cfg) => Box::new(nats::NatsOutput::new(cfg)),
+            OutputConfig::Mqtt(cfg) => Box::new(mqtt::MqttOutput::new(
+                cfg,
+                &config.monitoring.event_measurement,
+                control,
+            )?),
+            OutputConfig::Http(cfg) => Box::new(http::HttpOutput::new(cfg, config, control)?),
+            OutputConfig::DailyReport(cfg) => Box::new(report::ReportOutput::new(cfg, config)?),
+            OutputConfig::Sunspec(cfg) => {
+                let inverters: Vec<_> = config.inverter.keys().map(String::as_str).collect();
+                Box::new(sunspec::SunSpecOutput::new(cfg, &inverters)?)
+            }
+            #[cfg(feature = "kafka")]
+            OutputConfig::Kafka(cfg) => Box::new(kafka::KafkaOutput::new(cfg)?),
+            #[cfg(feature = "victron")]
+            OutputConfig::Victron(cfg) => {
+                let inverters: Vec<_> = config.inverter.keys().map(String::as_str).collect();
+                Box::new(victron::VictronOutput::new(cfg, &inverters))
+            }
+            #[cfg(feature = "ble")]
+            OutputConfig::Ble(cfg) => Box::new(ble::BleOutput::new(
+                cfg,
+                &config.monitoring.event_measurement,
+            )),
+        };
+        outputs.push((output, entry.filter.clone()));
+    }
+    Ok(Outputs {
+        outputs,
+        precision: config.monitoring.precision.clone(),
+        dedup: dedup::Deduplicator::default(),
+        databases,
+    })
+}
+
+/// Checks the outputs for settings that can't work or aren't safe.
+pub(crate) fn validate(config: &crate::Config) -> anyhow::Result<()> {
+    for entry in &config.output {
+        if let OutputConfig::Http(cfg) = &entry.output {
+            cfg.validate()?;
+        }
+    }
+    Ok(())
+}
+
+/// Base URL of the first HTTP output accepting commands, for sending them
+/// to the running daemon from the command line.
+pub(crate) fn control_url(config: &crate::Config) -> Option<String> {
+    config.output.iter().find_map(|entry| match &entry.output {
+        OutputConfig::Http(cfg) => cfg.control_url(),
+        _ => None,
+    })
+}
+
+impl Outputs {
+    /// Databases of the inverters by name, for inverters added at runtime.
+    pub(crate) fn databases(&self) -> Databases {
+        self.databases.clone()
+    }
+
+    /// Names of the outputs in the order they are written.
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.outputs.iter().map(|(output, _)| output.name().to_owned()).collect()
+    }
+
+    /// Writes the samples to every output and returns the result of each,
+    /// `None` if its filter left nothing to write. Samples that were written
+    /// before are skipped.
+    pub(crate) fn write_each(&mut self, samples: &[Sample]) -> Vec<Option<anyhow::Result<()>>> {
+        let samples = &self.dedup.apply(samples);
+        if samples.is_empty() {
+            return self.outputs.iter().map(|_| None).collect();
+        }
+        let rounded;
+        let samples = if self.precision.is_empty() {
+            samples
+        } else {
+            rounded = samples
+                .iter()
+                .map(|sample| sample.rounded(&self.precision))
+                .collect::<Vec<_>>();
+            &rounded
+        };
+        let mut results = Vec::with_capacity(self.outputs.len());
+        for (output, filter) in self.outputs.iter_mut() {
+            let filtered;
+            let samples = match filter {
+                Some(filter) => {
+                    filtered = filter.apply(samples);
+                    if filtered.is_empty() {
+                        results.push(None);
+                        continue;
+                    }
+                    &filtered
+                }
+                None => samples,
+            };
+            let _permit = connection_limit::acquire(output.name());
+            results.push(Some(output.write(samples)));
+        }
+        results
+    }
+
+    /// Writes the samples to every output. Failures are logged, the returned
+    /// flag tells whether all outputs succeeded.
+    pub(crate) fn write_all(&mut self, samples: &[Sample], context: &str) -> bool {
+        let results = self.write_each(samples);
+        let mut success = true;
+        for (index, ((output, _), result)) in self.outputs.iter().zip(results).enumerate() {
+            let Some(result) = result else {
+                continue;
+            };
+            crate::total_failure::record_output(index, result.is_ok());
+            if let Err(e) = result {
+                log::error!("[{context}] Failed to store data in {} ({e:#})", output.name());
+                success = false;
+            }
+        }
+        success
+    }
+
+    /// Flushes every output and returns the result of each.
+    pub(crate) fn flush_each(&mut self) -> Vec<anyhow::Result<()>> {
+        self.outputs.iter_mut().map(|(output, _)| output.flush()).collect()
+    }
+
+    /// Flushes every output. Failures are logged, the returned flag tells
+    /// whether all outputs succeeded.
+    pub(crate) fn flush_all(&mut self, context: &str) -> bool {
+        let results = self.flush_each();
+        let mut success = true;
+        for ((output, _), result) in self.outputs.iter().zip(results) {
+            if let Err(e) = result {
+                log::error!("[{context}] Failed to store data in {} ({e:#})", output.name());
+                success = false;
+            }
+        }
+        success
+    }
+}
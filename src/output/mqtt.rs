@@ -0,0 +1,534 @@
+use super::display::Display;
+use super::home_assistant;
+use super::template::Template;
+use super::{Output, Sample};
+use crate::audit::Origin;
+use crate::control::{Command, Control};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Publishes samples to an MQTT broker, topics and payloads are built from
+/// templates.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct MqttConfig {
+    /// `host:port` of the broker
+    address: String,
+    #[serde(default = "default_mqtt_client_id")]
+    client_id: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    /// Topic template, placeholders are `{measurement}`, `{field}` and the
+    /// tag names. With `{field}` every field is published on its own.
+    #[serde(default = "default_mqtt_topic")]
+    topic: String,
+    /// `value` for the plain field value, `json` for a JSON object,
+    /// `display` for the compact line of [`Display`] or a template which can
+    /// additionally use `{value}`, `{time}` and `{timestamp}`
+    #[serde(default = "default_mqtt_payload")]
+    payload: String,
+    #[serde(default)]
+    retain: bool,
+    /// 0 (fire and forget) or 1 (acknowledged by the broker)
+    #[serde(default = "default_mqtt_qos")]
+    qos: u8,
+    #[serde(default = "default_mqtt_timeout")]
+    timeout_secs: u64,
+    /// Announce every field as sensor via Home Assistant MQTT discovery
+    #[serde(default)]
+    home_assistant: bool,
+    #[serde(default = "default_discovery_prefix")]
+    discovery_prefix: String,
+    /// Accept commands published below this topic, e.g.
+    /// `<command_topic>/inverters/add`
+    #[serde(default)]
+    command_topic: Option<String>,
+    /// Accept `inverters/add` on the command topic. Messages carry no user,
+    /// so anyone allowed to publish there could add inverters
+    #[serde(default)]
+    allow_add_inverter: bool,
+}
+
+fn default_mqtt_client_id() -> String {
+    "solar_mon".to_owned()
+}
+
+fn default_mqtt_topic() -> String {
+    "solar/{inverter}/{input}/{field}".to_owned()
+}
+
+fn default_mqtt_payload() -> String {
+    "value".to_owned()
+}
+
+fn default_mqtt_qos() -> u8 {
+    1
+}
+
+fn default_mqtt_timeout() -> u64 {
+    10
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_owned()
+}
+
+enum Payload {
+    Value,
+    Json,
+    /// One message with the totals of all inverters on the topic as is
+    Display(Display),
+    Template(Template),
+}
+
+/// A single message ready to be published.
+pub(crate) struct Message {
+    pub(crate) topic: String,
+    pub(crate) payload: Vec<u8>,
+    pub(crate) retain: bool,
+}
+
+/// Minimal MQTT 3.1.1 session, enough to publish with QoS 0 and 1 and to
+/// receive messages with QoS 0.
+pub(crate) struct MqttConnection {
+    stream: TcpStream,
+    next_packet_id: u16,
+    qos: u8,
+}
+
+impl MqttConnection {
+    pub(crate) fn connect(
+        address: &str,
+        client_id: &str,
+        username: Option<&str>,
+        password: Option<&str>,
+        qos: u8,
+        timeout: std::time::Duration,
+    ) -> anyhow::Result<Self> {
+        let addr = address
+            .to_socket_addrs()
+            .context("Invalid MQTT address")?
+            .next()
+            .context("MQTT address did not resolve")?;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut body = Vec::new();
+        write_string(&mut body, "MQTT");
+        // protocol level 4 (3.1.1)
+        body.push(4);
+        let mut flags = 0x02; // clean session
+        if username.is_some() {
+            flags |= 0x80;
+        }
+        if password.is_some() {
+            flags |= 0x40;
+        }
+        body.push(flags);
+        // Keep alive disabled, the connection is only used while writing.
+        body.extend(0u16.to_be_bytes());
+        write_string(&mut body, client_id);
+        if let Some(username) = username {
+            write_string(&mut body, username);
+        }
+        if let Some(password) = password {
+            write_string(&mut body, password);
+        }
+        stream.write_all(&packet(0x10, &body))?;
+
+        let (packet_type, body) = read_packet(&mut stream)?;
+        if packet_type >> 4 != 2 || body.len() != 2 {
+            anyhow::bail!("Unexpected MQTT packet {packet_type:#04x} instead of CONNACK");
+        }
+        match body[1] {
+            0 => Ok(MqttConnection {
+                stream,
+                next_packet_id: 1,
+                qos,
+            }),
+            4 | 5 => anyhow::bail!("MQTT broker rejected the credentials"),
+            code => anyhow::bail!("MQTT broker refused the connection (code {code})"),
+        }
+    }
+
+    /// Publishes all messages and with QoS 1 waits until the broker
+    /// acknowledged every one of them.
+    pub(crate) fn publish(&mut self, messages: &[Message]) -> anyhow::Result<()> {
+        let mut buffer = Vec::new();
+        let mut pending = Vec::new();
+        for message in messages {
+            let mut body = Vec::new();
+            write_string(&mut body, &message.topic);
+            if self.qos > 0 {
+                let packet_id = self.next_packet_id;
+                self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+                body.extend(packet_id.to_be_bytes());
+                pending.push(packet_id);
+            }
+            body.extend_from_slice(&message.payload);
+            let header = 0x30 | (self.qos.min(1) << 1) | u8::from(message.retain);
+            buffer.extend(packet(header, &body));
+        }
+        self.stream.write_all(&buffer)?;
+        while !pending.is_empty() {
+            let (packet_type, body) = read_packet(&mut self.stream)?;
+            if packet_type >> 4 == 4 && body.len() >= 2 {
+                let packet_id = u16::from_be_bytes([body[0], body[1]]);
+                pending.retain(|id| *id != packet_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MqttConnection {
+    /// Subscribes to `filter` with QoS 0.
+    pub(crate) fn subscribe(&mut self, filter: &str) -> anyhow::Result<()> {
+        let mut body = Vec::new();
+        body.extend(self.next_packet_id.to_be_bytes());
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        write_string(&mut body, filter);
+        body.push(0);
+        self.stream.write_all(&packet(0x82, &body))?;
+        let (packet_type, body) = read_packet(&mut self.stream)?;
+        if packet_type >> 4 != 9 || body.get(2).is_none_or(|code| *code == 0x80) {
+            anyhow::bail!("MQTT broker rejected the subscription to {filter}");
+        }
+        Ok(())
+    }
+
+    /// Waits for the next message, without a timeout.
+    pub(crate) fn receive(&mut self) -> anyhow::Result<(String, Vec<u8>)> {
+        self.stream.set_read_timeout(None)?;
+        loop {
+            let (packet_type, body) = read_packet(&mut self.stream)?;
+            if packet_type >> 4 != 3 || body.len() < 2 {
+                continue;
+            }
+            let topic_len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+            let Some(topic) = body.get(2..2 + topic_len) else {
+                anyhow::bail!("Malformed MQTT message");
+            };
+            let topic = String::from_utf8_lossy(topic).into_owned();
+            // Messages are delivered with the QoS of the subscription at most.
+            return Ok((topic, body[2 + topic_len..].to_vec()));
+        }
+    }
+}
+
+/// Runs `command`, `None` if there is no such command. Only adding an
+/// inverter uses the payload, and only if `allow_add_inverter` is set.
+fn run_command(
+    config: &MqttConfig,
+    control: &Control,
+    command: &str,
+    payload: &[u8],
+) -> Option<anyhow::Result<()>> {
+    // MQTT messages carry no user.
+    let origin = Origin::new("mqtt", None);
+    match command {
+        "inverters/add" if !config.allow_add_inverter => Some(Err(anyhow::anyhow!(
+            "Adding inverters over MQTT needs allow_add_inverter = true"
+        ))),
+        "inverters/add" => Some(control.add_inverter(&origin, payload)),
+        _ => {
+            let path = command.strip_prefix("inverters/")?;
+            Some(control.send(&origin, Command::for_inverter(path)?))
+        }
+    }
+}
+
+/// Runs the commands published below the command topic and publishes the
+/// result of each to `<command_topic>/result`. Reconnects after failures.
+fn listen_for_commands(config: &MqttConfig, command_topic: &str, control: &Control) {
+    loop {
+        if let Err(e) = serve_commands(config, command_topic, control) {
+            log::warn!("MQTT command subscription failed ({e:#}), retrying in 30s");
+        }
+        std::thread::sleep(std::time::Duration::from_secs(30));
+    }
+}
+
+fn serve_commands(config: &MqttConfig, command_topic: &str, control: &Control) -> anyhow::Result<()> {
+    let mut connection = MqttConnection::connect(
+        &config.address,
+        // A second session next to the one publishing the samples
+        &format!("{}_commands", config.client_id),
+        config.username.as_deref(),
+        config.password.as_deref(),
+        0,
+        std::time::Duration::from_secs(config.timeout_secs),
+    )?;
+    connection.subscribe(&format!("{command_topic}/#"))?;
+    log::info!("Listening for MQTT commands on {command_topic}/#");
+    loop {
+        let (topic, payload) = connection.receive()?;
+        let command = topic.strip_prefix(command_topic).and_then(|rest| rest.strip_prefix('/'));
+        let Some(command) = command else {
+            continue;
+        };
+        // Unknown topics include the results published below.
+        let Some(result) = run_command(config, control, command, &payload) else {
+            continue;
+        };
+        let result = match result {
+            Ok(()) => serde_json::json!({ "command": command, "ok": true }),
+            Err(e) => {
+                log::warn!("MQTT command {command} failed ({e:#})");
+                serde_json::json!({ "command": command, "ok": false, "error": format!("{e:#}") })
+            }
+        };
+        connection.publish(&[Message {
+            topic: format!("{command_topic}/result"),
+            payload: result.to_string().into_bytes(),
+            retain: false,
+        }])?;
+    }
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    let len = u16::try_from(value.len()).unwrap_or(u16::MAX);
+    buffer.extend(len.to_be_bytes());
+    buffer.extend_from_slice(&value.as_bytes()[..usize::from(len)]);
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn read_packet(stream: &mut TcpStream) -> anyhow::Result<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte).context("MQTT broker closed the connection")?;
+    let packet_type = byte[0];
+    let mut length = 0usize;
+    for shift in 0..4 {
+        stream.read_exact(&mut byte)?;
+        length |= usize::from(byte[0] & 0x7f) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0; length];
+            stream.read_exact(&mut body)?;
+            return Ok((packet_type, body));
+        }
+    }
+    anyhow::bail!("Malformed MQTT packet length")
+}
+
+pub(crate) struct MqttOutput {
+    config: MqttConfig,
+    topic: Template,
+    payload: Payload,
+    connection: Option<MqttConnection>,
+    /// State topics announced to Home Assistant in this session
+    announced: HashSet<String>,
+    /// Last value of every lifetime counter by state topic
+    totals: HashMap<String, f64>,
+    /// Last display payload published
+    displayed: Option<String>,
+}
+
+impl MqttOutput {
+    pub(crate) fn new(
+        config: &MqttConfig,
+        event_measurement: &str,
+        control: Option<&Control>,
+    ) -> anyhow::Result<Self> {
+        let topic = Template::parse(&config.topic).context("Invalid MQTT topic template")?;
+        let payload = match config.payload.as_str() {
+            "value" => Payload::Value,
+            "json" => Payload::Json,
+            "display" => Payload::Display(Display::new(event_measurement)),
+            template => Payload::Template(
+                Template::parse(template).context("Invalid MQTT payload template")?,
+            ),
+        };
+        let needs_field = match &payload {
+            Payload::Value => true,
+            Payload::Json | Payload::Display(_) => false,
+            Payload::Template(template) => template.uses("value"),
+        };
+        if matches!(payload, Payload::Display(_)) && !topic.is_literal() {
+            anyhow::bail!("The MQTT display payload requires a topic without placeholders");
+        }
+        if needs_field && !topic.uses("field") {
+            anyhow::bail!("MQTT payloads with a single value require {{field}} in the topic");
+        }
+        if config.qos > 1 {
+            anyhow::bail!("MQTT QoS {} is not supported", config.qos);
+        }
+        if config.home_assistant
+            && (!topic.uses("field")
+                || matches!(payload, Payload::Template(_) | Payload::Display(_)))
+        {
+            anyhow::bail!("Home Assistant discovery requires {{field}} in the topic and a value or json payload");
+        }
+        if let (Some(command_topic), Some(control)) = (&config.command_topic, control) {
+            let config = config.clone();
+            let command_topic = command_topic.trim_end_matches('/').to_owned();
+            let control = control.clone();
+            std::thread::Builder::new()
+                .name("mqtt commands".to_owned())
+                .spawn(move || listen_for_commands(&config, &command_topic, &control))
+                .context("Failed to start MQTT command thread")?;
+        }
+        Ok(MqttOutput {
+            config: config.clone(),
+            topic,
+            payload,
+            connection: None,
+            announced: HashSet::new(),
+            totals: HashMap::new(),
+            displayed: None,
+        })
+    }
+
+    fn messages(&mut self, samples: &[Sample]) -> Vec<Message> {
+        if let Payload::Display(display) = &mut self.payload {
+            display.update(samples);
+            let payload = display.payload();
+            if payload.is_none() || payload == self.displayed {
+                return Vec::new();
+            }
+            self.displayed.clone_from(&payload);
+            return vec![Message {
+                topic: self.topic.render(|_| None),
+                payload: payload.unwrap_or_default().into_bytes(),
+                retain: self.config.retain,
+            }];
+        }
+        let mut messages = Vec::new();
+        for sample in samples {
+            let time = sample.time.to_rfc3339();
+            let timestamp = sample.time.timestamp().to_string();
+            let fields: Vec<_> = if self.topic.uses("field") {
+                sample.fields.iter().map(|(name, value)| Some((name.as_str(), *value))).collect()
+            } else {
+                vec![None]
+            };
+            for field in fields {
+                let value = field.map(|(_, value)| value.to_string());
+                let value_of = |name: &str| match name {
+                    "measurement" => Some(sample.measurement.as_str()),
+                    "field" => field.map(|(name, _)| name),
+                    "value" => value.as_deref(),
+                    "time" => Some(time.as_str()),
+                    "timestamp" => Some(timestamp.as_str()),
+                    tag => sample.tag_value(tag),
+                };
+                let payload = match (&self.payload, field) {
+                    (Payload::Value, _) => value.clone().unwrap_or_default(),
+                    (Payload::Json, Some((name, field_value))) => {
+                        // Same layout as for whole samples, reduced to the one field
+                        let mut json = sample.to_json();
+                        json["fields"] = serde_json::json!({ name: field_value });
+                        json.to_string()
+                    }
+                    (Payload::Json, None) => sample.to_json().to_string(),
+                    (Payload::Template(template), _) => template.render(value_of),
+                    // Published as a whole above
+                    (Payload::Display(_), _) => continue,
+                };
+                let topic = clean_topic(&self.topic.render(value_of));
+                if let (true, Some((name, field_value))) = (self.config.home_assistant, field) {
+                    if home_assistant::is_lifetime_total(name) {
+                        let last = self.totals.entry(topic.clone()).or_insert(field_value);
+                        if field_value < *last {
+                            log::debug!("Skipping {topic} = {field_value}, counter went down from {last}");
+                            continue;
+                        }
+                        *last = field_value;
+                    }
+                    if !self.announced.contains(&topic) {
+                        messages.push(home_assistant::discovery_message(
+                            &self.config.discovery_prefix,
+                            sample,
+                            name,
+                            &topic,
+                            matches!(self.payload, Payload::Json),
+                        ));
+                        self.announced.insert(topic.clone());
+                    }
+                }
+                messages.push(Message {
+                    topic,
+                    payload: payload.into_bytes(),
+                    retain: self.config.retain,
+                });
+            }
+        }
+        messages
+    }
+
+    fn publish(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let messages = self.messages(samples);
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert(MqttConnection::connect(
+                &self.config.address,
+                &self.config.client_id,
+                self.config.username.as_deref(),
+                self.config.password.as_deref(),
+                self.config.qos,
+                std::time::Duration::from_secs(self.config.timeout_secs),
+            )?),
+        };
+        connection.publish(&messages)
+    }
+}
+
+/// Removes the empty levels left by placeholders of missing tags, e.g. the
+/// `input` of inverter events.
+fn clean_topic(topic: &str) -> String {
+    topic.split('/').filter(|level| !level.is_empty()).collect::<Vec<_>>().join("/")
+}
+
+impl Output for MqttOutput {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let result = self.publish(samples);
+        if result.is_err() {
+            // Reconnect on the next write instead of reusing a broken session.
+            self.connection = None;
+            self.announced.clear();
+            self.displayed = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adding_inverters_needs_opt_in() {
+        let (control, commands) = Control::channel();
+        let config: MqttConfig = toml::from_str("address = \"127.0.0.1:1883\"").unwrap();
+        let payload = br#"{"name": "garage", "ip": "192.168.1.42"}"#;
+        let result = run_command(&config, &control, "inverters/add", payload).unwrap();
+        assert!(result.unwrap_err().to_string().contains("allow_add_inverter"));
+        assert!(commands.try_recv().is_err());
+        assert!(run_command(&config, &control, "inverters/unknown", b"").is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use super::{Output, Sample};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Publishes every sample as JSON message to a NATS subject.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct NatsConfig {
+    /// `host:port` of the NATS server
+    address: String,
+    /// Subject prefix, the inverter name is appended as last token
+    #[serde(default = "default_nats_subject")]
+    subject: String,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default = "default_nats_timeout")]
+    timeout_secs: u64,
+}
+
+fn default_nats_subject() -> String {
+    "solar".to_owned()
+}
+
+fn default_nats_timeout() -> u64 {
+    10
+}
+
+pub(crate) struct NatsOutput {
+    config: NatsConfig,
+    connection: Option<BufReader<TcpStream>>,
+}
+
+impl NatsOutput {
+    pub(crate) fn new(config: &NatsConfig) -> Self {
+        NatsOutput {
+            config: config.clone(),
+            connection: None,
+        }
+    }
+
+    fn connect(&self) -> anyhow::Result<BufReader<TcpStream>> {
+        let timeout = std::time::Duration::from_secs(self.config.timeout_secs);
+        let addr = self
+            .config
+            .address
+            .to_socket_addrs()
+            .context("Invalid NATS address")?
+            .next()
+            .context("NATS address did not resolve")?;
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut connection = BufReader::new(stream);
+
+        let info = read_line(&mut connection)?;
+        if !info.starts_with("INFO") {
+            anyhow::bail!("Unexpected NATS greeting: {info}");
+        }
+        let connect = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "solar_mon",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "auth_token": self.config.token,
+            "user": self.config.username,
+            "pass": self.config.password,
+        });
+        connection
+            .get_mut()
+            .write_all(format!("CONNECT {connect}\r\n").as_bytes())?;
+        Ok(connection)
+    }
+
+    fn publish(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert(self.connect()?),
+        };
+        let mut buffer = Vec::new();
+        for sample in samples {
+            let inverter = sample.tag_value("inverter").unwrap_or("unknown");
+            let payload = sample.to_json().to_string();
+            write!(
+                buffer,
+                "PUB {}.{inverter} {}\r\n{payload}\r\n",
+                self.config.subject,
+                payload.len()
+            )?;
+        }
+        // The PONG confirms that the server processed everything before it.
+        buffer.extend_from_slice(b"PING\r\n");
+        connection.get_mut().write_all(&buffer)?;
+        loop {
+            let line = read_line(connection)?;
+            match line.as_str() {
+                "PONG" => return Ok(()),
+                "PING" => connection.get_mut().write_all(b"PONG\r\n")?,
+                _ if line.starts_with("-ERR") => anyhow::bail!("NATS server error: {line}"),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn read_line(connection: &mut BufReader<TcpStream>) -> anyhow::Result<String> {
+    let mut line = String::new();
+    if connection.read_line(&mut line)? == 0 {
+        anyhow::bail!("NATS server closed the connection");
+    }
+    Ok(line.trim_end().to_owned())
+}
+
+impl Output for NatsOutput {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let result = self.publish(samples);
+        if result.is_err() {
+            // Reconnect on the next write instead of reusing a broken session.
+            self.connection = None;
+        }
+        result
+    }
+}
@@ -0,0 +1,310 @@
+use super::{Output, Sample};
+use anyhow::Context;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+/// Pushes samples with the Prometheus remote write protocol, for setups
+/// where the daemon can't be scraped (e.g. Grafana Cloud or Mimir).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct RemoteWriteConfig {
+    url: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+    #[serde(default)]
+    bearer_token: Option<String>,
+    #[serde(default = "default_metric_prefix")]
+    metric_prefix: String,
+    #[serde(default = "default_remote_write_timeout")]
+    timeout_secs: u64,
+    /// Attach the trace id of the poll to every value as exemplar
+    #[serde(default)]
+    exemplars: bool,
+}
+
+fn default_metric_prefix() -> String {
+    "solar_".to_owned()
+}
+
+fn default_remote_write_timeout() -> u64 {
+    10
+}
+
+// Messages of prometheus/prompb/remote.proto and types.proto, reduced to the
+// fields needed for writing.
+#[derive(Clone, PartialEq, prost::Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<PromSample>,
+    #[prost(message, repeated, tag = "3")]
+    exemplars: Vec<Exemplar>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PromSample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    /// Milliseconds since the epoch
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Exemplar {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(double, tag = "2")]
+    value: f64,
+    /// Milliseconds since the epoch
+    #[prost(int64, tag = "3")]
+    timestamp: i64,
+}
+
+pub(crate) struct RemoteWriteOutput {
+    config: RemoteWriteConfig,
+    client: reqwest::blocking::Client,
+}
+
+impl RemoteWriteOutput {
+    pub(crate) fn new(config: &RemoteWriteConfig, client: reqwest::blocking::Client) -> Self {
+        RemoteWriteOutput {
+            config: config.clone(),
+            client,
+        }
+    }
+
+    /// Every field becomes its own series named `<prefix><field>`, the
+    /// measurement is kept as `measurement` label.
+    fn to_write_request(&self, samples: &[Sample]) -> WriteRequest {
+        let mut timeseries = Vec::new();
+        for sample in samples {
+            for (field, value) in &sample.fields {
+                let mut labels = vec![
+                    Label {
+                        name: "__name__".to_owned(),
+                        value: sanitize_name(&format!("{}{field}", self.config.metric_prefix)),
+                    },
+                    Label {
+                        name: "measurement".to_owned(),
+                        value: sample.measurement.clone(),
+                    },
+                ];
+                labels.extend(sample.tags.iter().map(|(name, value)| Label {
+                    name: sanitize_name(name),
+                    value: value.clone(),
+                }));
+                // The protocol requires labels sorted by name.
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+                let exemplars = match &sample.trace_id {
+                    Some(trace_id) if self.config.exemplars => vec![Exemplar {
+                        labels: vec![Label {
+                            name: "trace_id".to_owned(),
+                            value: trace_id.clone(),
+                        }],
+                        value: *value,
+                        timestamp: sample.time.timestamp_millis(),
+                    }],
+                    _ => Vec::new(),
+                };
+                timeseries.push(TimeSeries {
+                    labels,
+                    samples: vec![PromSample {
+                        value: *value,
+                        timestamp: sample.time.timestamp_millis(),
+                    }],
+                    exemplars,
+                });
+            }
+        }
+        WriteRequest { timeseries }
+    }
+}
+
+/// Metric and label names must match `[a-zA-Z_][a-zA-Z0-9_]*`, register
+/// names like `pv1-power` become `pv1_power`.
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+impl Output for RemoteWriteOutput {
+    fn name(&self) -> &str {
+        "prometheus remote write"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&self.to_write_request(samples).encode_to_vec())
+            .context("Failed to compress remote write request")?;
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+            .header(reqwest::header::CONTENT_ENCODING, "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .timeout(std::time::Duration::from_secs(self.config.timeout_secs))
+            .body(body);
+        if let Some(username) = &self.config.username {
+            request = request.basic_auth(username, self.config.password.as_ref());
+        } else if let Some(token) = &self.config.bearer_token {
+            request = request.bearer_auth(token);
+        }
+        request
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Remote write request failed")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn output(exemplars: bool) -> RemoteWriteOutput {
+        let mut config: RemoteWriteConfig =
+            toml::from_str("url = \"http://127.0.0.1:9090/api/v1/write\"").unwrap();
+        config.exemplars = exemplars;
+        RemoteWriteOutput::new(&config, reqwest::blocking::Client::new())
+    }
+
+    fn sample() -> Sample {
+        let mut sample = Sample::new(
+            "solar",
+            chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        )
+        .tag("inverter", "roof")
+        .tag("input", "A")
+        .field("power", 512.5);
+        sample.trace_id = Some("018b3f6a2c1e0042".to_owned());
+        sample
+    }
+
+    fn labels(series: &TimeSeries) -> Vec<(&str, &str)> {
+        series
+            .labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn sorts_the_labels_by_name() {
+        let request = output(false).to_write_request(&[sample()]);
+        assert_eq!(request.timeseries.len(), 1);
+        let series = &request.timeseries[0];
+        assert_eq!(
+            labels(series),
+            [
+                ("__name__", "solar_power"),
+                ("input", "A"),
+                ("inverter", "roof"),
+                ("measurement", "solar"),
+            ]
+        );
+        assert_eq!(
+            series.samples,
+            [PromSample {
+                value: 512.5,
+                timestamp: 1_700_000_000_000,
+            }]
+        );
+        assert!(series.exemplars.is_empty());
+    }
+
+    #[test]
+    fn one_series_per_field() {
+        let request = output(false).to_write_request(&[sample().field("voltage", 230.0)]);
+        let names: Vec<_> = request
+            .timeseries
+            .iter()
+            .map(|series| labels(series)[0].1)
+            .collect();
+        assert_eq!(names, ["solar_power", "solar_voltage"]);
+    }
+
+    #[test]
+    fn attaches_the_trace_id_as_exemplar() {
+        let request = output(true).to_write_request(&[sample()]);
+        let exemplars = &request.timeseries[0].exemplars;
+        assert_eq!(exemplars.len(), 1);
+        assert_eq!(
+            exemplars[0].labels,
+            [Label {
+                name: "trace_id".to_owned(),
+                value: "018b3f6a2c1e0042".to_owned(),
+            }]
+        );
+        assert_eq!(exemplars[0].value, 512.5);
+        assert_eq!(exemplars[0].timestamp, 1_700_000_000_000);
+
+        let mut untraced = sample();
+        untraced.trace_id = None;
+        let request = output(true).to_write_request(&[untraced]);
+        assert!(request.timeseries[0].exemplars.is_empty());
+    }
+
+    #[test]
+    fn sanitizes_metric_and_label_names() {
+        let sample = Sample::new("solar", chrono::Utc.timestamp_opt(0, 0).unwrap())
+            .tag("grid-phase", "L1")
+            .tag("1st", "x")
+            .field("pv1.power", 1.0);
+        let request = output(false).to_write_request(&[sample]);
+        assert_eq!(
+            labels(&request.timeseries[0]),
+            [
+                ("_1st", "x"),
+                ("__name__", "solar_pv1_power"),
+                ("grid_phase", "L1"),
+                ("measurement", "solar"),
+            ]
+        );
+        assert_eq!(sanitize_name("power_ac"), "power_ac");
+        assert_eq!(sanitize_name(""), "_");
+    }
+
+    #[test]
+    fn golden_encoding() {
+        let mut sample = sample();
+        sample.tags.truncate(1);
+        let encoded = output(true).to_write_request(&[sample]).encode_to_vec();
+        let hex: String = encoded.iter().map(|byte| format!("{byte:02x}")).collect();
+        // One series with the labels __name__, inverter and measurement, the
+        // sample and the exemplar, as decoded by Prometheus.
+        assert_eq!(
+            hex,
+            concat!(
+                "0a83010a170a085f5f6e616d655f5f120b736f6c61725f706f7765720a100a0869",
+                "6e7665727465721204726f6f660a140a0b6d6561737572656d656e741205736f6c",
+                "617212100900000000000480401080d095ffbc311a2e0a1c0a0874726163655f69",
+                "641210303138623366366132633165303034321100000000000480401880d095ff",
+                "bc31",
+            )
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use super::{Output, Sample};
+use crate::aggregation::Aggregator;
+use crate::i18n::tr;
+use crate::table::{OutputFormat, Table};
+use crate::Config;
+use anyhow::Context;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Sends a summary of the day at a fixed local time through Telegram or
+/// email.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct ReportConfig {
+    /// Local time of the report as `HH:MM`, the readings of the day so far
+    /// are summed up
+    #[serde(default = "default_report_time")]
+    time: String,
+    #[serde(default)]
+    telegram: Option<TelegramConfig>,
+    #[serde(default)]
+    email: Option<EmailConfig>,
+}
+
+/// Bot sending the report to a chat.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TelegramConfig {
+    /// Token of the bot as given by the BotFather
+    token: String,
+    chat_id: String,
+    #[serde(default = "default_telegram_url")]
+    url: String,
+}
+
+/// Mail handed to the local mail transfer agent with `sendmail -t`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct EmailConfig {
+    to: String,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default = "default_sendmail")]
+    sendmail: String,
+}
+
+fn default_report_time() -> String {
+    "21:00".to_owned()
+}
+
+fn default_telegram_url() -> String {
+    "https://api.telegram.org".to_owned()
+}
+
+fn default_sendmail() -> String {
+    "/usr/sbin/sendmail".to_owned()
+}
+
+/// Readings of the current local day.
+struct Day {
+    date: Option<NaiveDate>,
+    /// Condenses the totals samples of the day per inverter
+    aggregator: Aggregator,
+    first_reading: BTreeMap<String, DateTime<Utc>>,
+    /// Latest AC power per inverter, summed up for the peak of the
+    /// installation
+    power: BTreeMap<String, f64>,
+    peak_power: f64,
+    /// Latest availability per inverter, see [`crate::availability`]
+    availability: BTreeMap<String, f64>,
+    /// Time, inverter and error code of every fault
+    faults: Vec<(DateTime<Utc>, String, Option<String>)>,
+    /// Day of the last report, so a day is only reported once
+    reported: Option<NaiveDate>,
+}
+
+impl Day {
+    fn new() -> Self {
+        Day {
+            date: None,
+            aggregator: Aggregator::new(std::time::Duration::MAX),
+            first_reading: BTreeMap::new(),
+            power: BTreeMap::new(),
+            peak_power: 0.0,
+            availability: BTreeMap::new(),
+            faults: Vec::new(),
+            reported: None,
+        }
+    }
+
+    /// Starts over on a new day, also without any reading on it.
+    fn roll_over(&mut self, date: NaiveDate) {
+        if self.date.is_some_and(|current| current >= date) {
+            return;
+        }
+        *self = Day {
+            date: Some(date),
+            reported: self.reported,
+            ..Day::new()
+        };
+    }
+
+    /// Text of the report of `today`, `None` if it was already reported.
+    fn report(&mut self, today: NaiveDate) -> Option<String> {
+        self.roll_over(today);
+        if self.reported == Some(today) {
+            return None;
+        }
+        self.reported = Some(today);
+        let date = today;
+        let totals = self.aggregator.flush();
+        let mut text = format!("{} {date}\n", tr("Solar report"));
+        if totals.is_empty() {
+            text.push_str(&format!("{}\n", tr("No readings")));
+            return Some(text);
+        }
+        let mut table = Table::new(["inverter", "energy", "peak", "online", "availability", "faults"]);
+        let mut energy = 0.0;
+        for sample in &totals {
+            let inverter = sample.tag_value("inverter").unwrap_or_default();
+            let inverter_energy = sample.field_value("energy_today_max").unwrap_or_default();
+            energy += inverter_energy;
+            let online = self.first_reading.get(inverter).map(|first| {
+                let local = |time: &DateTime<Utc>| time.with_timezone(&Local).format("%H:%M");
+                format!("{}-{}", local(first), local(&sample.time))
+            });
+            let availability = self.availability.get(inverter);
+            let faults = self.faults.iter().filter(|(_, name, _)| name == inverter).count();
+            table.push(vec![
+                inverter.into(),
+                format!("{inverter_energy:.2} kWh").into(),
+                format!("{:.0} W", sample.field_value("ac_power_max").unwrap_or_default()).into(),
+                online.into(),
+                availability.map(|ratio| format!("{:.1} %", ratio * 100.0)).into(),
+                faults.into(),
+            ]);
+        }
+        text.push_str(&format!(
+            "{} {energy:.2} kWh, {} {:.0} W\n\n",
+            tr("Energy"),
+            tr("peak"),
+            self.peak_power
+        ));
+        let mut rows = Vec::new();
+        if table.write(OutputFormat::Table, &mut rows).is_ok() {
+            text.push_str(&String::from_utf8_lossy(&rows));
+        }
+        if !self.faults.is_empty() {
+            text.push_str(&format!("\n{}\n", tr("Faults")));
+        }
+        for (time, inverter, error_code) in &self.faults {
+            let time = time.with_timezone(&Local).format("%H:%M");
+            let error_code = error_code.as_deref().unwrap_or(tr("no error code"));
+            text.push_str(&format!("{time} {inverter} ({error_code})\n"));
+        }
+        Some(text)
+    }
+}
+
+pub(crate) struct ReportOutput {
+    day: Arc<Mutex<Day>>,
+    event_measurement: String,
+}
+
+impl ReportOutput {
+    pub(crate) fn new(config: &ReportConfig, app_config: &Config) -> anyhow::Result<Self> {
+        let time = NaiveTime::parse_from_str(&config.time, "%H:%M")
+            .with_context(|| format!("Invalid report time {}, expected HH:MM", config.time))?;
+        if config.telegram.is_none() && config.email.is_none() {
+            anyhow::bail!("The daily report needs `telegram` or `email`");
+        }
+        let day = Arc::new(Mutex::new(Day::new()));
+        let thread_day = day.clone();
+        let config = config.clone();
+        std::thread::Builder::new()
+            .name("report".to_owned())
+            .spawn(move || loop {
+                std::thread::sleep(until(time, Local::now()));
+                let today = Local::now().date_naive();
+                let text = thread_day.lock().unwrap_or_else(PoisonError::into_inner).report(today);
+                if let Some(text) = text {
+                    config.send(&text);
+                }
+            })
+            .context("Failed to start report thread")?;
+        Ok(ReportOutput {
+            day,
+            event_measurement: app_config.monitoring.event_measurement.clone(),
+        })
+    }
+}
+
+/// Time from `now` until the next `time` of day, at least a minute so a
+/// report is not sent twice within the same minute.
+fn until(time: NaiveTime, now: DateTime<Local>) -> std::time::Duration {
+    let today = now.date_naive().and_time(time);
+    let next = if today > now.naive_local() + chrono::Duration::minutes(1) {
+        today
+    } else {
+        today + chrono::Duration::days(1)
+    };
+    (next - now.naive_local()).to_std().unwrap_or_default()
+}
+
+impl ReportConfig {
+    /// Sends `text` through every configured channel, failures are logged.
+    fn send(&self, text: &str) {
+        if let Some(telegram) = &self.telegram {
+            if let Err(e) = telegram.send(text) {
+                log::warn!("Failed to send the daily report to Telegram ({e:#})");
+            }
+        }
+        if let Some(email) = &self.email {
+            if let Err(e) = email.send(text) {
+                log::warn!("Failed to send the daily report by email ({e:#})");
+            }
+        }
+    }
+}
+
+impl TelegramConfig {
+    fn send(&self, text: &str) -> anyhow::Result<()> {
+        // Preformatted, so the table stays aligned.
+        let escaped = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+        reqwest::blocking::Client::new()
+            .post(format!("{}/bot{}/sendMessage", self.url, self.token))
+            .json(&serde_json::json!({
+                "chat_id": self.chat_id,
+                "text": format!("<pre>{escaped}</pre>"),
+                "parse_mode": "HTML",
+            }))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Failed to reach Telegram")?;
+        Ok(())
+    }
+}
+
+impl EmailConfig {
+    fn send(&self, text: &str) -> anyhow::Result<()> {
+        let subject = text.lines().next().unwrap_or_default();
+        let mut message = format!("To: {}\n", self.to);
+        if let Some(from) = &self.from {
+            message.push_str(&format!("From: {from}\n"));
+        }
+        message.push_str(&format!(
+            "Subject: {subject}\nContent-Type: text/plain; charset=utf-8\n\n{text}"
+        ));
+        let mut child = Command::new(&self.sendmail)
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.sendmail))?;
+        child
+            .stdin
+            .take()
+            .context("No stdin of sendmail")?
+            .write_all(message.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!("{} failed with {status}", self.sendmail);
+        }
+        Ok(())
+    }
+}
+
+impl Output for ReportOutput {
+    fn name(&self) -> &str {
+        "report"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let mut day = self.day.lock().unwrap_or_else(PoisonError::into_inner);
+        for sample in samples {
+            let Some(inverter) = sample.tag_value("inverter") else {
+                continue;
+            };
+            day.roll_over(sample.time.with_timezone(&Local).date_naive());
+            if sample.measurement == self.event_measurement {
+                if sample.tag_value("state") == Some("faulted") {
+                    let error_code = sample.tag_value("error_code").map(str::to_owned);
+                    day.faults.push((sample.time, inverter.to_owned(), error_code));
+                }
+                continue;
+            }
+            let Some(ac_power) = sample.field_value("ac_power") else {
+                continue;
+            };
+            if sample.tag_value("input").is_some() {
+                continue;
+            }
+            day.first_reading.entry(inverter.to_owned()).or_insert(sample.time);
+            day.power.insert(inverter.to_owned(), ac_power);
+            if let Some(availability) = sample.field_value("availability") {
+                day.availability.insert(inverter.to_owned(), availability);
+            }
+            day.peak_power = day.peak_power.max(day.power.values().sum());
+            let mut totals = Sample::new(sample.measurement.clone(), sample.time);
+            totals.tags.push(("inverter".to_owned(), inverter.to_owned()));
+            totals.fields.push(("ac_power".to_owned(), ac_power));
+            // Polls only reading the fast changing values don't have it.
+            if let Some(energy_today) = sample.field_value("energy_today") {
+                totals.fields.push(("energy_today".to_owned(), energy_today));
+            }
+            day.aggregator.push(vec![totals]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 6, day, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn until_next_report_time() {
+        let time = NaiveTime::from_hms_opt(21, 0, 0).unwrap();
+        let hours = |hours: u64| std::time::Duration::from_secs(hours * 3600);
+        assert_eq!(until(time, local(1, 20, 0, 0)), hours(1));
+        assert_eq!(until(time, local(1, 22, 0, 0)), hours(23));
+        // Just sent, the next one is tomorrow.
+        assert_eq!(until(time, local(1, 21, 0, 30)), hours(24) - std::time::Duration::from_secs(30));
+        assert_eq!(until(time, local(1, 20, 59, 30)), hours(24) + std::time::Duration::from_secs(30));
+    }
+
+    fn output() -> ReportOutput {
+        ReportOutput {
+            day: Arc::new(Mutex::new(Day::new())),
+            event_measurement: "events".to_owned(),
+        }
+    }
+
+    fn reading(time: DateTime<Local>, ac_power: f64, energy_today: f64) -> Sample {
+        Sample::new("roof", time.with_timezone(&Utc))
+            .field("ac_power", ac_power)
+            .field("energy_today", energy_today)
+            .tag("inverter", "roof")
+    }
+
+    #[test]
+    fn reports_the_readings_of_the_day_once() {
+        let mut output = output();
+        output.write(&[reading(local(1, 10, 0, 0), 300.0, 0.5)]).unwrap();
+        output.write(&[reading(local(1, 12, 0, 0), 450.0, 1.5)]).unwrap();
+        let mut day = output.day.lock().unwrap();
+        let text = day.report(local(1, 21, 0, 0).date_naive()).unwrap();
+        assert!(text.contains("2024-06-01"), "{text}");
+        assert!(text.contains("1.50 kWh"), "{text}");
+        assert!(text.contains("450 W"), "{text}");
+        assert_eq!(day.report(local(1, 21, 0, 0).date_naive()), None);
+    }
+
+    #[test]
+    fn reports_a_day_without_readings() {
+        let mut output = output();
+        output.write(&[reading(local(1, 10, 0, 0), 300.0, 0.5)]).unwrap();
+        let mut day = output.day.lock().unwrap();
+        assert!(day.report(local(1, 21, 0, 0).date_naive()).is_some());
+        let text = day.report(local(2, 21, 0, 0).date_naive()).unwrap();
+        assert!(text.contains("2024-06-02"), "{text}");
+        assert!(text.contains(tr("No readings")), "{text}");
+        assert!(!text.contains("0.50 kWh"), "{text}");
+    }
+
+    #[test]
+    fn new_day_starts_over() {
+        let mut output = output();
+        output.write(&[reading(local(1, 10, 0, 0), 300.0, 4.0)]).unwrap();
+        output.write(&[reading(local(2, 10, 0, 0), 200.0, 0.2)]).unwrap();
+        let text = output.day.lock().unwrap().report(local(2, 21, 0, 0).date_naive()).unwrap();
+        assert!(text.contains("0.20 kWh"), "{text}");
+        assert!(text.contains("200 W"), "{text}");
+        assert!(!text.contains("4.00 kWh"), "{text}");
+    }
+}
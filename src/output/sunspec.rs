@@ -0,0 +1,202 @@
+use super::{Output, Sample};
+use crate::modbus_tcp;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Serves the latest data of every inverter as SunSpec device on a Modbus
+/// TCP server, the inverters sorted by name get the unit ids 1, 2, ...
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct SunSpecConfig {
+    #[serde(default = "default_sunspec_listen")]
+    listen: String,
+}
+
+fn default_sunspec_listen() -> String {
+    "0.0.0.0:502".to_owned()
+}
+
+/// First register of the SunSpec map.
+const BASE_ADDRESS: u16 = 40000;
+const NOT_IMPLEMENTED_U16: u16 = 0xFFFF;
+const NOT_IMPLEMENTED_I16: u16 = 0x8000;
+
+/// Register map of each unit id, updated with every write.
+type Devices = Arc<Mutex<HashMap<u8, Vec<u16>>>>;
+
+/// Appends `value` as string of `registers` registers, padded with zeros.
+fn push_string(registers: &mut Vec<u16>, value: &str, len: usize) {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize(len * 2, 0);
+    registers.extend(bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])));
+}
+
+fn scaled(value: f64, scale: i32) -> u16 {
+    (value * 10f64.powi(-scale)).round().clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16 as u16
+}
+
+/// Values of one inverter taken from the samples of a poll.
+#[derive(Debug, Default)]
+struct Reading {
+    ac_power: Option<f64>,
+    energy_total: Option<f64>,
+    dc_voltage: Option<f64>,
+    dc_current: f64,
+    dc_power: f64,
+}
+
+/// SunSpec map with the common model (1), the single phase inverter model
+/// (101) and the end marker.
+fn register_map(unit_id: u8, inverter: &str, reading: &Reading) -> Vec<u16> {
+    let mut registers = vec![0x5375, 0x6e53];
+
+    // Common model
+    registers.extend([1, 66]);
+    push_string(&mut registers, "Deye", 16);
+    push_string(&mut registers, "Micro inverter", 16);
+    push_string(&mut registers, "solar_mon", 8);
+    push_string(&mut registers, env!("CARGO_PKG_VERSION"), 8);
+    push_string(&mut registers, inverter, 16);
+    registers.push(u16::from(unit_id));
+    registers.push(0x8000);
+
+    // Inverter model, values without a source are marked as not implemented
+    let model_start = registers.len();
+    registers.extend([101, 50]);
+    registers.extend([NOT_IMPLEMENTED_U16; 4]);
+    registers.push(NOT_IMPLEMENTED_I16);
+    registers.extend([NOT_IMPLEMENTED_U16; 6]);
+    registers.push(NOT_IMPLEMENTED_I16);
+    registers.push(reading.ac_power.map_or(NOT_IMPLEMENTED_I16, |power| scaled(power, 0)));
+    registers.push(0);
+    registers.extend([NOT_IMPLEMENTED_U16, NOT_IMPLEMENTED_I16]);
+    registers.extend([NOT_IMPLEMENTED_I16; 6]);
+    let energy_wh = reading.energy_total.map_or(0, |energy| (energy * 1000.0).round() as u32);
+    registers.extend([(energy_wh >> 16) as u16, energy_wh as u16]);
+    registers.push(0);
+    registers.extend([scaled(reading.dc_current, -1), (-1i16) as u16]);
+    registers.push(reading.dc_voltage.map_or(NOT_IMPLEMENTED_U16, |voltage| scaled(voltage, -1)));
+    registers.push((-1i16) as u16);
+    registers.extend([scaled(reading.dc_power, 0), 0]);
+    registers.extend([NOT_IMPLEMENTED_I16; 5]);
+    // 2 = sleeping, 4 = MPPT
+    let producing = reading.ac_power.is_some_and(|power| power > 0.0);
+    registers.push(if producing { 4 } else { 2 });
+    registers.push(NOT_IMPLEMENTED_U16);
+    registers.extend([0; 12]);
+    debug_assert_eq!(registers.len() - model_start, 52);
+
+    registers.extend([0xFFFF, 0]);
+    registers
+}
+
+/// Answers one Modbus request, only reading holding registers is
+/// supported.
+fn respond(devices: &Devices, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+    match pdu {
+        [0x03, start_hi, start_lo, count_hi, count_lo] => {
+            let start = u16::from_be_bytes([*start_hi, *start_lo]);
+            let count = usize::from(u16::from_be_bytes([*count_hi, *count_lo]));
+            let devices = devices.lock().unwrap_or_else(PoisonError::into_inner);
+            match devices.get(&unit_id) {
+                None => modbus_tcp::exception(pdu, modbus_tcp::GATEWAY_TARGET_FAILED),
+                Some(_) if count == 0 || count > 125 => modbus_tcp::exception(pdu, 0x03),
+                Some(registers) => {
+                    let offset = start.checked_sub(BASE_ADDRESS).map(usize::from);
+                    match offset.and_then(|offset| registers.get(offset..offset + count)) {
+                        Some(values) => {
+                            let mut response = vec![0x03, (count * 2) as u8];
+                            for value in values {
+                                response.extend(value.to_be_bytes());
+                            }
+                            response
+                        }
+                        None => modbus_tcp::exception(pdu, 0x02),
+                    }
+                }
+            }
+        }
+        [0x03, ..] => modbus_tcp::exception(pdu, 0x03),
+        _ => modbus_tcp::exception(pdu, 0x01),
+    }
+}
+
+pub(crate) struct SunSpecOutput {
+    unit_ids: HashMap<String, u8>,
+    devices: Devices,
+    /// Last lifetime production by inverter, for polls only reading the
+    /// fast changing values
+    energy_totals: HashMap<String, f64>,
+}
+
+impl SunSpecOutput {
+    pub(crate) fn new(config: &SunSpecConfig, inverters: &[&str]) -> anyhow::Result<Self> {
+        let mut inverters = inverters.to_vec();
+        inverters.sort_unstable();
+        let unit_ids = inverters
+            .into_iter()
+            .zip(1..=247)
+            .map(|(name, unit_id)| (name.to_owned(), unit_id))
+            .collect();
+        let listener = TcpListener::bind(&config.listen)
+            .with_context(|| format!("Failed to listen on {}", config.listen))?;
+        let devices = Devices::default();
+        let server_devices = devices.clone();
+        modbus_tcp::spawn_server("sunspec", listener, move |unit_id, pdu| {
+            respond(&server_devices, unit_id, pdu)
+        })
+        .context("Failed to start Modbus server thread")?;
+        Ok(SunSpecOutput {
+            unit_ids,
+            devices,
+            energy_totals: HashMap::new(),
+        })
+    }
+}
+
+impl Output for SunSpecOutput {
+    fn name(&self) -> &str {
+        "sunspec"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let mut readings: HashMap<&str, Reading> = HashMap::new();
+        for sample in samples {
+            let Some(inverter) = sample.tag_value("inverter") else {
+                continue;
+            };
+            let reading = readings.entry(inverter).or_default();
+            for (name, value) in &sample.fields {
+                match (sample.tag_value("input").is_some(), name.as_str()) {
+                    (false, "ac_power") => reading.ac_power = Some(*value),
+                    (false, "energy_total") => reading.energy_total = Some(*value),
+                    (true, "voltage") => {
+                        reading.dc_voltage = Some(reading.dc_voltage.unwrap_or_default().max(*value));
+                    }
+                    (true, "current") => reading.dc_current += value,
+                    (true, "power") => reading.dc_power += value,
+                    _ => {}
+                }
+            }
+        }
+        let mut devices = self.devices.lock().unwrap_or_else(PoisonError::into_inner);
+        for (inverter, mut reading) in readings {
+            // Event samples carry no values worth updating the map for.
+            if reading.ac_power.is_none() && reading.dc_voltage.is_none() {
+                continue;
+            }
+            match reading.energy_total {
+                Some(energy) => {
+                    self.energy_totals.insert(inverter.to_owned(), energy);
+                }
+                None => reading.energy_total = self.energy_totals.get(inverter).copied(),
+            }
+            if let Some(unit_id) = self.unit_ids.get(inverter) {
+                devices.insert(*unit_id, register_map(*unit_id, inverter, &reading));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::Context;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A string with `{name}` placeholders, e.g. `solar/{inverter}/{field}`.
+/// `{{` and `}}` produce literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub(crate) fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next().with_context(|| format!("Unclosed placeholder in '{template}'"))? {
+                            '}' => break,
+                            c => name.push(c),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(name));
+                }
+                '}' => anyhow::bail!("Unmatched '}}' in '{template}'"),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Whether the template contains the placeholder `name`.
+    pub(crate) fn uses(&self, name: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Placeholder(placeholder) if placeholder == name))
+    }
+
+    /// Whether the template has no placeholders at all.
+    pub(crate) fn is_literal(&self) -> bool {
+        self.segments.iter().all(|segment| matches!(segment, Segment::Literal(_)))
+    }
+
+    /// Fills in the placeholders, unknown ones are left empty.
+    pub(crate) fn render<'a>(&self, value_of: impl Fn(&str) -> Option<&'a str>) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Placeholder(name) => rendered.push_str(value_of(name).unwrap_or_default()),
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> String {
+        Template::parse(template).unwrap().render(|name| match name {
+            "inverter" => Some("roof"),
+            "field" => Some("power"),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        assert_eq!(render("solar/{inverter}/{field}"), "solar/roof/power");
+        assert_eq!(render("{inverter}{field}"), "roofpower");
+    }
+
+    #[test]
+    fn unknown_placeholders_render_empty() {
+        assert_eq!(render("solar/{unknown}/{field}"), "solar//power");
+    }
+
+    #[test]
+    fn literals() {
+        let template = Template::parse("solar/state").unwrap();
+        assert!(template.is_literal());
+        assert_eq!(render("solar/state"), "solar/state");
+        assert_eq!(render(""), "");
+        assert_eq!(render("{{\"value\": {field}}}"), "{\"value\": power}");
+        assert!(Template::parse("{{field}}").unwrap().is_literal());
+    }
+
+    #[test]
+    fn uses() {
+        let template = Template::parse("solar/{inverter}/{{field}}").unwrap();
+        assert!(template.uses("inverter"));
+        assert!(!template.uses("field"));
+        assert!(!template.is_literal());
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!(Template::parse("solar/{inverter").is_err());
+        assert!(Template::parse("solar/{").is_err());
+        assert!(Template::parse("solar/inverter}").is_err());
+        assert!(Template::parse("{field}}").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use super::{Output, Sample};
+use anyhow::Context;
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus_crossroads::Crossroads;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, PoisonError};
+
+const BUS_ITEM: &str = "com.victronenergy.BusItem";
+
+/// Publishes every inverter as `com.victronenergy.pvinverter` service on the
+/// system bus of a Venus OS device.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct VictronConfig {
+    /// Device instance of the first inverter (sorted by name), the following
+    /// ones are numbered consecutively
+    #[serde(default = "default_device_instance")]
+    device_instance: u32,
+    /// 0 = AC input 1, 1 = AC output, 2 = AC input 2
+    #[serde(default)]
+    position: i32,
+    /// Nominal power of one inverter in W
+    #[serde(default = "default_max_power")]
+    max_power: f64,
+}
+
+fn default_device_instance() -> u32 {
+    32
+}
+
+fn default_max_power() -> f64 {
+    600.0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i32),
+    Double(f64),
+    Text(String),
+}
+
+impl Value {
+    fn variant(&self) -> Variant<Box<dyn RefArg>> {
+        match self {
+            Value::Int(value) => Variant(Box::new(*value)),
+            Value::Double(value) => Variant(Box::new(*value)),
+            Value::Text(value) => Variant(Box::new(value.clone())),
+        }
+    }
+
+    fn text(&self, path: &str) -> String {
+        match (self, path) {
+            (Value::Double(value), _) if path.ends_with("Power") => format!("{value:.0} W"),
+            (Value::Double(value), _) if path.contains("Energy") => format!("{value:.2} kWh"),
+            (Value::Double(value), _) => value.to_string(),
+            (Value::Int(value), _) => value.to_string(),
+            (Value::Text(value), _) => value.clone(),
+        }
+    }
+
+    fn item(&self, path: &str) -> HashMap<String, Variant<Box<dyn RefArg>>> {
+        HashMap::from([
+            ("Value".to_owned(), self.variant()),
+            ("Text".to_owned(), Variant(Box::new(self.text(path)) as Box<dyn RefArg>)),
+        ])
+    }
+}
+
+type Values = Arc<Mutex<HashMap<String, Value>>>;
+
+/// Runs the D-Bus service of one inverter until the updates channel closes.
+fn serve(
+    inverter: &str,
+    values: &Values,
+    updates: &mpsc::Receiver<Vec<(String, Value)>>,
+) -> anyhow::Result<()> {
+    let connection = Connection::new_system().context("Failed to connect to the system bus")?;
+    let name = format!(
+        "com.victronenergy.pvinverter.solar_mon_{}",
+        inverter.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+    connection
+        .request_name(name.as_str(), false, true, false)
+        .with_context(|| format!("Failed to acquire {name}"))?;
+
+    let mut crossroads = Crossroads::new();
+    let token = crossroads.register(BUS_ITEM, |builder| {
+        builder.method("GetValue", (), ("value",), |ctx, values: &mut Values, ()| {
+            let values = values.lock().unwrap_or_else(PoisonError::into_inner);
+            let path = ctx.path().to_string();
+            let value: Variant<Box<dyn RefArg>> = if path == "/" {
+                let all: HashMap<String, Variant<Box<dyn RefArg>>> = values
+                    .iter()
+                    .map(|(path, value)| (path.trim_start_matches('/').to_owned(), value.variant()))
+                    .collect();
+                Variant(Box::new(all))
+            } else {
+                values
+                    .get(&path)
+                    .map(Value::variant)
+                    .ok_or_else(|| dbus::MethodErr::no_path(&ctx.path().clone()))?
+            };
+            Ok((value,))
+        });
+        builder.method("GetText", (), ("text",), |ctx, values: &mut Values, ()| {
+            let values = values.lock().unwrap_or_else(PoisonError::into_inner);
+            let path = ctx.path().to_string();
+            Ok((values.get(&path).map(|value| value.text(&path)).unwrap_or_default(),))
+        });
+        builder.method("GetItems", (), ("items",), |_, values: &mut Values, ()| {
+            let values = values.lock().unwrap_or_else(PoisonError::into_inner);
+            let items: HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>> = values
+                .iter()
+                .map(|(path, value)| (path.clone(), value.item(path)))
+                .collect();
+            Ok((items,))
+        });
+        // All values are read only.
+        builder.method(
+            "SetValue",
+            ("value",),
+            ("result",),
+            |_, _: &mut Values, (_,): (Variant<Box<dyn RefArg>>,)| Ok((-1i32,)),
+        );
+        builder.signal::<(HashMap<String, Variant<Box<dyn RefArg>>>,), _>(
+            "PropertiesChanged",
+            ("changes",),
+        );
+    });
+    crossroads.insert("/", &[token], values.clone());
+    let paths: Vec<_> = values
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .keys()
+        .cloned()
+        .collect();
+    for path in paths {
+        crossroads.insert(path, &[token], values.clone());
+    }
+
+    connection.start_receive(
+        dbus::message::MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = crossroads.handle_message(message, connection);
+            true
+        }),
+    );
+    log::info!("[{inverter}] Registered {name} on the system bus");
+
+    loop {
+        connection.process(std::time::Duration::from_millis(200))?;
+        loop {
+            let changed = match updates.try_recv() {
+                Ok(changed) => changed,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            };
+            let mut values = values.lock().unwrap_or_else(PoisonError::into_inner);
+            for (path, value) in changed {
+                if values.get(&path) == Some(&value) {
+                    continue;
+                }
+                let signal = dbus::Message::signal(
+                    &dbus::Path::from(path.clone()),
+                    &BUS_ITEM.into(),
+                    &"PropertiesChanged".into(),
+                )
+                .append1(value.item(&path));
+                let _ = connection.send(signal);
+                values.insert(path, value);
+            }
+        }
+    }
+}
+
+struct Service {
+    updates: mpsc::Sender<Vec<(String, Value)>>,
+}
+
+pub(crate) struct VictronOutput {
+    config: VictronConfig,
+    device_instances: HashMap<String, u32>,
+    services: HashMap<String, Service>,
+}
+
+impl VictronOutput {
+    pub(crate) fn new(config: &VictronConfig, inverters: &[&str]) -> Self {
+        let mut inverters = inverters.to_vec();
+        inverters.sort_unstable();
+        let device_instances = inverters
+            .into_iter()
+            .zip(config.device_instance..)
+            .map(|(name, instance)| (name.to_owned(), instance))
+            .collect();
+        VictronOutput {
+            config: config.clone(),
+            device_instances,
+            services: HashMap::new(),
+        }
+    }
+
+    /// Starts the service of `inverter` with its static values.
+    fn start(&self, inverter: &str) -> anyhow::Result<Service> {
+        let device_instance = *self
+            .device_instances
+            .get(inverter)
+            .with_context(|| format!("Inverter {inverter} is not configured"))?;
+        let values: HashMap<String, Value> = [
+            ("/Mgmt/ProcessName", Value::Text("solar_mon".to_owned())),
+            ("/Mgmt/ProcessVersion", Value::Text(env!("CARGO_PKG_VERSION").to_owned())),
+            ("/Mgmt/Connection", Value::Text("SolarmanV5".to_owned())),
+            ("/DeviceInstance", Value::Int(device_instance as i32)),
+            ("/ProductId", Value::Int(0)),
+            ("/ProductName", Value::Text("Deye micro inverter".to_owned())),
+            ("/CustomName", Value::Text(inverter.to_owned())),
+            ("/FirmwareVersion", Value::Text(String::new())),
+            ("/Connected", Value::Int(0)),
+            ("/Position", Value::Int(self.config.position)),
+            ("/StatusCode", Value::Int(0)),
+            ("/ErrorCode", Value::Int(0)),
+            ("/Ac/MaxPower", Value::Double(self.config.max_power)),
+            ("/Ac/Power", Value::Double(0.0)),
+            ("/Ac/Energy/Forward", Value::Double(0.0)),
+            ("/Ac/L1/Power", Value::Double(0.0)),
+            ("/Ac/L1/Energy/Forward", Value::Double(0.0)),
+        ]
+        .into_iter()
+        .map(|(path, value)| (path.to_owned(), value))
+        .collect();
+        let values = Arc::new(Mutex::new(values));
+        let (updates, receiver) = mpsc::channel();
+        let name = inverter.to_owned();
+        std::thread::Builder::new()
+            .name(format!("dbus-{inverter}"))
+            .spawn(move || {
+                if let Err(e) = serve(&name, &values, &receiver) {
+                    log::error!("[{name}] D-Bus service stopped ({e:#})");
+                }
+            })
+            .context("Failed to start D-Bus thread")?;
+        Ok(Service { updates })
+    }
+}
+
+impl Output for VictronOutput {
+    fn name(&self) -> &str {
+        "victron d-bus"
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        for sample in samples {
+            let (Some(inverter), None) = (sample.tag_value("inverter"), sample.tag_value("input")) else {
+                continue;
+            };
+            let Some(power) = sample.field_value("ac_power") else {
+                continue;
+            };
+            let mut changed = vec![
+                ("/Connected".to_owned(), Value::Int(1)),
+                // 7 = running, 8 = standby
+                ("/StatusCode".to_owned(), Value::Int(if power > 0.0 { 7 } else { 8 })),
+                ("/Ac/Power".to_owned(), Value::Double(power)),
+                ("/Ac/L1/Power".to_owned(), Value::Double(power)),
+            ];
+            // Missing in polls only reading the fast changing values
+            if let Some(energy) = sample.field_value("energy_total") {
+                changed.push(("/Ac/Energy/Forward".to_owned(), Value::Double(energy)));
+                changed.push(("/Ac/L1/Energy/Forward".to_owned(), Value::Double(energy)));
+            }
+            if !self.services.contains_key(inverter) {
+                let service = self.start(inverter)?;
+                self.services.insert(inverter.to_owned(), service);
+            }
+            let sent = self.services[inverter].updates.send(changed);
+            if sent.is_err() {
+                // Restarted with the next write.
+                self.services.remove(inverter);
+                anyhow::bail!("D-Bus service of {inverter} is not running");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Directories of the files the daemon writes, so packages can put them in
+//! the usual places instead of the working directory.
+
+use crate::cli::Cli;
+use crate::Config;
+use anyhow::Context;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Defaults used if these directories exist, as created by packages.
+const STATE_DIR: &str = "/var/lib/sun600-monitoring";
+const CACHE_DIR: &str = "/var/cache/sun600-monitoring";
+const LOG_DIR: &str = "/var/log/sun600-monitoring";
+
+/// Replaces `path` with `contents` through a temporary file next to it, so
+/// a crash never leaves a truncated file behind. The temporary file is only
+/// readable by the owner; with `private` the new file stays that way,
+/// otherwise it gets the permissions of the file it replaces.
+pub(crate) fn replace_file(path: &Path, contents: &[u8], private: bool) -> anyhow::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".new");
+    let temp_path = PathBuf::from(temp_path);
+    // Left over from a crash, maybe with other permissions
+    match std::fs::remove_file(&temp_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove {}", temp_path.display()));
+        }
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| match std::fs::metadata(path) {
+            Ok(metadata) if !private => std::fs::set_permissions(&temp_path, metadata.permissions()),
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        })
+        .and_then(|()| std::fs::rename(&temp_path, path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Directory given on the command line, else in the config, else the
+/// package default if it exists. `None` keeps paths relative to the working
+/// directory.
+fn resolve(flag: Option<&Path>, configured: Option<&Path>, default: &str) -> Option<PathBuf> {
+    flag.or(configured).map(Path::to_path_buf).or_else(|| {
+        let default = Path::new(default);
+        (cfg!(unix) && default.is_dir()).then(|| default.to_path_buf())
+    })
+}
+
+/// Resolves and creates the directories and places the relative paths of
+/// the config and the command line in them. With a state directory the
+/// state and status files are written there even if the config does not
+/// name them, and so is the fault log.
+pub(crate) fn apply(config: &mut Config, cli: &mut Cli) -> anyhow::Result<()> {
+    config.state_dir = resolve(cli.state_dir.as_deref(), config.state_dir.as_deref(), STATE_DIR);
+    config.cache_dir = resolve(cli.cache_dir.as_deref(), config.cache_dir.as_deref(), CACHE_DIR);
+    config.log_dir = resolve(cli.log_dir.as_deref(), config.log_dir.as_deref(), LOG_DIR);
+    for dir in [&config.state_dir, &config.cache_dir, &config.log_dir].into_iter().flatten() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+
+    if let Some(state_dir) = &config.state_dir {
+        let monitoring = &mut config.monitoring;
+        let state_file = monitoring.state_file.get_or_insert_with(|| "state.json".into());
+        *state_file = state_dir.join(&*state_file);
+        let status_file = monitoring.status_file.get_or_insert_with(|| "status".into());
+        *status_file = state_dir.join(&*status_file);
+        let fault_log = monitoring.fault_log.get_or_insert_with(|| "faults.jsonl".into());
+        *fault_log = state_dir.join(&*fault_log);
+        if let Some(archive) = &mut config.archive {
+            archive.directory = state_dir.join(&archive.directory);
+        }
+    }
+    if let Some(log_dir) = &config.log_dir {
+        if let Some(audit) = &mut config.audit {
+            audit.file = log_dir.join(&audit.file);
+        }
+        #[cfg(unix)]
+        if let Some(log_file) = &mut cli.log_file {
+            *log_file = log_dir.join(&*log_file);
+        } else if cli.daemon {
+            cli.log_file = Some(log_dir.join("solar_mon.log"));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,488 @@
+//! Decoding of SolarmanV5 traffic recorded with Wireshark or tcpdump, to
+//! help working out the registers of models without register map.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use solar_mon::solarmanv5::{self, Accept, FrameDecoder, Request, Response, StickFrame};
+use solar_mon::registers;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+
+/// A captured packet with the link layer it was captured on.
+struct Packet<'a> {
+    time: DateTime<Utc>,
+    link_type: u32,
+    data: &'a [u8],
+}
+
+/// Reader of the fields of a capture file in its byte order.
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let bytes = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let bytes = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+}
+
+/// Packets of a pcap or pcapng file.
+fn packets(file: &[u8]) -> anyhow::Result<Vec<Packet<'_>>> {
+    match file.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng_packets(file),
+        Some(magic) => {
+            let magic = u32::from_le_bytes(magic.try_into()?);
+            let (big, nanos) = match magic {
+                0xa1b2_c3d4 => (false, false),
+                0xa1b2_3c4d => (false, true),
+                0xd4c3_b2a1 => (true, false),
+                0x4d3c_b2a1 => (true, true),
+                _ => anyhow::bail!("Not a pcap or pcapng file"),
+            };
+            pcap_packets(file, Endian { big }, nanos)
+        }
+        None => anyhow::bail!("Not a pcap or pcapng file"),
+    }
+}
+
+fn pcap_packets(file: &[u8], endian: Endian, nanos: bool) -> anyhow::Result<Vec<Packet<'_>>> {
+    let link_type = endian.u32(file, 20).context("Truncated pcap header")?;
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while let Some(captured) = endian.u32(file, offset + 8) {
+        let seconds = endian.u32(file, offset).unwrap_or_default();
+        let fraction = endian.u32(file, offset + 4).unwrap_or_default();
+        let start = offset + 16;
+        let Some(data) = file.get(start..).and_then(|data| data.get(..captured as usize)) else {
+            log::warn!("Capture ends with a truncated packet");
+            break;
+        };
+        let nanos = if nanos { fraction } else { fraction.saturating_mul(1000) };
+        packets.push(Packet {
+            time: DateTime::from_timestamp(i64::from(seconds), nanos).unwrap_or_default(),
+            link_type,
+            data,
+        });
+        offset = start + data.len();
+    }
+    Ok(packets)
+}
+
+/// Packets of the enhanced and simple packet blocks, other blocks are
+/// skipped.
+fn pcapng_packets(file: &[u8]) -> anyhow::Result<Vec<Packet<'_>>> {
+    let mut endian = Endian { big: false };
+    // Link type and timestamp units per second of every interface
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut packets = Vec::new();
+    let mut offset = 0;
+    while offset + 12 <= file.len() {
+        if file[offset..offset + 4] == [0x0a, 0x0d, 0x0d, 0x0a] {
+            // Section header, its byte order magic sets the order of the section
+            endian.big = file.get(offset + 8..offset + 12) == Some(&[0x1a, 0x2b, 0x3c, 0x4d]);
+            interfaces.clear();
+        }
+        let block_type = endian.u32(file, offset).context("Truncated block")?;
+        let length = endian.u32(file, offset + 4).context("Truncated block")? as usize;
+        if length < 12 || !length.is_multiple_of(4) {
+            log::warn!("Invalid block length {length} at offset {offset}, ignoring the rest");
+            break;
+        }
+        let Some(block) = offset
+            .checked_add(length)
+            .and_then(|end| file.get(offset + 8..end - 4))
+        else {
+            log::warn!("Capture ends with a truncated block");
+            break;
+        };
+        if endian.u32(file, offset + length - 4) != Some(length as u32) {
+            log::warn!("Block lengths at offset {offset} differ, ignoring the rest");
+            break;
+        }
+        match block_type {
+            1 => {
+                let link_type = u32::from(endian.u16(block, 0).unwrap_or_default());
+                interfaces.push((link_type, timestamp_resolution(block, endian)));
+            }
+            6 => {
+                let interface = endian.u32(block, 0).unwrap_or_default() as usize;
+                let (link_type, per_second) =
+                    interfaces.get(interface).copied().unwrap_or((1, 1_000_000));
+                let high = u64::from(endian.u32(block, 4).unwrap_or_default());
+                let low = u64::from(endian.u32(block, 8).unwrap_or_default());
+                let captured = endian.u32(block, 12).unwrap_or_default() as usize;
+                let timestamp = high << 32 | low;
+                let nanos = u128::from(timestamp) * 1_000_000_000 / u128::from(per_second);
+                let Some(data) = block.get(20..).and_then(|data| data.get(..captured)) else {
+                    log::warn!("Skipping packet longer than its block at offset {offset}");
+                    offset += length;
+                    continue;
+                };
+                packets.push(Packet {
+                    time: DateTime::from_timestamp_nanos(nanos as i64),
+                    link_type,
+                    data,
+                });
+            }
+            3 => {
+                let link_type = interfaces.first().map_or(1, |interface| interface.0);
+                let Some(original) = endian.u32(block, 0) else {
+                    log::warn!("Skipping empty packet block at offset {offset}");
+                    offset += length;
+                    continue;
+                };
+                let data = &block[4..];
+                let data = &data[..data.len().min(original as usize)];
+                packets.push(Packet {
+                    time: DateTime::default(),
+                    link_type,
+                    data,
+                });
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    Ok(packets)
+}
+
+/// Timestamp units per second of an interface description block, from its
+/// `if_tsresol` option.
+fn timestamp_resolution(block: &[u8], endian: Endian) -> u64 {
+    let mut offset = 8;
+    while let (Some(code), Some(length)) =
+        (endian.u16(block, offset), endian.u16(block, offset + 2))
+    {
+        if code == 0 {
+            break;
+        }
+        if code == 9 {
+            if let Some(resolution) = block.get(offset + 4) {
+                return match resolution {
+                    power if power & 0x80 != 0 => 1 << (power & 0x7f).min(63),
+                    power => 10u64.saturating_pow(u32::from(*power)),
+                };
+            }
+        }
+        offset += 4 + usize::from(length).div_ceil(4) * 4;
+    }
+    1_000_000
+}
+
+/// A TCP segment with payload.
+struct Segment<'a> {
+    source: SocketAddr,
+    destination: SocketAddr,
+    sequence: u32,
+    payload: &'a [u8],
+}
+
+/// TCP segment of a packet, `None` for anything else.
+fn tcp_segment(link_type: u32, data: &[u8]) -> Option<Segment<'_>> {
+    let ip = match link_type {
+        // BSD loopback, four bytes address family
+        0 => data.get(4..)?,
+        // Ethernet, possibly with a VLAN tag
+        1 => match data.get(12..14)? {
+            [0x81, 0x00] => data.get(18..)?,
+            _ => data.get(14..)?,
+        },
+        // Raw IP
+        101 | 228 | 229 => data,
+        // Linux cooked capture v1 and v2
+        113 => data.get(16..)?,
+        276 => data.get(20..)?,
+        _ => return None,
+    };
+    let (source, destination, tcp) = match ip.first()? >> 4 {
+        4 => {
+            let header_length = usize::from(ip[0] & 0x0f) * 4;
+            let total_length = usize::from(u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]));
+            if *ip.get(9)? != 6 {
+                return None;
+            }
+            let address = |offset: usize| -> Option<IpAddr> {
+                let octets: [u8; 4] = ip.get(offset..offset + 4)?.try_into().ok()?;
+                Some(Ipv4Addr::from(octets).into())
+            };
+            let tcp = ip.get(header_length..total_length.min(ip.len()))?;
+            (address(12)?, address(16)?, tcp)
+        }
+        6 => {
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let payload_length = usize::from(u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]));
+            let address = |offset: usize| -> Option<IpAddr> {
+                let octets: [u8; 16] = ip.get(offset..offset + 16)?.try_into().ok()?;
+                Some(Ipv6Addr::from(octets).into())
+            };
+            let tcp = ip.get(40..(40 + payload_length).min(ip.len()))?;
+            (address(8)?, address(24)?, tcp)
+        }
+        _ => return None,
+    };
+    let port = |offset: usize| Some(u16::from_be_bytes([*tcp.get(offset)?, *tcp.get(offset + 1)?]));
+    let data_offset = usize::from(tcp.get(12)? >> 4) * 4;
+    Some(Segment {
+        source: SocketAddr::new(source, port(0)?),
+        destination: SocketAddr::new(destination, port(2)?),
+        sequence: u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?),
+        payload: tcp.get(data_offset..)?,
+    })
+}
+
+/// Bytes of one direction of a connection not yet decoded.
+struct Stream {
+    next_sequence: Option<u32>,
+    buffer: Vec<u8>,
+    decoder: FrameDecoder,
+}
+
+impl Default for Stream {
+    fn default() -> Self {
+        Stream {
+            next_sequence: None,
+            buffer: Vec::new(),
+            decoder: FrameDecoder::new(Accept::Any),
+        }
+    }
+}
+
+impl Stream {
+    /// Appends the new part of `segment`, retransmitted bytes are dropped.
+    fn push(&mut self, segment: &Segment) {
+        let payload = match self.next_sequence {
+            Some(next) => {
+                let behind = next.wrapping_sub(segment.sequence);
+                if behind > u32::MAX / 2 {
+                    let missing = segment.sequence.wrapping_sub(next);
+                    log::warn!("{missing} bytes missing in the capture");
+                    self.buffer.clear();
+                    segment.payload
+                } else {
+                    segment.payload.get(behind as usize..).unwrap_or_default()
+                }
+            }
+            None => segment.payload,
+        };
+        self.next_sequence = Some(segment.sequence.wrapping_add(segment.payload.len() as u32));
+        self.buffer.extend_from_slice(payload);
+    }
+}
+
+/// Prints every SolarmanV5 frame found in the TCP traffic of a capture.
+pub(crate) fn decode(path: &Path) -> anyhow::Result<()> {
+    let file = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+    // First register of the last read request per connection, to number the
+    // registers of its response
+    let mut requested: HashMap<(SocketAddr, SocketAddr), u16> = HashMap::new();
+    let mut stdout = std::io::stdout().lock();
+    let mut frames = 0;
+    for packet in packets(&file)? {
+        let Some(segment) = tcp_segment(packet.link_type, packet.data) else {
+            continue;
+        };
+        if segment.payload.is_empty() {
+            continue;
+        }
+        let key = (segment.source, segment.destination);
+        let stream = streams.entry(key).or_default();
+        stream.push(&segment);
+        while let Some(length) = stream.decoder.decode(&mut stream.buffer) {
+            let frame: Vec<u8> = stream.buffer.drain(..length).collect();
+            writeln!(
+                stdout,
+                "{} {} -> {} {}",
+                packet.time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                segment.source,
+                segment.destination,
+                solarmanv5::hex(&frame)
+            )?;
+            describe(&mut stdout, &frame, key, &mut requested)?;
+            frames += 1;
+        }
+    }
+    writeln!(stdout, "{frames} SolarmanV5 frames")?;
+    Ok(())
+}
+
+fn describe(
+    out: &mut impl Write,
+    frame: &[u8],
+    key: (SocketAddr, SocketAddr),
+    requested: &mut HashMap<(SocketAddr, SocketAddr), u16>,
+) -> anyhow::Result<()> {
+    let control_code = u16::from_le_bytes([frame[3], frame[4]]);
+    let serial = u32::from_le_bytes([frame[7], frame[8], frame[9], frame[10]]);
+    let msg_id = u16::from_le_bytes([frame[5], frame[6]]);
+    match control_code {
+        0x4510 => {
+            let request = match Request::from_bytes(frame) {
+                Ok(request) => request,
+                Err(e) => {
+                    log::warn!("Skipping invalid request frame ({e})");
+                    return Ok(());
+                }
+            };
+            let rtu = request.payload.modbus_rtu_frame;
+            writeln!(out, "  request serial {serial} msg {msg_id:#06x}")?;
+            writeln!(out, "  modbus {}", solarmanv5::describe_rtu_request(rtu))?;
+            if let [_, 0x03 | 0x04, start_hi, start_lo, ..] = rtu {
+                requested.insert(key, u16::from_be_bytes([*start_hi, *start_lo]));
+            }
+        }
+        0x1510 => {
+            let response = match Response::from_bytes(frame) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::warn!("Skipping invalid response frame ({e})");
+                    return Ok(());
+                }
+            };
+            let rtu = response.payload.rtu_frame;
+            writeln!(
+                out,
+                "  response serial {serial} msg {msg_id:#06x} status {:#04x}",
+                response.payload.status
+            )?;
+            writeln!(out, "  modbus {}", solarmanv5::describe_rtu_response(rtu))?;
+            let start = requested.remove(&(key.1, key.0));
+            if let (Some(start), [_, 0x03 | 0x04, byte_count, ..]) = (start, rtu) {
+                let count = u16::from(*byte_count / 2);
+                match registers::decode_values(rtu, start, count) {
+                    Ok(values) => {
+                        for (register, value) in (start..).zip(values) {
+                            let signed = value as i16;
+                            writeln!(
+                                out,
+                                "    {register:#06x} = {value:#06x} {value:>5} {signed:>6}"
+                            )?;
+                        }
+                    }
+                    Err(e) => writeln!(out, "    {e}")?,
+                }
+            }
+        }
+        _ => match StickFrame::from_bytes(frame) {
+            Ok(stick_frame) => writeln!(
+                out,
+                "  {} serial {serial} msg {msg_id:#06x}, {} bytes payload",
+                stick_frame.frame_type.name(),
+                stick_frame.payload.len()
+            )?,
+            Err(_) => writeln!(
+                out,
+                "  control code {control_code:#06x} serial {serial} msg {msg_id:#06x}"
+            )?,
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len().div_ceil(4) * 4) as u32;
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend(length.to_le_bytes());
+        block.extend(body);
+        block.resize(length as usize - 4, 0);
+        block.extend(length.to_le_bytes());
+        block
+    }
+
+    /// Section header and an Ethernet interface.
+    fn pcapng_header() -> Vec<u8> {
+        let mut body = vec![0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0];
+        body.extend([0xff; 8]);
+        let mut file = block(0x0a0d_0d0a, &body);
+        file.extend(block(1, &[1, 0, 0, 0, 0, 0, 4, 0]));
+        file
+    }
+
+    fn enhanced_packet(data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0; 12];
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(data);
+        block(6, &body)
+    }
+
+    #[test]
+    fn pcapng_packets_are_read() {
+        let mut file = pcapng_header();
+        file.extend(enhanced_packet(b"first"));
+        file.extend(enhanced_packet(b"second"));
+        let packets = packets(&file).unwrap();
+        let data: Vec<_> = packets.iter().map(|packet| packet.data).collect();
+        assert_eq!(data, [b"first".as_slice(), b"second"]);
+        assert_eq!(packets[0].link_type, 1);
+    }
+
+    #[test]
+    fn truncated_pcapng_keeps_the_packets_before() {
+        let mut file = pcapng_header();
+        file.extend(enhanced_packet(b"first"));
+        let second = enhanced_packet(b"second");
+        for end in 0..second.len() {
+            let mut truncated = file.clone();
+            truncated.extend(&second[..end]);
+            let packets = packets(&truncated).unwrap();
+            assert_eq!(packets.len(), 1, "truncated after {end} bytes");
+        }
+    }
+
+    #[test]
+    fn invalid_pcapng_blocks_are_skipped() {
+        let mut file = pcapng_header();
+        file.extend(enhanced_packet(b"first"));
+        // Simple packet block without content
+        file.extend(block(3, &[]));
+        // Captured length beyond the block
+        let mut oversized = enhanced_packet(b"second");
+        oversized[20..24].copy_from_slice(&1000u32.to_le_bytes());
+        file.extend(oversized);
+        file.extend(enhanced_packet(b"third"));
+        let data: Vec<_> = packets(&file).unwrap().iter().map(|packet| packet.data).collect();
+        assert_eq!(data, [b"first".as_slice(), b"third"]);
+        // Block lengths that don't fit the file
+        for length in [0u32, 13, 0xffff_fff0] {
+            let mut invalid = pcapng_header();
+            invalid.extend(enhanced_packet(b"first"));
+            let mut second = enhanced_packet(b"second");
+            second[4..8].copy_from_slice(&length.to_le_bytes());
+            invalid.extend(second);
+            assert_eq!(packets(&invalid).unwrap().len(), 1, "length {length}");
+        }
+    }
+
+    #[test]
+    fn truncated_pcap_keeps_the_packets_before() {
+        let mut file = 0xa1b2_c3d4u32.to_le_bytes().to_vec();
+        file.extend([2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0]);
+        for data in [b"first".as_slice(), b"second"] {
+            file.extend([0; 8]);
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(data);
+        }
+        assert_eq!(packets(&file).unwrap().len(), 2);
+        file.truncate(file.len() - 1);
+        let packets = packets(&file).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, b"first");
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// What happens when a poller hands over data while the queue is full.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OverflowPolicy {
+    /// Discard the data that was just polled
+    DropNewest,
+    /// Discard the oldest queued data to make room
+    DropOldest,
+    /// Wait until the writer caught up, delaying the next poll
+    Block,
+}
+
+/// Bounded FIFO between the polling threads and the output writer.
+pub(crate) struct BoundedQueue<T> {
+    items: Mutex<VecDeque<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+    /// Set on shutdown, [`BoundedQueue::pop`] returns `None` once empty
+    closed: AtomicBool,
+}
+
+impl<T> BoundedQueue<T> {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        BoundedQueue {
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<T>> {
+        // The queue stays consistent even if a thread panicked while holding it.
+        self.items.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Enqueues `item` according to the overflow policy. Returns the item
+    /// that was discarded to stay within capacity, if any.
+    pub(crate) fn push(&self, item: T) -> Option<T> {
+        let mut items = self.lock();
+        let mut dropped = None;
+        if items.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropNewest => return Some(item),
+                OverflowPolicy::DropOldest => dropped = items.pop_front(),
+                OverflowPolicy::Block => {
+                    while items.len() >= self.capacity {
+                        items = self
+                            .not_full
+                            .wait(items)
+                            .unwrap_or_else(PoisonError::into_inner);
+                    }
+                }
+            }
+        }
+        items.push_back(item);
+        self.not_empty.notify_one();
+        dropped
+    }
+
+    /// Blocks until an item is available, `None` once the queue is closed
+    /// and empty.
+    pub(crate) fn pop(&self) -> Option<T> {
+        let mut items = self.lock();
+        loop {
+            if let Some(item) = items.pop_front() {
+                self.not_full.notify_one();
+                return Some(item);
+            }
+            if self.closed.load(Ordering::Relaxed) {
+                return None;
+            }
+            items = self
+                .not_empty
+                .wait(items)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Lets the consumer finish once the remaining items are taken.
+    pub(crate) fn close(&self) {
+        let _items = self.lock();
+        self.closed.store(true, Ordering::Relaxed);
+        self.not_empty.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closed_queue_is_drained() {
+        let queue = BoundedQueue::new(4, OverflowPolicy::Block);
+        queue.push(1);
+        queue.push(2);
+        queue.close();
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::output::Sample;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Polls further apart than this many intervals, e.g. around an outage of
+/// the stick, don't give a ramp rate.
+const MAX_GAP_INTERVALS: i64 = 3;
+
+/// Rate of change of the power of every input between consecutive polls of
+/// one inverter, to see how steep the ramps at cloud edges are and whether
+/// faster polling would catch more of them.
+pub(crate) struct RampRates {
+    max_gap: chrono::Duration,
+    /// Time and power of the previous poll per input name
+    previous: HashMap<String, (DateTime<Utc>, f64)>,
+}
+
+impl RampRates {
+    pub(crate) fn new(interval_secs: u32) -> Self {
+        RampRates {
+            max_gap: chrono::Duration::seconds(i64::from(interval_secs) * MAX_GAP_INTERVALS),
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Adds `power_ramp` in W/s to the input samples of a poll.
+    pub(crate) fn apply(&mut self, samples: &mut [Sample]) {
+        for sample in samples {
+            let (Some(input), Some(power)) =
+                (sample.tag_value("input"), sample.field_value("power"))
+            else {
+                continue;
+            };
+            let previous = self.previous.insert(input.to_owned(), (sample.time, power));
+            let Some((time, previous_power)) = previous else {
+                continue;
+            };
+            let elapsed = sample.time - time;
+            if elapsed <= chrono::Duration::zero() || elapsed > self.max_gap {
+                continue;
+            }
+            let secs = elapsed.num_milliseconds() as f64 / 1000.0;
+            sample
+                .fields
+                .push(("power_ramp".to_owned(), (power - previous_power) / secs));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `power_ramp` of input `A` polled `secs` after the first poll.
+    fn ramp(rates: &mut RampRates, secs: i64, power: f64) -> Option<f64> {
+        let time = DateTime::from_timestamp(1_700_000_000 + secs, 0)?;
+        let mut samples = [Sample::new("solar", time).tag("input", "A").field("power", power)];
+        rates.apply(&mut samples);
+        samples[0].field_value("power_ramp")
+    }
+
+    #[test]
+    fn first_poll_has_no_ramp() {
+        assert_eq!(ramp(&mut RampRates::new(10), 0, 100.0), None);
+    }
+
+    #[test]
+    fn ramp_in_watts_per_second() {
+        let mut rates = RampRates::new(10);
+        ramp(&mut rates, 0, 100.0);
+        assert_eq!(ramp(&mut rates, 10, 300.0), Some(20.0));
+        assert_eq!(ramp(&mut rates, 15, 250.0), Some(-10.0));
+    }
+
+    #[test]
+    fn gaps_longer_than_three_intervals_have_no_ramp() {
+        let mut rates = RampRates::new(10);
+        ramp(&mut rates, 0, 100.0);
+        assert_eq!(ramp(&mut rates, 30, 400.0), Some(10.0));
+        assert_eq!(ramp(&mut rates, 61, 400.0), None);
+        // The poll after the gap is the base of the next ramp.
+        assert_eq!(ramp(&mut rates, 71, 500.0), Some(10.0));
+    }
+
+    #[test]
+    fn repeated_time_has_no_ramp() {
+        let mut rates = RampRates::new(10);
+        ramp(&mut rates, 10, 100.0);
+        assert_eq!(ramp(&mut rates, 10, 200.0), None);
+        assert_eq!(ramp(&mut rates, 5, 200.0), None);
+    }
+
+    #[test]
+    fn only_input_samples_get_a_ramp() {
+        let mut rates = RampRates::new(10);
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut samples = [Sample::new("solar", time).field("power", 100.0)];
+        rates.apply(&mut samples);
+        rates.apply(&mut samples);
+        assert_eq!(samples[0].field_value("power_ramp"), None);
+    }
+}
@@ -0,0 +1,62 @@
+//! Occasional dump of every register of the poll block with its raw value,
+//! to notice registers changing that the register map does not decode yet.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use solar_mon::registers::RegisterMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct RegisterDumpConfig {
+    /// Minimum time between two dumps of the same inverter
+    #[serde(default = "default_dump_interval")]
+    pub(crate) interval_secs: u64,
+    /// Append the dumps to this file instead of the log
+    #[serde(default)]
+    file: Option<PathBuf>,
+}
+
+fn default_dump_interval() -> u64 {
+    3600
+}
+
+impl RegisterDumpConfig {
+    /// Dumps the block in the response `rtu_frame` of a full read. Failures
+    /// are logged, the poll goes on.
+    pub(crate) fn write(&self, inverter_name: &str, register_map: &RegisterMap, rtu_frame: &[u8]) {
+        let dump = match register_map.dump(rtu_frame) {
+            Ok(dump) => dump,
+            Err(e) => {
+                log::debug!("[{inverter_name}] Failed to dump the registers ({e:#})");
+                return;
+            }
+        };
+        // One line per register, easy to compare between dumps.
+        let lines: Vec<_> = dump
+            .into_iter()
+            .map(|(register, raw, name)| {
+                let name = name.as_deref().unwrap_or("-");
+                format!("{register:#06x} {raw:#06x} {raw:>5} {name}")
+            })
+            .collect();
+        let Some(path) = &self.file else {
+            log::info!("[{inverter_name}] Register dump\n{}", lines.join("\n"));
+            return;
+        };
+        let time = chrono::Utc::now().to_rfc3339();
+        let text: String = lines
+            .iter()
+            .map(|line| format!("{time} {inverter_name} {line}\n"))
+            .collect();
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .with_context(|| format!("Failed to write register dump {}", path.display()));
+        if let Err(e) = written {
+            log::warn!("[{inverter_name}] {e:#}");
+        }
+    }
+}
@@ -0,0 +1,667 @@
+//! Register layout of the Deye micro inverters and the Modbus framing needed
+//! to read it.
+
+use crate::error_code::ErrorCode;
+
+/// Registers holding the DC values of one MPPT input, both signed as the
+/// inverter reports small negative currents at dawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputRegisters {
+    /// Letter the input is known by, `A` for PV1 and so on
+    pub name: String,
+    pub voltage: u16,
+    pub current: u16,
+}
+
+/// Registers of a bidirectional grid meter. Energies are 32 bit counters in
+/// 0.1 kWh split into a low and a high word, the power is signed in W with
+/// positive values for import.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeterRegisters {
+    pub import_energy: [u16; 2],
+    pub export_energy: [u16; 2],
+    pub power: u16,
+}
+
+impl MeterRegisters {
+    /// First register and number of registers of the smallest block
+    /// containing all meter values.
+    fn block(&self) -> (u16, u16) {
+        let registers = [
+            self.import_energy[0],
+            self.import_energy[1],
+            self.export_energy[0],
+            self.export_energy[1],
+            self.power,
+        ];
+        let start = registers.iter().copied().min().unwrap_or_default();
+        let end = registers.iter().copied().max().unwrap_or_default();
+        (start, end - start + 1)
+    }
+
+    /// Modbus RTU frame reading all meter registers at once.
+    pub fn request_frame(&self, slave_id: u8) -> Vec<u8> {
+        let (start, count) = self.block();
+        read_frame(slave_id, start, count)
+    }
+
+    /// Decodes the Modbus response to [`Self::request_frame`].
+    pub fn decode(&self, rtu_frame: &[u8]) -> anyhow::Result<MeterReading> {
+        let (start, count) = self.block();
+        self.decode_block(rtu_frame, start, count)
+    }
+
+    /// Decodes the meter values from a response to a read of `count`
+    /// registers starting at `start`.
+    fn decode_block(&self, rtu_frame: &[u8], start: u16, count: u16) -> anyhow::Result<MeterReading> {
+        let register = |register| read_register(rtu_frame, start, count, register);
+        let energy = |[low, high]: [u16; 2]| -> anyhow::Result<f64> {
+            let value = u32::from(register(high)?) << 16 | u32::from(register(low)?);
+            Ok(f64::from(value) / 10.0)
+        };
+        Ok(MeterReading {
+            import_energy: energy(self.import_energy)?,
+            export_energy: energy(self.export_energy)?,
+            power: f64::from(register(self.power)? as i16),
+        })
+    }
+}
+
+/// Registers of the insulation monitoring some models run before
+/// connecting to the grid: the insulation resistance of the PV side to
+/// earth in kOhm and the residual current in mA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsulationRegisters {
+    pub resistance: u16,
+    pub leakage_current: u16,
+}
+
+/// Holding registers of the grid protection settings the country profile
+/// sets, one register each. Voltages are in 0.1 V, frequencies in 0.01 Hz
+/// and the reconnect delay in s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridProtectionRegisters {
+    /// Grid voltage above which the inverter disconnects
+    pub over_voltage: u16,
+    /// Grid voltage below which the inverter disconnects
+    pub under_voltage: u16,
+    pub over_frequency: u16,
+    pub under_frequency: u16,
+    /// Time the grid has to be within the limits before reconnecting
+    pub reconnect_delay: u16,
+}
+
+impl GridProtectionRegisters {
+    fn registers(&self) -> [u16; 5] {
+        [
+            self.over_voltage,
+            self.under_voltage,
+            self.over_frequency,
+            self.under_frequency,
+            self.reconnect_delay,
+        ]
+    }
+
+    /// First register and number of registers of the smallest block
+    /// containing all settings.
+    fn block(&self) -> (u16, u16) {
+        let registers = self.registers();
+        let start = registers.iter().copied().min().unwrap_or_default();
+        let end = registers.iter().copied().max().unwrap_or_default();
+        (start, end - start + 1)
+    }
+
+    /// Modbus RTU frame reading all settings at once.
+    pub fn request_frame(&self, slave_id: u8) -> Vec<u8> {
+        let (start, count) = self.block();
+        read_frame(slave_id, start, count)
+    }
+
+    /// Decodes the Modbus response to [`Self::request_frame`].
+    pub fn decode(&self, rtu_frame: &[u8]) -> anyhow::Result<GridProtection> {
+        let (start, count) = self.block();
+        let register = |register| read_register(rtu_frame, start, count, register).map(f64::from);
+        Ok(GridProtection {
+            over_voltage: register(self.over_voltage)? / 10.0,
+            under_voltage: register(self.under_voltage)? / 10.0,
+            over_frequency: register(self.over_frequency)? / 100.0,
+            under_frequency: register(self.under_frequency)? / 100.0,
+            reconnect_delay_secs: register(self.reconnect_delay)?,
+        })
+    }
+}
+
+/// Grid protection settings, voltages in V and frequencies in Hz.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridProtection {
+    pub over_voltage: f64,
+    pub under_voltage: f64,
+    pub over_frequency: f64,
+    pub under_frequency: f64,
+    pub reconnect_delay_secs: f64,
+}
+
+/// Most registers a single Modbus read may request.
+pub const MAX_REGISTERS: u16 = 125;
+
+/// Most registers a single Modbus write (function 0x10) may set.
+pub const MAX_WRITE_REGISTERS: usize = 123;
+
+/// Factors converting the raw register values into V, A, W and kWh. Some
+/// firmware revisions report values with a different resolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scales {
+    pub voltage: f64,
+    pub current: f64,
+    pub ac_power: f64,
+    pub energy_today: f64,
+    pub energy_total: f64,
+}
+
+impl Default for Scales {
+    fn default() -> Self {
+        Scales {
+            voltage: 0.1,
+            current: 0.1,
+            ac_power: 0.1,
+            energy_today: 0.1,
+            energy_total: 0.1,
+        }
+    }
+}
+
+/// Modbus function reading the register block of a poll.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadFunction {
+    /// 0x03, used by the micro inverters
+    #[default]
+    HoldingRegisters,
+    /// 0x04, used by some other Deye variants
+    InputRegisters,
+}
+
+impl ReadFunction {
+    pub fn code(self) -> u8 {
+        match self {
+            ReadFunction::HoldingRegisters => 0x03,
+            ReadFunction::InputRegisters => 0x04,
+        }
+    }
+}
+
+/// Block of registers read with every poll and where the values are located
+/// in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterMap {
+    pub slave_id: u8,
+    pub function: ReadFunction,
+    /// Added to the register numbers of the block request, for variants
+    /// with the same layout at other addresses. Wraps around, so 0xffff
+    /// addresses every register one lower.
+    pub base: u16,
+    pub start: u16,
+    pub count: u16,
+    pub inputs: Vec<InputRegisters>,
+    /// Low and high word of the signed AC output power
+    pub ac_power: [u16; 2],
+    /// Energy produced today, reset at night
+    pub energy_today: u16,
+    /// Low and high word of the lifetime production
+    pub energy_total: [u16; 2],
+    /// Grid meter read with a separate request, only some models have one
+    pub meter: Option<MeterRegisters>,
+    /// Whether the meter registers are part of the block instead of being
+    /// read with a separate request
+    pub meter_in_block: bool,
+    /// Bit field of the reasons the output is derated, part of the block
+    pub derating: Option<u16>,
+    /// Insulation monitoring, part of the block
+    pub insulation: Option<InsulationRegisters>,
+    pub scales: Scales,
+}
+
+impl RegisterMap {
+    /// Layout shared by the SUN300 (one input) up to the SUN2000G3 (four
+    /// inputs), PV1 voltage and current start at 0x6d.
+    pub fn micro(inputs: u8) -> Self {
+        const START: u16 = 0x3b;
+        let inputs: Vec<_> = (0..u16::from(inputs))
+            .map(|idx| InputRegisters {
+                name: char::from(b'A' + idx as u8).to_string(),
+                voltage: 0x6d + 2 * idx,
+                current: 0x6e + 2 * idx,
+            })
+            .collect();
+        let last = inputs.iter().map(|input| input.current).max().unwrap_or(START);
+        RegisterMap {
+            slave_id: 1,
+            function: ReadFunction::HoldingRegisters,
+            base: 0,
+            start: START,
+            // The dual input block has always been read as a whole.
+            count: (last - START + 1).max(0x36),
+            inputs,
+            ac_power: [0x56, 0x57],
+            energy_today: 0x3c,
+            energy_total: [0x3f, 0x40],
+            meter: None,
+            meter_in_block: false,
+            derating: None,
+            insulation: None,
+            scales: Scales::default(),
+        }
+    }
+
+    pub fn with_meter(mut self, meter: MeterRegisters) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Reads the derating reasons from `register`, extending the block to
+    /// it if needed.
+    pub fn with_derating(mut self, register: u16) -> anyhow::Result<Self> {
+        self.include(register)?;
+        self.derating = Some(register);
+        Ok(self)
+    }
+
+    /// Reads the insulation monitoring values, extending the block to their
+    /// registers if needed.
+    pub fn with_insulation(mut self, insulation: InsulationRegisters) -> anyhow::Result<Self> {
+        self.include(insulation.resistance)?;
+        self.include(insulation.leakage_current)?;
+        self.insulation = Some(insulation);
+        Ok(self)
+    }
+
+    /// Extends the block so it contains `register`.
+    fn include(&mut self, register: u16) -> anyhow::Result<()> {
+        let end = block_end(self.start, self.count)?.max(block_end(register, 1)?);
+        self.start = self.start.min(register);
+        self.count = end - self.start;
+        Ok(())
+    }
+
+    /// Reads the block with `function` at the addresses shifted by `base`,
+    /// see [`Self::base`].
+    pub fn with_addressing(mut self, function: ReadFunction, base: u16) -> Self {
+        self.function = function;
+        self.base = base;
+        self
+    }
+
+    pub fn with_scales(mut self, scales: Scales) -> Self {
+        self.scales = scales;
+        self
+    }
+
+    /// Extends the block to the meter registers so every value of a poll
+    /// comes from the same Modbus transaction. The block may then exceed
+    /// [`MAX_REGISTERS`] if the meter registers are far apart.
+    pub fn single_request(mut self) -> anyhow::Result<Self> {
+        if let Some(meter) = &self.meter {
+            let (meter_start, meter_count) = meter.block();
+            let end = block_end(self.start, self.count)?.max(block_end(meter_start, meter_count)?);
+            self.start = self.start.min(meter_start);
+            self.count = end - self.start;
+            self.meter_in_block = true;
+        }
+        Ok(self)
+    }
+
+    /// Modbus RTU frame of the separate meter request, if there is one.
+    pub fn meter_request_frame(&self) -> Option<Vec<u8>> {
+        match &self.meter {
+            Some(meter) if !self.meter_in_block => Some(meter.request_frame(self.slave_id)),
+            _ => None,
+        }
+    }
+
+    /// Modbus RTU frame reading the whole block.
+    pub fn request_frame(&self) -> Vec<u8> {
+        read_request(self.slave_id, self.function, self.start.wrapping_add(self.base), self.count)
+    }
+
+    /// Decodes a register snapshot pushed by the logger stick, the raw
+    /// values of the block starting at [`Self::start`] without Modbus
+    /// framing. Longer snapshots are fine, the rest is ignored.
+    pub fn decode_snapshot(&self, registers: &[u8]) -> anyhow::Result<MonitoringData> {
+        let mut rtu_frame = vec![self.slave_id, self.function.code(), 0];
+        rtu_frame.extend(registers);
+        self.decode(&rtu_frame)
+    }
+
+    /// Decodes the Modbus responses to [`Self::request_frame`] and, if
+    /// there is one, [`Self::meter_request_frame`] in this order.
+    pub fn decode_responses(&self, rtu_frames: &[Vec<u8>]) -> anyhow::Result<MonitoringData> {
+        let response = |index: usize| {
+            rtu_frames
+                .get(index)
+                .ok_or_else(|| anyhow::anyhow!("Response {} is missing", index + 1))
+        };
+        let mut data = self.decode(response(0)?)?;
+        if let Some(meter) = self.meter.as_ref().filter(|_| !self.meter_in_block) {
+            data.meter = Some(meter.decode(response(1)?)?);
+        }
+        Ok(data)
+    }
+
+    /// Decodes the Modbus response to [`Self::request_frame`]. The meter
+    /// reading is left empty unless the meter is part of the block, see
+    /// [`MeterRegisters::decode`].
+    pub fn decode(&self, rtu_frame: &[u8]) -> anyhow::Result<MonitoringData> {
+        let expected = 3 + 2 * usize::from(self.count);
+        if rtu_frame.len() < expected {
+            return Err(ErrorCode::ModbusResponseTooShort.error(format!(
+                "Modbus response too short ({} bytes, expected {expected})",
+                rtu_frame.len()
+            )));
+        }
+        let register = |register| read_register(rtu_frame, self.start, self.count, register);
+        let (inputs, ac_power) = self.decode_fast_values(rtu_frame, self.start, self.count)?;
+        let double_register = |[low, high]: [u16; 2]| -> anyhow::Result<u32> {
+            Ok(u32::from(register(high)?) << 16 | u32::from(register(low)?))
+        };
+        let energy_today = f64::from(register(self.energy_today)?);
+        let energy_total = f64::from(double_register(self.energy_total)?);
+        Ok(MonitoringData {
+            inputs,
+            ac_power,
+            energy_today: scaled(energy_today, self.scales.energy_today),
+            energy_total: scaled(energy_total, self.scales.energy_total),
+            meter: match &self.meter {
+                Some(meter) if self.meter_in_block => {
+                    Some(meter.decode_block(rtu_frame, self.start, self.count)?)
+                }
+                _ => None,
+            },
+            derating: self.derating.map(|derating| register(derating).map(Derating)).transpose()?,
+            insulation: match &self.insulation {
+                Some(insulation) => Some(InsulationReading {
+                    resistance: f64::from(register(insulation.resistance)?),
+                    leakage_current: f64::from(register(insulation.leakage_current)?),
+                }),
+                None => None,
+            },
+        })
+    }
+
+    /// Names of the registers of the block the map decodes, by register.
+    /// Words of 32 bit values are suffixed with `_low` and `_high`.
+    pub fn register_names(&self) -> Vec<(u16, String)> {
+        let mut names = Vec::new();
+        let double = |prefix: &str, [low, high]: [u16; 2]| {
+            [(low, format!("{prefix}_low")), (high, format!("{prefix}_high"))]
+        };
+        for input in &self.inputs {
+            let input_name = input.name.to_lowercase();
+            names.push((input.voltage, format!("input_{input_name}_voltage")));
+            names.push((input.current, format!("input_{input_name}_current")));
+        }
+        names.extend(double("ac_power", self.ac_power));
+        names.push((self.energy_today, "energy_today".to_owned()));
+        names.extend(double("energy_total", self.energy_total));
+        if let Some(meter) = self.meter.as_ref().filter(|_| self.meter_in_block) {
+            names.extend(double("meter_import_energy", meter.import_energy));
+            names.extend(double("meter_export_energy", meter.export_energy));
+            names.push((meter.power, "meter_power".to_owned()));
+        }
+        if let Some(derating) = self.derating {
+            names.push((derating, "derating".to_owned()));
+        }
+        if let Some(insulation) = &self.insulation {
+            names.push((insulation.resistance, "insulation_resistance".to_owned()));
+            names.push((insulation.leakage_current, "leakage_current".to_owned()));
+        }
+        names.sort_unstable();
+        names
+    }
+
+    /// Raw value of every register of the block in the response to
+    /// [`Self::request_frame`] with its name, `None` for registers the map
+    /// does not decode.
+    pub fn dump(&self, rtu_frame: &[u8]) -> anyhow::Result<Vec<(u16, u16, Option<String>)>> {
+        let names = self.register_names();
+        let values = decode_values(rtu_frame, self.start, self.count)?;
+        Ok((self.start..)
+            .zip(values)
+            .map(|(register, raw)| {
+                let name = names.iter().find(|(named, _)| *named == register);
+                (register, raw, name.map(|(_, name)| name.clone()))
+            })
+            .collect())
+    }
+
+    /// Block of the quickly changing values, the input voltages and
+    /// currents and the AC power, which can be read on their own between
+    /// full reads.
+    pub fn fast_block(&self) -> (u16, u16) {
+        let registers = self
+            .inputs
+            .iter()
+            .flat_map(|input| [input.voltage, input.current])
+            .chain(self.ac_power);
+        let first = registers.clone().min().unwrap_or(self.start);
+        let last = registers.max().unwrap_or(self.start);
+        (first, last - first + 1)
+    }
+
+    /// Modbus RTU frame reading [`Self::fast_block`].
+    pub fn fast_request_frame(&self) -> Vec<u8> {
+        let (start, count) = self.fast_block();
+        read_request(self.slave_id, self.function, start.wrapping_add(self.base), count)
+    }
+
+    /// Decodes the Modbus response to [`Self::fast_request_frame`], the
+    /// other values are taken over from `previous`.
+    pub fn decode_fast(
+        &self,
+        rtu_frame: &[u8],
+        previous: &MonitoringData,
+    ) -> anyhow::Result<MonitoringData> {
+        let (start, count) = self.fast_block();
+        let expected = 3 + 2 * usize::from(count);
+        if rtu_frame.len() < expected {
+            return Err(ErrorCode::ModbusResponseTooShort.error(format!(
+                "Modbus response too short ({} bytes, expected {expected})",
+                rtu_frame.len()
+            )));
+        }
+        let (inputs, ac_power) = self.decode_fast_values(rtu_frame, start, count)?;
+        Ok(MonitoringData {
+            inputs,
+            ac_power,
+            ..previous.clone()
+        })
+    }
+
+    /// Input readings and AC power from a response to a read of `count`
+    /// registers starting at `start`.
+    fn decode_fast_values(
+        &self,
+        rtu_frame: &[u8],
+        start: u16,
+        count: u16,
+    ) -> anyhow::Result<(Vec<InputReading>, f64)> {
+        let register = |register| read_register(rtu_frame, start, count, register);
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| {
+                Ok(InputReading {
+                    name: input.name.clone(),
+                    voltage: scaled(signed(register(input.voltage)?), self.scales.voltage),
+                    current: scaled(signed(register(input.current)?), self.scales.current),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let [low, high] = self.ac_power;
+        // Signed like the input values
+        let ac_power = (u32::from(register(high)?) << 16 | u32::from(register(low)?)) as i32;
+        Ok((inputs, scaled(f64::from(ac_power), self.scales.ac_power)))
+    }
+}
+
+fn signed(raw: u16) -> f64 {
+    f64::from(raw as i16)
+}
+
+/// Applies `scale` to a raw value. Dividing by the reciprocal keeps e.g.
+/// 312 * 0.1 at exactly 31.2.
+fn scaled(raw: f64, scale: f64) -> f64 {
+    raw / scale.recip()
+}
+
+/// Modbus RTU frame reading `count` holding registers starting at `start`.
+pub fn read_frame(slave_id: u8, start: u16, count: u16) -> Vec<u8> {
+    read_request(slave_id, ReadFunction::HoldingRegisters, start, count)
+}
+
+/// Modbus RTU frame reading `count` registers starting at `start` with
+/// `function`.
+pub fn read_request(slave_id: u8, function: ReadFunction, start: u16, count: u16) -> Vec<u8> {
+    let mut frame = vec![slave_id, function.code()];
+    frame.extend(start.to_be_bytes());
+    frame.extend(count.to_be_bytes());
+    frame.extend(crc16(&frame).to_le_bytes());
+    frame
+}
+
+/// Modbus RTU frame writing `values` to the holding registers starting at
+/// `start`. Fails for more than [`MAX_WRITE_REGISTERS`] values, a write
+/// split over several frames would not be applied at once.
+pub fn write_frame(slave_id: u8, start: u16, values: &[u16]) -> anyhow::Result<Vec<u8>> {
+    if values.is_empty() || values.len() > MAX_WRITE_REGISTERS {
+        anyhow::bail!(
+            "A write sets 1 to {MAX_WRITE_REGISTERS} registers, not {}",
+            values.len()
+        );
+    }
+    let mut frame = vec![slave_id, 0x10];
+    frame.extend(start.to_be_bytes());
+    frame.extend((values.len() as u16).to_be_bytes());
+    frame.push((2 * values.len()) as u8);
+    for value in values {
+        frame.extend(value.to_be_bytes());
+    }
+    frame.extend(crc16(&frame).to_le_bytes());
+    Ok(frame)
+}
+
+/// First address after a block of `count` registers from `start`. Fails for
+/// blocks running past the last address.
+fn block_end(start: u16, count: u16) -> anyhow::Result<u16> {
+    start.checked_add(count).ok_or_else(|| {
+        anyhow::anyhow!("{count} registers from {start:#x} run past the last register address")
+    })
+}
+
+/// Raw values of all registers in a response to [`read_frame`].
+pub fn decode_values(rtu_frame: &[u8], start: u16, count: u16) -> anyhow::Result<Vec<u16>> {
+    (start..block_end(start, count)?)
+        .map(|register| read_register(rtu_frame, start, count, register))
+        .collect()
+}
+
+/// Raw value of `register` in a response to a read of `count` registers
+/// starting at `start`.
+fn read_register(rtu_frame: &[u8], start: u16, count: u16, register: u16) -> anyhow::Result<u16> {
+    let offset = register
+        .checked_sub(start)
+        .filter(|offset| *offset < count)
+        .map(|offset| 3 + 2 * usize::from(offset))
+        .ok_or_else(|| {
+            ErrorCode::RegisterOutsideBlock
+                .error(format!("Register {register:#x} is not part of the read block"))
+        })?;
+    match rtu_frame.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(ErrorCode::ModbusResponseTooShort
+            .error(format!("Modbus response too short ({} bytes)", rtu_frame.len()))),
+    }
+}
+
+impl Default for RegisterMap {
+    fn default() -> Self {
+        RegisterMap::micro(2)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputReading {
+    pub name: String,
+    pub voltage: f64,
+    pub current: f64,
+}
+
+impl InputReading {
+    pub fn power(&self) -> f64 {
+        self.voltage * self.current
+    }
+}
+
+impl MonitoringData {
+    /// Sum of the power of all PV inputs in W.
+    pub fn dc_power(&self) -> f64 {
+        self.inputs.iter().map(InputReading::power).sum()
+    }
+}
+
+/// Grid side counters, energies in kWh and power in W.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeterReading {
+    pub import_energy: f64,
+    pub export_energy: f64,
+    pub power: f64,
+}
+
+/// Insulation monitoring values, resistance in kOhm and residual current
+/// in mA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsulationReading {
+    pub resistance: f64,
+    pub leakage_current: f64,
+}
+
+/// Raw value of the derating register. Whether the inverter limits its
+/// output and why is told by its bits, which differ between models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Derating(pub u16);
+
+impl Derating {
+    /// Any bit set, unknown ones included.
+    pub fn active(self) -> bool {
+        self.0 != 0
+    }
+
+    /// Whether bit `bit` is set, 0 being the lowest.
+    pub fn bit(self, bit: u8) -> bool {
+        bit < 16 && self.0 & (1 << bit) != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitoringData {
+    pub inputs: Vec<InputReading>,
+    /// Power fed into the grid in W
+    pub ac_power: f64,
+    /// Production of the current day in kWh
+    pub energy_today: f64,
+    /// Lifetime production in kWh
+    pub energy_total: f64,
+    pub meter: Option<MeterReading>,
+    pub derating: Option<Derating>,
+    pub insulation: Option<InsulationReading>,
+}
+
+/// CRC-16/MODBUS of `data`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
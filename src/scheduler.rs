@@ -0,0 +1,188 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Due times of the tasks that are not currently running.
+struct Schedule {
+    due: Mutex<BinaryHeap<Reverse<(Instant, usize)>>>,
+    changed: Condvar,
+    stopped: AtomicBool,
+}
+
+impl Schedule {
+    fn lock(&self) -> MutexGuard<'_, BinaryHeap<Reverse<(Instant, usize)>>> {
+        self.due.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Blocks until a task is due and takes it out of the schedule, so no
+    /// other worker can run it at the same time. `None` once stopped.
+    fn next(&self) -> Option<usize> {
+        let mut due = self.lock();
+        loop {
+            if self.stopped.load(Ordering::Relaxed) {
+                return None;
+            }
+            let wait = match due.peek() {
+                Some(Reverse((time, index))) => {
+                    let wait = time.saturating_duration_since(Instant::now());
+                    if wait.is_zero() {
+                        let index = *index;
+                        due.pop();
+                        return Some(index);
+                    }
+                    wait
+                }
+                // Every task is running, wait until one is handed back.
+                None => Duration::from_secs(3600),
+            };
+            due = self
+                .changed
+                .wait_timeout(due, wait)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    fn reschedule(&self, index: usize, delay: Duration) {
+        self.lock().push(Reverse((Instant::now() + delay, index)));
+        self.changed.notify_all();
+    }
+
+    /// Makes a waiting task due right away.
+    fn wake(&self, index: usize) {
+        let mut due = self.lock();
+        let waiting = due.len();
+        due.retain(|Reverse((_, other))| *other != index);
+        if due.len() < waiting {
+            due.push(Reverse((Instant::now(), index)));
+            self.changed.notify_all();
+        }
+    }
+}
+
+/// Runs tasks on a few worker threads, each whenever it is due. Tasks can
+/// be added while it is running.
+pub(crate) struct Scheduler<T> {
+    schedule: Schedule,
+    tasks: Mutex<Vec<Arc<Mutex<T>>>>,
+    /// Signals new tasks, which may need additional workers
+    added: Condvar,
+}
+
+impl<T: Send> Scheduler<T> {
+    /// Creates a scheduler for `tasks`, each with the delay until it is due
+    /// for the first time.
+    pub(crate) fn new(tasks: Vec<(T, Duration)>) -> Self {
+        let now = Instant::now();
+        let due = tasks.iter().enumerate().map(|(index, (_, delay))| Reverse((now + *delay, index)));
+        Scheduler {
+            schedule: Schedule {
+                due: Mutex::new(due.collect()),
+                changed: Condvar::new(),
+                stopped: AtomicBool::new(false),
+            },
+            tasks: Mutex::new(
+                tasks.into_iter().map(|(task, _)| Arc::new(Mutex::new(task))).collect(),
+            ),
+            added: Condvar::new(),
+        }
+    }
+
+    fn lock_tasks(&self) -> MutexGuard<'_, Vec<Arc<Mutex<T>>>> {
+        self.tasks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds a task, it is due after `delay`. Returns its index for
+    /// [`Self::update`].
+    pub(crate) fn add(&self, task: T, delay: Duration) -> usize {
+        let index = {
+            let mut tasks = self.lock_tasks();
+            tasks.push(Arc::new(Mutex::new(task)));
+            tasks.len() - 1
+        };
+        self.schedule.reschedule(index, delay);
+        self.added.notify_all();
+        index
+    }
+
+    /// Changes the task with `index`, the position in the initial tasks or
+    /// as returned by [`Self::add`], once it is not running and makes it due
+    /// right away so the change takes effect without waiting for its turn.
+    pub(crate) fn update(&self, index: usize, change: impl FnOnce(&mut T)) {
+        let task = self.lock_tasks()[index].clone();
+        let mut task = task.lock().unwrap_or_else(PoisonError::into_inner);
+        change(&mut task);
+        // Running tasks are rescheduled before they are released, so it is
+        // either waiting now or about to run anyway.
+        self.schedule.wake(index);
+    }
+
+    /// Whether a step asked to stop.
+    pub(crate) fn stopped(&self) -> bool {
+        self.schedule.stopped.load(Ordering::Relaxed)
+    }
+
+    fn stop(&self) {
+        let _tasks = self.lock_tasks();
+        {
+            let _due = self.schedule.lock();
+            self.schedule.stopped.store(true, Ordering::Relaxed);
+            self.schedule.changed.notify_all();
+        }
+        self.added.notify_all();
+    }
+
+    /// Runs `step` for every task on `threads` worker threads, or on a
+    /// thread per task without a limit, until a step returns `None`. `step`
+    /// returns the delay until the task is due again. A task is only ever
+    /// run by one worker at a time and never before it is due, so every
+    /// inverter keeps its own poll interval while sharing a few threads.
+    /// Returns once the running steps finished.
+    pub(crate) fn run(
+        &self,
+        threads: Option<usize>,
+        step: impl Fn(&mut T) -> Option<Duration> + Sync,
+    ) {
+        std::thread::scope(|scope| {
+            let mut workers = 0;
+            let mut tasks = self.lock_tasks();
+            while !self.stopped() {
+                let wanted = threads.unwrap_or(tasks.len()).max(1).min(tasks.len());
+                for _ in workers..wanted {
+                    scope.spawn(|| {
+                        while let Some(index) = self.schedule.next() {
+                            let task = self.lock_tasks()[index].clone();
+                            let mut task = task.lock().unwrap_or_else(PoisonError::into_inner);
+                            match step(&mut task) {
+                                Some(delay) => self.schedule.reschedule(index, delay),
+                                None => self.stop(),
+                            }
+                        }
+                    });
+                }
+                workers = workers.max(wanted);
+                tasks = self.added.wait(tasks).unwrap_or_else(PoisonError::into_inner);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_returns_once_a_step_stops() {
+        let scheduler = Scheduler::new(vec![(0, Duration::ZERO), (0, Duration::ZERO)]);
+        scheduler.run(Some(1), |steps: &mut u32| {
+            *steps += 1;
+            (*steps < 3).then_some(Duration::from_millis(1))
+        });
+        assert!(scheduler.stopped());
+        let steps: Vec<_> =
+            scheduler.lock_tasks().iter().map(|task| *task.lock().unwrap()).collect();
+        assert!(steps.contains(&3), "{steps:?}");
+    }
+}
@@ -0,0 +1,372 @@
+//! Encrypted store for the passwords, tokens and API keys of the config, so
+//! they don't have to be kept in the config file in plain text. A string
+//! value `secret:<name>` anywhere in the config is replaced by the secret
+//! `<name>` when the config is loaded.
+//!
+//! The store is an [age](https://age-encryption.org) file holding the
+//! secrets as JSON object, encrypted to the identity in the key file or to
+//! the passphrase in `SOLAR_MON_SECRETS_KEY`.
+
+use crate::cli::SecretsCommand;
+use age::secrecy::{ExposeSecret, SecretString};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the passphrase, preferred over the key file.
+const KEY_VARIABLE: &str = "SOLAR_MON_SECRETS_KEY";
+
+/// Prefix of the config values taken from the store.
+const REFERENCE_PREFIX: &str = "secret:";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SecretsConfig {
+    /// The encrypted store, see `secrets set`
+    file: PathBuf,
+    /// age identity file, see `secrets generate-key`. Used if
+    /// `SOLAR_MON_SECRETS_KEY` is not set
+    #[serde(default)]
+    key_file: Option<PathBuf>,
+}
+
+/// What the store is encrypted to.
+enum Key {
+    Passphrase(SecretString),
+    Identities {
+        identities: Vec<Box<dyn age::Identity>>,
+        recipients: Vec<Box<dyn age::Recipient + Send>>,
+    },
+}
+
+impl Key {
+    fn of(config: &SecretsConfig) -> anyhow::Result<Self> {
+        if let Some(passphrase) = std::env::var(KEY_VARIABLE)
+            .ok()
+            .filter(|key| !key.is_empty())
+        {
+            return Ok(Key::Passphrase(passphrase.into()));
+        }
+        let Some(key_file) = &config.key_file else {
+            anyhow::bail!("The secrets are locked, set {KEY_VARIABLE} or key_file in [secrets]");
+        };
+        let identities = age::IdentityFile::from_file(key_file.display().to_string())
+            .with_context(|| format!("Failed to read secrets key file {}", key_file.display()))?;
+        Self::with_identities(identities)
+    }
+
+    fn with_identities(identities: age::IdentityFile<age::NoCallbacks>) -> anyhow::Result<Self> {
+        Ok(Key::Identities {
+            recipients: identities
+                .to_recipients()
+                .context("Invalid secrets key file")?,
+            identities: identities
+                .into_identities()
+                .context("Invalid secrets key file")?,
+        })
+    }
+
+    fn encryptor(&self) -> anyhow::Result<age::Encryptor> {
+        match self {
+            Key::Passphrase(passphrase) => {
+                Ok(age::Encryptor::with_user_passphrase(passphrase.clone()))
+            }
+            Key::Identities { recipients, .. } => age::Encryptor::with_recipients(
+                recipients.iter().map(|recipient| recipient.as_ref() as _),
+            )
+            .context("Invalid secrets key file"),
+        }
+    }
+
+    fn decrypt(&self, encrypted: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let passphrase_identity;
+        let identities: Vec<&dyn age::Identity> = match self {
+            Key::Passphrase(passphrase) => {
+                passphrase_identity = age::scrypt::Identity::new(passphrase.clone());
+                vec![&passphrase_identity]
+            }
+            Key::Identities { identities, .. } => identities
+                .iter()
+                .map(|identity| identity.as_ref())
+                .collect(),
+        };
+        let mut reader = age::Decryptor::new_buffered(encrypted)
+            .context("The secrets file is no age file")?
+            .decrypt(identities.into_iter())
+            .context("Failed to decrypt the secrets file, wrong key?")?;
+        let mut decrypted = Vec::new();
+        reader
+            .read_to_end(&mut decrypted)
+            .context("The secrets file is damaged")?;
+        Ok(decrypted)
+    }
+}
+
+/// An unlocked store.
+struct Store {
+    path: PathBuf,
+    key: Key,
+    secrets: BTreeMap<String, String>,
+}
+
+impl Store {
+    /// Opens the store of `config`, an empty one if the file does not
+    /// exist yet.
+    fn open(config: &SecretsConfig) -> anyhow::Result<Self> {
+        Self::open_with(&config.file, Key::of(config)?)
+    }
+
+    fn open_with(path: &Path, key: Key) -> anyhow::Result<Self> {
+        let secrets = match std::fs::read(path) {
+            Ok(encrypted) => {
+                let decrypted = key
+                    .decrypt(&encrypted)
+                    .with_context(|| format!("Failed to open secrets file {}", path.display()))?;
+                serde_json::from_slice(&decrypted)
+                    .with_context(|| format!("Invalid secrets file {}", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read secrets file {}", path.display()))
+            }
+        };
+        Ok(Store {
+            path: path.to_path_buf(),
+            key,
+            secrets,
+        })
+    }
+
+    fn get(&self, name: &str) -> anyhow::Result<&str> {
+        self.secrets
+            .get(name)
+            .map(String::as_str)
+            .with_context(|| format!("There is no secret {name}"))
+    }
+
+    /// Encrypts the store again, replacing the file only once it is
+    /// completely written.
+    fn save(&self) -> anyhow::Result<()> {
+        let mut encrypted = Vec::new();
+        let mut writer = self.key.encryptor()?.wrap_output(&mut encrypted)?;
+        serde_json::to_writer(&mut writer, &self.secrets)?;
+        writer.finish()?;
+        crate::paths::replace_file(&self.path, &encrypted, true)
+    }
+}
+
+/// Writes a new file only the owner can read.
+fn write_private(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Replaces the `secret:<name>` strings in `value` and everything below it.
+fn resolve_value(value: &mut toml::Value, store: &Store) -> anyhow::Result<()> {
+    match value {
+        toml::Value::String(text) => {
+            if let Some(name) = text.strip_prefix(REFERENCE_PREFIX) {
+                *text = store.get(name)?.to_owned();
+            }
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                resolve_value(value, store)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                resolve_value(value, store)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces the references to secrets in a parsed config, if it has a
+/// `[secrets]` section.
+pub(crate) fn resolve(config: &mut toml::Table) -> anyhow::Result<()> {
+    let Some(secrets_config) = config.get("secrets") else {
+        return Ok(());
+    };
+    let secrets_config =
+        SecretsConfig::deserialize(secrets_config.clone()).context("Invalid [secrets] section")?;
+    let store = Store::open(&secrets_config)?;
+    for (key, value) in config.iter_mut().filter(|(key, _)| *key != "secrets") {
+        resolve_value(value, &store).with_context(|| format!("Failed to resolve {key}"))?;
+    }
+    Ok(())
+}
+
+/// The `[secrets]` section of the config file, read on its own since the
+/// whole config may refer to secrets that are yet to be stored.
+fn config_of(config_path: &Path) -> anyhow::Result<SecretsConfig> {
+    let text = std::fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config file {}", config_path.display()))?;
+    let mut config: toml::Table = toml::from_str(&text).context("Failed to parse config file.")?;
+    let section = config
+        .remove("secrets")
+        .context("The config has no [secrets] section")?;
+    SecretsConfig::deserialize(section).context("Invalid [secrets] section")
+}
+
+pub(crate) fn run(config_path: &Path, command: &SecretsCommand) -> anyhow::Result<()> {
+    match command {
+        SecretsCommand::Set { name } => set(config_path, name),
+        SecretsCommand::Remove { name } => remove(config_path, name),
+        SecretsCommand::List => list(config_path),
+        SecretsCommand::GenerateKey { path } => generate_key(path),
+    }
+}
+
+/// Stores the secret `name`, read as a line from stdin.
+fn set(config_path: &Path, name: &str) -> anyhow::Result<()> {
+    let config = config_of(config_path)?;
+    let mut store = Store::open(&config)?;
+    let mut stdout = std::io::stdout().lock();
+    write!(stdout, "Value of {name}: ")?;
+    stdout.flush()?;
+    let mut value = String::new();
+    if std::io::stdin().lock().read_line(&mut value)? == 0 {
+        anyhow::bail!("No value given");
+    }
+    store.secrets.insert(
+        name.to_owned(),
+        value.trim_end_matches(['\r', '\n']).to_owned(),
+    );
+    store.save()?;
+    writeln!(
+        stdout,
+        "Stored {name}, refer to it as \"{REFERENCE_PREFIX}{name}\" in the config"
+    )?;
+    Ok(())
+}
+
+fn remove(config_path: &Path, name: &str) -> anyhow::Result<()> {
+    let config = config_of(config_path)?;
+    let mut store = Store::open(&config)?;
+    if store.secrets.remove(name).is_none() {
+        anyhow::bail!("There is no secret {name}");
+    }
+    store.save()
+}
+
+/// Prints the names of the stored secrets, not their values.
+fn list(config_path: &Path) -> anyhow::Result<()> {
+    let config = config_of(config_path)?;
+    let store = Store::open(&config)?;
+    let mut stdout = std::io::stdout().lock();
+    for name in store.secrets.keys() {
+        writeln!(stdout, "{name}")?;
+    }
+    Ok(())
+}
+
+/// Writes a new age identity to a new key file.
+fn generate_key(path: &Path) -> anyhow::Result<()> {
+    let identity = age::x25519::Identity::generate();
+    let contents = format!(
+        "# public key: {}\n{}\n",
+        identity.to_public(),
+        identity.to_string().expose_secret()
+    );
+    write_private(path, contents.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity_key() -> Key {
+        let identity = age::x25519::Identity::generate();
+        let contents = identity.to_string().expose_secret().to_owned();
+        Key::with_identities(age::IdentityFile::from_buffer(contents.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn store_with(path: &Path, key: Key, secrets: &[(&str, &str)]) -> Store {
+        let mut store = Store::open_with(path, key).unwrap();
+        for (name, value) in secrets {
+            store
+                .secrets
+                .insert((*name).to_owned(), (*value).to_owned());
+        }
+        store.save().unwrap();
+        store
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.age");
+        let store = store_with(&path, identity_key(), &[("mqtt", "hunter2")]);
+        let reopened = Store::open_with(&path, store.key).unwrap();
+        assert_eq!(reopened.get("mqtt").unwrap(), "hunter2");
+        assert!(reopened.get("other").is_err());
+        let mut config: toml::Table = toml::from_str("password = \"secret:mqtt\"").unwrap();
+        resolve_value(&mut config["password"], &reopened).unwrap();
+        assert_eq!(config["password"].as_str(), Some("hunter2"));
+    }
+
+    #[test]
+    fn passphrase_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.age");
+        store_with(
+            &path,
+            Key::Passphrase("right".to_owned().into()),
+            &[("a", "b")],
+        );
+        let reopened = Store::open_with(&path, Key::Passphrase("right".to_owned().into()));
+        assert_eq!(reopened.unwrap().get("a").unwrap(), "b");
+        assert!(Store::open_with(&path, Key::Passphrase("wrong".to_owned().into())).is_err());
+    }
+
+    #[test]
+    fn wrong_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.age");
+        store_with(&path, identity_key(), &[("a", "b")]);
+        assert!(Store::open_with(&path, identity_key()).is_err());
+    }
+
+    #[test]
+    fn damaged_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.age");
+        let store = store_with(&path, identity_key(), &[("a", "b")]);
+        let mut encrypted = std::fs::read(&path).unwrap();
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        std::fs::write(&path, &encrypted).unwrap();
+        assert!(Store::open_with(&path, store.key).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_file_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.age");
+        std::fs::write(&path, "").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let store = Store {
+            path: path.clone(),
+            key: identity_key(),
+            secrets: BTreeMap::new(),
+        };
+        store.save().unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,13 @@
+//! Counters about the daemon itself, served by `/api/status` of the HTTP
+//! output.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Times the InfluxDB client was rebuilt after repeated failed writes
+pub(crate) static INFLUX_RECONNECTS: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn to_json() -> serde_json::Value {
+    serde_json::json!({
+        "influx_reconnects": INFLUX_RECONNECTS.load(Ordering::Relaxed),
+    })
+}
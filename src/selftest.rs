@@ -0,0 +1,82 @@
+use crate::{connect_inverter, output, to_samples, Config};
+use crate::table::{OutputFormat, Table};
+use serde_json::Value;
+use solar_mon::error_code::ErrorCode;
+use std::io::Write;
+
+const PASS: &str = "ok";
+const FAIL: &str = "FAIL";
+const SKIPPED: &str = "-";
+
+/// Polls every inverter once, writes the samples to every output and prints
+/// a table with the result of each step. Fails if any step failed, outputs
+/// whose filter drops the samples are skipped. With `measurement` the
+/// samples are written to that measurement instead of the configured one.
+pub(crate) fn selftest(
+    config: &Config,
+    measurement: Option<&str>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let mut outputs = output::create_sinks(config)?;
+    let mut header = vec!["inverter".to_owned(), "poll".to_owned()];
+    header.extend(outputs.names());
+
+    let mut inverters: Vec<_> = config.inverter.iter().collect();
+    inverters.sort_unstable_by_key(|(name, _)| name.as_str());
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (inverter_name, inverter_cfg) in inverters {
+        let mut row = vec![inverter_name.clone()];
+        let data = connect_inverter(inverter_cfg, &config.monitoring)
+            .and_then(|mut inverter| inverter.get_data());
+        match data {
+            Ok(data) => {
+                row.push(PASS.to_owned());
+                let mut samples =
+                    to_samples(inverter_name, inverter_cfg, &config.monitoring, &data);
+                if let Some(measurement) = measurement {
+                    for sample in &mut samples {
+                        measurement.clone_into(&mut sample.measurement);
+                    }
+                }
+                // Flushed right away, a buffering output would report
+                // success without having written anything.
+                let written = outputs.write_each(&samples);
+                let flushed = outputs.flush_each();
+                for ((name, result), flushed) in header[2..].iter().zip(written).zip(flushed) {
+                    row.push(match result.map(|result| result.and(flushed)) {
+                        Some(Ok(())) => PASS.to_owned(),
+                        Some(Err(e)) => {
+                            errors.push(format!("{inverter_name} -> {name}: {e:#}"));
+                            FAIL.to_owned()
+                        }
+                        None => SKIPPED.to_owned(),
+                    });
+                }
+            }
+            Err(e) => {
+                errors.push(format!("{inverter_name}: {}: {e:#}", ErrorCode::of(&e)));
+                row.push(FAIL.to_owned());
+                row.resize(header.len(), SKIPPED.to_owned());
+            }
+        }
+        rows.push(row);
+    }
+
+    let mut table = Table::new(header);
+    for row in rows {
+        table.push(row.into_iter().map(Value::from).collect());
+    }
+    let mut stdout = std::io::stdout().lock();
+    table.write(format, &mut stdout)?;
+    for error in &errors {
+        match format {
+            OutputFormat::Table => writeln!(stdout, "{error}")?,
+            _ => log::error!("{error}"),
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("Self-test failed in {} steps", errors.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+#[cfg(unix)]
+pub(crate) use unix::{daemonize, restart};
+#[cfg(windows)]
+pub(crate) use windows::{install, run, uninstall};
+
+#[cfg(unix)]
+mod unix {
+    use crate::cli::Cli;
+    use anyhow::Context;
+
+    /// Forks into the background, detaches from the controlling terminal and
+    /// returns in the daemon process. The parent process exits.
+    pub(crate) fn daemonize(cli: &Cli) -> anyhow::Result<()> {
+        // The daemon changes its working directory, so relative paths given on
+        // the command line are resolved beforehand.
+        let working_dir = std::env::current_dir().context("Failed to get working directory")?;
+        let mut daemon = daemonize::Daemonize::new()
+            .working_directory(&working_dir)
+            .umask(cli.umask);
+        if let Some(pid_file) = &cli.pid_file {
+            daemon = daemon.pid_file(working_dir.join(pid_file));
+        }
+        if let Some(log_file) = &cli.log_file {
+            let log = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)
+                .with_context(|| format!("Failed to open log file {}", log_file.display()))?;
+            let log_err = log.try_clone().context("Failed to duplicate log file handle")?;
+            daemon = daemon.stdout(log).stderr(log_err);
+        }
+        daemon.start().context("Failed to daemonize")
+    }
+
+    /// Replaces the process with a new instance started with the same
+    /// arguments, e.g. to apply a changed config. Only returns on failure.
+    pub(crate) fn restart() -> anyhow::Error {
+        use std::os::unix::process::CommandExt;
+        match std::env::current_exe() {
+            Ok(exe) => std::process::Command::new(exe)
+                .args(std::env::args_os().skip(1))
+                .exec()
+                .into(),
+            Err(e) => anyhow::Error::new(e).context("Failed to locate executable"),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use crate::cli::Cli;
+    use anyhow::Context;
+    use clap::Parser;
+    use std::ffi::OsString;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    const SERVICE_NAME: &str = "solar_mon";
+    const SERVICE_DISPLAY_NAME: &str = "SUN600G3 Monitoring";
+    const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Registers the current executable as an automatically started service.
+    /// The config path is stored as an absolute path, as services are started
+    /// from the system directory.
+    pub(crate) fn install(cli: &Cli) -> anyhow::Result<()> {
+        let config = std::fs::canonicalize(&cli.config)
+            .with_context(|| format!("Config file {} not found", cli.config.display()))?;
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .context("Failed to connect to the service manager")?;
+        let service_info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from(SERVICE_DISPLAY_NAME),
+            service_type: SERVICE_TYPE,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: std::env::current_exe().context("Failed to locate executable")?,
+            launch_arguments: vec![
+                OsString::from("--run-as-service"),
+                OsString::from("--config"),
+                config.into_os_string(),
+            ],
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+        let service = manager
+            .create_service(&service_info, ServiceAccess::CHANGE_CONFIG)
+            .context("Failed to create service")?;
+        service
+            .set_description("Reads Deye SUN600G3 inverters and stores the data in InfluxDB")
+            .context("Failed to set service description")?;
+        Ok(())
+    }
+
+    pub(crate) fn uninstall() -> anyhow::Result<()> {
+        let manager =
+            ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+                .context("Failed to connect to the service manager")?;
+        let service = manager
+            .open_service(SERVICE_NAME, ServiceAccess::DELETE)
+            .context("Failed to open service")?;
+        service.delete().context("Failed to delete service")
+    }
+
+    /// Hands the main thread over to the service control manager. Only returns
+    /// once the service has been stopped.
+    pub(crate) fn run() -> anyhow::Result<()> {
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .context("Failed to start service dispatcher")
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            log::error!("Service exited unexpectedly: {e}");
+        }
+    }
+
+    fn run_service() -> anyhow::Result<()> {
+        // The launch arguments stored at install time are passed to the
+        // process, not to service_main.
+        let mut cli = Cli::parse();
+        let config = crate::init(&mut cli)?;
+        crate::init_logging(&config)?;
+        let locks = crate::instance_lock::InstanceLocks::acquire(&config)?;
+
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let status_handle =
+            service_control_handler::register(SERVICE_NAME, move |event| match event {
+                ServiceControl::Stop => {
+                    // The receiver only goes away once the service is stopping anyway.
+                    let _ = stop_tx.send(());
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            })
+            .context("Failed to register service control handler")?;
+
+        let status = |current_state, controls_accepted| ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        };
+        status_handle
+            .set_service_status(status(ServiceState::Running, ServiceControlAccept::STOP))
+            .context("Failed to report service as running")?;
+
+        std::thread::spawn(move || {
+            if let Err(e) = crate::run_daemon(config, locks) {
+                log::error!("Monitoring exited unexpectedly: {e}");
+            }
+        });
+        // Monitoring threads never finish on their own, they end with the process.
+        let _ = stop_rx.recv();
+
+        status_handle
+            .set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty()))
+            .context("Failed to report service as stopped")
+    }
+}
@@ -0,0 +1,282 @@
+//! Interactive creation of a config file: finds the logger sticks in the
+//! network, test polls them, checks the connection to InfluxDB and an MQTT
+//! broker and writes the config once it is valid.
+
+use crate::output::MqttConnection;
+use crate::{connect_inverter, parse_config, InverterConfig, MonitoringConfig};
+use anyhow::Context;
+use solar_mon::at_command;
+use std::io::{BufRead, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Questions on stdout, answers from stdin.
+struct Prompt {
+    input: std::io::StdinLock<'static>,
+    output: std::io::StdoutLock<'static>,
+}
+
+impl Prompt {
+    fn say(&mut self, text: &str) -> anyhow::Result<()> {
+        writeln!(self.output, "{text}")?;
+        Ok(())
+    }
+
+    /// Asks for a line, an empty answer takes `default`.
+    fn ask(&mut self, question: &str, default: &str) -> anyhow::Result<String> {
+        if default.is_empty() {
+            write!(self.output, "{question}: ")?;
+        } else {
+            write!(self.output, "{question} [{default}]: ")?;
+        }
+        self.output.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            anyhow::bail!("Setup aborted");
+        }
+        let answer = answer.trim();
+        Ok(if answer.is_empty() { default } else { answer }.to_owned())
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> anyhow::Result<bool> {
+        let choices = if default { "Y/n" } else { "y/N" };
+        loop {
+            let answer = self
+                .ask(&format!("{question} ({choices})"), "")?
+                .to_lowercase();
+            match answer.as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => self.say("Please answer y or n")?,
+            }
+        }
+    }
+
+    /// Asks until the answer parses.
+    fn ask_parsed<T: std::str::FromStr>(
+        &mut self,
+        question: &str,
+        default: &str,
+    ) -> anyhow::Result<T> {
+        loop {
+            match self.ask(question, default)?.parse() {
+                Ok(value) => return Ok(value),
+                Err(_) => self.say("Invalid value, please try again")?,
+            }
+        }
+    }
+}
+
+/// Walks through the setup and writes the config to `path`.
+pub(crate) fn setup(path: &Path) -> anyhow::Result<()> {
+    let mut prompt = Prompt {
+        input: std::io::stdin().lock(),
+        output: std::io::stdout().lock(),
+    };
+    if path.exists()
+        && !prompt.confirm(&format!("{} exists, overwrite it?", path.display()), false)?
+    {
+        return Ok(());
+    }
+    let mut config = toml::Table::new();
+    let inverters = ask_inverters(&mut prompt)?;
+    if inverters.is_empty() {
+        anyhow::bail!("No inverter configured");
+    }
+    config.insert("monitoring".to_owned(), ask_influx(&mut prompt)?.into());
+    config.insert("inverter".to_owned(), inverters.into());
+    if let Some(mqtt) = ask_mqtt(&mut prompt)? {
+        config.insert("output".to_owned(), vec![toml::Value::from(mqtt)].into());
+    }
+    let text = toml::to_string(&config).context("Failed to build the config")?;
+    // Catches anything the questions missed before it is written.
+    parse_config(&text).context("The resulting config is invalid")?;
+    // May hold the MQTT password, so only the owner may read it
+    crate::paths::replace_file(path, text.as_bytes(), true)?;
+    prompt.say(&format!(
+        "Wrote {}, start the monitoring with `solar_mon --config {0}`",
+        path.display()
+    ))
+}
+
+/// Defaults of the monitoring section for the test polls, only the
+/// timeouts matter.
+fn test_monitoring_config() -> anyhow::Result<MonitoringConfig> {
+    let mut table = toml::Table::new();
+    table.insert("influx_ip".to_owned(), "127.0.0.1".into());
+    Ok(toml::Value::from(table).try_into()?)
+}
+
+fn ask_inverters(prompt: &mut Prompt) -> anyhow::Result<toml::Table> {
+    prompt.say("Searching the network for logger sticks...")?;
+    let broadcast = IpAddr::V4(Ipv4Addr::BROADCAST);
+    let found = at_command::discover(broadcast, at_command::PORT, DISCOVERY_TIMEOUT)
+        .unwrap_or_else(|e| {
+            log::warn!("Discovery failed ({e:#})");
+            Vec::new()
+        });
+    let mut inverters = toml::Table::new();
+    for stick in found {
+        let question = format!(
+            "Found logger stick {} at {}, add it?",
+            stick.serial, stick.addr
+        );
+        if prompt.confirm(&question, true)? {
+            add_inverter(prompt, &mut inverters, stick.addr, None)?;
+        }
+    }
+    loop {
+        let question = if inverters.is_empty() {
+            "IP address of a logger stick, optionally with :port"
+        } else {
+            "IP address of another logger stick (empty to continue)"
+        };
+        let answer = prompt.ask(question, "")?;
+        if answer.is_empty() && !inverters.is_empty() {
+            return Ok(inverters);
+        }
+        if let Ok(addr) = answer.parse::<SocketAddr>() {
+            add_inverter(prompt, &mut inverters, addr.ip(), Some(addr.port()))?;
+        } else if let Ok(ip) = answer.parse() {
+            add_inverter(prompt, &mut inverters, ip, None)?;
+        } else {
+            prompt.say("Invalid IP address")?;
+        }
+    }
+}
+
+/// Asks for the details of the inverter at `ip` and test polls it.
+fn add_inverter(
+    prompt: &mut Prompt,
+    inverters: &mut toml::Table,
+    ip: IpAddr,
+    port: Option<u16>,
+) -> anyhow::Result<()> {
+    let default_name = format!("inverter{}", inverters.len() + 1);
+    let name = loop {
+        let name = prompt.ask("Name of the inverter", &default_name)?;
+        if !inverters.contains_key(&name) {
+            break name;
+        }
+        prompt.say(&format!("There already is an inverter named {name}"))?;
+    };
+    let mppt_inputs: u8 = loop {
+        let inputs = prompt.ask_parsed("Number of PV inputs (1 to 4)", "2")?;
+        if (1..=4).contains(&inputs) {
+            break inputs;
+        }
+        prompt.say("The inverters have 1 to 4 inputs")?;
+    };
+    let mut table = toml::Table::new();
+    table.insert("ip".to_owned(), ip.to_string().into());
+    if let Some(port) = port {
+        table.insert("port".to_owned(), i64::from(port).into());
+    }
+    table.insert("mppt_inputs".to_owned(), i64::from(mppt_inputs).into());
+    prompt.say("Test poll...")?;
+    let inverter_cfg: InverterConfig = toml::Value::from(table.clone()).try_into()?;
+    let data = connect_inverter(&inverter_cfg, &test_monitoring_config()?)
+        .and_then(|mut inverter| inverter.get_data());
+    match data {
+        Ok(data) => prompt.say(&format!(
+            "ok: {:.0} W, {:.1} kWh today, {:.1} kWh total",
+            data.ac_power, data.energy_today, data.energy_total
+        ))?,
+        Err(e) => {
+            prompt.say(&format!("Failed: {e:#}"))?;
+            // The sticks are only reachable while the sun shines.
+            if !prompt.confirm("Add it anyway?", false)? {
+                return Ok(());
+            }
+        }
+    }
+    inverters.insert(name, table.into());
+    Ok(())
+}
+
+fn ask_influx(prompt: &mut Prompt) -> anyhow::Result<toml::Table> {
+    loop {
+        let host = prompt.ask("InfluxDB host", "127.0.0.1")?;
+        let port: u16 = prompt.ask_parsed("InfluxDB port", "8086")?;
+        let database = prompt.ask("InfluxDB database", "solar")?;
+        let mut table = toml::Table::new();
+        table.insert("influx_ip".to_owned(), host.clone().into());
+        table.insert("influx_port".to_owned(), i64::from(port).into());
+        table.insert("database".to_owned(), database.into());
+        // InfluxDB answers `/ping` with 204 without needing credentials.
+        let ping = reqwest::blocking::Client::new()
+            .get(format!("http://{host}:{port}/ping"))
+            .timeout(TEST_TIMEOUT)
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status);
+        match ping {
+            Ok(_) => {
+                prompt.say("ok: InfluxDB is reachable")?;
+                return Ok(table);
+            }
+            Err(e) => {
+                prompt.say(&format!("Failed to reach InfluxDB: {e}"))?;
+                if prompt.confirm("Keep these settings anyway?", false)? {
+                    return Ok(table);
+                }
+            }
+        }
+    }
+}
+
+fn ask_mqtt(prompt: &mut Prompt) -> anyhow::Result<Option<toml::Table>> {
+    if !prompt.confirm(
+        "Also publish to an MQTT broker, e.g. for Home Assistant?",
+        false,
+    )? {
+        return Ok(None);
+    }
+    loop {
+        let address = prompt.ask("Broker address (host:port)", "127.0.0.1:1883")?;
+        let username = prompt.ask("Username (empty for none)", "")?;
+        let password = if username.is_empty() {
+            String::new()
+        } else {
+            prompt.ask("Password", "")?
+        };
+        let home_assistant = prompt.confirm("Announce the values to Home Assistant?", true)?;
+        let mut table = toml::Table::new();
+        table.insert("type".to_owned(), "mqtt".into());
+        table.insert("address".to_owned(), address.clone().into());
+        if !username.is_empty() {
+            table.insert("username".to_owned(), username.clone().into());
+            table.insert("password".to_owned(), password.clone().into());
+        }
+        if home_assistant {
+            table.insert("home_assistant".to_owned(), true.into());
+        }
+        let credentials = Some(username.as_str()).filter(|username| !username.is_empty());
+        let password = credentials.map(|_| password.as_str());
+        let connection = MqttConnection::connect(
+            &address,
+            "solar_mon_setup",
+            credentials,
+            password,
+            0,
+            TEST_TIMEOUT,
+        );
+        match connection {
+            Ok(_) => {
+                prompt.say("ok: connected to the broker")?;
+                return Ok(Some(table));
+            }
+            Err(e) => {
+                prompt.say(&format!("Failed to connect to the broker: {e:#}"))?;
+                if prompt.confirm("Keep these settings anyway?", false)? {
+                    return Ok(Some(table));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::output::Sample;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SmoothingMethod {
+    /// Mean of the last `samples` values
+    MovingAverage,
+    /// Exponential moving average with a weight of `2 / (samples + 1)`
+    Ema,
+}
+
+/// Smoothing of a single field, configured per field name.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub(crate) struct SmoothingConfig {
+    pub(crate) method: SmoothingMethod,
+    pub(crate) samples: usize,
+}
+
+/// Measurement, tags and field name of a series.
+type SeriesKey = (String, Vec<(String, String)>, String);
+
+enum FieldState {
+    Window(VecDeque<f64>),
+    Ema(f64),
+}
+
+/// Smooths the fields of consecutive polls of one inverter. Every series,
+/// i.e. measurement, tags and field, is tracked on its own.
+pub(crate) struct Smoother {
+    config: HashMap<String, SmoothingConfig>,
+    state: HashMap<SeriesKey, FieldState>,
+}
+
+impl Smoother {
+    pub(crate) fn new(config: HashMap<String, SmoothingConfig>) -> Self {
+        Smoother {
+            config,
+            state: HashMap::new(),
+        }
+    }
+
+    /// Replaces the configured fields by their smoothed values.
+    pub(crate) fn apply(&mut self, samples: &mut [Sample]) {
+        if self.config.is_empty() {
+            return;
+        }
+        for sample in samples {
+            for (name, value) in &mut sample.fields {
+                let Some(config) = self.config.get(name) else {
+                    continue;
+                };
+                let samples = config.samples.max(1);
+                let key = (sample.measurement.clone(), sample.tags.clone(), name.clone());
+                let state = self.state.entry(key).or_insert_with(|| match config.method {
+                    SmoothingMethod::MovingAverage => FieldState::Window(VecDeque::with_capacity(samples)),
+                    SmoothingMethod::Ema => FieldState::Ema(*value),
+                });
+                *value = match state {
+                    FieldState::Window(window) => {
+                        if window.len() == samples {
+                            window.pop_front();
+                        }
+                        window.push_back(*value);
+                        window.iter().sum::<f64>() / window.len() as f64
+                    }
+                    FieldState::Ema(average) => {
+                        let alpha = 2.0 / (samples as f64 + 1.0);
+                        *average += alpha * (*value - *average);
+                        *average
+                    }
+                };
+            }
+        }
+    }
+
+    /// Forgets all history, e.g. after the inverter was unreachable.
+    pub(crate) fn reset(&mut self) {
+        self.state.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn smoother(method: SmoothingMethod, samples: usize) -> Smoother {
+        Smoother::new(HashMap::from([("ac_power".to_owned(), SmoothingConfig { method, samples })]))
+    }
+
+    /// Smoothed `ac_power` of a poll with `power` and an unsmoothed field.
+    fn smooth(smoother: &mut Smoother, power: f64) -> f64 {
+        let mut samples = [Sample::new("solar", Utc::now())
+            .tag("inverter", "roof")
+            .field("ac_power", power)
+            .field("energy_today", power)];
+        smoother.apply(&mut samples);
+        assert_eq!(samples[0].field_value("energy_today"), Some(power));
+        samples[0].field_value("ac_power").unwrap()
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut smoother = smoother(SmoothingMethod::MovingAverage, 3);
+        assert_eq!(smooth(&mut smoother, 300.0), 300.0);
+        assert_eq!(smooth(&mut smoother, 600.0), 450.0);
+        assert_eq!(smooth(&mut smoother, 0.0), 300.0);
+        // The first value drops out of the window.
+        assert_eq!(smooth(&mut smoother, 900.0), 500.0);
+    }
+
+    #[test]
+    fn exponential_moving_average() {
+        let mut smoother = smoother(SmoothingMethod::Ema, 3);
+        assert_eq!(smooth(&mut smoother, 100.0), 100.0);
+        assert_eq!(smooth(&mut smoother, 300.0), 200.0);
+        assert_eq!(smooth(&mut smoother, 200.0), 200.0);
+    }
+
+    #[test]
+    fn polls_without_the_field_keep_the_history() {
+        let mut smoother = smoother(SmoothingMethod::MovingAverage, 2);
+        smooth(&mut smoother, 100.0);
+        let mut samples = [Sample::new("solar", Utc::now()).tag("inverter", "roof")];
+        smoother.apply(&mut samples);
+        assert_eq!(smooth(&mut smoother, 300.0), 200.0);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut smoother = smoother(SmoothingMethod::Ema, 5);
+        smooth(&mut smoother, 100.0);
+        smoother.reset();
+        assert_eq!(smooth(&mut smoother, 500.0), 500.0);
+    }
+
+    #[test]
+    fn series_are_smoothed_apart() {
+        let mut smoother = smoother(SmoothingMethod::MovingAverage, 0);
+        let time = Utc::now();
+        let mut samples = [
+            Sample::new("solar", time).tag("inverter", "roof").field("ac_power", 100.0),
+            Sample::new("solar", time).tag("inverter", "barn").field("ac_power", 300.0),
+        ];
+        smoother.apply(&mut samples);
+        assert_eq!(samples[0].field_value("ac_power"), Some(100.0));
+        assert_eq!(samples[1].field_value("ac_power"), Some(300.0));
+        // Zero samples smooth like one, the value is used as is.
+        assert_eq!(smooth(&mut smoother, 700.0), 700.0);
+    }
+}
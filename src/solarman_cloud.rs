@@ -0,0 +1,450 @@
+use anyhow::Context;
+use chrono::{NaiveDate, Timelike};
+use crate::output::{self, Sample};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Tokens are valid for about two months, a day old one is still safe.
+const TOKEN_CACHE_AGE: Duration = Duration::from_secs(24 * 3600);
+
+/// Access to the Solarman OpenAPI, the cloud the logger sticks report to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct SolarmanCloudConfig {
+    #[serde(default = "default_base_url")]
+    pub(crate) base_url: String,
+    pub(crate) app_id: String,
+    pub(crate) app_secret: String,
+    pub(crate) email: String,
+    pub(crate) password: String,
+    pub(crate) station_id: u64,
+    #[serde(default = "default_measurement_name")]
+    pub(crate) measurement: String,
+    /// Fetch the current values of inverters that can't be polled locally
+    #[serde(default)]
+    pub(crate) live_fallback: Option<LiveFallbackConfig>,
+}
+
+/// Falls back to the values the logger sticks report to the cloud while an
+/// inverter can't be polled during daylight, e.g. because the firmware of
+/// its stick only talks to the cloud.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct LiveFallbackConfig {
+    /// Minimum time between two requests per inverter, the sticks report
+    /// about every five minutes
+    #[serde(default = "default_fallback_interval")]
+    interval_secs: u64,
+    /// Local hours `[from, to)` in which failed polls are filled in
+    #[serde(default = "crate::default_daylight_hours")]
+    daylight_hours: [u32; 2],
+    /// Key in the data list of the device per field of the totals sample
+    #[serde(default = "default_fallback_fields")]
+    fields: BTreeMap<String, String>,
+    /// Keys of the voltage and current of the inputs, `{n}` is the input
+    /// number starting at 1
+    #[serde(default = "default_fallback_input_keys")]
+    input_keys: [String; 2],
+}
+
+fn default_fallback_interval() -> u64 {
+    300
+}
+
+fn default_fallback_fields() -> BTreeMap<String, String> {
+    [("ac_power", "APo_t1"), ("energy_today", "Etdy_ge1"), ("energy_total", "Et_ge0")]
+        .into_iter()
+        .map(|(field, key)| (field.to_owned(), key.to_owned()))
+        .collect()
+}
+
+fn default_fallback_input_keys() -> [String; 2] {
+    ["DV{n}".to_owned(), "DC{n}".to_owned()]
+}
+
+fn default_base_url() -> String {
+    "https://globalapi.solarmanpv.com".to_owned()
+}
+
+fn default_measurement_name() -> String {
+    "solarman_station".to_owned()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenResponse {
+    access_token: Option<String>,
+    #[serde(default)]
+    success: bool,
+    msg: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryResponse {
+    #[serde(default)]
+    success: bool,
+    msg: Option<String>,
+    #[serde(default)]
+    station_data_items: Vec<StationDataItem>,
+}
+
+/// A single entry of the station history, values the station does not
+/// report are missing.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StationDataItem {
+    /// Unix timestamp in seconds
+    pub(crate) date_time: f64,
+    /// Current production in W
+    pub(crate) generation_power: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurrentDataResponse {
+    #[serde(default)]
+    success: bool,
+    msg: Option<String>,
+    /// Unix timestamp in seconds of the values
+    collection_time: Option<f64>,
+    #[serde(default)]
+    data_list: Vec<DataItem>,
+}
+
+/// Entry of the data list of a device, the same in the Solarman and Deye
+/// Cloud APIs.
+#[derive(Debug, Deserialize)]
+pub(crate) struct DataItem {
+    key: String,
+    value: Option<String>,
+}
+
+/// The numeric values of a data list by key, others are skipped.
+pub(crate) fn data_values(items: Vec<DataItem>) -> HashMap<String, f64> {
+    items
+        .into_iter()
+        .filter_map(|item| Some((item.key, item.value?.parse().ok()?)))
+        .collect()
+}
+
+/// Key of the input with index `idx` in a data list, `{n}` in the template
+/// is the input number starting at 1.
+pub(crate) fn input_key(template: &str, idx: usize) -> String {
+    template.replace("{n}", &(idx + 1).to_string())
+}
+
+/// The password as the Solarman and Deye Cloud APIs expect it, the hex
+/// encoded SHA-256 digest.
+pub(crate) fn password_hash(password: &str) -> String {
+    Sha256::digest(password.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+pub(crate) struct SolarmanCloud {
+    config: SolarmanCloudConfig,
+    client: reqwest::blocking::Client,
+    access_token: String,
+}
+
+impl SolarmanCloud {
+    /// Logs in with the account credentials.
+    pub(crate) fn login(config: &SolarmanCloudConfig) -> anyhow::Result<Self> {
+        let client = reqwest::blocking::Client::new();
+        let response: TokenResponse = client
+            .post(format!("{}/account/v1.0/token", config.base_url))
+            .query(&[("appId", config.app_id.as_str()), ("language", "en")])
+            .json(&serde_json::json!({
+                "appSecret": config.app_secret,
+                "email": config.email,
+                "password": password_hash(&config.password),
+            }))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Failed to reach Solarman cloud")?
+            .json()
+            .context("Failed to parse Solarman login response")?;
+        let access_token = match response.access_token {
+            Some(token) if response.success => token,
+            _ => anyhow::bail!(
+                "Solarman login failed ({})",
+                response.msg.unwrap_or_default()
+            ),
+        };
+        Ok(SolarmanCloud {
+            config: config.clone(),
+            client,
+            access_token,
+        })
+    }
+
+    /// Reuses the access token cached in `cache_dir` if it is younger than
+    /// a day, else logs in and caches the new token.
+    pub(crate) fn connect(
+        config: &SolarmanCloudConfig,
+        cache_dir: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let Some(cache_file) = cache_dir.map(|dir| dir.join("solarman_token")) else {
+            return SolarmanCloud::login(config);
+        };
+        let fresh = std::fs::metadata(&cache_file)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age < TOKEN_CACHE_AGE));
+        let cached = fresh.then(|| std::fs::read_to_string(&cache_file).ok()).flatten();
+        if let Some(access_token) = cached {
+            log::debug!("Using cached Solarman access token");
+            return Ok(SolarmanCloud {
+                config: config.clone(),
+                client: reqwest::blocking::Client::new(),
+                access_token: access_token.trim().to_owned(),
+            });
+        }
+        let cloud = SolarmanCloud::login(config)?;
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let written = options
+            .open(&cache_file)
+            .and_then(|mut file| file.write_all(cloud.access_token.as_bytes()));
+        if let Err(e) = written {
+            let path = cache_file.display();
+            log::warn!("Failed to cache the Solarman access token in {path} ({e})");
+        }
+        Ok(cloud)
+    }
+
+    /// Fetches the detailed (roughly five minute) history of the configured
+    /// station for a single day.
+    pub(crate) fn station_day(&self, day: NaiveDate) -> anyhow::Result<Vec<StationDataItem>> {
+        let day = day.format("%Y-%m-%d").to_string();
+        let response: HistoryResponse = self
+            .client
+            .post(format!("{}/station/v1.0/history", self.config.base_url))
+            .query(&[("language", "en")])
+            .bearer_auth(&self.access_token)
+            .json(&serde_json::json!({
+                "stationId": self.config.station_id,
+                "timeType": 1,
+                "startTime": day,
+                "endTime": day,
+            }))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Failed to reach Solarman cloud")?
+            .json()
+            .context("Failed to parse Solarman history response")?;
+        if !response.success {
+            anyhow::bail!(
+                "Solarman history request failed ({})",
+                response.msg.unwrap_or_default()
+            );
+        }
+        Ok(response.station_data_items)
+    }
+}
+
+impl SolarmanCloud {
+    /// Fetches the latest values a device reported, by key.
+    fn current_data(&self, device_serial: &str) -> anyhow::Result<(i64, HashMap<String, f64>)> {
+        let response: CurrentDataResponse = self
+            .client
+            .post(format!("{}/device/v1.0/currentData", self.config.base_url))
+            .query(&[("language", "en")])
+            .bearer_auth(&self.access_token)
+            .json(&serde_json::json!({ "deviceSn": device_serial }))
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .context("Failed to reach Solarman cloud")?
+            .json()
+            .context("Failed to parse Solarman current data response")?;
+        if !response.success {
+            anyhow::bail!(
+                "Solarman current data request failed ({})",
+                response.msg.unwrap_or_default()
+            );
+        }
+        let time = response
+            .collection_time
+            .context("Solarman current data has no collection time")?;
+        Ok((time as i64, data_values(response.data_list)))
+    }
+}
+
+/// Requests of one inverter.
+#[derive(Debug, Default)]
+struct FallbackState {
+    requested: Option<Instant>,
+    /// Collection time of the values written last
+    collected: Option<i64>,
+}
+
+/// Cloud session shared by the pollers for the live fallback.
+pub(crate) struct LiveFallback {
+    config: SolarmanCloudConfig,
+    fallback: LiveFallbackConfig,
+    cache_dir: Option<PathBuf>,
+    /// Logged in on first use, dropped after a failed request. Shared so
+    /// the requests don't hold the lock.
+    cloud: Mutex<Option<Arc<SolarmanCloud>>>,
+    inverters: Mutex<HashMap<String, FallbackState>>,
+}
+
+static LIVE_FALLBACK: OnceLock<Option<LiveFallback>> = OnceLock::new();
+
+/// Sets up the live fallback if the config has one.
+pub(crate) fn init_live_fallback(config: &crate::Config) {
+    let fallback = config.solarman.as_ref().and_then(|cloud_config| {
+        Some(LiveFallback {
+            config: cloud_config.clone(),
+            fallback: cloud_config.live_fallback.clone()?,
+            cache_dir: config.cache_dir.clone(),
+            cloud: Mutex::new(None),
+            inverters: Mutex::new(HashMap::new()),
+        })
+    });
+    // Only the first config loaded counts.
+    let _ = LIVE_FALLBACK.set(fallback);
+}
+
+pub(crate) fn live_fallback() -> Option<&'static LiveFallback> {
+    LIVE_FALLBACK.get()?.as_ref()
+}
+
+impl LiveFallback {
+    /// Latest values of a device, logging in first if there is no session.
+    /// No lock is held during the requests.
+    fn current_data(&self, device_serial: &str) -> anyhow::Result<(i64, HashMap<String, f64>)> {
+        let session = self.cloud.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        let session = match session {
+            Some(session) => session,
+            None => {
+                let session =
+                    Arc::new(SolarmanCloud::connect(&self.config, self.cache_dir.as_deref())?);
+                self.cloud
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get_or_insert(session)
+                    .clone()
+            }
+        };
+        session.current_data(device_serial).inspect_err(|_| {
+            // The token may have expired, log in again next time.
+            let mut cloud = self.cloud.lock().unwrap_or_else(PoisonError::into_inner);
+            if cloud.as_ref().is_some_and(|current| Arc::ptr_eq(current, &session)) {
+                *cloud = None;
+            }
+        })
+    }
+
+    /// Samples of the values the inverter reported to the cloud, tagged
+    /// `source=cloud`, if it's daylight, the last request is long enough
+    /// ago and there are new values.
+    pub(crate) fn fetch(
+        &self,
+        inverter_name: &str,
+        device_serial: &str,
+        measurement: &str,
+        input_names: &[&str],
+    ) -> anyhow::Result<Vec<Sample>> {
+        let [from, to] = self.fallback.daylight_hours;
+        if !(from..to).contains(&chrono::Local::now().hour()) {
+            return Ok(Vec::new());
+        }
+        let interval = Duration::from_secs(self.fallback.interval_secs);
+        {
+            let mut inverters = self.inverters.lock().unwrap_or_else(PoisonError::into_inner);
+            let state = inverters.entry(inverter_name.to_owned()).or_default();
+            if state.requested.is_some_and(|requested| requested.elapsed() < interval) {
+                return Ok(Vec::new());
+            }
+            state.requested = Some(Instant::now());
+        }
+        let (collected, values) = self.current_data(device_serial)?;
+        {
+            let mut inverters = self.inverters.lock().unwrap_or_else(PoisonError::into_inner);
+            let state = inverters.entry(inverter_name.to_owned()).or_default();
+            if state.collected.is_some_and(|last| collected <= last) {
+                return Ok(Vec::new());
+            }
+            state.collected = Some(collected);
+        }
+        let time = chrono::DateTime::from_timestamp(collected, 0)
+            .context("Invalid collection time of Solarman current data")?;
+        let mut samples = Vec::new();
+        for (n, input) in input_names.iter().enumerate() {
+            let [voltage_key, current_key] = &self.fallback.input_keys;
+            let (Some(voltage), Some(current)) =
+                (values.get(&input_key(voltage_key, n)), values.get(&input_key(current_key, n)))
+            else {
+                continue;
+            };
+            samples.push(
+                Sample::new(measurement, time)
+                    .field("voltage", *voltage)
+                    .field("current", *current)
+                    .field("power", voltage * current)
+                    .tag("inverter", inverter_name)
+                    .tag("input", *input)
+                    .tag("source", "cloud"),
+            );
+        }
+        let mut totals = Sample::new(measurement, time)
+            .tag("inverter", inverter_name)
+            .tag("source", "cloud");
+        for (field, key) in &self.fallback.fields {
+            if let Some(value) = values.get(key) {
+                totals = totals.field(field.clone(), *value);
+            }
+        }
+        if !totals.fields.is_empty() {
+            samples.push(totals);
+        }
+        Ok(samples)
+    }
+}
+
+/// Copies the station history of the days `from..=to` into the outputs.
+pub(crate) fn import(config: &crate::Config, from: NaiveDate, to: NaiveDate) -> anyhow::Result<()> {
+    let cloud_config = config
+        .solarman
+        .as_ref()
+        .context("import-solarman requires a [solarman] section in the config")?;
+    let cloud = SolarmanCloud::connect(cloud_config, config.cache_dir.as_deref())?;
+    let mut outputs = output::create_sinks(config)?;
+    let station = cloud_config.station_id.to_string();
+
+    for day in from.iter_days().take_while(|day| *day <= to) {
+        let samples: Vec<_> = cloud
+            .station_day(day)
+            .with_context(|| format!("Failed to fetch history of {day}"))?
+            .into_iter()
+            .filter_map(|item| {
+                let power = item.generation_power?;
+                let time = chrono::DateTime::from_timestamp(item.date_time as i64, 0)?;
+                Some(
+                    Sample::new(cloud_config.measurement.clone(), time)
+                        .field("power", power)
+                        .tag("station", station.clone())
+                        .tag("source", "solarman"),
+                )
+            })
+            .collect();
+        log::info!("Importing {} samples of {day}", samples.len());
+        if samples.is_empty() {
+            continue;
+        }
+        if !outputs.write_all(&samples, "import") {
+            anyhow::bail!("Failed to store history of {day}");
+        }
+    }
+    if !outputs.flush_all("import") {
+        anyhow::bail!("Failed to store the imported history");
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Finding frames in received bytes without doing any I/O, so reading from
+//! a socket, a capture file or a test buffer shares one implementation.
+
+use super::frame::{checksum, verify_frame, Response, StickFrame};
+use crate::error_code::ErrorCode;
+
+/// Largest payload length accepted from the stick, anything above is taken
+/// as a corrupted header.
+const MAX_PAYLOAD_LENGTH: usize = 1024;
+
+/// Which intact frames [`FrameDecoder`] returns, the others are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accept {
+    /// Responses to requests, skipping e.g. heartbeats
+    Response,
+    /// Frames a logger stick sends on its own, see [`StickFrame`]
+    Stick,
+    /// Every frame whatever its control code, e.g. to follow recorded
+    /// traffic in both directions
+    Any,
+}
+
+impl Accept {
+    fn check(self, frame: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Accept::Response => {
+                if frame.len() < Response::MIN_LENGTH || frame[3..5] != [0x10, 0x15] {
+                    return Err(ErrorCode::UnexpectedFrame.error("Not a response frame"));
+                }
+                Ok(())
+            }
+            Accept::Stick => StickFrame::from_bytes(frame).map(|_| ()),
+            Accept::Any => Ok(()),
+        }
+    }
+}
+
+/// Looks for the next frame at the start of a buffer the caller fills with
+/// received bytes.
+///
+/// Bytes in front of a start byte, frames with an implausible length, a
+/// wrong end byte or checksum and frames not accepted are dropped from the
+/// buffer, so the stream resynchronizes on the next intact frame.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    accept: Accept,
+    /// Bytes dropped since the last frame
+    skipped: usize,
+    /// Why the last skipped frame was rejected
+    rejected: Option<anyhow::Error>,
+    rejected_frames: u64,
+    checksum_errors: u64,
+}
+
+impl FrameDecoder {
+    pub fn new(accept: Accept) -> Self {
+        FrameDecoder {
+            accept,
+            skipped: 0,
+            rejected: None,
+            rejected_frames: 0,
+            checksum_errors: 0,
+        }
+    }
+
+    /// Drops everything in front of the next accepted frame from `buffer`
+    /// and returns the length of the frame now at its start. `None` means
+    /// more bytes have to be appended first. Bytes after the frame belong to
+    /// the following frames.
+    pub fn decode(&mut self, buffer: &mut Vec<u8>) -> Option<usize> {
+        loop {
+            let start = buffer.iter().position(|b| *b == 0xA5).unwrap_or(buffer.len());
+            self.skipped += start;
+            buffer.drain(..start);
+            if buffer.len() < 3 {
+                return None;
+            }
+            let payload_length = usize::from(u16::from_le_bytes([buffer[1], buffer[2]]));
+            if payload_length > MAX_PAYLOAD_LENGTH {
+                self.skip(buffer);
+                continue;
+            }
+            let frame_length = 11 + payload_length + 2;
+            let frame = buffer.get(..frame_length)?;
+            if let Err(e) = verify_frame(frame).and_then(|()| self.accept.check(frame)) {
+                log::debug!("Skipping frame ({e})");
+                self.rejected_frames += 1;
+                if frame[frame_length - 2] != checksum(&frame[1..frame_length - 2]) {
+                    self.checksum_errors += 1;
+                }
+                self.rejected = Some(e);
+                self.skip(buffer);
+                continue;
+            }
+            let skipped = std::mem::take(&mut self.skipped);
+            if skipped > 0 {
+                log::debug!("Resynchronized after skipping {skipped} bytes");
+            }
+            self.rejected = None;
+            return Some(frame_length);
+        }
+    }
+
+    /// Drops the start byte of a frame that turned out to be none.
+    fn skip(&mut self, buffer: &mut Vec<u8>) {
+        buffer.remove(0);
+        self.skipped += 1;
+    }
+
+    /// Bytes dropped while looking for the next frame.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Why the last frame skipped while looking for the next one was
+    /// rejected, if any was.
+    pub fn take_rejection(&mut self) -> Option<anyhow::Error> {
+        self.rejected.take()
+    }
+
+    /// Intact looking frames skipped, including those with a wrong
+    /// checksum.
+    pub fn rejected_frames(&self) -> u64 {
+        self.rejected_frames
+    }
+
+    /// Frames skipped because of a wrong checksum.
+    pub fn checksum_errors(&self) -> u64 {
+        self.checksum_errors
+    }
+}
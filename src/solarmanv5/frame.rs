@@ -0,0 +1,427 @@
+//! Building and parsing of the SolarmanV5 frames.
+
+use crate::error_code::ErrorCode;
+
+/// Space separated hex dump of `bytes`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+}
+
+/// Short description of a Modbus RTU request for the trace log.
+pub fn describe_rtu_request(frame: &[u8]) -> String {
+    match frame {
+        [slave, function @ (0x03 | 0x04), start_hi, start_lo, count_hi, count_lo, ..] => {
+            let start = u16::from_be_bytes([*start_hi, *start_lo]);
+            let count = u16::from_be_bytes([*count_hi, *count_lo]);
+            format!(
+                "slave {slave} function {function:#04x} registers {start:#06x}..={:#06x} ({count})",
+                start.saturating_add(count.saturating_sub(1))
+            )
+        }
+        [slave, function, ..] => format!("slave {slave} function {function:#04x}"),
+        _ => format!("of {} bytes", frame.len()),
+    }
+}
+
+/// Short description of a Modbus RTU response for the trace log.
+pub fn describe_rtu_response(frame: &[u8]) -> String {
+    match frame {
+        [slave, function, exception, ..] if function & 0x80 != 0 => format!(
+            "slave {slave} function {:#04x} exception {exception:#04x}",
+            function & 0x7f
+        ),
+        [slave, function @ (0x03 | 0x04), byte_count, ..] => format!(
+            "slave {slave} function {function:#04x} {} registers",
+            byte_count / 2
+        ),
+        [slave, function, ..] => format!("slave {slave} function {function:#04x}"),
+        _ => format!("of {} bytes", frame.len()),
+    }
+}
+
+/// Sum of all bytes truncated to 8 bits, covering everything between the
+/// start byte and the checksum itself.
+pub(crate) fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().map(|b| *b as u32).sum::<u32>() as u8
+}
+
+/// Checks start byte, end byte and checksum of a complete frame.
+pub fn verify_frame(frame: &[u8]) -> anyhow::Result<()> {
+    let len = frame.len();
+    if len < 13 || frame[0] != 0xA5 || frame[len - 1] != 0x15 {
+        return Err(ErrorCode::MalformedFrame.error("Malformed frame"));
+    }
+    let expected = checksum(&frame[1..len - 2]);
+    if frame[len - 2] != expected {
+        return Err(ErrorCode::ChecksumMismatch.error(format!(
+            "Checksum mismatch (expected {expected:#04x}, got {:#04x})",
+            frame[len - 2]
+        )));
+    }
+    Ok(())
+}
+
+/// Frames a logger stick sends on its own to the server it is configured to
+/// report to, by control code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StickFrameType {
+    /// Sent once after connecting, with firmware and hardware details
+    Announcement,
+    /// Readings of the inverter
+    Data,
+    /// WiFi status of the stick
+    Wifi,
+    /// Keeps the connection alive, about once a minute
+    Heartbeat,
+}
+
+impl StickFrameType {
+    pub fn control_code(self) -> u16 {
+        match self {
+            StickFrameType::Announcement => 0x4110,
+            StickFrameType::Data => 0x4210,
+            StickFrameType::Wifi => 0x4310,
+            StickFrameType::Heartbeat => 0x4710,
+        }
+    }
+
+    pub fn from_control_code(code: u16) -> Option<Self> {
+        match code {
+            0x4110 => Some(StickFrameType::Announcement),
+            0x4210 => Some(StickFrameType::Data),
+            0x4310 => Some(StickFrameType::Wifi),
+            0x4710 => Some(StickFrameType::Heartbeat),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StickFrameType::Announcement => "announcement",
+            StickFrameType::Data => "data",
+            StickFrameType::Wifi => "wifi",
+            StickFrameType::Heartbeat => "heartbeat",
+        }
+    }
+
+    /// Control code of the server's answer, e.g. 0x1710 to a heartbeat.
+    pub fn response_code(self) -> u16 {
+        self.control_code() - 0x3000
+    }
+}
+
+/// A frame received from a logger stick connected to us, the payload
+/// borrows from the receive buffer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StickFrame<'a> {
+    pub frame_type: StickFrameType,
+    pub msg_id: u16,
+    pub logger_serial: [u8; 4],
+    pub payload: &'a [u8],
+}
+
+impl<'a> StickFrame<'a> {
+    /// Parses a frame checked by [`verify_frame`], failing for control codes
+    /// other than those of [`StickFrameType`].
+    pub fn from_bytes(data: &'a [u8]) -> anyhow::Result<Self> {
+        if data.len() < 13 {
+            return Err(ErrorCode::ResponseTooShort.error("Frame too short for its header"));
+        }
+        let control_code = u16::from_le_bytes([data[3], data[4]]);
+        let frame_type = StickFrameType::from_control_code(control_code).ok_or_else(|| {
+            ErrorCode::UnexpectedFrame.error(format!("Unknown control code {control_code:#06x}"))
+        })?;
+        let mut logger_serial = [0; 4];
+        logger_serial.copy_from_slice(&data[7..11]);
+        Ok(StickFrame {
+            frame_type,
+            msg_id: u16::from_le_bytes([data[5], data[6]]),
+            logger_serial,
+            payload: &data[11..data.len() - 2],
+        })
+    }
+
+    /// Register snapshot of a data frame, behind the frame type, sensor
+    /// type and the three times also found in requests.
+    pub fn data_registers(&self) -> Option<&'a [u8]> {
+        match self.frame_type {
+            StickFrameType::Data => self.payload.get(15..),
+            _ => None,
+        }
+    }
+
+    /// The answer the stick expects from its server. It acknowledges the
+    /// frame and tells the stick the time, `unix_time` in seconds. Sticks
+    /// drop the connection after a few frames without answer.
+    pub fn response(&self, unix_time: u32) -> Vec<u8> {
+        let mut frame = vec![0xA5];
+        frame.extend(10u16.to_le_bytes());
+        frame.extend(self.frame_type.response_code().to_le_bytes());
+        frame.extend(self.msg_id.to_le_bytes());
+        frame.extend(self.logger_serial);
+        // Frame type of the received payload, status ok, time and an unused
+        // word
+        frame.push(self.payload.first().copied().unwrap_or_default());
+        frame.push(0x01);
+        frame.extend(unix_time.to_le_bytes());
+        frame.extend([0; 4]);
+        frame.push(checksum(&frame[1..]));
+        frame.push(0x15);
+        frame
+    }
+}
+
+/// A SolarmanV5 frame sent to the logger stick.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub header: RequestHeader,
+    pub payload: RequestPayload<'a>,
+}
+
+impl<'a> Request<'a> {
+    /// Request passing the Modbus RTU `frame` on to the inverter.
+    pub fn modbus(logger_serial: [u8; 4], frame: &'a [u8]) -> Self {
+        Request {
+            header: RequestHeader {
+                msg_id: 0,
+                logger_serial,
+            },
+            payload: RequestPayload {
+                frame_type: RequestFrameType::SolarInverter,
+                sensor_type: 0,
+                total_working_second: 0,
+                uptime_second: 0,
+                offset_seconds: 0,
+                modbus_rtu_frame: frame,
+            },
+        }
+    }
+
+    /// Total length of the serialized frame.
+    pub fn frame_length(&self) -> usize {
+        // header (11) + payload + checksum + end byte
+        11 + usize::from(self.payload.length()) + 2
+    }
+
+    /// Appends the serialized frame to `bytes`, so one buffer can be reused
+    /// for every request.
+    pub fn write_to(&self, bytes: &mut Vec<u8>) {
+        let start = bytes.len();
+        bytes.reserve(self.frame_length());
+        self.header.write_to(self.payload.length(), bytes);
+        self.payload.write_to(bytes);
+        let checksum = checksum(&bytes[start + 1..]);
+        bytes.push(checksum);
+        bytes.push(0x15);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.frame_length());
+        self.write_to(&mut bytes);
+        bytes
+    }
+
+    /// Parses a serialized request, the RTU frame borrows from `data`.
+    pub fn from_bytes(data: &'a [u8]) -> anyhow::Result<Self> {
+        verify_frame(data)?;
+        if data.len() < 11 + 15 + 2 {
+            anyhow::bail!("Request too short ({} bytes)", data.len());
+        }
+        let payload_length = u16::from_le_bytes([data[1], data[2]]);
+        if usize::from(payload_length) != data.len() - 13 {
+            anyhow::bail!(
+                "Length field ({payload_length}) does not match payload ({} bytes)",
+                data.len() - 13
+            );
+        }
+        if data[3..5] != [0x10, 0x45] {
+            anyhow::bail!("Not a request frame");
+        }
+        let mut logger_serial = [0; 4];
+        logger_serial.copy_from_slice(&data[7..11]);
+        let payload = &data[11..data.len() - 2];
+        let le_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                payload[offset],
+                payload[offset + 1],
+                payload[offset + 2],
+                payload[offset + 3],
+            ])
+        };
+        Ok(Request {
+            header: RequestHeader {
+                msg_id: u16::from_le_bytes([data[5], data[6]]),
+                logger_serial,
+            },
+            payload: RequestPayload {
+                frame_type: RequestFrameType::try_from(payload[0])?,
+                sensor_type: u16::from_be_bytes([payload[1], payload[2]]),
+                total_working_second: le_u32(3),
+                uptime_second: le_u32(7),
+                offset_seconds: le_u32(11),
+                modbus_rtu_frame: &payload[15..],
+            },
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RequestHeader {
+    pub msg_id: u16,
+    pub logger_serial: [u8; 4],
+}
+
+impl RequestHeader {
+    fn write_to(&self, payload_length: u16, bytes: &mut Vec<u8>) {
+        bytes.push(0xA5);
+        bytes.extend(payload_length.to_le_bytes());
+        bytes.extend([0x10, 0x45]);
+        bytes.extend(self.msg_id.to_le_bytes());
+        bytes.extend(self.logger_serial);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RequestFrameType {
+    SolarInverter = 0x02,
+    DataLoggingStick = 0x01,
+    SolarmanCloud = 0x00,
+}
+
+impl TryFrom<u8> for RequestFrameType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        match value {
+            0x02 => Ok(RequestFrameType::SolarInverter),
+            0x01 => Ok(RequestFrameType::DataLoggingStick),
+            0x00 => Ok(RequestFrameType::SolarmanCloud),
+            _ => anyhow::bail!("Unknown frame type {value:#04x}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RequestPayload<'a> {
+    pub frame_type: RequestFrameType,
+    pub sensor_type: u16,
+    pub total_working_second: u32,
+    pub uptime_second: u32,
+    pub offset_seconds: u32,
+    pub modbus_rtu_frame: &'a [u8],
+}
+
+impl RequestPayload<'_> {
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.frame_type.clone() as u8);
+        bytes.extend(self.sensor_type.to_be_bytes());
+        bytes.extend(self.total_working_second.to_le_bytes());
+        bytes.extend(self.uptime_second.to_le_bytes());
+        bytes.extend(self.offset_seconds.to_le_bytes());
+        bytes.extend(self.modbus_rtu_frame);
+    }
+
+    fn length(&self) -> u16 {
+        (15 + self.modbus_rtu_frame.len()).try_into().expect("RTU frame length does not exceed 65521")
+    }
+}
+
+#[derive(Debug)]
+pub struct ResponseHeader {
+    pub length: u16,
+    pub msg_id: [u8; 2],
+    pub logger_serial: [u8; 4],
+}
+
+impl ResponseHeader {
+    fn from_bytes(data: &[u8]) -> Self {
+        let length = u16::from_le_bytes(
+            data[1..3]
+                .try_into()
+                .expect("constant slice length will never fail"),
+        );
+        let mut logger_serial = [0; 4];
+        logger_serial.copy_from_slice(&data[7..11]);
+        let mut msg_id = [0; 2];
+        msg_id.copy_from_slice(&data[5..7]);
+        ResponseHeader {
+            length,
+            logger_serial,
+            msg_id,
+        }
+    }
+}
+
+/// Payload of a response, the RTU frame is borrowed from the receive buffer.
+#[derive(Debug)]
+pub struct ResponsePayload<'a> {
+    pub status: u8,
+    pub total_working_time: [u8; 4],
+    pub power_on_time: [u8; 4],
+    pub offset_time: [u8; 4],
+    pub rtu_frame: &'a [u8],
+    pub checksum: u8,
+}
+
+impl<'a> ResponsePayload<'a> {
+    fn from_bytes(data: &'a [u8]) -> Self {
+        let status = data[1];
+        let mut total_working_time = [0; 4];
+        total_working_time.copy_from_slice(&data[2..6]);
+        let mut power_on_time = [0; 4];
+        power_on_time.copy_from_slice(&data[6..10]);
+        let mut offset_time = [0; 4];
+        offset_time.copy_from_slice(&data[10..14]);
+        ResponsePayload {
+            status,
+            total_working_time,
+            power_on_time,
+            offset_time,
+            rtu_frame: &data[14..data.len() - 2],
+            checksum: data[data.len() - 2],
+        }
+    }
+}
+
+impl ResponsePayload<'_> {
+    /// Time on the stick clock when it sent the response, since the Unix
+    /// epoch. The stick reports when it was powered on and the uptime since,
+    /// `None` if it never learned the time.
+    pub fn stick_time(&self) -> Option<std::time::Duration> {
+        let offset = u32::from_le_bytes(self.offset_time);
+        if offset == 0 {
+            return None;
+        }
+        let uptime = u32::from_le_bytes(self.power_on_time);
+        Some(std::time::Duration::from_secs(u64::from(offset) + u64::from(uptime)))
+    }
+}
+
+/// A SolarmanV5 frame received from the logger stick.
+#[derive(Debug)]
+pub struct Response<'a> {
+    pub header: ResponseHeader,
+    pub payload: ResponsePayload<'a>,
+}
+
+impl<'a> Response<'a> {
+    /// Length of a response without RTU frame.
+    pub const MIN_LENGTH: usize = 11 + 16;
+
+    /// Splits a received frame into its parts. Only the length is checked,
+    /// see [`verify_frame`] for validating the framing itself.
+    pub fn from_bytes(data: &'a [u8]) -> anyhow::Result<Self> {
+        if data.len() < Self::MIN_LENGTH {
+            return Err(ErrorCode::ResponseTooShort.error(format!(
+                "Response too short ({} bytes, at least {} required)",
+                data.len(),
+                Self::MIN_LENGTH
+            )));
+        }
+        Ok(Response {
+            header: ResponseHeader::from_bytes(&data[0..11]),
+            payload: ResponsePayload::from_bytes(&data[11..]),
+        })
+    }
+}
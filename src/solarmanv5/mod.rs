@@ -0,0 +1,454 @@
+//! The SolarmanV5 protocol the logger sticks speak. [`frame`] and
+//! [`decoder`] build and parse frames without any I/O, this module talks to
+//! the sticks over TCP with them.
+
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpStream};
+use anyhow::Context;
+use crate::connection_limit::{self, Permit};
+use crate::encryption::{self, Encryption, PayloadCipher};
+use crate::error_code::{self, ErrorCode};
+
+pub mod decoder;
+pub mod frame;
+
+pub use decoder::{Accept, FrameDecoder};
+pub(crate) use frame::checksum;
+pub use frame::{
+    describe_rtu_request, describe_rtu_response, hex, verify_frame, Request, RequestFrameType,
+    RequestHeader, RequestPayload, Response, ResponseHeader, ResponsePayload, StickFrame,
+    StickFrameType,
+};
+
+/// Timeouts of the connection to the logger stick. Sticks often take a long
+/// time to answer while connecting should fail fast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: std::time::Duration,
+    pub read: std::time::Duration,
+    pub write: std::time::Duration,
+}
+
+impl From<std::time::Duration> for Timeouts {
+    /// Uses the same timeout for everything.
+    fn from(timeout: std::time::Duration) -> Self {
+        Timeouts {
+            connect: timeout,
+            read: timeout,
+            write: timeout,
+        }
+    }
+}
+
+/// Communication statistics of a device since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Modbus requests sent to the stick
+    pub requests: u64,
+    /// Requests without a valid response
+    pub failures: u64,
+    /// Failures because the stick did not answer in time
+    pub timeouts: u64,
+    /// Received frames with a wrong checksum
+    pub checksum_errors: u64,
+    /// Received frames skipped while waiting for the response, including
+    /// those with a wrong checksum
+    pub rejected_frames: u64,
+    /// Sum of the round trip times of all successful requests
+    pub latency: std::time::Duration,
+}
+
+impl LinkStats {
+    /// Mean round trip time of the successful requests.
+    pub fn average_latency(&self) -> Option<std::time::Duration> {
+        let successes = u32::try_from(self.requests - self.failures).ok()?;
+        self.latency.checked_div(successes)
+    }
+}
+
+/// Estimates how far the clock of a logger stick is ahead of the host from
+/// the times stamped on its responses. Fresh readings show the skew itself,
+/// readings the stick held back for a while a smaller difference.
+#[derive(Debug, Clone, Default)]
+pub struct ClockSkew {
+    /// Stick minus host time of the recent responses in ms
+    differences: std::collections::VecDeque<i64>,
+}
+
+impl ClockSkew {
+    /// Responses the estimate is based on
+    const WINDOW: usize = 16;
+    /// Smaller ages are within the one second resolution of the stick
+    const MIN_AGE_MS: i64 = 2000;
+    /// Larger ages are taken as the stick clock being set back
+    const MAX_AGE_MS: i64 = 15 * 60 * 1000;
+
+    /// Records a response stamped `stick_time` by the stick and received at
+    /// host time `received`, both since the Unix epoch. Returns how old its
+    /// reading is, `None` if it is as fresh as the others.
+    pub fn update(
+        &mut self,
+        stick_time: std::time::Duration,
+        received: std::time::Duration,
+    ) -> Option<std::time::Duration> {
+        let difference = stick_time.as_millis() as i64 - received.as_millis() as i64;
+        if self.differences.len() == Self::WINDOW {
+            self.differences.pop_front();
+        }
+        self.differences.push_back(difference);
+        let age = self.skew_ms()? - difference;
+        if age > Self::MAX_AGE_MS {
+            self.differences.clear();
+            self.differences.push_back(difference);
+            return None;
+        }
+        (age >= Self::MIN_AGE_MS).then(|| std::time::Duration::from_millis(age as u64))
+    }
+
+    /// Milliseconds the stick clock is ahead of the host, negative if it is
+    /// behind.
+    pub fn skew_ms(&self) -> Option<i64> {
+        self.differences.iter().copied().max()
+    }
+}
+
+/// Whether `error` was caused by a read or write timeout.
+pub(crate) fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+            matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock)
+        })
+    })
+}
+
+pub struct SolarmanDevice {
+    addr: std::net::IpAddr,
+    port: u16,
+    timeouts: Timeouts,
+    logger_serial: [u8; 4],
+    send_buffer: Vec<u8>,
+    receive_buffer: Vec<u8>,
+    stats: LinkStats,
+    /// Put in front of every log message, e.g. to tell the polls apart
+    log_prefix: String,
+    clock: ClockSkew,
+    /// Age of the reading in the last response
+    data_age: Option<std::time::Duration>,
+    reading_time: Option<std::time::SystemTime>,
+    encryption: Encryption,
+    /// Key replacing the one derived from the logger serial
+    key: Option<[u8; 16]>,
+    /// Whether requests are encrypted now
+    encrypting: bool,
+}
+
+impl SolarmanDevice {
+    pub fn new(
+        addr: std::net::IpAddr,
+        port: u16,
+        timeouts: impl Into<Timeouts>,
+    ) -> anyhow::Result<Self> {
+        let mut device = SolarmanDevice {
+            addr,
+            port,
+            timeouts: timeouts.into(),
+            logger_serial: [0; 4],
+            send_buffer: Vec::new(),
+            receive_buffer: Vec::new(),
+            stats: LinkStats::default(),
+            log_prefix: String::new(),
+            clock: ClockSkew::default(),
+            data_age: None,
+            reading_time: None,
+            encryption: Encryption::Auto,
+            key: None,
+            encrypting: false,
+        };
+        device.detect_serial()?;
+        Ok(device)
+    }
+
+    /// Creates a device for a logger stick whose serial is already known,
+    /// skipping the detection exchange.
+    pub fn with_serial(
+        addr: std::net::IpAddr,
+        port: u16,
+        timeouts: impl Into<Timeouts>,
+        logger_serial: [u8; 4],
+    ) -> Self {
+        SolarmanDevice {
+            addr,
+            port,
+            timeouts: timeouts.into(),
+            logger_serial,
+            send_buffer: Vec::new(),
+            receive_buffer: Vec::new(),
+            stats: LinkStats::default(),
+            log_prefix: String::new(),
+            clock: ClockSkew::default(),
+            data_age: None,
+            reading_time: None,
+            encryption: Encryption::Auto,
+            key: None,
+            encrypting: false,
+        }
+    }
+
+    pub fn logger_serial(&self) -> [u8; 4] {
+        self.logger_serial
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    /// How long before its reception the stick took the reading of the
+    /// last response according to its own clock, `None` if it was fresh or
+    /// the stick does not report its time.
+    pub fn data_age(&self) -> Option<std::time::Duration> {
+        self.data_age
+    }
+
+    /// When the stick took the reading of the last response in host time,
+    /// only set together with [`Self::data_age`].
+    pub fn reading_time(&self) -> Option<std::time::SystemTime> {
+        self.reading_time
+    }
+
+    /// Estimated skew of the stick clock, see [`ClockSkew`].
+    pub fn clock_skew_ms(&self) -> Option<i64> {
+        self.clock.skew_ms()
+    }
+
+    /// Whether the payload of requests is encrypted, `key` replaces the key
+    /// derived from the logger serial. Detection of encrypted responses
+    /// stays on unless encryption is [`Encryption::Never`].
+    pub fn set_encryption(&mut self, encryption: Encryption, key: Option<[u8; 16]>) {
+        self.encryption = encryption;
+        self.key = key;
+        self.encrypting = encryption == Encryption::Always;
+    }
+
+    /// Whether the requests are encrypted, either configured or because the
+    /// stick answered encrypted.
+    pub fn encrypted(&self) -> bool {
+        self.encrypting
+    }
+
+    fn cipher(&self) -> PayloadCipher {
+        match self.key {
+            Some(key) => PayloadCipher::new(key),
+            None => PayloadCipher::for_serial(self.logger_serial),
+        }
+    }
+
+    /// Sets the text put in front of the log messages of the following
+    /// requests.
+    pub fn set_log_prefix(&mut self, prefix: impl Into<String>) {
+        self.log_prefix = prefix.into();
+    }
+
+    /// Connects to the stick, the connection counts towards the
+    /// [`connection_limit`] until the permit is dropped.
+    fn create_connection(&self) -> anyhow::Result<(std::net::TcpStream, Permit)> {
+        let addr = SocketAddr::new(self.addr, self.port);
+        let permit = connection_limit::acquire(addr);
+        let stream = TcpStream::connect_timeout(&addr, self.timeouts.connect)
+            .map_err(error_code::connect_error)?;
+        stream.set_read_timeout(Some(self.timeouts.read)).context("Failed to set read timeout")?;
+        stream.set_write_timeout(Some(self.timeouts.write)).context("failed to set write timeout")?;
+        Ok((stream, permit))
+    }
+
+    fn detect_serial(&mut self) -> anyhow::Result<()> {
+        let (mut connection, _permit) = self.create_connection()?;
+        connection.write_all(&Request::modbus(self.logger_serial, &[]).to_bytes())?;
+
+        let mut response_buffer = Vec::new();
+        read_frame(&mut connection, &mut response_buffer).context("Failed reading serial detection response")?;
+        let response = Response::from_bytes(&response_buffer)?;
+        self.logger_serial = response.header.logger_serial;
+        Ok(())
+    }
+
+    /// Sends a Modbus RTU frame to the inverter and returns the RTU frame of
+    /// the response. It borrows from the device's receive buffer and is only
+    /// valid until the next request.
+    pub fn send_modbus_frame(&mut self, frame: &[u8]) -> anyhow::Result<&[u8]> {
+        let start = std::time::Instant::now();
+        self.stats.requests += 1;
+        if let Err(e) = self.exchange(frame) {
+            self.stats.failures += 1;
+            if is_timeout(&e) {
+                self.stats.timeouts += 1;
+            }
+            return Err(e);
+        }
+        let response = match Response::from_bytes(&self.receive_buffer) {
+            Ok(response) => response,
+            Err(e) => {
+                self.stats.failures += 1;
+                return Err(e);
+            }
+        };
+        self.stats.latency += start.elapsed();
+        let received = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.data_age = response
+            .payload
+            .stick_time()
+            .and_then(|stick_time| self.clock.update(stick_time, received));
+        // The stick time corrected by the skew, the same for every response
+        // carrying the same reading
+        self.reading_time = self.data_age.map(|age| {
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(received.as_millis() as u64) - age
+        });
+        let prefix = &self.log_prefix;
+        log::debug!("{prefix}Recieved Response: {response:?}");
+        if log::log_enabled!(log::Level::Trace) {
+            let rtu_frame = response.payload.rtu_frame;
+            log::trace!(
+                "{prefix}Modbus response {} [{}]",
+                describe_rtu_response(rtu_frame),
+                hex(rtu_frame)
+            );
+        }
+        Ok(response.payload.rtu_frame)
+    }
+
+    /// Sends the request and reads the response frame into the receive
+    /// buffer.
+    fn exchange(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+        let (mut connection, _permit) = self.create_connection()?;
+        self.encode_request(frame);
+        connection.write_all(&self.send_buffer)?;
+        self.receive_buffer.clear();
+        read_frame_counted(&mut connection, &mut self.receive_buffer, &mut self.stats)?;
+        self.decrypt_response()
+    }
+
+    /// Puts the request frame carrying `frame` into the send buffer.
+    fn encode_request(&mut self, frame: &[u8]) {
+        let request = Request::modbus(self.logger_serial, frame);
+        let prefix = &self.log_prefix;
+        log::debug!("{prefix}Sending Request: {request:?}");
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("{prefix}Modbus request {} [{}]", describe_rtu_request(frame), hex(frame));
+        }
+        self.send_buffer.clear();
+        request.write_to(&mut self.send_buffer);
+        if self.encrypting {
+            self.send_buffer = self.cipher().encrypt_frame(&self.send_buffer);
+        }
+    }
+
+    /// Decrypts the response in the receive buffer if it is encrypted and
+    /// encrypts the following requests from then on.
+    fn decrypt_response(&mut self) -> anyhow::Result<()> {
+        if self.encryption == Encryption::Never || !encryption::is_encrypted(&self.receive_buffer) {
+            return Ok(());
+        }
+        let prefix = &self.log_prefix;
+        match self.cipher().decrypt_response(&self.receive_buffer) {
+            Ok(plain) => {
+                self.receive_buffer = plain;
+                if !self.encrypting {
+                    log::info!("{prefix}Logger stick answered encrypted, encrypting requests from now on");
+                    self.encrypting = true;
+                }
+            }
+            Err(e) if self.encrypting => return Err(e),
+            // A plain response with a broken RTU frame
+            Err(e) => log::debug!("{prefix}Response is not encrypted after all ({e})"),
+        }
+        Ok(())
+    }
+}
+
+/// Number of bytes discarded while searching for a frame before giving up.
+const MAX_SKIPPED_BYTES: usize = 4096;
+
+/// Appends the next bytes arriving from `reader` to `buffer`.
+fn read_more(reader: &mut impl Read, buffer: &mut Vec<u8>) -> std::io::Result<()> {
+    let mut chunk = [0; 256];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed before a complete frame was received",
+                ))
+            }
+            Ok(read) => {
+                buffer.extend_from_slice(&chunk[..read]);
+                return Ok(());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the next valid response frame into `buffer`, which has to be
+/// empty or hold bytes already received from `reader`.
+///
+/// Frames that are no responses (e.g. heartbeats) and broken ones are
+/// skipped as described at [`FrameDecoder`]. Bytes after the frame are
+/// discarded.
+pub fn read_frame(reader: &mut impl Read, buffer: &mut Vec<u8>) -> anyhow::Result<()> {
+    read_frame_counted(reader, buffer, &mut LinkStats::default())
+}
+
+/// [`read_frame`] counting the rejected frames in `stats`.
+fn read_frame_counted(
+    reader: &mut impl Read,
+    buffer: &mut Vec<u8>,
+    stats: &mut LinkStats,
+) -> anyhow::Result<()> {
+    let frame_length = read_frame_with(reader, buffer, stats, Accept::Response)?;
+    buffer.truncate(frame_length);
+    Ok(())
+}
+
+/// Like [`read_frame`], but reads the next frame a logger stick sent on its
+/// own to its server, see [`StickFrame`]. Returns the length of the frame
+/// at the start of `buffer`, the bytes after it belong to the next frames.
+pub fn read_stick_frame(reader: &mut impl Read, buffer: &mut Vec<u8>) -> anyhow::Result<usize> {
+    read_frame_with(reader, buffer, &mut LinkStats::default(), Accept::Stick)
+}
+
+/// Reads until `buffer` starts with a frame of the `accept`ed kind and
+/// returns its length.
+fn read_frame_with(
+    reader: &mut impl Read,
+    buffer: &mut Vec<u8>,
+    stats: &mut LinkStats,
+    accept: Accept,
+) -> anyhow::Result<usize> {
+    let mut decoder = FrameDecoder::new(accept);
+    let result = loop {
+        if let Some(frame_length) = decoder.decode(buffer) {
+            break Ok(frame_length);
+        }
+        let skipped = decoder.skipped();
+        if skipped > MAX_SKIPPED_BYTES {
+            break Err(ErrorCode::NoFrame.error(format!("No valid frame found in {skipped} bytes")));
+        }
+        if let Err(e) = read_more(reader, buffer) {
+            break Err(read_error(e, decoder.take_rejection()));
+        }
+    };
+    stats.rejected_frames += decoder.rejected_frames();
+    stats.checksum_errors += decoder.checksum_errors();
+    result
+}
+
+/// When the stream ends right after a rejected frame, the reason for the
+/// rejection is the more useful error.
+fn read_error(error: std::io::Error, rejected: Option<anyhow::Error>) -> anyhow::Error {
+    match rejected {
+        Some(rejected) if error.kind() == std::io::ErrorKind::UnexpectedEof => rejected,
+        _ => error.into(),
+    }
+}
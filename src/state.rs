@@ -0,0 +1,223 @@
+use crate::output::Sample;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use solar_mon::error_code::ErrorCode;
+use std::fmt;
+
+/// Coarse operating state of an inverter as seen by the poller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InverterState {
+    /// No successful connection yet since startup
+    Connecting,
+    /// The logger stick can't be reached
+    Offline,
+    /// The stick answers, but the inverter data is unusable
+    Faulted,
+    /// Connected without any PV power, e.g. at night
+    Idle,
+    Producing,
+    /// Polling was paused on request, e.g. during maintenance
+    Paused,
+}
+
+impl InverterState {
+    fn as_str(self) -> &'static str {
+        match self {
+            InverterState::Connecting => "connecting",
+            InverterState::Offline => "offline",
+            InverterState::Faulted => "faulted",
+            InverterState::Idle => "idle",
+            InverterState::Producing => "producing",
+            InverterState::Paused => "paused",
+        }
+    }
+
+    /// Stable numeric representation for outputs without string support.
+    fn code(self) -> f64 {
+        match self {
+            InverterState::Connecting => 0.0,
+            InverterState::Offline => 1.0,
+            InverterState::Faulted => 2.0,
+            InverterState::Idle => 3.0,
+            InverterState::Producing => 4.0,
+            InverterState::Paused => 5.0,
+        }
+    }
+}
+
+impl fmt::Display for InverterState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct StateEvent {
+    pub(crate) inverter: String,
+    pub(crate) time: DateTime<Utc>,
+    pub(crate) from: InverterState,
+    pub(crate) to: InverterState,
+    /// Seconds spent in the previous state
+    pub(crate) duration_secs: i64,
+    /// Short machine readable cause, e.g. `connect_failed`
+    pub(crate) reason: &'static str,
+    /// Code of the error causing the change, if any
+    pub(crate) error_code: Option<ErrorCode>,
+}
+
+impl StateEvent {
+    pub(crate) fn with_error_code(mut self, error_code: ErrorCode) -> Self {
+        self.error_code = Some(error_code);
+        self
+    }
+
+    pub(crate) fn to_sample(&self, measurement: &str) -> Sample {
+        let sample = Sample::new(measurement, self.time)
+            .field("state_code", self.to.code())
+            .field("previous_duration_secs", self.duration_secs as f64)
+            .tag("inverter", self.inverter.clone())
+            .tag("state", self.to.as_str())
+            .tag("previous_state", self.from.as_str())
+            .tag("reason", self.reason);
+        match self.error_code {
+            Some(error_code) => sample.tag("error_code", error_code.code()),
+            None => sample,
+        }
+    }
+}
+
+pub(crate) struct StateTracker {
+    inverter: String,
+    state: InverterState,
+    since: DateTime<Utc>,
+}
+
+impl StateTracker {
+    pub(crate) fn new(inverter: &str) -> Self {
+        StateTracker {
+            inverter: inverter.to_owned(),
+            state: InverterState::Connecting,
+            since: Utc::now(),
+        }
+    }
+
+    /// Continues from a state remembered before a restart.
+    pub(crate) fn restore(inverter: &str, state: InverterState, since: DateTime<Utc>) -> Self {
+        StateTracker {
+            inverter: inverter.to_owned(),
+            state,
+            since,
+        }
+    }
+
+    /// Moves to `state`, returning the transition event if the state changed.
+    pub(crate) fn update(&mut self, state: InverterState, reason: &'static str) -> Option<StateEvent> {
+        if state == self.state {
+            return None;
+        }
+        let now = Utc::now();
+        let event = StateEvent {
+            inverter: self.inverter.clone(),
+            time: now,
+            from: self.state,
+            to: state,
+            duration_secs: (now - self.since).num_seconds(),
+            reason,
+            error_code: None,
+        };
+        self.state = state;
+        self.since = now;
+        Some(event)
+    }
+}
+
+/// Whether a failed poll means the stick is unreachable rather than
+/// answering with bad data.
+pub(crate) fn is_connection_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.downcast_ref::<std::io::Error>().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transitions(tracker: &mut StateTracker, states: &[InverterState]) -> Vec<String> {
+        states
+            .iter()
+            .filter_map(|state| tracker.update(*state, "test"))
+            .map(|event| format!("{} -> {}", event.from, event.to))
+            .collect()
+    }
+
+    #[test]
+    fn starts_connecting() {
+        let mut tracker = StateTracker::new("roof");
+        assert!(tracker.update(InverterState::Connecting, "test").is_none());
+        let event = tracker.update(InverterState::Producing, "connected").unwrap();
+        assert_eq!(event.inverter, "roof");
+        assert_eq!((event.from, event.to), (InverterState::Connecting, InverterState::Producing));
+        assert_eq!(event.reason, "connected");
+        assert!(event.error_code.is_none());
+    }
+
+    #[test]
+    fn reports_only_changes() {
+        use InverterState::*;
+        let mut tracker = StateTracker::new("roof");
+        let states = [Idle, Idle, Producing, Producing, Faulted, Offline, Offline, Idle, Paused];
+        assert_eq!(
+            transitions(&mut tracker, &states),
+            [
+                "connecting -> idle",
+                "idle -> producing",
+                "producing -> faulted",
+                "faulted -> offline",
+                "offline -> idle",
+                "idle -> paused",
+            ]
+        );
+    }
+
+    #[test]
+    fn restored_state_keeps_its_duration() {
+        let since = Utc::now() - chrono::Duration::hours(2);
+        let mut tracker = StateTracker::restore("roof", InverterState::Offline, since);
+        assert!(tracker.update(InverterState::Offline, "connect_failed").is_none());
+        let event = tracker.update(InverterState::Idle, "connected").unwrap();
+        assert_eq!(event.from, InverterState::Offline);
+        assert!((7199..=7201).contains(&event.duration_secs), "{}", event.duration_secs);
+        // The next state counts from the transition.
+        let event = tracker.update(InverterState::Producing, "pv_power").unwrap();
+        assert!(event.duration_secs <= 1, "{}", event.duration_secs);
+    }
+
+    #[test]
+    fn event_sample() {
+        let mut tracker = StateTracker::new("roof");
+        let event = tracker
+            .update(InverterState::Offline, "connect_failed")
+            .unwrap()
+            .with_error_code(ErrorCode::ConnectTimeout);
+        let sample = event.to_sample("solar_state");
+        assert_eq!(sample.measurement, "solar_state");
+        assert_eq!(sample.tag_value("inverter"), Some("roof"));
+        assert_eq!(sample.tag_value("state"), Some("offline"));
+        assert_eq!(sample.tag_value("previous_state"), Some("connecting"));
+        assert_eq!(sample.tag_value("reason"), Some("connect_failed"));
+        assert_eq!(sample.tag_value("error_code"), Some("E001"));
+        assert_eq!(sample.field_value("state_code"), Some(1.0));
+
+        let event = tracker.update(InverterState::Idle, "connected").unwrap();
+        assert_eq!(event.to_sample("solar_state").tag_value("error_code"), None);
+    }
+
+    #[test]
+    fn connection_errors() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert!(is_connection_error(&anyhow::Error::new(io).context("Failed to connect")));
+        assert!(!is_connection_error(&anyhow::anyhow!("Malformed frame")));
+    }
+}
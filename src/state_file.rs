@@ -0,0 +1,127 @@
+use crate::state::InverterState;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Minimum time between two writes of unchanged-in-kind updates, to spare
+/// SD cards on small hosts.
+const SAVE_INTERVAL_SECS: i64 = 60;
+
+/// What is remembered about an inverter across restarts.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub(crate) struct InverterRecord {
+    /// Serial of the logger stick, as decimal number like on its label
+    pub(crate) logger_serial: Option<u32>,
+    /// Unix time of the last successful poll
+    pub(crate) last_success: Option<i64>,
+    pub(crate) state: Option<InverterState>,
+    /// Unix time the state was entered
+    pub(crate) state_since: Option<i64>,
+    /// Firmware versions last read, see [`crate::firmware`]
+    #[serde(default)]
+    pub(crate) inverter_firmware: Option<String>,
+    #[serde(default)]
+    pub(crate) stick_firmware: Option<String>,
+    /// `energy_total` of the last full read, see [`crate::energy_stats`]
+    #[serde(default)]
+    pub(crate) last_energy_total: Option<f64>,
+    /// Production in kWh by month (`YYYY-MM`, local time)
+    #[serde(default)]
+    pub(crate) energy_months: BTreeMap<String, f64>,
+    /// Value of the monthly production with a tariff, see [`crate::tariff`]
+    #[serde(default)]
+    pub(crate) savings_months: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub(crate) earnings_months: BTreeMap<String, f64>,
+    /// CO2 avoided in kg by month, see [`crate::carbon`]
+    #[serde(default)]
+    pub(crate) co2_months: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct StateContents {
+    #[serde(default)]
+    inverters: BTreeMap<String, InverterRecord>,
+}
+
+struct Inner {
+    contents: StateContents,
+    dirty: bool,
+    last_save: i64,
+}
+
+/// Small JSON file shared by all pollers. Without a configured path all
+/// updates are kept in memory only.
+pub(crate) struct StateFile {
+    path: Option<PathBuf>,
+    inner: Mutex<Inner>,
+}
+
+impl StateFile {
+    pub(crate) fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let contents = match path {
+            Some(path) if path.exists() => {
+                let data = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read state file {}", path.display()))?;
+                serde_json::from_str(&data)
+                    .with_context(|| format!("State file {} is corrupt", path.display()))?
+            }
+            _ => StateContents::default(),
+        };
+        Ok(StateFile {
+            path: path.map(Path::to_path_buf),
+            inner: Mutex::new(Inner {
+                contents,
+                dirty: false,
+                last_save: 0,
+            }),
+        })
+    }
+
+    pub(crate) fn get(&self, inverter: &str) -> InverterRecord {
+        let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.contents.inverters.get(inverter).cloned().unwrap_or_default()
+    }
+
+    /// Applies `change` to the record of `inverter`. The file is written
+    /// right away if `urgent`, otherwise at most once per minute.
+    pub(crate) fn update(&self, inverter: &str, urgent: bool, change: impl FnOnce(&mut InverterRecord)) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        change(inner.contents.inverters.entry(inverter.to_owned()).or_default());
+        inner.dirty = true;
+        let now = chrono::Utc::now().timestamp();
+        if urgent || now - inner.last_save >= SAVE_INTERVAL_SECS {
+            self.save(&mut inner, now);
+        }
+    }
+
+    /// Saves pending changes right away, e.g. before exiting.
+    pub(crate) fn flush(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        self.save(&mut inner, chrono::Utc::now().timestamp());
+    }
+
+    fn save(&self, inner: &mut Inner, now: i64) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !inner.dirty {
+            return;
+        }
+        // Write to a temporary file first so a crash can't leave a truncated file.
+        let tmp_path = path.with_extension("tmp");
+        let result = serde_json::to_vec_pretty(&inner.contents)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(std::fs::write(&tmp_path, data)?))
+            .and_then(|()| Ok(std::fs::rename(&tmp_path, path)?));
+        match result {
+            Ok(()) => {
+                inner.dirty = false;
+                inner.last_save = now;
+            }
+            Err(e) => log::warn!("Failed to write state file {} ({e})", path.display()),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Results of the CLI commands as rows, printed as aligned table for people
+//! or as JSON or CSV for scripts, see `--output-format`. Only the table
+//! translates the column names.
+
+use crate::i18n::tr;
+use serde_json::Value;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    #[default]
+    Table,
+    /// Array of objects keyed by the column names
+    Json,
+    Csv,
+}
+
+/// Rows of a command result. Cells are JSON values so numbers stay numbers
+/// in the JSON output, `null` is an empty cell.
+pub(crate) struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<Value>>,
+    /// Whether the table and CSV output start with the column names
+    print_header: bool,
+}
+
+impl Table {
+    pub(crate) fn new(header: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Table {
+            header: header.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+            print_header: true,
+        }
+    }
+
+    /// Leaves out the column names, e.g. for rows appended to earlier output.
+    pub(crate) fn without_header(mut self) -> Self {
+        self.print_header = false;
+        self
+    }
+
+    /// Adds a row, missing cells at its end are empty.
+    pub(crate) fn push(&mut self, mut row: Vec<Value>) {
+        row.resize(self.header.len(), Value::Null);
+        self.rows.push(row);
+    }
+
+    pub(crate) fn write(&self, format: OutputFormat, mut out: impl Write) -> anyhow::Result<()> {
+        match format {
+            OutputFormat::Table => {
+                let header: Vec<_> = self.header.iter().map(|name| Value::from(tr(name))).collect();
+                let widths: Vec<_> = (0..self.header.len())
+                    .map(|column| {
+                        let cells = self.rows.iter().map(|row| text(&row[column]).chars().count());
+                        cells.chain([text(&header[column]).chars().count()]).max().unwrap_or_default()
+                    })
+                    .collect();
+                if self.print_header {
+                    writeln!(out, "{}", aligned(&header, &widths))?;
+                }
+                for row in &self.rows {
+                    writeln!(out, "{}", aligned(row, &widths))?;
+                }
+            }
+            OutputFormat::Json => {
+                let objects: Vec<serde_json::Map<String, Value>> = self
+                    .rows
+                    .iter()
+                    .map(|row| self.header.iter().cloned().zip(row.iter().cloned()).collect())
+                    .collect();
+                serde_json::to_writer_pretty(&mut out, &objects)?;
+                writeln!(out)?;
+            }
+            OutputFormat::Csv => {
+                let mut csv_writer = csv::Writer::from_writer(out);
+                if self.print_header {
+                    csv_writer.write_record(&self.header)?;
+                }
+                for row in &self.rows {
+                    csv_writer.write_record(row.iter().map(text))?;
+                }
+                csv_writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Row of the table output, numbers right aligned.
+fn aligned(row: &[Value], widths: &[usize]) -> String {
+    let cells: Vec<_> = row
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| match cell {
+            Value::Number(_) => format!("{:>width$}", text(cell)),
+            _ => format!("{:<width$}", text(cell)),
+        })
+        .collect();
+    cells.join("  ").trim_end().to_owned()
+}
+
+/// Cell as shown in the table and CSV output.
+pub(crate) fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Number cell rounded to `decimals`, so the table does not show float
+/// noise like 31.499999.
+pub(crate) fn number(value: f64, decimals: i32) -> Value {
+    let factor = 10f64.powi(decimals);
+    Value::from((value * factor).round() / factor)
+}
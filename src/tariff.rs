@@ -0,0 +1,96 @@
+//! What the produced energy is worth: the electricity price saved by
+//! consuming it and the feed-in rate earned for the rest.
+
+use chrono::{DateTime, Local, Timelike};
+use serde::{Deserialize, Serialize};
+
+/// Prices per kWh in any currency, the results are in the same one.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub(crate) struct TariffConfig {
+    /// Price of energy bought from the grid
+    price_per_kwh: f64,
+    /// Rate paid for energy fed into the grid
+    #[serde(default)]
+    feed_in_rate: f64,
+    /// Share of the production consumed in the house, the rest is fed in
+    #[serde(default = "default_self_consumption")]
+    self_consumption: f64,
+    /// Prices differing from `price_per_kwh` at certain hours
+    #[serde(default)]
+    time_of_use: Vec<TimeOfUseConfig>,
+}
+
+/// Price during the local hours `[from, to)`, which may wrap around
+/// midnight, e.g. `[22, 6]` for a night tariff.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TimeOfUseConfig {
+    hours: [u32; 2],
+    price_per_kwh: f64,
+}
+
+fn default_self_consumption() -> f64 {
+    1.0
+}
+
+impl TariffConfig {
+    /// Price of grid energy at `hour`, the first matching window wins.
+    fn price_at(&self, hour: u32) -> f64 {
+        let window = self.time_of_use.iter().find(|window| {
+            let [from, to] = window.hours;
+            if from <= to {
+                (from..to).contains(&hour)
+            } else {
+                hour >= from || hour < to
+            }
+        });
+        window.map_or(self.price_per_kwh, |window| window.price_per_kwh)
+    }
+
+    /// Savings and feed-in earnings of `energy` kWh produced at `time`.
+    pub(crate) fn value(&self, energy: f64, time: DateTime<Local>) -> (f64, f64) {
+        let consumed = energy * self.self_consumption.clamp(0.0, 1.0);
+        (consumed * self.price_at(time.hour()), (energy - consumed) * self.feed_in_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tariff(time_of_use: &str) -> TariffConfig {
+        toml::from_str(&format!("price_per_kwh = 0.3\ntime_of_use = [{time_of_use}]")).unwrap()
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let tariff = tariff("{ hours = [10, 14], price_per_kwh = 0.1 }");
+        assert_eq!(tariff.price_at(9), 0.3);
+        assert_eq!(tariff.price_at(10), 0.1);
+        assert_eq!(tariff.price_at(13), 0.1);
+        assert_eq!(tariff.price_at(14), 0.3);
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let tariff = tariff("{ hours = [22, 6], price_per_kwh = 0.2 }");
+        assert_eq!(tariff.price_at(21), 0.3);
+        assert_eq!(tariff.price_at(22), 0.2);
+        assert_eq!(tariff.price_at(23), 0.2);
+        assert_eq!(tariff.price_at(0), 0.2);
+        assert_eq!(tariff.price_at(5), 0.2);
+        assert_eq!(tariff.price_at(6), 0.3);
+        assert_eq!(tariff.price_at(12), 0.3);
+    }
+
+    #[test]
+    fn first_window_wins() {
+        let tariff = tariff(
+            "{ hours = [23, 1], price_per_kwh = 0.1 }, { hours = [20, 6], price_per_kwh = 0.2 }",
+        );
+        assert_eq!(tariff.price_at(20), 0.2);
+        assert_eq!(tariff.price_at(23), 0.1);
+        assert_eq!(tariff.price_at(0), 0.1);
+        assert_eq!(tariff.price_at(1), 0.2);
+        assert_eq!(tariff.price_at(6), 0.3);
+    }
+}
@@ -0,0 +1,182 @@
+//! Detects that every inverter and every output has been failing, so
+//! orchestrated deployments can let systemd or Kubernetes restart the
+//! daemon instead of leaving it running without effect.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Failing time after which `exit` gives up.
+const DEFAULT_EXIT_AFTER: Duration = Duration::from_secs(3600);
+
+/// What to do once every inverter and output is failing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum OnTotalFailure {
+    #[default]
+    KeepRunning,
+    /// Exit with an error once everything has been failing this long
+    ExitAfter(Duration),
+}
+
+impl FromStr for OnTotalFailure {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        match text.split_whitespace().collect::<Vec<_>>()[..] {
+            ["keep-running"] => Ok(OnTotalFailure::KeepRunning),
+            ["exit"] => Ok(OnTotalFailure::ExitAfter(DEFAULT_EXIT_AFTER)),
+            ["exit-after", duration] => Ok(OnTotalFailure::ExitAfter(parse_duration(duration)?)),
+            _ => anyhow::bail!(
+                "Invalid on_total_failure {text:?}, expected \"keep-running\", \"exit\" or \
+                 \"exit-after <duration>\""
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for OnTotalFailure {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<OnTotalFailure> for String {
+    fn from(policy: OnTotalFailure) -> Self {
+        match policy {
+            OnTotalFailure::KeepRunning => "keep-running".to_owned(),
+            OnTotalFailure::ExitAfter(duration) => {
+                format!("exit-after {}s", duration.as_secs())
+            }
+        }
+    }
+}
+
+/// Parses durations like `90s`, `30m`, `1h` or `2d`.
+fn parse_duration(text: &str) -> anyhow::Result<Duration> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid duration {text:?}, expected e.g. 30m"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => anyhow::bail!("Invalid duration unit in {text:?}, expected s, m, h or d"),
+    };
+    Ok(Duration::from_secs(number * unit_secs))
+}
+
+/// Since when each inverter and output is failing, `None` while it works.
+struct Failing {
+    inverters: BTreeMap<String, Option<Instant>>,
+    outputs: BTreeMap<usize, Option<Instant>>,
+}
+
+/// Shared by the pollers and the writer.
+static FAILING: Mutex<Failing> = Mutex::new(Failing {
+    inverters: BTreeMap::new(),
+    outputs: BTreeMap::new(),
+});
+
+fn update<K: Ord>(components: &mut BTreeMap<K, Option<Instant>>, key: K, success: bool) {
+    let since = components.entry(key).or_default();
+    if success {
+        *since = None;
+    } else {
+        since.get_or_insert_with(Instant::now);
+    }
+}
+
+/// Records the outcome of a step of the poller of `inverter_name`.
+pub(crate) fn record_inverter(inverter_name: &str, success: bool) {
+    let mut failing = FAILING.lock().unwrap_or_else(PoisonError::into_inner);
+    update(&mut failing.inverters, inverter_name.to_owned(), success);
+}
+
+/// Records the outcome of a write to the output at `index`.
+pub(crate) fn record_output(index: usize, success: bool) {
+    let mut failing = FAILING.lock().unwrap_or_else(PoisonError::into_inner);
+    update(&mut failing.outputs, index, success);
+}
+
+impl Failing {
+    /// Since when every inverter and output is failing, `None` if one of
+    /// them works or none of them was used yet.
+    fn since(&self) -> Option<Instant> {
+        if self.inverters.is_empty() || self.outputs.is_empty() {
+            return None;
+        }
+        let all: Option<Vec<Instant>> =
+            self.inverters.values().chain(self.outputs.values()).copied().collect();
+        all?.into_iter().max()
+    }
+}
+
+impl OnTotalFailure {
+    /// Whether the daemon should exit now.
+    pub(crate) fn exit_due(self) -> bool {
+        let OnTotalFailure::ExitAfter(duration) = self else {
+            return false;
+        };
+        let failing = FAILING.lock().unwrap_or_else(PoisonError::into_inner);
+        failing.since().is_some_and(|since| since.elapsed() >= duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(172_800));
+        for invalid in ["", "h", "10", "10x", "1.5h", "-1h", "1h30m"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid:?}");
+        }
+        assert_eq!(
+            "exit-after 30m".parse::<OnTotalFailure>().unwrap(),
+            OnTotalFailure::ExitAfter(Duration::from_secs(1800))
+        );
+        assert_eq!(
+            "exit".parse::<OnTotalFailure>().unwrap(),
+            OnTotalFailure::ExitAfter(DEFAULT_EXIT_AFTER)
+        );
+        assert!("exit-after".parse::<OnTotalFailure>().is_err());
+    }
+
+    #[test]
+    fn since_needs_everything_failing() {
+        let mut failing = Failing {
+            inverters: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+        };
+        update(&mut failing.inverters, "roof".to_owned(), false);
+        // No output was used yet.
+        assert_eq!(failing.since(), None);
+        update(&mut failing.outputs, 0, true);
+        assert_eq!(failing.since(), None);
+        update(&mut failing.outputs, 0, false);
+        let roof = failing.inverters["roof"].unwrap();
+        let output = failing.outputs[&0].unwrap();
+        // The last component that started failing counts.
+        assert_eq!(failing.since(), Some(roof.max(output)));
+        update(&mut failing.inverters, "garage".to_owned(), true);
+        assert_eq!(failing.since(), None);
+        update(&mut failing.inverters, "garage".to_owned(), false);
+        let garage = failing.inverters["garage"].unwrap();
+        assert_eq!(failing.since(), Some(garage));
+        // Failing again keeps the start.
+        update(&mut failing.inverters, "garage".to_owned(), false);
+        assert_eq!(failing.inverters["garage"], Some(garage));
+    }
+}
@@ -0,0 +1,250 @@
+//! Ways of getting Modbus RTU frames to an inverter and its responses back.
+//! [`Inverter`](crate::inverter::Inverter) talks to the logger sticks with
+//! SolarmanV5 by default, the other transports reach inverters wired to a
+//! Modbus TCP gateway or a serial port, [`MockTransport`] replays canned
+//! responses to test decoding without sockets.
+
+use crate::error_code;
+use crate::registers::crc16;
+use crate::solarmanv5::{self, LinkStats, SolarmanDevice, Timeouts};
+use anyhow::Context;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Instant;
+
+pub trait Transport {
+    /// Sends the Modbus RTU request `frame` and receives the RTU frame of
+    /// the response. It borrows from the transport and is only valid until
+    /// the next request.
+    fn send_modbus_frame(&mut self, frame: &[u8]) -> anyhow::Result<&[u8]>;
+
+    /// Communication statistics since the transport was created, see
+    /// [`LinkStats`].
+    fn stats(&self) -> LinkStats {
+        LinkStats::default()
+    }
+}
+
+impl Transport for SolarmanDevice {
+    fn send_modbus_frame(&mut self, frame: &[u8]) -> anyhow::Result<&[u8]> {
+        SolarmanDevice::send_modbus_frame(self, frame)
+    }
+
+    fn stats(&self) -> LinkStats {
+        SolarmanDevice::stats(self)
+    }
+}
+
+/// Updates `stats` with the outcome of a request started at `start`.
+fn count<T>(stats: &mut LinkStats, start: Instant, result: &anyhow::Result<T>) {
+    stats.requests += 1;
+    match result {
+        Ok(_) => stats.latency += start.elapsed(),
+        Err(e) => {
+            stats.failures += 1;
+            if solarmanv5::is_timeout(e) {
+                stats.timeouts += 1;
+            }
+        }
+    }
+}
+
+/// Unit id and PDU of a Modbus RTU request.
+fn split_rtu(frame: &[u8]) -> anyhow::Result<(u8, &[u8])> {
+    match frame {
+        [unit_id, pdu @ .., _, _] if !pdu.is_empty() => Ok((*unit_id, pdu)),
+        _ => anyhow::bail!("Modbus RTU frame of {} bytes is too short", frame.len()),
+    }
+}
+
+/// Inverter behind a Modbus TCP gateway, e.g. an RS485 adapter instead of a
+/// logger stick. The connection is kept open between requests and opened
+/// again after a failure.
+pub struct ModbusTcp {
+    addr: SocketAddr,
+    timeouts: Timeouts,
+    connection: Option<TcpStream>,
+    transaction_id: u16,
+    response: Vec<u8>,
+    stats: LinkStats,
+}
+
+impl ModbusTcp {
+    /// Does not connect yet, that happens with the first request.
+    pub fn new(addr: std::net::IpAddr, port: u16, timeouts: impl Into<Timeouts>) -> Self {
+        ModbusTcp {
+            addr: SocketAddr::new(addr, port),
+            timeouts: timeouts.into(),
+            connection: None,
+            transaction_id: 0,
+            response: Vec::new(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    fn exchange(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+        let (unit_id, pdu) = split_rtu(frame)?;
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => {
+                let stream = TcpStream::connect_timeout(&self.addr, self.timeouts.connect)
+                    .map_err(error_code::connect_error)?;
+                stream
+                    .set_read_timeout(Some(self.timeouts.read))
+                    .context("Failed to set read timeout")?;
+                stream
+                    .set_write_timeout(Some(self.timeouts.write))
+                    .context("Failed to set write timeout")?;
+                self.connection.insert(stream)
+            }
+        };
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        let mut request = self.transaction_id.to_be_bytes().to_vec();
+        request.extend([0, 0]);
+        request.extend(((pdu.len() + 1) as u16).to_be_bytes());
+        request.push(unit_id);
+        request.extend(pdu);
+        connection.write_all(&request)?;
+
+        let mut header = [0; 7];
+        connection.read_exact(&mut header)?;
+        let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        if header[..2] != self.transaction_id.to_be_bytes() || !(2..=254).contains(&length) {
+            anyhow::bail!("Unexpected Modbus TCP header {}", solarmanv5::hex(&header));
+        }
+        let mut response_pdu = vec![0; length - 1];
+        connection.read_exact(&mut response_pdu)?;
+        self.response.clear();
+        self.response.push(header[6]);
+        self.response.extend(response_pdu);
+        self.response.extend(crc16(&self.response).to_le_bytes());
+        Ok(())
+    }
+}
+
+impl Transport for ModbusTcp {
+    fn send_modbus_frame(&mut self, frame: &[u8]) -> anyhow::Result<&[u8]> {
+        let start = Instant::now();
+        let result = self.exchange(frame);
+        count(&mut self.stats, start, &result);
+        if result.is_err() {
+            // The stream may still carry the late response.
+            self.connection = None;
+        }
+        result.map(|()| self.response.as_slice())
+    }
+
+    fn stats(&self) -> LinkStats {
+        self.stats
+    }
+}
+
+/// Inverter on a serial RS485 bus. `port` is the opened serial port, e.g.
+/// of the `serialport` crate, with its baud rate and read timeout already
+/// set.
+pub struct SerialRtu<S> {
+    port: S,
+    response: Vec<u8>,
+    stats: LinkStats,
+}
+
+impl<S: Read + Write> SerialRtu<S> {
+    pub fn new(port: S) -> Self {
+        SerialRtu {
+            port,
+            response: Vec::new(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    fn exchange(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+        split_rtu(frame)?;
+        self.port.write_all(frame)?;
+        self.port.flush()?;
+        self.response.clear();
+        self.response.resize(2, 0);
+        self.port.read_exact(&mut self.response)?;
+        // Length of the rest of the response by its function code
+        let remaining = match self.response[1] {
+            code if code & 0x80 != 0 => 3,
+            0x01..=0x04 => {
+                let mut byte_count = [0];
+                self.port.read_exact(&mut byte_count)?;
+                self.response.push(byte_count[0]);
+                usize::from(byte_count[0]) + 2
+            }
+            0x05 | 0x06 | 0x0f | 0x10 => 6,
+            code => anyhow::bail!("Unsupported Modbus function code 0x{code:02x} in response"),
+        };
+        let start = self.response.len();
+        self.response.resize(start + remaining, 0);
+        self.port.read_exact(&mut self.response[start..])?;
+        let (rtu, crc) = self.response.split_at(self.response.len() - 2);
+        if crc16(rtu).to_le_bytes() != crc {
+            self.stats.checksum_errors += 1;
+            anyhow::bail!(
+                "Wrong CRC in Modbus response {}",
+                solarmanv5::hex(&self.response)
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Transport for SerialRtu<S> {
+    fn send_modbus_frame(&mut self, frame: &[u8]) -> anyhow::Result<&[u8]> {
+        let start = Instant::now();
+        let result = self.exchange(frame);
+        count(&mut self.stats, start, &result);
+        result.map(|()| self.response.as_slice())
+    }
+
+    fn stats(&self) -> LinkStats {
+        self.stats
+    }
+}
+
+/// Answers requests with canned RTU responses in order and records the
+/// requests, for testing without sockets.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    responses: VecDeque<Vec<u8>>,
+    requests: Vec<Vec<u8>>,
+    current: Vec<u8>,
+    stats: LinkStats,
+}
+
+impl MockTransport {
+    pub fn new(responses: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        MockTransport {
+            responses: responses.into_iter().collect(),
+            ..MockTransport::default()
+        }
+    }
+
+    /// Queues another response.
+    pub fn push_response(&mut self, response: Vec<u8>) {
+        self.responses.push_back(response);
+    }
+
+    /// RTU frames of all requests so far.
+    pub fn requests(&self) -> &[Vec<u8>] {
+        &self.requests
+    }
+}
+
+impl Transport for MockTransport {
+    fn send_modbus_frame(&mut self, frame: &[u8]) -> anyhow::Result<&[u8]> {
+        let start = Instant::now();
+        self.requests.push(frame.to_vec());
+        let result = self.responses.pop_front().context("No response queued");
+        count(&mut self.stats, start, &result);
+        self.current = result?;
+        Ok(&self.current)
+    }
+
+    fn stats(&self) -> LinkStats {
+        self.stats
+    }
+}
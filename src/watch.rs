@@ -0,0 +1,303 @@
+//! Continuously refreshing table of the current values, for a quick check
+//! on site over SSH.
+
+use crate::i18n::tr;
+use crate::table::{self, OutputFormat, Table};
+use crate::{connect_inverter, Config, InverterConfig};
+use chrono::{DateTime, Local};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Alignment, Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Cell, Row};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use solar_mon::error_code::ErrorCode;
+use solar_mon::inverter::{Inverter, MonitoringData};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+const HEADER: [&str; 8] =
+    ["inverter", "input", "voltage", "current", "power", "energy_today", "status", "last_error"];
+
+/// Connection and latest values of one watched inverter.
+struct Watched<'a> {
+    name: &'a str,
+    inverter_cfg: &'a InverterConfig,
+    inverter: Option<Inverter>,
+    data: Option<MonitoringData>,
+    /// Time of the last successful poll
+    updated: Option<DateTime<Local>>,
+    /// Code and message of the last failure and when it happened
+    last_error: Option<(DateTime<Local>, String)>,
+    /// Whether the last poll failed
+    failing: bool,
+}
+
+impl Watched<'_> {
+    fn poll(&mut self, config: &Config) {
+        let inverter = match self.inverter.take() {
+            Some(inverter) => Ok(inverter),
+            None => connect_inverter(self.inverter_cfg, &config.monitoring),
+        };
+        let result = inverter.and_then(|mut inverter| {
+            let data = inverter.get_data()?;
+            self.inverter = Some(inverter);
+            Ok(data)
+        });
+        match result {
+            Ok(data) => {
+                self.data = Some(data);
+                self.updated = Some(Local::now());
+                self.failing = false;
+            }
+            Err(e) => {
+                // Reconnects with the next poll.
+                self.last_error = Some((Local::now(), format!("{}: {e}", ErrorCode::of(&e))));
+                self.failing = true;
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        match (&self.updated, self.failing) {
+            (None, false) => "connecting".to_owned(),
+            (_, true) => "failing".to_owned(),
+            (Some(updated), false) => format!("ok {}", updated.format("%H:%M:%S")),
+        }
+    }
+
+    fn rows(&self) -> Vec<Vec<Value>> {
+        let last_error = self
+            .last_error
+            .as_ref()
+            .map(|(time, error)| format!("{} {error}", time.format("%H:%M:%S")));
+        let Some(data) = &self.data else {
+            let mut row = vec![Value::Null; 8];
+            row[0] = Value::from(self.name);
+            row[6] = Value::from(self.status());
+            row[7] = Value::from(last_error);
+            return vec![row];
+        };
+        let totals = vec![
+            Value::from(self.name),
+            Value::from("AC"),
+            Value::Null,
+            Value::Null,
+            table::number(data.ac_power, 0),
+            table::number(data.energy_today, 1),
+            Value::from(self.status()),
+            Value::from(last_error),
+        ];
+        let inputs = data.inputs.iter().map(|input| {
+            vec![
+                Value::from(self.name),
+                Value::from(self.inverter_cfg.input_name(&input.name)),
+                table::number(input.voltage, 1),
+                table::number(input.current, 1),
+                table::number(input.power(), 0),
+            ]
+        });
+        std::iter::once(totals).chain(inputs).collect()
+    }
+}
+
+/// Polls `inverters`, every configured one if empty, every `interval` and
+/// redraws the table until interrupted. Inverters that fail keep their last
+/// values and are reconnected with the next round.
+pub(crate) fn watch(
+    config: &Config,
+    inverters: &[String],
+    interval: Duration,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    if let Some(unknown) = inverters.iter().find(|name| !config.inverter.contains_key(*name)) {
+        anyhow::bail!("Inverter {unknown} is not configured");
+    }
+    let mut watched: Vec<_> = config
+        .inverter
+        .iter()
+        .filter(|(name, _)| inverters.is_empty() || inverters.contains(name))
+        .map(|(name, inverter_cfg)| Watched {
+            name,
+            inverter_cfg,
+            inverter: None,
+            data: None,
+            updated: None,
+            last_error: None,
+            failing: false,
+        })
+        .collect();
+    watched.sort_unstable_by_key(|watched| watched.name);
+    if format == OutputFormat::Table {
+        let mut terminal = ratatui::try_init()?;
+        let result = watch_terminal(&mut terminal, config, &mut watched, interval);
+        ratatui::try_restore()?;
+        return result;
+    }
+    let mut first = true;
+    loop {
+        let round_start = Instant::now();
+        for inverter in &mut watched {
+            inverter.poll(config);
+        }
+        print(&watched, format, first)?;
+        first = false;
+        std::thread::sleep(interval.saturating_sub(round_start.elapsed()));
+    }
+}
+
+/// Redraws the table after every poll until q, Esc or Ctrl+C is pressed.
+fn watch_terminal(
+    terminal: &mut DefaultTerminal,
+    config: &Config,
+    watched: &mut [Watched],
+    interval: Duration,
+) -> anyhow::Result<()> {
+    terminal.draw(|frame| draw(frame, watched))?;
+    loop {
+        let round_start = Instant::now();
+        for idx in 0..watched.len() {
+            watched[idx].poll(config);
+            terminal.draw(|frame| draw(frame, watched))?;
+            if quit_pressed(Duration::ZERO)? {
+                return Ok(());
+            }
+        }
+        while let Some(left) = interval.checked_sub(round_start.elapsed()) {
+            if quit_pressed(left)? {
+                return Ok(());
+            }
+            // Also after resizing the terminal.
+            terminal.draw(|frame| draw(frame, watched))?;
+        }
+    }
+}
+
+/// Waits up to `timeout` for an input event, `true` if it quits.
+fn quit_pressed(timeout: Duration) -> std::io::Result<bool> {
+    if !event::poll(timeout)? {
+        return Ok(false);
+    }
+    Ok(match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => {
+            matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+        }
+        _ => false,
+    })
+}
+
+fn draw(frame: &mut Frame, watched: &[Watched]) {
+    let [title, body] =
+        Layout::vertical([Constraint::Length(2), Constraint::Fill(1)]).areas(frame.area());
+    frame.render_widget(
+        Line::from(format!(
+            "{}  (q to quit)",
+            Local::now().format("%Y-%m-%d %H:%M:%S")
+        )),
+        title,
+    );
+    let rows = watched.iter().flat_map(|watched| {
+        let style = if watched.failing {
+            Style::new().fg(Color::Red)
+        } else {
+            Style::new()
+        };
+        watched.rows().into_iter().map(move |row| {
+            let cells = row.into_iter().map(|value| match value {
+                Value::Number(_) => {
+                    Cell::from(Line::from(table::text(&value)).alignment(Alignment::Right))
+                }
+                value => Cell::from(table::text(&value)),
+            });
+            Row::new(cells).style(style)
+        })
+    });
+    let widths = [
+        Constraint::Min(8),
+        Constraint::Min(5),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(6),
+        Constraint::Length(12),
+        Constraint::Min(11),
+        Constraint::Fill(1),
+    ];
+    let header = Row::new(HEADER.map(|name| Cell::from(tr(name)))).bold();
+    frame.render_widget(ratatui::widgets::Table::new(rows, widths).header(header), body);
+}
+
+/// Prints the rows of every round.
+fn print(watched: &[Watched], format: OutputFormat, first: bool) -> anyhow::Result<()> {
+    let mut table = Table::new(HEADER);
+    if format == OutputFormat::Csv && !first {
+        table = table.without_header();
+    }
+    for row in watched.iter().flat_map(Watched::rows) {
+        table.push(row);
+    }
+    let mut stdout = std::io::stdout().lock();
+    table.write(format, &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use solar_mon::registers::InputReading;
+
+    #[test]
+    fn draws_the_table() {
+        let inverter_cfg: InverterConfig = toml::from_str("").unwrap();
+        let watched = [
+            Watched {
+                name: "garage",
+                inverter_cfg: &inverter_cfg,
+                inverter: None,
+                data: None,
+                updated: None,
+                last_error: None,
+                failing: false,
+            },
+            Watched {
+                name: "roof",
+                inverter_cfg: &inverter_cfg,
+                inverter: None,
+                data: Some(MonitoringData {
+                    inputs: vec![InputReading {
+                        name: "A".to_owned(),
+                        voltage: 35.1,
+                        current: 6.2,
+                    }],
+                    ac_power: 210.0,
+                    energy_today: 1.24,
+                    energy_total: 345.6,
+                    meter: None,
+                    derating: None,
+                    insulation: None,
+                }),
+                updated: None,
+                last_error: None,
+                failing: true,
+            },
+        ];
+        let mut terminal = Terminal::new(TestBackend::new(100, 6)).unwrap();
+        terminal.draw(|frame| draw(frame, &watched)).unwrap();
+        let lines: Vec<String> = terminal
+            .backend()
+            .buffer()
+            .content
+            .chunks(100)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect())
+            .collect();
+        assert!(lines[0].contains("(q to quit)"), "{lines:#?}");
+        assert!(lines[2].starts_with("inverter"), "{lines:#?}");
+        assert!(lines[3].contains("garage") && lines[3].contains("connecting"), "{lines:#?}");
+        assert!(lines[4].contains("roof") && lines[4].contains("failing"), "{lines:#?}");
+        assert!(lines[5].contains("35.1") && lines[5].contains("218"), "{lines:#?}");
+    }
+}
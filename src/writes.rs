@@ -0,0 +1,224 @@
+use crate::audit::{Action, Origin};
+use crate::{connect_inverter, Config};
+use anyhow::Context;
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use solar_mon::inverter::Inverter;
+use solar_mon::registers;
+
+/// Restricts commands changing the inverters, like the power limit or
+/// switching them off, to times where a mistake does not cost production.
+/// Without the section the command line writes at any time and the Modbus
+/// bridge never forwards writes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct WritesConfig {
+    /// Local times writes are allowed at, never if empty
+    #[serde(default)]
+    windows: Vec<TimeWindow>,
+}
+
+/// Daily time range like `18:00-06:30`, which may span midnight. The same
+/// start and end, e.g. `00:00-00:00`, is the whole day.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok();
+        value
+            .split_once('-')
+            .and_then(|(start, end)| Some(TimeWindow { start: parse(start)?, end: parse(end)? }))
+            .ok_or_else(|| format!("'{value}' is not a time window like 18:00-06:30"))
+    }
+}
+
+impl From<TimeWindow> for String {
+    fn from(window: TimeWindow) -> Self {
+        format!("{}-{}", window.start.format("%H:%M"), window.end.format("%H:%M"))
+    }
+}
+
+impl TimeWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&time)
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl WritesConfig {
+    pub(crate) fn allowed_now(&self) -> bool {
+        let now = chrono::Local::now().time();
+        self.windows.iter().any(|window| window.contains(now))
+    }
+
+    fn describe_windows(&self) -> String {
+        if self.windows.is_empty() {
+            return "never".to_owned();
+        }
+        let windows: Vec<String> = self.windows.iter().copied().map(String::from).collect();
+        windows.join(", ")
+    }
+}
+
+/// Fails if `[writes]` forbids writing now, unless `force` overrides it.
+/// Without the section the command line may always write.
+pub(crate) fn check(config: Option<&WritesConfig>, force: bool) -> anyhow::Result<()> {
+    match config {
+        Some(config) if !config.allowed_now() && !force => anyhow::bail!(
+            "Writes are only allowed at {}, pass --i-know-what-i-am-doing to write anyway",
+            config.describe_windows()
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the Modbus request `pdu` changes registers or coils.
+pub(crate) fn is_write(pdu: &[u8]) -> bool {
+    matches!(pdu.first(), Some(0x05 | 0x06 | 0x0f | 0x10 | 0x16 | 0x17))
+}
+
+/// First register and values written by a Modbus request `pdu`, `None` for
+/// other requests including writes of coils.
+pub(crate) fn written_registers(pdu: &[u8]) -> Option<(u16, Vec<u16>)> {
+    let word = |offset: usize| Some(u16::from_be_bytes([*pdu.get(offset)?, *pdu.get(offset + 1)?]));
+    match pdu.first()? {
+        0x06 => Some((word(1)?, vec![word(3)?])),
+        0x10 => {
+            let values = (0..usize::from(word(3)?))
+                .map(|index| word(6 + 2 * index))
+                .collect::<Option<_>>()?;
+            Some((word(1)?, values))
+        }
+        _ => None,
+    }
+}
+
+/// Writes `values` to the holding registers starting at `register` of an
+/// inverter, e.g. its power limit. The write is recorded in the audit log.
+pub(crate) fn write_registers(
+    config: &Config,
+    origin: &Origin,
+    inverter_name: &str,
+    register: u16,
+    values: &[u16],
+    force: bool,
+) -> anyhow::Result<()> {
+    let mut old_values = None;
+    let result = check(config.writes.as_ref(), force).and_then(|()| {
+        let inverter_cfg = config
+            .inverter
+            .get(inverter_name)
+            .with_context(|| format!("Inverter {inverter_name} is not configured"))?;
+        let mut inverter = connect_inverter(inverter_cfg, &config.monitoring)?;
+        old_values = read_registers(&mut inverter, register, values.len() as u16);
+        write(&mut inverter, register, values)
+    });
+    Action::new(origin, "write_registers")
+        .inverter(inverter_name)
+        .registers(register, old_values, values.to_vec())
+        .record(&result);
+    result?;
+    log::info!("[{inverter_name}] Wrote {values:?} to register {register:#06x}");
+    Ok(())
+}
+
+pub(crate) fn write(inverter: &mut Inverter, register: u16, values: &[u16]) -> anyhow::Result<()> {
+    let frame = registers::write_frame(inverter.slave_id(), register, values)?;
+    let response = inverter.send_raw(&frame)?;
+    match response.get(1) {
+        Some(0x10) => Ok(()),
+        Some(function) if function & 0x80 != 0 => anyhow::bail!(
+            "Inverter rejected the write (exception {:#04x})",
+            response.get(2).copied().unwrap_or_default()
+        ),
+        _ => anyhow::bail!("Unexpected response to the write"),
+    }
+}
+
+/// Current values of `count` registers starting at `register`, `None` if
+/// they could not be read. Only used to record what a write replaced.
+pub(crate) fn read_registers(
+    inverter: &mut Inverter,
+    register: u16,
+    count: u16,
+) -> Option<Vec<u16>> {
+    let frame = registers::read_frame(inverter.slave_id(), register, count);
+    let values = inverter
+        .send_raw(&frame)
+        .and_then(|response| registers::decode_values(response, register, count));
+    match values {
+        Ok(values) => Some(values),
+        Err(e) => {
+            log::warn!("Failed to read register {register:#06x} before writing it ({e:#})");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(value: &str) -> Result<TimeWindow, String> {
+        TimeWindow::try_from(value.to_owned())
+    }
+
+    fn time(value: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(value, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn window_spanning_midnight() {
+        let window = window("18:00-06:30").unwrap();
+        assert!(!window.contains(time("17:59")));
+        assert!(window.contains(time("18:00")));
+        assert!(window.contains(time("23:59")));
+        assert!(window.contains(time("00:00")));
+        assert!(window.contains(time("06:29")));
+        assert!(!window.contains(time("06:30")));
+        assert!(!window.contains(time("12:00")));
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let window = window("05:00-07:00").unwrap();
+        assert!(!window.contains(time("04:59")));
+        assert!(window.contains(time("05:00")));
+        assert!(!window.contains(time("07:00")));
+    }
+
+    #[test]
+    fn same_start_and_end_is_the_whole_day() {
+        let window = window("00:00-00:00").unwrap();
+        for hour in ["00:00", "06:30", "12:00", "23:59"] {
+            assert!(window.contains(time(hour)));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_windows() {
+        for value in ["18:00", "18:00-", "-06:30", "25:00-06:00", "18:00-06:60", "evening"] {
+            assert!(window(value).is_err(), "{value}");
+        }
+        assert_eq!(String::from(window(" 18:00 - 06:30 ").unwrap()), "18:00-06:30");
+    }
+
+    #[test]
+    fn no_windows_never_allow_writes() {
+        let config: WritesConfig = toml::from_str("windows = []").unwrap();
+        assert!(!config.allowed_now());
+        assert!(check(Some(&config), false).is_err());
+        assert!(check(Some(&config), true).is_ok());
+        assert!(check(None, false).is_ok());
+    }
+}
@@ -0,0 +1,101 @@
+use solar_mon::at_command::{self, parse_signal_quality};
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::time::Duration;
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// Answers the greeting and the given commands like a logger stick, returns
+/// the received datagrams.
+fn mock_stick(
+    replies: &'static [(&'static str, &'static str)],
+) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+    let socket = UdpSocket::bind((LOCALHOST, 0)).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let handle = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        let mut buffer = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buffer) {
+            let datagram = String::from_utf8_lossy(&buffer[..len]).into_owned();
+            let reply = match datagram.as_str() {
+                "WIFIKIT-214028-READ" => Some("10.10.100.254,ACCF23000000,4012345678"),
+                command => replies
+                    .iter()
+                    .find(|(request, _)| *request == command)
+                    .map(|(_, reply)| *reply),
+            };
+            if let Some(reply) = reply {
+                socket.send_to(reply.as_bytes(), peer).unwrap();
+            }
+            received.push(datagram);
+            if received.last().is_some_and(|datagram| datagram == "AT+Q\n") {
+                break;
+            }
+        }
+        received
+    });
+    (port, handle)
+}
+
+#[test]
+fn reads_signal_quality() {
+    let (port, stick) = mock_stick(&[("AT+WSLQ\n", "+ok=Normal, 83%\r\n\r\n")]);
+    let quality = at_command::signal_quality(LOCALHOST, port, Duration::from_secs(1)).unwrap();
+    assert_eq!(quality, 83);
+    assert_eq!(
+        stick.join().unwrap(),
+        ["WIFIKIT-214028-READ", "+ok", "AT+WSLQ\n", "AT+Q\n"]
+    );
+}
+
+#[test]
+fn disconnected_stick_has_no_signal() {
+    let (port, stick) = mock_stick(&[("AT+WSLQ\n", "+ok=No Connection\r\n\r\n")]);
+    assert!(at_command::signal_quality(LOCALHOST, port, Duration::from_secs(1)).is_err());
+    stick.join().unwrap();
+}
+
+#[test]
+fn error_reply_fails() {
+    let (port, stick) = mock_stick(&[("AT+WSLQ\n", "+ERR=-2\r\n\r\n")]);
+    let error = at_command::signal_quality(LOCALHOST, port, Duration::from_secs(1)).unwrap_err();
+    assert!(error.to_string().contains("-2"));
+    stick.join().unwrap();
+}
+
+#[test]
+fn reboots_stick() {
+    let (port, stick) = mock_stick(&[("AT+Z\n", "+ok\r\n\r\n")]);
+    at_command::reboot(LOCALHOST, port, Duration::from_secs(1)).unwrap();
+    assert_eq!(stick.join().unwrap(), ["WIFIKIT-214028-READ", "+ok", "AT+Z\n", "AT+Q\n"]);
+}
+
+#[test]
+fn reads_firmware_version() {
+    let (port, stick) = mock_stick(&[("AT+YZVER\n", "+ok=LSW3_15_FFFF_1.0.65\r\n\r\n")]);
+    let version = at_command::firmware_version(LOCALHOST, port, Duration::from_secs(1)).unwrap();
+    assert_eq!(version, "LSW3_15_FFFF_1.0.65");
+    stick.join().unwrap();
+}
+
+#[test]
+fn parses_signal_quality() {
+    assert_eq!(parse_signal_quality("Normal, 100%"), Some(100));
+    assert_eq!(parse_signal_quality("Weak,7%"), Some(7));
+    assert_eq!(parse_signal_quality("No Connection"), None);
+}
+
+#[test]
+fn discovers_answering_sticks() {
+    let (port, stick) = mock_stick(&[]);
+    let sticks = at_command::discover(LOCALHOST, port, Duration::from_millis(300)).unwrap();
+    assert_eq!(
+        sticks,
+        [at_command::DiscoveredStick {
+            addr: LOCALHOST,
+            mac: "ACCF23000000".to_owned(),
+            serial: 4012345678,
+        }]
+    );
+    assert_eq!(stick.join().unwrap(), ["WIFIKIT-214028-READ"]);
+}
@@ -0,0 +1,120 @@
+//! In-process stand-in for a logger stick, answering each connection with a
+//! canned reply.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const SERIAL: [u8; 4] = [0x78, 0x56, 0x34, 0x12];
+pub const TIMEOUT: Duration = Duration::from_millis(500);
+pub const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// Reply to one connection, the connection is closed afterwards.
+pub enum Reply {
+    Frame(Vec<u8>),
+    Delayed(Duration, Vec<u8>),
+    Close,
+}
+
+pub struct MockStick {
+    port: u16,
+    handle: JoinHandle<Vec<Vec<u8>>>,
+}
+
+impl MockStick {
+    /// Serves `replies` in order, one per accepted connection.
+    pub fn start(replies: Vec<Reply>) -> Self {
+        let listener = TcpListener::bind((LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in replies {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&mut stream));
+                // Write errors are expected when the client already gave up.
+                let _ = match reply {
+                    Reply::Frame(frame) => stream.write_all(&frame),
+                    Reply::Delayed(delay, frame) => {
+                        std::thread::sleep(delay);
+                        stream.write_all(&frame)
+                    }
+                    Reply::Close => Ok(()),
+                };
+            }
+            requests
+        });
+        MockStick { port, handle }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Waits until all replies were served and returns the received requests.
+    pub fn requests(self) -> Vec<Vec<u8>> {
+        self.handle.join().unwrap()
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Vec<u8> {
+    let mut frame = vec![0; 3];
+    stream.read_exact(&mut frame).unwrap();
+    let payload_length = u16::from_le_bytes([frame[1], frame[2]]) as usize;
+    // remaining header, payload, checksum and end byte
+    frame.resize(11 + payload_length + 2, 0);
+    stream.read_exact(&mut frame[3..]).unwrap();
+    frame
+}
+
+/// Builds a response frame from the stick carrying `rtu_frame`.
+pub fn response_frame(serial: [u8; 4], rtu_frame: &[u8]) -> Vec<u8> {
+    let payload_length = (14 + rtu_frame.len()) as u16;
+    let mut frame = vec![0xA5];
+    frame.extend(payload_length.to_le_bytes());
+    frame.extend([0x10, 0x15, 0x00, 0x00]);
+    frame.extend(serial);
+    frame.extend([0x02, 0x01]);
+    frame.extend([0; 12]);
+    frame.extend(rtu_frame);
+    let checksum = frame[1..].iter().map(|b| *b as u32).sum::<u32>() as u8;
+    frame.push(checksum);
+    frame.push(0x15);
+    frame
+}
+
+/// Sets the power on time and uptime reported in a response frame, which
+/// make up the time on the stick clock.
+pub fn with_stick_time(mut frame: Vec<u8>, powered_on: u32, uptime: u32) -> Vec<u8> {
+    frame[17..21].copy_from_slice(&uptime.to_le_bytes());
+    frame[21..25].copy_from_slice(&powered_on.to_le_bytes());
+    let checksum_idx = frame.len() - 2;
+    frame[checksum_idx] = frame[1..checksum_idx].iter().map(|b| *b as u32).sum::<u32>() as u8;
+    frame
+}
+
+/// The short reply the stick sends to the empty serial detection request.
+pub fn serial_frame() -> Vec<u8> {
+    response_frame(SERIAL, &[0x00, 0x00])
+}
+
+/// Modbus response to the monitoring read of 0x36 registers starting at
+/// 0x3b, with raw PV1/PV2 voltage and current register values.
+pub fn monitoring_rtu(voltage_a: u16, current_a: u16, voltage_b: u16, current_b: u16) -> Vec<u8> {
+    let mut registers = [0u16; 0x36];
+    registers[0x6d - 0x3b] = voltage_a;
+    registers[0x6e - 0x3b] = current_a;
+    registers[0x6f - 0x3b] = voltage_b;
+    registers[0x70 - 0x3b] = current_b;
+    let mut rtu = vec![0x01, 0x03, 0x36 * 2];
+    for register in registers {
+        rtu.extend(register.to_be_bytes());
+    }
+    // The CRC is not checked by the decoder.
+    rtu.extend([0x00, 0x00]);
+    rtu
+}
+
+pub fn monitoring_frame() -> Vec<u8> {
+    response_frame(SERIAL, &monitoring_rtu(312, 25, 298, 31))
+}
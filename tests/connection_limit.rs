@@ -0,0 +1,20 @@
+use solar_mon::connection_limit;
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[test]
+fn waits_for_a_free_connection() {
+    connection_limit::set_max_connections(2);
+    let first = connection_limit::acquire("first");
+    let _second = connection_limit::acquire("second");
+
+    let (acquired_tx, acquired_rx) = mpsc::channel();
+    let waiting = std::thread::spawn(move || {
+        let _third = connection_limit::acquire("third");
+        acquired_tx.send(()).unwrap();
+    });
+    assert!(acquired_rx.recv_timeout(Duration::from_millis(200)).is_err());
+    drop(first);
+    acquired_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    waiting.join().unwrap();
+}
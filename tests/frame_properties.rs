@@ -0,0 +1,110 @@
+use proptest::prelude::*;
+use solar_mon::solarmanv5::{
+    verify_frame, Request, RequestFrameType, RequestHeader, RequestPayload, Response,
+};
+
+#[derive(Debug, Clone)]
+struct RequestParts {
+    msg_id: u16,
+    logger_serial: [u8; 4],
+    frame_type: RequestFrameType,
+    sensor_type: u16,
+    total_working_second: u32,
+    uptime_second: u32,
+    offset_seconds: u32,
+    modbus_rtu_frame: Vec<u8>,
+}
+
+impl RequestParts {
+    fn request(&self) -> Request<'_> {
+        Request {
+            header: RequestHeader {
+                msg_id: self.msg_id,
+                logger_serial: self.logger_serial,
+            },
+            payload: RequestPayload {
+                frame_type: self.frame_type.clone(),
+                sensor_type: self.sensor_type,
+                total_working_second: self.total_working_second,
+                uptime_second: self.uptime_second,
+                offset_seconds: self.offset_seconds,
+                modbus_rtu_frame: &self.modbus_rtu_frame,
+            },
+        }
+    }
+}
+
+fn request_parts() -> impl Strategy<Value = RequestParts> {
+    (
+        any::<u16>(),
+        any::<[u8; 4]>(),
+        prop_oneof![
+            Just(RequestFrameType::SolarInverter),
+            Just(RequestFrameType::DataLoggingStick),
+            Just(RequestFrameType::SolarmanCloud),
+        ],
+        any::<u16>(),
+        any::<(u32, u32, u32)>(),
+        prop::collection::vec(any::<u8>(), 0..300),
+    )
+        .prop_map(
+            |(msg_id, logger_serial, frame_type, sensor_type, times, modbus_rtu_frame)| {
+                RequestParts {
+                    msg_id,
+                    logger_serial,
+                    frame_type,
+                    sensor_type,
+                    total_working_second: times.0,
+                    uptime_second: times.1,
+                    offset_seconds: times.2,
+                    modbus_rtu_frame,
+                }
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn serialized_request_parses_back(parts in request_parts()) {
+        let request = parts.request();
+        let bytes = request.to_bytes();
+        prop_assert_eq!(Request::from_bytes(&bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn serialized_request_upholds_framing(parts in request_parts()) {
+        let request = parts.request();
+        let bytes = request.to_bytes();
+        let len = bytes.len();
+        prop_assert_eq!(len, request.frame_length());
+        prop_assert_eq!(bytes[0], 0xA5);
+        prop_assert_eq!(bytes[len - 1], 0x15);
+        prop_assert_eq!(usize::from(u16::from_le_bytes([bytes[1], bytes[2]])), len - 13);
+        let checksum = bytes[1..len - 2].iter().map(|b| *b as u32).sum::<u32>() as u8;
+        prop_assert_eq!(bytes[len - 2], checksum);
+        prop_assert!(verify_frame(&bytes).is_ok());
+    }
+
+    #[test]
+    fn write_to_appends_to_buffer(parts in request_parts(), prefix in prop::collection::vec(any::<u8>(), 0..32)) {
+        let request = parts.request();
+        let mut buffer = prefix.clone();
+        request.write_to(&mut buffer);
+        prop_assert_eq!(&buffer[..prefix.len()], &prefix[..]);
+        prop_assert_eq!(&buffer[prefix.len()..], &request.to_bytes()[..]);
+    }
+
+    #[test]
+    fn corrupted_request_is_rejected(parts in request_parts(), idx in any::<prop::sample::Index>(), flip in 1u8..=255) {
+        let mut bytes = parts.request().to_bytes();
+        let idx = idx.index(bytes.len());
+        bytes[idx] ^= flip;
+        prop_assert!(Request::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn response_parsing_never_panics(data in prop::collection::vec(any::<u8>(), 0..200)) {
+        let parsed = Response::from_bytes(&data);
+        prop_assert_eq!(parsed.is_ok(), data.len() >= Response::MIN_LENGTH);
+    }
+}
@@ -0,0 +1,106 @@
+//! Checks the register maps against the example frames in
+//! `tests/fixtures/register_maps`, one file per inverter model. The frames
+//! are synthetic, built from the documented register layout with made up
+//! values; they pin down the decoding, not the layout of the models.
+
+use serde::Deserialize;
+use solar_mon::registers::RegisterMap;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct Fixture {
+    inputs: u8,
+    /// Hex bytes of the frames, whitespace is ignored
+    request: String,
+    response: String,
+    expected: Expected,
+}
+
+#[derive(Deserialize)]
+struct Expected {
+    ac_power: f64,
+    energy_today: f64,
+    energy_total: f64,
+    inputs: Vec<ExpectedInput>,
+}
+
+#[derive(Deserialize)]
+struct ExpectedInput {
+    name: String,
+    voltage: f64,
+    current: f64,
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    let digits: String = text.split_whitespace().collect();
+    (0..digits.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&digits[idx..idx + 2], 16).unwrap())
+        .collect()
+}
+
+fn assert_close(file: &str, name: &str, actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-6,
+        "{file}: {name} is {actual}, expected {expected}"
+    );
+}
+
+fn check(path: &Path) {
+    let file = path.display().to_string();
+    let fixture: Fixture = toml::from_str(&std::fs::read_to_string(path).unwrap())
+        .unwrap_or_else(|e| panic!("{file}: {e}"));
+    let map = RegisterMap::micro(fixture.inputs);
+    assert_eq!(
+        map.request_frame(),
+        hex(&fixture.request),
+        "{file}: request"
+    );
+    let data = map
+        .decode(&hex(&fixture.response))
+        .unwrap_or_else(|e| panic!("{file}: {e:#}"));
+    let expected = fixture.expected;
+    assert_close(&file, "ac_power", data.ac_power, expected.ac_power);
+    assert_close(
+        &file,
+        "energy_today",
+        data.energy_today,
+        expected.energy_today,
+    );
+    assert_close(
+        &file,
+        "energy_total",
+        data.energy_total,
+        expected.energy_total,
+    );
+    assert_eq!(data.inputs.len(), expected.inputs.len(), "{file}: inputs");
+    for (input, expected) in data.inputs.iter().zip(&expected.inputs) {
+        assert_eq!(input.name, expected.name, "{file}: input name");
+        let name = format!("input {} ", input.name);
+        assert_close(
+            &file,
+            &(name.clone() + "voltage"),
+            input.voltage,
+            expected.voltage,
+        );
+        assert_close(&file, &(name + "current"), input.current, expected.current);
+    }
+}
+
+#[test]
+fn fixtures_decode_to_expected_values() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/register_maps");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "toml")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No fixtures in {}", dir.display());
+    for path in paths {
+        check(&path);
+    }
+}
@@ -0,0 +1,331 @@
+use solar_mon::error_code::ErrorCode;
+use solar_mon::registers::{
+    crc16, decode_values, read_frame, write_frame, GridProtectionRegisters, InsulationRegisters,
+    MeterRegisters, ReadFunction, RegisterMap, Scales, MAX_WRITE_REGISTERS,
+};
+
+fn response(start: u16, count: u16, values: &[(u16, u16)]) -> Vec<u8> {
+    let mut rtu = vec![0x01, 0x03, (count * 2) as u8];
+    let mut registers = vec![0u16; count as usize];
+    for (register, value) in values {
+        registers[(register - start) as usize] = *value;
+    }
+    for register in registers {
+        rtu.extend(register.to_be_bytes());
+    }
+    rtu.extend(crc16(&rtu).to_le_bytes());
+    rtu
+}
+
+#[test]
+fn dual_input_request_is_unchanged() {
+    assert_eq!(
+        RegisterMap::micro(2).request_frame(),
+        [0x1, 0x3, 0x0, 0x3b, 0x0, 0x36, 0xb4, 0x11]
+    );
+}
+
+#[test]
+fn single_input_decodes_one_reading() {
+    let map = RegisterMap::micro(1);
+    let data = map
+        .decode(&response(
+            map.start,
+            map.count,
+            &[(0x6d, 345), (0x6e, 12), (0x56, 3910), (0x3c, 17), (0x3f, 5), (0x40, 1)],
+        ))
+        .unwrap();
+    assert_eq!(data.inputs.len(), 1);
+    assert_eq!(data.inputs[0].name, "A");
+    assert_eq!(data.inputs[0].voltage, 34.5);
+    assert_eq!(data.inputs[0].current, 1.2);
+    assert_eq!(data.ac_power, 391.0);
+    assert_eq!(data.energy_today, 1.7);
+    assert_eq!(data.energy_total, f64::from(0x1_0005) / 10.0);
+    assert_eq!(data.dc_power(), 34.5 * 1.2);
+}
+
+#[test]
+fn input_registers_at_other_base() {
+    let map = RegisterMap::micro(2).with_addressing(ReadFunction::InputRegisters, 0x1000);
+    let frame = map.request_frame();
+    assert_eq!(frame[..6], [0x1, 0x4, 0x10, 0x3b, 0x0, 0x36]);
+    assert_eq!(frame[6..], crc16(&frame[..6]).to_le_bytes());
+    // Registers keep their numbers when decoding.
+    let mut rtu = response(map.start, map.count, &[(0x6d, 312)]);
+    rtu[1] = 0x04;
+    assert_eq!(map.decode(&rtu).unwrap().inputs[0].voltage, 31.2);
+    assert_eq!(map.decode_snapshot(&rtu[3..]).unwrap().inputs[0].voltage, 31.2);
+}
+
+#[test]
+fn fast_read_covers_inputs_and_ac_power() {
+    let map = RegisterMap::micro(2);
+    assert_eq!(map.fast_block(), (0x56, 0x70 - 0x56 + 1));
+    assert_eq!(map.fast_request_frame()[..6], [0x1, 0x3, 0x0, 0x56, 0x0, 0x1b]);
+
+    let full = map
+        .decode(&response(map.start, map.count, &[(0x6d, 312), (0x56, 400), (0x3c, 17)]))
+        .unwrap();
+    let (start, count) = map.fast_block();
+    let fast = response(start, count, &[(0x6d, 330), (0x70, 25), (0x56, 420)]);
+    let data = map.decode_fast(&fast, &full).unwrap();
+    assert_eq!(data.inputs[0].voltage, 33.0);
+    assert_eq!(data.inputs[1].current, 2.5);
+    assert_eq!(data.ac_power, 42.0);
+    // Taken over from the full read
+    assert_eq!(data.energy_today, 1.7);
+    assert!(map.decode_fast(&fast[..20], &full).is_err());
+}
+
+#[test]
+fn scale_overrides_apply_to_their_field_only() {
+    let map = RegisterMap::micro(1).with_scales(Scales {
+        current: 0.01,
+        ..Scales::default()
+    });
+    let data = map
+        .decode(&response(map.start, map.count, &[(0x6d, 345), (0x6e, 123), (0x56, 3910)]))
+        .unwrap();
+    assert_eq!(data.inputs[0].voltage, 34.5);
+    assert_eq!(data.inputs[0].current, 1.23);
+    assert_eq!(data.ac_power, 391.0);
+}
+
+#[test]
+fn negative_values_are_signed() {
+    let map = RegisterMap::micro(1);
+    let data = map
+        .decode(&response(
+            map.start,
+            map.count,
+            &[(0x6d, 123), (0x6e, (-2i16) as u16), (0x56, (-15i16) as u16), (0x57, 0xffff)],
+        ))
+        .unwrap();
+    assert_eq!(data.inputs[0].current, -0.2);
+    assert_eq!(data.ac_power, -1.5);
+}
+
+#[test]
+fn four_inputs_extend_the_read_block() {
+    let map = RegisterMap::micro(4);
+    assert_eq!(map.count, 0x3a);
+    let values = [
+        (0x6d, 300),
+        (0x6e, 10),
+        (0x6f, 310),
+        (0x70, 20),
+        (0x71, 320),
+        (0x72, 30),
+        (0x73, 330),
+        (0x74, 40),
+    ];
+    let data = map.decode(&response(map.start, map.count, &values)).unwrap();
+    let names: Vec<_> = data.inputs.iter().map(|input| input.name.as_str()).collect();
+    assert_eq!(names, ["A", "B", "C", "D"]);
+    assert_eq!(data.inputs[3].voltage, 33.0);
+    assert_eq!(data.inputs[3].current, 4.0);
+    assert_eq!(data.inputs[2].power(), 32.0 * 3.0);
+}
+
+#[test]
+fn short_response_is_rejected() {
+    let map = RegisterMap::micro(4);
+    let mut rtu = response(map.start, map.count, &[]);
+    rtu.truncate(100);
+    assert!(map.decode(&rtu).is_err());
+}
+
+#[test]
+fn meter_reads_signed_power_and_split_counters() {
+    let meter = MeterRegisters {
+        import_energy: [0x4e, 0x50],
+        export_energy: [0x51, 0x52],
+        power: 0xa9,
+    };
+    let frame = meter.request_frame(1);
+    assert_eq!(frame[..6], [0x1, 0x3, 0x0, 0x4e, 0x0, 0xa9 - 0x4e + 1]);
+
+    let rtu = response(
+        0x4e,
+        0xa9 - 0x4e + 1,
+        &[
+            (0x4e, 0x0002),
+            (0x50, 0x0001),
+            (0x51, 1234),
+            (0x52, 0),
+            (0xa9, (-350i16) as u16),
+        ],
+    );
+    let reading = meter.decode(&rtu).unwrap();
+    assert_eq!(reading.import_energy, f64::from(0x1_0002) / 10.0);
+    assert_eq!(reading.export_energy, 123.4);
+    assert_eq!(reading.power, -350.0);
+}
+
+#[test]
+fn single_request_reads_meter_with_the_block() {
+    let map = RegisterMap::micro(2)
+        .with_meter(MeterRegisters {
+            import_energy: [0x4e, 0x50],
+            export_energy: [0x51, 0x52],
+            power: 0xa9,
+        })
+        .single_request()
+        .unwrap();
+    assert_eq!(map.meter_request_frame(), None);
+    assert_eq!(map.request_frame()[..6], [0x1, 0x3, 0x0, 0x3b, 0x0, 0xa9 - 0x3b + 1]);
+
+    let data = map
+        .decode(&response(
+            map.start,
+            map.count,
+            &[(0x6d, 312), (0x6e, 25), (0x51, 1234), (0xa9, (-350i16) as u16)],
+        ))
+        .unwrap();
+    assert_eq!(data.inputs[0].voltage, 31.2);
+    let meter = data.meter.unwrap();
+    assert_eq!(meter.export_energy, 123.4);
+    assert_eq!(meter.power, -350.0);
+}
+
+#[test]
+fn write_frame_sets_multiple_registers() {
+    let frame = write_frame(1, 0x28, &[50, 0x0102]).unwrap();
+    assert_eq!(frame[..11], [0x01, 0x10, 0x00, 0x28, 0x00, 0x02, 0x04, 0x00, 0x32, 0x01, 0x02]);
+    assert_eq!(frame[11..], crc16(&frame[..11]).to_le_bytes());
+}
+
+#[test]
+fn write_frame_rejects_too_many_registers() {
+    let frame = write_frame(1, 0x28, &[0; MAX_WRITE_REGISTERS]).unwrap();
+    // The byte count still fits its single byte.
+    assert_eq!(frame[6], 246);
+    assert_eq!(frame.len(), 7 + 2 * MAX_WRITE_REGISTERS + 2);
+    assert!(write_frame(1, 0x28, &[0; MAX_WRITE_REGISTERS + 1]).is_err());
+    assert!(write_frame(1, 0x28, &[]).is_err());
+}
+
+#[test]
+fn decode_values_returns_every_register_of_a_read() {
+    assert_eq!(read_frame(1, 0x28, 2)[..6], [0x01, 0x03, 0x00, 0x28, 0x00, 0x02]);
+    let rtu = response(0x28, 2, &[(0x28, 50), (0x29, 0x0102)]);
+    assert_eq!(decode_values(&rtu, 0x28, 2).unwrap(), [50, 0x0102]);
+    assert!(decode_values(&rtu[..5], 0x28, 2).is_err());
+    assert!(decode_values(&rtu, 0xffff, 2).is_err());
+}
+
+#[test]
+fn snapshot_decodes_like_a_read_of_the_block() {
+    let map = RegisterMap::micro(2);
+    let rtu = response(map.start, map.count, &[(0x6d, 345), (0x70, 12), (0x3c, 17)]);
+    let mut snapshot = rtu[3..rtu.len() - 2].to_vec();
+    // Registers past the block are ignored.
+    snapshot.extend([0xff; 8]);
+    assert_eq!(map.decode_snapshot(&snapshot).unwrap(), map.decode(&rtu).unwrap());
+    let err = map.decode_snapshot(&snapshot[..20]).unwrap_err();
+    assert_eq!(ErrorCode::of(&err), ErrorCode::ModbusResponseTooShort);
+}
+
+#[test]
+fn responses_of_a_poll_decode_with_the_meter() {
+    let meter = MeterRegisters {
+        import_energy: [0x4e, 0x50],
+        export_energy: [0x51, 0x52],
+        power: 0xa9,
+    };
+    let map = RegisterMap::micro(2).with_meter(meter);
+    let block = response(map.start, map.count, &[(0x6d, 312)]);
+    let meter_rtu = response(0x4e, 0xa9 - 0x4e + 1, &[(0xa9, (-350i16) as u16)]);
+    let data = map.decode_responses(&[block.clone(), meter_rtu]).unwrap();
+    assert_eq!(data.inputs[0].voltage, 31.2);
+    assert_eq!(data.meter.unwrap().power, -350.0);
+    assert!(map.decode_responses(&[block]).is_err());
+}
+
+#[test]
+fn grid_protection_decodes_limits_and_delay() {
+    let registers = GridProtectionRegisters {
+        over_voltage: 0x1b,
+        under_voltage: 0x1c,
+        over_frequency: 0x1d,
+        under_frequency: 0x20,
+        reconnect_delay: 0x1e,
+    };
+    assert_eq!(registers.request_frame(1)[..6], [0x1, 0x3, 0x0, 0x1b, 0x0, 6]);
+
+    let rtu = response(
+        0x1b,
+        6,
+        &[(0x1b, 2530), (0x1c, 1840), (0x1d, 5150), (0x20, 4750), (0x1e, 60)],
+    );
+    let settings = registers.decode(&rtu).unwrap();
+    assert_eq!(settings.over_voltage, 253.0);
+    assert_eq!(settings.under_voltage, 184.0);
+    assert_eq!(settings.over_frequency, 51.5);
+    assert_eq!(settings.under_frequency, 47.5);
+    assert_eq!(settings.reconnect_delay_secs, 60.0);
+    assert!(registers.decode(&rtu[..10]).is_err());
+}
+
+#[test]
+fn derating_register_extends_the_block() {
+    let map = RegisterMap::micro(2).with_derating(0x47).unwrap();
+    assert_eq!((map.start, map.count), (0x3b, 0x36));
+    let map = RegisterMap::micro(2).with_derating(0x80).unwrap();
+    assert_eq!((map.start, map.count), (0x3b, 0x80 - 0x3b + 1));
+
+    let data = map.decode(&response(map.start, map.count, &[(0x80, 0b1001)])).unwrap();
+    let derating = data.derating.unwrap();
+    assert!(derating.active());
+    assert_eq!((0..16).filter(|bit| derating.bit(*bit)).collect::<Vec<_>>(), [0, 3]);
+    assert!(!derating.bit(16));
+    assert_eq!(RegisterMap::micro(2).decode(&response(0x3b, 0x36, &[])).unwrap().derating, None);
+}
+
+#[test]
+fn insulation_values_are_read_with_the_block() {
+    let map = RegisterMap::micro(2)
+        .with_insulation(InsulationRegisters {
+            resistance: 0x30,
+            leakage_current: 0x48,
+        })
+        .unwrap();
+    assert_eq!((map.start, map.count), (0x30, 0x70 - 0x30 + 1));
+    let data = map
+        .decode(&response(map.start, map.count, &[(0x30, 2000), (0x48, 12)]))
+        .unwrap();
+    let insulation = data.insulation.unwrap();
+    assert_eq!(insulation.resistance, 2000.0);
+    assert_eq!(insulation.leakage_current, 12.0);
+}
+
+#[test]
+fn dump_names_mapped_registers_only() {
+    let map = RegisterMap::micro(2);
+    let dump = map.dump(&response(0x3b, 0x36, &[(0x3c, 17), (0x50, 0xbeef), (0x6d, 312)])).unwrap();
+    assert_eq!(dump.len(), 0x36);
+    assert_eq!(dump[0], (0x3b, 0, None));
+    assert_eq!(dump[1], (0x3c, 17, Some("energy_today".to_owned())));
+    assert_eq!(dump[0x50 - 0x3b], (0x50, 0xbeef, None));
+    assert_eq!(dump[0x57 - 0x3b].2.as_deref(), Some("ac_power_high"));
+    assert_eq!(dump[0x6d - 0x3b], (0x6d, 312, Some("input_a_voltage".to_owned())));
+    assert!(map.dump(&response(0x3b, 0x10, &[])).is_err());
+}
+
+#[test]
+fn rejects_blocks_past_the_last_address() {
+    assert!(RegisterMap::micro(2).with_derating(0xffff).is_err());
+    assert!(RegisterMap::micro(2).with_derating(0xfffe).is_ok());
+    let insulation = InsulationRegisters {
+        resistance: 0x30,
+        leakage_current: 0xffff,
+    };
+    assert!(RegisterMap::micro(2).with_insulation(insulation).is_err());
+    let map = RegisterMap::micro(2).with_meter(MeterRegisters {
+        import_energy: [0xfffd, 0xfffe],
+        export_energy: [0xfffe, 0xfffe],
+        power: 0xffff,
+    });
+    assert!(map.single_request().is_err());
+}
@@ -0,0 +1,480 @@
+mod common;
+
+use common::{MockStick, Reply, LOCALHOST, SERIAL, TIMEOUT};
+use solar_mon::encryption::{Encryption, PayloadCipher};
+use solar_mon::error_code::ErrorCode;
+use solar_mon::inverter::Inverter;
+use solar_mon::registers::RegisterMap;
+use solar_mon::solarmanv5::{
+    self, Accept, ClockSkew, FrameDecoder, SolarmanDevice, StickFrame, StickFrameType, Timeouts,
+};
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+#[test]
+fn reads_monitoring_data() {
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Frame(common::monitoring_frame()),
+    ]);
+    let mut inverter = Inverter::new(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    let data = inverter.get_data().unwrap();
+    assert_eq!(data.inputs.len(), 2);
+    assert_eq!(data.inputs[0].name, "A");
+    assert_eq!(data.inputs[0].voltage, 31.2);
+    assert_eq!(data.inputs[0].current, 2.5);
+    assert_eq!(data.inputs[1].name, "B");
+    assert_eq!(data.inputs[1].voltage, 29.8);
+    assert_eq!(data.inputs[1].current, 3.1);
+
+    let requests = stick.requests();
+    assert_eq!(requests.len(), 2);
+    // The detected serial is used for every following request.
+    assert_eq!(requests[0][7..11], [0; 4]);
+    assert_eq!(requests[1][7..11], SERIAL);
+    assert_eq!(requests[1][26..34], [0x1, 0x3, 0x0, 0x3b, 0x0, 0x36, 0xb4, 0x11]);
+    assert_eq!(inverter.last_responses(), [common::monitoring_rtu(312, 25, 298, 31)]);
+}
+
+#[test]
+fn reads_four_inputs() {
+    let mut rtu = vec![0x01, 0x03, 0x3a * 2];
+    for register in 0x3b..0x3b + 0x3a {
+        let value: u16 = if (0x6d..=0x74).contains(&register) { register } else { 0 };
+        rtu.extend(value.to_be_bytes());
+    }
+    rtu.extend([0x00, 0x00]);
+    let stick = MockStick::start(vec![Reply::Frame(common::response_frame(SERIAL, &rtu))]);
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL)
+        .with_register_map(RegisterMap::micro(4));
+    let data = inverter.get_data().unwrap();
+    assert_eq!(data.inputs.len(), 4);
+    assert_eq!(data.inputs[3].name, "D");
+    assert_eq!(data.inputs[3].voltage, f64::from(0x73) / 10.0);
+    assert_eq!(data.inputs[3].current, f64::from(0x74) / 10.0);
+
+    let requests = stick.requests();
+    assert_eq!(requests[0][26..32], [0x1, 0x3, 0x0, 0x3b, 0x0, 0x3a]);
+}
+
+#[test]
+fn reads_current_values_once() {
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Frame(common::monitoring_frame()),
+    ]);
+    let data = solar_mon::read_current(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    assert_eq!(data.inputs[0].voltage, 31.2);
+    assert_eq!(data.inputs[1].current, 3.1);
+    assert_eq!(stick.requests().len(), 2);
+}
+
+#[test]
+fn reads_current_values_async() {
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Delayed(Duration::from_millis(50), common::monitoring_frame()),
+    ]);
+    let future = solar_mon::read_current_async(LOCALHOST, stick.port(), TIMEOUT);
+    let data = block_on(future).unwrap();
+    assert_eq!(data.inputs[0].voltage, 31.2);
+
+    let refused = solar_mon::read_current_async(LOCALHOST, stick.port(), TIMEOUT);
+    assert!(block_on(refused).is_err());
+}
+
+/// Minimal executor polling `future` on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+fn truncated_serial_response_fails() {
+    let mut frame = common::serial_frame();
+    frame.truncate(20);
+    let stick = MockStick::start(vec![Reply::Frame(frame)]);
+    assert!(SolarmanDevice::new(LOCALHOST, stick.port(), TIMEOUT).is_err());
+    stick.requests();
+}
+
+#[test]
+fn truncated_response_fails() {
+    let mut frame = common::monitoring_frame();
+    frame.truncate(60);
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Frame(frame),
+    ]);
+    let mut inverter = Inverter::new(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    assert!(inverter.get_data().is_err());
+    stick.requests();
+}
+
+#[test]
+fn closed_connection_fails() {
+    let stick = MockStick::start(vec![Reply::Frame(common::serial_frame()), Reply::Close]);
+    let mut inverter = Inverter::new(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    let err = inverter.get_data().unwrap_err();
+    assert_eq!(ErrorCode::of(&err), ErrorCode::ConnectionLost);
+    stick.requests();
+}
+
+#[test]
+fn refused_connection_fails() {
+    let port = std::net::TcpListener::bind((LOCALHOST, 0)).unwrap().local_addr().unwrap().port();
+    let err = SolarmanDevice::new(LOCALHOST, port, TIMEOUT).err().unwrap();
+    assert_eq!(ErrorCode::of(&err), ErrorCode::ConnectFailed);
+    assert_eq!(ErrorCode::of(&err).code(), "E002");
+}
+
+#[test]
+fn wrong_checksum_fails() {
+    let mut frame = common::monitoring_frame();
+    let checksum_idx = frame.len() - 2;
+    frame[checksum_idx] = frame[checksum_idx].wrapping_add(1);
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Frame(frame),
+    ]);
+    let mut inverter = Inverter::new(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    let err = inverter.get_data().unwrap_err();
+    assert!(err.to_string().contains("Checksum mismatch"));
+    assert_eq!(ErrorCode::of(&err), ErrorCode::ChecksumMismatch);
+    assert_eq!(ErrorCode::of(&err).code(), "E014");
+    stick.requests();
+}
+
+#[test]
+fn garbage_before_frame_is_skipped() {
+    let mut frame = vec![0x00, 0x15, 0xA5, 0xff, 0xff, 0x42];
+    frame.extend(common::monitoring_frame());
+    let stick = MockStick::start(vec![Reply::Frame(frame)]);
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL);
+    let data = inverter.get_data().unwrap();
+    assert_eq!(data.inputs[0].voltage, 31.2);
+    stick.requests();
+}
+
+#[test]
+fn resynchronizes_after_corrupted_frame() {
+    let mut corrupted = common::response_frame(SERIAL, &common::monitoring_rtu(1, 2, 3, 4));
+    let checksum_idx = corrupted.len() - 2;
+    corrupted[checksum_idx] = corrupted[checksum_idx].wrapping_add(1);
+    let mut frames = corrupted;
+    frames.extend(common::monitoring_frame());
+    let stick = MockStick::start(vec![Reply::Frame(frames)]);
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL);
+    let data = inverter.get_data().unwrap();
+    assert_eq!(data.inputs[0].voltage, 31.2);
+    stick.requests();
+}
+
+#[test]
+fn counts_link_statistics() {
+    let mut corrupted = common::response_frame(SERIAL, &common::monitoring_rtu(1, 2, 3, 4));
+    let checksum_idx = corrupted.len() - 2;
+    corrupted[checksum_idx] = corrupted[checksum_idx].wrapping_add(1);
+    let mut frames = corrupted;
+    frames.extend(common::monitoring_frame());
+    let stick = MockStick::start(vec![
+        Reply::Frame(frames),
+        Reply::Delayed(TIMEOUT * 2, common::monitoring_frame()),
+    ]);
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL);
+    assert!(inverter.get_data().is_ok());
+    assert!(inverter.get_data().is_err());
+    let stats = inverter.link_stats();
+    assert_eq!(stats.requests, 2);
+    assert_eq!(stats.failures, 1);
+    assert_eq!(stats.timeouts, 1);
+    assert_eq!(stats.checksum_errors, 1);
+    assert_eq!(stats.rejected_frames, 1);
+    assert!(stats.average_latency().is_some());
+    stick.requests();
+}
+
+#[test]
+fn delayed_response_within_timeout_succeeds() {
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Delayed(TIMEOUT / 4, common::monitoring_frame()),
+    ]);
+    let mut inverter = Inverter::new(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    assert!(inverter.get_data().is_ok());
+    stick.requests();
+}
+
+#[test]
+fn delayed_response_after_timeout_fails() {
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Delayed(TIMEOUT + Duration::from_millis(300), common::monitoring_frame()),
+    ]);
+    let mut inverter = Inverter::new(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    let err = inverter.get_data().unwrap_err();
+    assert_eq!(ErrorCode::of(&err), ErrorCode::ReadTimeout);
+    stick.requests();
+}
+
+#[test]
+fn read_timeout_is_separate_from_connect_timeout() {
+    let stick = MockStick::start(vec![Reply::Delayed(
+        TIMEOUT + Duration::from_millis(300),
+        common::monitoring_frame(),
+    )]);
+    let timeouts = Timeouts {
+        connect: TIMEOUT,
+        read: TIMEOUT * 3,
+        write: TIMEOUT,
+    };
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), timeouts, SERIAL);
+    assert!(inverter.get_data().is_ok());
+    stick.requests();
+}
+
+#[test]
+fn device_reuses_buffers_across_requests() {
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::serial_frame()),
+        Reply::Frame(common::response_frame(SERIAL, &common::monitoring_rtu(1, 2, 3, 4))),
+        Reply::Frame(common::response_frame(SERIAL, &common::monitoring_rtu(5, 6, 7, 8))),
+    ]);
+    let mut device = SolarmanDevice::new(LOCALHOST, stick.port(), TIMEOUT).unwrap();
+    let request = [0x1, 0x3, 0x0, 0x3b, 0x0, 0x36, 0xb4, 0x11];
+    assert_eq!(device.send_modbus_frame(&request).unwrap()[103..105], [0, 1]);
+    assert_eq!(device.send_modbus_frame(&request).unwrap()[103..105], [0, 5]);
+    assert_eq!(stick.requests().len(), 3);
+}
+
+#[test]
+fn clock_skew_dates_back_held_back_readings() {
+    let mut clock = ClockSkew::default();
+    let host = |secs: u64| Duration::from_secs(1_700_000_000 + secs);
+    // The stick clock is 5 s ahead.
+    assert_eq!(clock.update(host(5), host(0)), None);
+    assert_eq!(clock.update(host(65), host(60)), None);
+    assert_eq!(clock.skew_ms(), Some(5000));
+    // A reading taken a minute before it was received
+    assert_eq!(clock.update(host(65), host(120)), Some(Duration::from_secs(60)));
+    // Jitter within the resolution of the stick clock is ignored.
+    assert_eq!(clock.update(host(184), host(180)), None);
+}
+
+#[test]
+fn clock_skew_restarts_after_clock_was_set_back() {
+    let mut clock = ClockSkew::default();
+    let host = |secs: u64| Duration::from_secs(1_700_000_000 + secs);
+    assert_eq!(clock.update(host(3600), host(0)), None);
+    assert_eq!(clock.update(host(60), host(60)), None);
+    assert_eq!(clock.skew_ms(), Some(0));
+}
+
+#[test]
+fn device_estimates_clock_skew_from_responses() {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    let stick = MockStick::start(vec![
+        Reply::Frame(common::with_stick_time(common::monitoring_frame(), now - 3600, 3610)),
+        Reply::Frame(common::with_stick_time(common::monitoring_frame(), now - 3600, 3500)),
+        Reply::Delayed(
+            Duration::from_millis(50),
+            common::with_stick_time(common::monitoring_frame(), now - 3600, 3500),
+        ),
+        Reply::Frame(common::monitoring_frame()),
+    ]);
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL);
+    inverter.get_data().unwrap();
+    assert_eq!(inverter.data_age(), None);
+    let skew = inverter.clock_skew_ms().unwrap();
+    assert!((9000..=10_000).contains(&skew), "skew {skew}");
+    inverter.get_data().unwrap();
+    let age = inverter.data_age().unwrap();
+    assert!((109..=111).contains(&age.as_secs()), "age {age:?}");
+    let reading_time = inverter.reading_time().unwrap();
+    // The same reading again gets the same time.
+    inverter.get_data().unwrap();
+    assert_eq!(inverter.reading_time(), Some(reading_time));
+    // Sticks without a time leave the readings alone.
+    inverter.get_data().unwrap();
+    assert_eq!(inverter.data_age(), None);
+    stick.requests();
+}
+
+#[test]
+fn detects_encrypted_responses() {
+    let cipher = PayloadCipher::for_serial(SERIAL);
+    let encrypted = cipher.encrypt_frame(&common::monitoring_frame());
+    let stick = MockStick::start(vec![
+        Reply::Frame(encrypted.clone()),
+        Reply::Frame(encrypted),
+    ]);
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL);
+    assert!(!inverter.encrypted());
+    assert_eq!(inverter.get_data().unwrap().inputs[0].voltage, 31.2);
+    assert!(inverter.encrypted());
+    assert_eq!(inverter.get_data().unwrap().inputs[1].current, 3.1);
+
+    let requests = stick.requests();
+    // Plain until the stick answered encrypted
+    assert_eq!(requests[0][26..32], [0x1, 0x3, 0x0, 0x3b, 0x0, 0x36]);
+    assert_eq!((requests[1].len() - 13) % 16, 0);
+    let request = cipher.decrypt_frame(&requests[1]).unwrap();
+    assert_eq!(request, requests[0]);
+}
+
+#[test]
+fn configured_key_encrypts_from_the_start() {
+    let cipher = PayloadCipher::new([0x42; 16]);
+    let stick = MockStick::start(vec![
+        Reply::Frame(cipher.encrypt_frame(&common::monitoring_frame())),
+        Reply::Frame(PayloadCipher::for_serial(SERIAL).encrypt_frame(&common::monitoring_frame())),
+    ]);
+    let mut inverter = Inverter::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL)
+        .with_encryption(Encryption::Always, Some([0x42; 16]));
+    assert_eq!(inverter.get_data().unwrap().inputs[0].current, 2.5);
+    // Encrypted with another key
+    let err = inverter.get_data().unwrap_err();
+    assert_eq!(ErrorCode::of(&err), ErrorCode::DecryptionFailed);
+
+    let requests = stick.requests();
+    let request = cipher.decrypt_frame(&requests[0]).unwrap();
+    assert_eq!(request[26..32], [0x1, 0x3, 0x0, 0x3b, 0x0, 0x36]);
+}
+
+#[test]
+fn plain_responses_of_block_length_stay_plain() {
+    // 14 + 2 bytes of payload like the serial detection reply
+    let frame = common::serial_frame();
+    assert_eq!(frame.len() - 13, 16);
+    let stick = MockStick::start(vec![Reply::Frame(frame)]);
+    let mut device = SolarmanDevice::with_serial(LOCALHOST, stick.port(), TIMEOUT, SERIAL);
+    assert_eq!(device.send_modbus_frame(&[0x01, 0x03]).unwrap(), [0x00, 0x00]);
+    assert!(!device.encrypted());
+    stick.requests();
+}
+
+/// Frame with `control_code` a stick sends on its own.
+fn stick_frame(control_code: u16, msg_id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xA5];
+    frame.extend((payload.len() as u16).to_le_bytes());
+    frame.extend(control_code.to_le_bytes());
+    frame.extend(msg_id.to_le_bytes());
+    frame.extend(SERIAL);
+    frame.extend(payload);
+    let checksum = frame[1..].iter().map(|b| *b as u32).sum::<u32>() as u8;
+    frame.extend([checksum, 0x15]);
+    frame
+}
+
+#[test]
+fn parses_the_frames_a_stick_sends() {
+    for (code, frame_type) in [
+        (0x4110, StickFrameType::Announcement),
+        (0x4210, StickFrameType::Data),
+        (0x4310, StickFrameType::Wifi),
+        (0x4710, StickFrameType::Heartbeat),
+    ] {
+        let frame = stick_frame(code, 7, &[0x01, 0xaa]);
+        let parsed = StickFrame::from_bytes(&frame).unwrap();
+        assert_eq!(parsed.frame_type, frame_type);
+        assert_eq!(parsed.msg_id, 7);
+        assert_eq!(parsed.logger_serial, SERIAL);
+        assert_eq!(parsed.payload, [0x01, 0xaa]);
+    }
+    let err = StickFrame::from_bytes(&stick_frame(0x1510, 7, &[0x01])).unwrap_err();
+    assert_eq!(ErrorCode::of(&err), ErrorCode::UnexpectedFrame);
+}
+
+#[test]
+fn answers_stick_frames_with_the_time() {
+    let frame = stick_frame(0x4710, 0x0102, &[0x00]);
+    let response = StickFrame::from_bytes(&frame).unwrap().response(0x6543_2100);
+    solarmanv5::verify_frame(&response).unwrap();
+    assert_eq!(response[1..3], [10, 0]);
+    // Control code 0x1710, the message id and serial of the heartbeat
+    assert_eq!(response[3..11], [0x10, 0x17, 0x02, 0x01, 0x78, 0x56, 0x34, 0x12]);
+    assert_eq!(response[11..21], [0x00, 0x01, 0x00, 0x21, 0x43, 0x65, 0, 0, 0, 0]);
+}
+
+#[test]
+fn reading_stick_frames_keeps_the_next_one() {
+    let mut stream = vec![0x00];
+    stream.extend(stick_frame(0x4110, 1, &[0x02; 20]));
+    stream.extend(stick_frame(0x4710, 2, &[0x00]));
+    let mut reader = stream.as_slice();
+    let mut buffer = Vec::new();
+    let length = solarmanv5::read_stick_frame(&mut reader, &mut buffer).unwrap();
+    assert_eq!(StickFrame::from_bytes(&buffer[..length]).unwrap().msg_id, 1);
+    buffer.drain(..length);
+    let length = solarmanv5::read_stick_frame(&mut reader, &mut buffer).unwrap();
+    let heartbeat = StickFrame::from_bytes(&buffer[..length]).unwrap();
+    assert_eq!(heartbeat.frame_type, StickFrameType::Heartbeat);
+}
+
+#[test]
+fn data_frames_carry_a_register_snapshot() {
+    let mut payload = vec![0x08];
+    payload.extend([0; 14]);
+    payload.extend([0x01, 0x38, 0x00, 0x19]);
+    let frame = stick_frame(0x4210, 3, &payload);
+    let data = StickFrame::from_bytes(&frame).unwrap();
+    assert_eq!(data.data_registers(), Some([0x01, 0x38, 0x00, 0x19].as_slice()));
+    let heartbeat = stick_frame(0x4710, 4, &payload);
+    assert_eq!(StickFrame::from_bytes(&heartbeat).unwrap().data_registers(), None);
+}
+
+#[test]
+fn decoder_waits_for_the_rest_of_a_frame() {
+    let frame = common::monitoring_frame();
+    let mut decoder = FrameDecoder::new(Accept::Response);
+    let mut buffer = Vec::new();
+    for byte in &frame[..frame.len() - 1] {
+        buffer.push(*byte);
+        assert_eq!(decoder.decode(&mut buffer), None);
+    }
+    buffer.push(frame[frame.len() - 1]);
+    buffer.extend([0xA5, 0x01]);
+    assert_eq!(decoder.decode(&mut buffer), Some(frame.len()));
+    assert_eq!(buffer[..frame.len()], frame[..]);
+    assert_eq!(decoder.skipped(), 0);
+}
+
+#[test]
+fn decoder_skips_garbage_and_other_frames() {
+    let mut corrupted = common::monitoring_frame();
+    let checksum_idx = corrupted.len() - 2;
+    corrupted[checksum_idx] = corrupted[checksum_idx].wrapping_add(1);
+    let heartbeat = stick_frame(0x4710, 1, &[0x00]);
+    let mut buffer = vec![0x00, 0x15, 0xA5, 0xff, 0xff];
+    buffer.extend(&corrupted);
+    buffer.extend(&heartbeat);
+    buffer.extend(common::monitoring_frame());
+
+    let mut decoder = FrameDecoder::new(Accept::Response);
+    let length = decoder.decode(&mut buffer).unwrap();
+    assert_eq!(buffer[..length], common::monitoring_frame()[..]);
+    assert_eq!(decoder.rejected_frames(), 2);
+    assert_eq!(decoder.checksum_errors(), 1);
+
+    let mut buffer = heartbeat.clone();
+    let length = FrameDecoder::new(Accept::Any).decode(&mut buffer).unwrap();
+    assert_eq!(buffer[..length], heartbeat[..]);
+}
@@ -0,0 +1,152 @@
+use solar_mon::inverter::Inverter;
+use solar_mon::registers::{crc16, RegisterMap};
+use solar_mon::transport::{MockTransport, ModbusTcp, SerialRtu, Transport};
+use std::io::{Cursor, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::time::Duration;
+
+const DUAL_INPUT_REQUEST: [u8; 8] = [0x1, 0x3, 0x0, 0x3b, 0x0, 0x36, 0xb4, 0x11];
+
+/// Response to the dual input monitoring read with the PV1 voltage and
+/// current set.
+fn monitoring_response(voltage: u16, current: u16) -> Vec<u8> {
+    let mut registers = [0u16; 0x36];
+    registers[0x6d - 0x3b] = voltage;
+    registers[0x6e - 0x3b] = current;
+    let mut rtu = vec![0x01, 0x03, 0x36 * 2];
+    for register in registers {
+        rtu.extend(register.to_be_bytes());
+    }
+    rtu.extend(crc16(&rtu).to_le_bytes());
+    rtu
+}
+
+#[test]
+fn decodes_without_socket() {
+    let transport = MockTransport::new([monitoring_response(312, 25)]);
+    let mut inverter = Inverter::from_device(transport);
+    let data = inverter.get_data().unwrap();
+    assert_eq!(data.inputs[0].voltage, 31.2);
+    assert_eq!(data.inputs[0].current, 2.5);
+    assert_eq!(inverter.device().requests(), [DUAL_INPUT_REQUEST]);
+    assert_eq!(inverter.link_stats().requests, 1);
+
+    // Nothing queued any more
+    assert!(inverter.get_data().is_err());
+    assert_eq!(inverter.link_stats().failures, 1);
+}
+
+#[test]
+fn fast_reads_complete_the_last_full_read() {
+    let mut fast = vec![0x01, 0x03, 0x1b * 2];
+    for register in 0x56..=0x70u16 {
+        let value: u16 = if register == 0x6d { 330 } else { 0 };
+        fast.extend(value.to_be_bytes());
+    }
+    fast.extend(crc16(&fast).to_le_bytes());
+    let transport = MockTransport::new([monitoring_response(312, 25), fast]);
+    let mut inverter = Inverter::from_device(transport);
+    // Without a full read yet the fast read reads everything.
+    assert_eq!(inverter.get_fast_data().unwrap().inputs[0].current, 2.5);
+    let data = inverter.get_fast_data().unwrap();
+    assert_eq!(data.inputs[0].voltage, 33.0);
+    assert!(inverter.last_responses().is_empty());
+    let requests = inverter.device().requests();
+    assert_eq!(requests[0], DUAL_INPUT_REQUEST);
+    assert_eq!(requests[1][..6], [0x1, 0x3, 0x0, 0x56, 0x0, 0x1b]);
+}
+
+#[test]
+fn mock_uses_register_map() {
+    let transport = MockTransport::new([]);
+    let mut inverter = Inverter::from_device(transport).with_register_map(RegisterMap::micro(4));
+    assert!(inverter.get_data().is_err());
+    assert_eq!(inverter.device().requests()[0][..6], [0x1, 0x3, 0x0, 0x3b, 0x0, 0x3a]);
+}
+
+/// Serial port replaying `input` and recording what is written.
+struct FakePort {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for FakePort {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for FakePort {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn serial_rtu_reads_response_by_length() {
+    let mut input = monitoring_response(298, 31);
+    // Exception response to the second request
+    let mut exception = vec![0x01, 0x83, 0x02];
+    exception.extend(crc16(&exception).to_le_bytes());
+    input.extend(&exception);
+    let port = FakePort {
+        input: Cursor::new(input),
+        output: Vec::new(),
+    };
+    let mut inverter = Inverter::from_device(SerialRtu::new(port));
+    let data = inverter.get_data().unwrap();
+    assert_eq!(data.inputs[0].voltage, 29.8);
+    assert_eq!(inverter.send_raw(&DUAL_INPUT_REQUEST).unwrap(), exception);
+}
+
+#[test]
+fn serial_rtu_rejects_wrong_crc() {
+    let mut input = monitoring_response(298, 31);
+    let last = input.len() - 1;
+    input[last] ^= 0xff;
+    let port = FakePort {
+        input: Cursor::new(input),
+        output: Vec::new(),
+    };
+    let mut transport = SerialRtu::new(port);
+    assert!(transport.send_modbus_frame(&DUAL_INPUT_REQUEST).is_err());
+    assert_eq!(transport.stats().checksum_errors, 1);
+}
+
+#[test]
+fn modbus_tcp_wraps_rtu_frames() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let gateway = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut requests = Vec::new();
+        // Both requests arrive on the same connection.
+        for _ in 0..2 {
+            let mut request = [0; 12];
+            stream.read_exact(&mut request).unwrap();
+            let rtu = monitoring_response(312, 25);
+            let pdu = &rtu[1..rtu.len() - 2];
+            let mut response = request[..4].to_vec();
+            response.extend(((pdu.len() + 1) as u16).to_be_bytes());
+            response.push(request[6]);
+            response.extend(pdu);
+            stream.write_all(&response).unwrap();
+            requests.push(request);
+        }
+        requests
+    });
+
+    let transport = ModbusTcp::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port, Duration::from_secs(1));
+    let mut inverter = Inverter::from_device(transport);
+    assert_eq!(inverter.get_data().unwrap().inputs[0].voltage, 31.2);
+    assert_eq!(inverter.get_data().unwrap().inputs[0].current, 2.5);
+    assert_eq!(inverter.last_responses(), [monitoring_response(312, 25)]);
+
+    let requests = gateway.join().unwrap();
+    assert_eq!(requests[0], [0, 1, 0, 0, 0, 6, 1, 0x3, 0x0, 0x3b, 0x0, 0x36]);
+    assert_eq!(requests[1][..2], [0, 2]);
+}